@@ -1,18 +1,31 @@
 use std::{
+    io::ErrorKind,
     path::Path,
     process::Command,
 };
 
+/// The files wasm-pack produces that get served from the static directory.
+const FRONTEND_FILES: [&str; 2] = ["woof_passkey_login.js", "woof_passkey_login_bg.wasm"];
+
 fn main() {
     let dir = "woof-passkey-login"; // update to your directory
     println!("cargo:rerun-if-changed={}/", dir);
+    // Installing wasm-pack later on should be picked up by the next build.
+    println!("cargo:rerun-if-env-changed=PATH");
 
     let dest_path = Path::new(&dir).join("pkg");
-    let output = Command::new("wasm-pack")
+    let output = match Command::new("wasm-pack")
         .args(&["build", "--target", "web"])
         .arg(dir)
         .output()
-        .expect("To build wasm files successfully");
+    {
+        Ok(output) => output,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            skip_frontend_build();
+            return;
+        }
+        Err(err) => panic!("Could not run wasm-pack: {}", err),
+    };
 
     if !output.status.success() {
         panic!(
@@ -21,8 +34,8 @@ fn main() {
         );
     }
 
-    let js_file = dest_path.join("woof_passkey_login.js");
-    let wasm_file = dest_path.join("woof_passkey_login_bg.wasm");
+    let js_file = dest_path.join(FRONTEND_FILES[0]);
+    let wasm_file = dest_path.join(FRONTEND_FILES[1]);
 
     for file in &[&js_file, &wasm_file] {
         let file = std::fs::metadata(file).expect("file to exist");
@@ -35,7 +48,30 @@ fn main() {
     // Copy the files to the static directory
     let static_dir = Path::new("static");
     std::fs::create_dir_all(&static_dir).expect("to create static directory");
-    std::fs::copy(&js_file, static_dir.join("woof_passkey_login.js")).expect("to copy js file");
-    std::fs::copy(&wasm_file, static_dir.join("woof_passkey_login_bg.wasm"))
-        .expect("to copy wasm file");
+    std::fs::copy(&js_file, static_dir.join(FRONTEND_FILES[0])).expect("to copy js file");
+    std::fs::copy(&wasm_file, static_dir.join(FRONTEND_FILES[1])).expect("to copy wasm file");
+}
+
+/// Lets the build carry on without wasm-pack, so working on the server doesn't require the whole
+/// frontend toolchain. Whatever prebuilt frontend files are already in the static directory are
+/// served as-is.
+fn skip_frontend_build() {
+    println!(
+        "cargo:warning=wasm-pack was not found on PATH, so the passkey login component was not \
+         built. Install it from https://rustwasm.github.io/wasm-pack/installer/ to build it."
+    );
+
+    let static_dir = Path::new("static");
+    let prebuilt = FRONTEND_FILES
+        .iter()
+        .all(|file| static_dir.join(file).is_file());
+
+    if prebuilt {
+        println!("cargo:warning=Using the prebuilt passkey login component in static/ instead.");
+    } else {
+        println!(
+            "cargo:warning=No prebuilt passkey login component was found in static/ either, the \
+             login page will not work until it is built."
+        );
+    }
 }
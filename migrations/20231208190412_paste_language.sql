@@ -0,0 +1,2 @@
+ALTER TABLE pastes
+    ADD COLUMN language TEXT; -- Optional language of the paste used for syntax highlighting (example: rust)
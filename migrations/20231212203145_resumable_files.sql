@@ -0,0 +1,19 @@
+-- The original files table was never written to and assumed files are uploaded in one go, so it's
+-- replaced with one that can track the progress of resumable TUS uploads.
+DROP TABLE files CASCADE;
+
+CREATE TABLE files (
+    id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY, -- ID of the file.
+    uuid UUID NOT NULL UNIQUE, -- UUID of the file, used to identify the upload in TUS URLs.
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE, -- ID of the user who uploaded the file, if any.
+    filename TEXT, -- Name of the file given by the client, if any (example: my_file.txt)
+    content_type TEXT, -- MIME type of the file given by the client, if any (example: text/plain)
+    size BIGINT NOT NULL, -- Total size of the file in bytes.
+    "offset" BIGINT NOT NULL DEFAULT 0, -- How many bytes of the file have been uploaded so far.
+    completed BOOLEAN NOT NULL DEFAULT FALSE, -- Whether every byte of the file has been uploaded.
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP -- When the upload was created.
+);
+
+-- Dropping the old table also dropped the foreign key slugs had on it.
+ALTER TABLE slugs
+    ADD CONSTRAINT slugs_file_id_fkey FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE;
@@ -0,0 +1,2 @@
+ALTER TABLE pastes
+    ADD COLUMN views BIGINT NOT NULL DEFAULT 0; -- How many times the paste has been viewed.
@@ -0,0 +1,2 @@
+ALTER TABLE credentials
+    ADD COLUMN label TEXT; -- Optional user-given label for the credential (example: Work laptop)
@@ -0,0 +1,2 @@
+ALTER TABLE credentials
+    ADD COLUMN aaguid UUID; -- AAGUID of the authenticator that created the credential, if it told us.
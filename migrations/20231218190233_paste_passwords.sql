@@ -0,0 +1,2 @@
+ALTER TABLE pastes
+    ADD COLUMN password_hash TEXT; -- An Argon2 hash of the password needed to view the paste, if any.
@@ -0,0 +1,2 @@
+ALTER TABLE pastes
+    ADD COLUMN render TEXT CHECK (render IN ('plain', 'code', 'markdown')); -- How the paste should be rendered.
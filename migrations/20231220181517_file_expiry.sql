@@ -0,0 +1,2 @@
+ALTER TABLE files
+    ADD COLUMN expires_at TIMESTAMPTZ; -- When the upload expires if it has not been finished by then.
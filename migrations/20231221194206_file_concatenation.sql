@@ -0,0 +1,3 @@
+ALTER TABLE files
+    ADD COLUMN is_partial BOOLEAN NOT NULL DEFAULT FALSE, -- Whether the upload is a partial upload to be concatenated into a final one.
+    ADD COLUMN partials UUID[]; -- UUIDs of the partial uploads a final upload is made of, in order.
@@ -0,0 +1,4 @@
+ALTER TABLE users
+    ADD COLUMN email TEXT; -- Optional email address the user can log in with instead of their username.
+
+CREATE UNIQUE INDEX users_email_key ON users (lower(email));
@@ -0,0 +1,2 @@
+ALTER TABLE files
+    ALTER COLUMN size DROP NOT NULL; -- NULL until the client sets the length of a deferred upload.
@@ -0,0 +1,5 @@
+-- Lists the most recent public pastes without scanning the whole table. Expired pastes can't be
+-- left out of the index since `now()` isn't allowed in its predicate, but the sweeper deletes them.
+-- It's ordered the same way as the listing, so a page is a single range scan of it.
+CREATE INDEX pastes_recent_public_idx ON pastes (created_at DESC, id DESC)
+    WHERE password_hash IS NULL;
@@ -0,0 +1,4 @@
+ALTER TABLE users
+    ADD COLUMN session_version INTEGER NOT NULL DEFAULT 0, -- Bumped to log the user out of every session.
+    ADD COLUMN session_auth_hash TEXT NOT NULL
+        GENERATED ALWAYS AS (md5(username || ':' || session_version::text)) STORED; -- Sessions are only valid while this matches.
@@ -0,0 +1,7 @@
+CREATE TABLE paste_idempotency_keys (
+    scope TEXT NOT NULL, -- Who the key belongs to, either a user or an anonymous session.
+    idempotency_key TEXT NOT NULL, -- The Idempotency-Key the paste was created with.
+    paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE, -- ID of the paste that was created.
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP, -- When the key was first used.
+    PRIMARY KEY (scope, idempotency_key)
+);
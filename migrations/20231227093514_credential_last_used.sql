@@ -0,0 +1,2 @@
+ALTER TABLE credentials
+    ADD COLUMN last_used_at TIMESTAMPTZ; -- NULL until the credential is first used to log in.
@@ -0,0 +1 @@
+UPDATE users SET session_version = session_version + 1 WHERE id = $1 RETURNING *
\ No newline at end of file
@@ -0,0 +1 @@
+DELETE FROM credentials WHERE user_uuid = $1
\ No newline at end of file
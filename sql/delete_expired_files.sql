@@ -0,0 +1 @@
+DELETE FROM files WHERE NOT completed AND expires_at < now() RETURNING uuid
\ No newline at end of file
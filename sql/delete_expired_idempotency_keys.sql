@@ -0,0 +1 @@
+DELETE FROM paste_idempotency_keys WHERE created_at <= $1
\ No newline at end of file
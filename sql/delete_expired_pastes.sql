@@ -0,0 +1 @@
+DELETE FROM pastes WHERE expires_at < now()
\ No newline at end of file
@@ -0,0 +1 @@
+DELETE FROM files WHERE uuid = $1
\ No newline at end of file
@@ -0,0 +1 @@
+DELETE FROM pastes WHERE user_id = $1
\ No newline at end of file
@@ -0,0 +1,2 @@
+UPDATE slugs SET enabled = NULL WHERE slug = $1
+RETURNING id, file_id, paste_id AS "paste_id: _", slug, enabled, created_at
\ No newline at end of file
@@ -0,0 +1 @@
+SELECT passkey->'cred'->>'cred_id' AS cred_id FROM credentials WHERE user_uuid = $1
\ No newline at end of file
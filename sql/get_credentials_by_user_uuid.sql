@@ -0,0 +1 @@
+SELECT id, label, aaguid, created_at, updated_at, last_used_at FROM credentials WHERE user_uuid = $1 ORDER BY created_at
\ No newline at end of file
@@ -1 +1,4 @@
-SELECT * FROM pastes WHERE id = $1
\ No newline at end of file
+SELECT pastes.*, users.username AS "author?"
+FROM pastes
+LEFT JOIN users ON users.id = pastes.user_id
+WHERE pastes.id = $1
\ No newline at end of file
@@ -0,0 +1 @@
+SELECT paste_id FROM paste_idempotency_keys WHERE scope = $1 AND idempotency_key = $2 AND created_at > $3
\ No newline at end of file
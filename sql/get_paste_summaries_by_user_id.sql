@@ -0,0 +1,7 @@
+SELECT
+    (SELECT slug FROM slugs WHERE slugs.paste_id = pastes.id ORDER BY id LIMIT 1)
+        AS "slug: SlugString",
+    title, created_at, expires_at
+FROM pastes
+WHERE user_id = $1
+ORDER BY created_at DESC, id DESC
\ No newline at end of file
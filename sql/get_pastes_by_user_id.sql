@@ -0,0 +1,11 @@
+SELECT
+    pastes.*,
+    (SELECT slug FROM slugs WHERE slugs.paste_id = pastes.id ORDER BY slugs.id LIMIT 1)
+        AS "slug: SlugString"
+FROM pastes
+WHERE user_id = $1
+  AND ($2::text IS NULL OR title ILIKE $2 OR content ILIKE $2)
+  AND ($3::timestamptz IS NULL OR created_at < $3 OR (created_at = $3 AND id < $4))
+  AND ($5 OR expires_at IS NULL OR expires_at > now())
+ORDER BY created_at DESC, id DESC
+LIMIT $6
\ No newline at end of file
@@ -0,0 +1,18 @@
+SELECT
+    pastes.id, pastes.title, pastes.language, pastes.created_at, pastes.expires_at, pastes.views,
+    pastes.render, slug.slug
+FROM pastes
+JOIN LATERAL (
+    SELECT slugs.slug
+    FROM slugs
+    WHERE slugs.paste_id = pastes.id AND slugs.enabled IS NOT NULL
+    ORDER BY slugs.id
+    LIMIT 1
+) AS slug ON true
+WHERE pastes.password_hash IS NULL
+  AND (pastes.expires_at IS NULL OR pastes.expires_at > now())
+  -- One row comparison, so a page is read straight off pastes_recent_public_idx.
+  AND (pastes.created_at, pastes.id)
+    < (COALESCE($1::timestamptz, 'infinity'), COALESCE($2::integer, 0))
+ORDER BY pastes.created_at DESC, pastes.id DESC
+LIMIT $3
\ No newline at end of file
@@ -0,0 +1 @@
+SELECT id, file_id, paste_id AS "paste_id: _", slug, enabled, created_at FROM slugs WHERE paste_id = $1 ORDER BY id LIMIT 1
\ No newline at end of file
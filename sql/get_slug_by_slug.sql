@@ -1 +1 @@
-SELECT * FROM slugs WHERE slug = $1
\ No newline at end of file
+SELECT id, file_id, paste_id AS "paste_id: _", slug, enabled, created_at FROM slugs WHERE slug = $1
\ No newline at end of file
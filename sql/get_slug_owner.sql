@@ -0,0 +1,5 @@
+SELECT COALESCE(pastes.user_id, files.user_id) AS owner
+FROM slugs
+LEFT JOIN pastes ON pastes.id = slugs.paste_id
+LEFT JOIN files ON files.id = slugs.file_id
+WHERE slugs.slug = $1
\ No newline at end of file
@@ -0,0 +1,5 @@
+SELECT
+    id, uuid, user_id, filename, content_type, size, "offset", completed, created_at, expires_at,
+    is_partial, partials AS "partials: _"
+FROM files
+WHERE NOT completed AND $1 = ANY(partials)
\ No newline at end of file
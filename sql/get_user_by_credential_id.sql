@@ -0,0 +1,4 @@
+SELECT users.*
+FROM users
+JOIN credentials ON credentials.user_uuid = users.uuid
+WHERE credentials.passkey::json->'cred'->>'cred_id' = $1
\ No newline at end of file
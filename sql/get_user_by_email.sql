@@ -0,0 +1 @@
+SELECT * FROM users WHERE lower(email) = lower($1)
\ No newline at end of file
@@ -0,0 +1 @@
+UPDATE pastes SET views = views + 1 WHERE id = $1 RETURNING views
\ No newline at end of file
@@ -1,4 +1,4 @@
 INSERT INTO credentials
-    ( user_uuid, passkey )
+    ( user_uuid, passkey, label, aaguid )
 VALUES
-    ( $1, $2 )
\ No newline at end of file
+    ( $1, $2, $3, $4 )
\ No newline at end of file
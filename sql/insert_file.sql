@@ -0,0 +1,7 @@
+INSERT INTO files
+    ( uuid, user_id, filename, content_type, size, expires_at, is_partial, partials )
+VALUES
+    ( $1, $2, $3, $4, $5, $6, $7, $8 )
+RETURNING
+    id, uuid, user_id, filename, content_type, size, "offset", completed, created_at, expires_at,
+    is_partial, partials AS "partials: _"
\ No newline at end of file
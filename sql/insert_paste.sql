@@ -1,5 +1,5 @@
 INSERT INTO pastes
-    ( user_id, title, content, expires_at )
+    ( user_id, title, content, expires_at, language, password_hash, render )
 VALUES
-    ( $1, $2, $3, $4 )
+    ( $1, $2, $3, $4, $5, $6, $7 )
 RETURNING *
\ No newline at end of file
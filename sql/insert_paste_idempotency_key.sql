@@ -0,0 +1,8 @@
+INSERT INTO paste_idempotency_keys AS existing
+    ( scope, idempotency_key, paste_id )
+VALUES
+    ( $1, $2, $3 )
+ON CONFLICT ( scope, idempotency_key ) DO UPDATE
+    SET paste_id = EXCLUDED.paste_id, created_at = CURRENT_TIMESTAMP
+    WHERE existing.created_at <= $4
+RETURNING paste_id
\ No newline at end of file
@@ -0,0 +1,6 @@
+INSERT INTO slugs
+    ( paste_id, slug )
+VALUES
+    ( $1, $2 )
+ON CONFLICT ( slug ) DO NOTHING
+RETURNING id, file_id, paste_id AS "paste_id: _", slug, enabled, created_at
\ No newline at end of file
@@ -1,5 +1,5 @@
 INSERT INTO users
-    ( username, uuid )
+    ( username, uuid, email )
 VALUES
-    ( $1, $2 )
+    ( $1, $2, $3 )
 RETURNING *
\ No newline at end of file
@@ -0,0 +1 @@
+UPDATE pastes SET user_id = NULL WHERE user_id = $1
\ No newline at end of file
@@ -0,0 +1,4 @@
+UPDATE credentials
+SET label = $3, updated_at = CURRENT_TIMESTAMP
+WHERE id = $1 AND user_uuid = $2
+RETURNING id, label, aaguid, created_at, updated_at, last_used_at
\ No newline at end of file
@@ -0,0 +1,3 @@
+UPDATE credentials
+SET last_used_at = CURRENT_TIMESTAMP
+WHERE user_uuid = $1 AND passkey::json->'cred'->>'cred_id' = $2
\ No newline at end of file
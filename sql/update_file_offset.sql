@@ -0,0 +1,6 @@
+UPDATE files
+SET "offset" = $2, size = COALESCE(size, $4), completed = COALESCE($2 = COALESCE(size, $4), FALSE)
+WHERE uuid = $1 AND "offset" = $3 AND ($4::bigint IS NULL OR size IS NULL)
+RETURNING
+    id, uuid, user_id, filename, content_type, size, "offset", completed, created_at, expires_at,
+    is_partial, partials AS "partials: _"
\ No newline at end of file
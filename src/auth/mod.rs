@@ -1,11 +1,13 @@
 use axum::{
     error_handling::HandleErrorLayer,
+    extract::{
+        DefaultBodyLimit,
+        Query,
+    },
     http::StatusCode,
+    middleware,
     response::Redirect,
-    routing::{
-        get,
-        post,
-    },
+    routing::post,
     BoxError,
     Extension,
     Router,
@@ -18,8 +20,8 @@ use tower_sessions::{
     cookie::time::Duration,
     Expiry,
     MemoryStore,
-    SessionManagerLayer,
 };
+use utoipa::ToSchema;
 
 use crate::{
     auth::passkeys::{
@@ -30,56 +32,294 @@ use crate::{
         backend::AuthSession,
         registration::{
             finish_register,
+            start_conditional_register,
             start_register,
         },
         PasskeyAuthState,
+        PasskeyConfigError,
+    },
+    config::Config,
+    http::{
+        csrf::require_csrf_token,
+        rate_limit::{
+            rate_limit,
+            RateLimiter,
+        },
+        redirect::validate_redirect,
+        session::session_layer,
+        ApiContext,
     },
 };
 
 pub mod passkeys;
+pub mod password;
 
-/// Parameters passed to authentication handlers.
-#[derive(Deserialize)]
+/// The largest request body, in bytes, accepted when finishing a passkey ceremony.
+///
+/// Credentials are small, even registrations carrying an attestation certificate chain come in
+/// well under this, so anything bigger is turned away before it's buffered and parsed.
+const CREDENTIAL_BODY_LIMIT: usize = 16 * 1024;
+
+/// Parameters passed to registration handlers.
+#[derive(Deserialize, ToSchema)]
 pub struct AuthParams {
     /// The user's username as stored in the database.
     username: String,
+    /// An email address the user can log in with instead of their username.
+    #[serde(default)]
+    email: Option<String>,
+    /// A human-friendly name shown in the authenticator's UI during registration.
+    ///
+    /// Defaults to the username when omitted.
+    #[serde(default)]
+    display_name: Option<String>,
+    /// A label for the credential being registered (e.g. "Work laptop").
+    #[serde(default)]
+    label: Option<String>,
+}
+
+/// Parameters passed to authentication handlers.
+#[derive(Deserialize, ToSchema)]
+pub struct LoginParams {
+    /// Either the user's username or their email address.
+    ///
+    /// Clients that only know about usernames can keep sending it as `username`.
+    #[serde(alias = "username")]
+    identifier: String,
+}
+
+/// Parameters passed to [logout].
+#[derive(Deserialize)]
+pub struct LogoutParams {
+    /// Where to send the user once they're logged out, instead of the index page.
+    #[serde(default)]
+    redirect: Option<String>,
 }
 
 /// Handler that clears a user's session, logging them out.
-pub async fn logout(mut auth_session: AuthSession) -> Redirect {
+///
+/// This has to be a `POST` carrying the session's CSRF token, otherwise any site could log users
+/// out by linking to it.
+///
+/// A `redirect` that isn't allowed by [validate_redirect] is ignored rather than rejected, since
+/// the user has been logged out by then either way.
+pub async fn logout(
+    ctx: Extension<ApiContext>,
+    mut auth_session: AuthSession,
+    Query(params): Query<LogoutParams>,
+) -> Redirect {
     // If there is an error logging out, we don't care for now.
     auth_session.logout().ok();
-    Redirect::temporary("/")
+
+    let target = params
+        .redirect
+        .and_then(|target| validate_redirect(&ctx.config, &target).ok());
+    Redirect::to(target.as_deref().unwrap_or("/"))
+}
+
+/// The name of the cookie holding the session of an in-progress passkey ceremony.
+///
+/// It's kept apart from the login session's cookie, which starting a ceremony would otherwise
+/// replace, and lets finishing a ceremony tell whether the browser held on to the session at all.
+pub const CHALLENGE_SESSION_COOKIE: &str = "woof_challenge";
+
+/// How long the session holding an in-progress passkey ceremony lives for, as configured by
+/// [Config::challenge_expiry].
+///
+/// This needs to be long enough for the user to interact with their authenticator, otherwise the
+/// registration/authentication state will be gone by the time they finish.
+pub fn challenge_session_expiry(config: &Config) -> Expiry {
+    Expiry::OnInactivity(Duration::seconds(config.challenge_expiry))
 }
 
+/// The relying party passkeys are registered with and the origin it's served from.
+const RP_ID: &str = "videah-macbook.squeaker-squeaker.ts.net";
+const RP_ORIGIN: &str = "https://videah-macbook.squeaker-squeaker.ts.net";
+
 /// Defines the [Router] for the authentication API.
-pub fn router() -> Router {
+///
+/// Fails if passkey authentication can't be set up for the relying party.
+pub fn router(config: &Config) -> Result<Router, PasskeyConfigError> {
+    let passkey_state = PasskeyAuthState::from_config(RP_ID.to_string(), RP_ORIGIN, config)?;
+
     let session_store = MemoryStore::default();
     let auth_service = ServiceBuilder::new()
-        .layer(Extension(PasskeyAuthState::new(
-            "videah-macbook.squeaker-squeaker.ts.net".to_string(),
-            "https://localhost".to_string(),
-        )))
+        .layer(Extension(passkey_state))
         .layer(HandleErrorLayer::new(|_: BoxError| async {
             StatusCode::BAD_REQUEST
         }))
         .layer(
-            SessionManagerLayer::new(session_store)
-                .with_secure(false)
-                .with_expiry(Expiry::OnInactivity(Duration::seconds(20))),
+            session_layer(session_store, config)
+                .with_name(CHALLENGE_SESSION_COOKIE)
+                .with_expiry(challenge_session_expiry(config)),
         );
 
-    Router::new()
-        .route("/logout", get(logout))
-        .route("/api/users/start_register", post(start_register))
-        .route("/api/users/finish_register", post(finish_register))
+    // Starting a ceremony is the part that can be abused to enumerate users or hammer the server,
+    // finishing one needs a valid challenge anyway.
+    let rate_limiter = RateLimiter::per_minute(config.auth_rate_limit);
+    let rate_limit_layer = middleware::from_fn_with_state(rate_limiter, rate_limit);
+    let credential_body_limit = DefaultBodyLimit::max(CREDENTIAL_BODY_LIMIT);
+    // Registering while logged in adds a passkey to the account, so it needs the CSRF token just
+    // like any other change to it. Signing up anonymously goes ahead without one.
+    let csrf_layer = middleware::from_fn(require_csrf_token);
+
+    let router = Router::new()
+        .route("/logout", post(logout).layer(csrf_layer.clone()))
+        .route(
+            "/api/users/start_register",
+            post(start_register)
+                .layer(rate_limit_layer.clone())
+                .layer(csrf_layer.clone()),
+        )
+        .route(
+            "/api/users/start_conditional_register",
+            post(start_conditional_register)
+                .layer(rate_limit_layer.clone())
+                .layer(csrf_layer.clone()),
+        )
+        .route(
+            "/api/users/finish_register",
+            post(finish_register)
+                .layer(credential_body_limit.clone())
+                .layer(csrf_layer),
+        )
         .route(
             "/api/users/start_authentication",
-            post(start_authentication),
+            post(start_authentication).layer(rate_limit_layer),
         )
         .route(
             "/api/users/finish_authentication",
-            post(finish_authentication),
+            post(finish_authentication).layer(credential_body_limit),
         )
-        .layer(auth_service)
+        .layer(auth_service);
+
+    Ok(router)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::Request,
+    };
+    use clap::Parser;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::http::{
+        csrf::CSRF_HEADER,
+        testing::{
+            csrf_token,
+            insert_user_with_passkey,
+            login_as,
+            post_json,
+            with_auth,
+        },
+    };
+
+    #[test]
+    fn default_challenge_session_outlives_slow_users() {
+        let config = Config::parse_from(["woof", "--database-url", "postgres://localhost/woof"]);
+        let expiry = challenge_session_expiry(&config);
+
+        // Users regularly take longer than 20 seconds to complete a passkey prompt.
+        assert!(matches!(
+            expiry,
+            Expiry::OnInactivity(duration) if duration > Duration::seconds(20)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn oversized_credentials_are_rejected(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(router(&config).unwrap(), db);
+
+        // Far too big to be a credential, but still valid JSON so only its size gives it away.
+        let body = format!(r#"{{"id":"{}"}}"#, "a".repeat(CREDENTIAL_BODY_LIMIT));
+
+        for uri in [
+            "/api/users/finish_register",
+            "/api/users/finish_authentication",
+        ] {
+            let request = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body.clone()))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{uri}");
+        }
+    }
+
+    #[sqlx::test]
+    async fn adding_a_passkey_needs_the_csrf_token(db: PgPool) -> sqlx::Result<()> {
+        insert_user_with_passkey(&db, "woof", None).await?;
+        let user_id: i32 = sqlx::query_scalar("SELECT id FROM users WHERE username = 'woof'")
+            .fetch_one(&db)
+            .await?;
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(router(&config).unwrap(), db);
+        let params = serde_json::json!({ "username": "woof" });
+
+        let cookie = login_as(&app, user_id).await;
+        for uri in [
+            "/api/users/start_register",
+            "/api/users/start_conditional_register",
+            "/api/users/finish_register",
+        ] {
+            let request = post_json(uri, Some(&cookie), &params);
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{uri}");
+        }
+
+        let mut request = post_json("/api/users/start_register", Some(&cookie), &params);
+        let token = csrf_token(&app, &cookie).await;
+        request
+            .headers_mut()
+            .insert(CSRF_HEADER, token.parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Signing up doesn't need one, there's no session for another site to ride on.
+        let params = serde_json::json!({ "username": "bark" });
+        let request = post_json("/api/users/start_register", None, &params);
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn logout_only_redirects_to_allowed_targets(db: PgPool) -> sqlx::Result<()> {
+        insert_user_with_passkey(&db, "woof", None).await?;
+        let user_id: i32 = sqlx::query_scalar("SELECT id FROM users WHERE username = 'woof'")
+            .fetch_one(&db)
+            .await?;
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(router(&config).unwrap(), db);
+
+        for (redirect, expected) in [
+            ("%2Fme", "/me"),
+            ("https%3A%2F%2Fevil.example", "/"),
+            ("%2F%2Fevil.example", "/"),
+        ] {
+            let cookie = login_as(&app, user_id).await;
+            let token = csrf_token(&app, &cookie).await;
+            let request = Request::builder()
+                .method("POST")
+                .uri(format!("/logout?redirect={redirect}"))
+                .header("cookie", cookie)
+                .header(CSRF_HEADER, token)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::SEE_OTHER, "{redirect}");
+            assert_eq!(response.headers()["location"], expected, "{redirect}");
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,202 @@
+//! Extracts the AAGUID of the authenticator that created a passkey.
+//!
+//! The AAGUID identifies the make and model of an authenticator (e.g. a YubiKey 5 or iCloud
+//! Keychain). webauthn-rs only keeps it around when attestation is requested, so it's read
+//! straight out of the authenticator data in the attestation object sent during registration.
+
+use uuid::Uuid;
+
+/// The offset of the AAGUID in the authenticator data, after the RP ID hash, flags and counter.
+const AAGUID_OFFSET: usize = 32 + 1 + 4;
+
+/// Set in the authenticator data flags when attested credential data (and thus the AAGUID) is
+/// included.
+const ATTESTED_CREDENTIAL_DATA_FLAG: u8 = 0x40;
+
+/// Well-known AAGUIDs of passkey providers, mapped to a human-friendly name.
+const KNOWN_AUTHENTICATORS: &[(&str, &str)] = &[
+    (
+        "ea9b8d66-4d01-1d21-3ce4-b6b48cb575d4",
+        "Google Password Manager",
+    ),
+    ("adce0002-35bc-c60a-648b-0b25f1f05503", "Chrome on Mac"),
+    ("fbfc3007-154e-4ecc-8c0b-6e020557d7bd", "iCloud Keychain"),
+    ("08987058-cadc-4b81-b6e1-30de50dcbe96", "Windows Hello"),
+    ("bada5566-a7aa-401f-bd96-45619a55120d", "1Password"),
+    ("d548826e-79b4-db40-a3d8-11116f7e8349", "Bitwarden"),
+    ("531126d6-e717-415c-9320-3d9aa6981239", "Dashlane"),
+];
+
+/// Gets the AAGUID from a CBOR encoded attestation object.
+///
+/// Returns `None` if the attestation object is malformed, doesn't include attested credential
+/// data, or the authenticator chose not to identify itself with an all-zero AAGUID.
+pub fn aaguid_from_attestation_object(attestation_object: &[u8]) -> Option<Uuid> {
+    let auth_data = CborReader::new(attestation_object).map_value("authData")?;
+
+    let flags = *auth_data.get(32)?;
+    if flags & ATTESTED_CREDENTIAL_DATA_FLAG == 0 {
+        return None;
+    }
+
+    let aaguid = Uuid::from_slice(auth_data.get(AAGUID_OFFSET..AAGUID_OFFSET + 16)?).ok()?;
+    (!aaguid.is_nil()).then_some(aaguid)
+}
+
+/// Gets the name of a well-known authenticator from its AAGUID.
+pub fn authenticator_name(aaguid: &Uuid) -> Option<&'static str> {
+    KNOWN_AUTHENTICATORS
+        .iter()
+        .find(|(known, _)| Uuid::parse_str(known).is_ok_and(|known| &known == aaguid))
+        .map(|(_, name)| *name)
+}
+
+/// Just enough of a CBOR decoder to pull a byte string out of the top-level map of an
+/// attestation object, skipping over everything else.
+struct CborReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> CborReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        CborReader { bytes, position: 0 }
+    }
+
+    /// Finds the byte string stored under `key` in a map of text keys.
+    fn map_value(mut self, key: &str) -> Option<&'a [u8]> {
+        let (major, entries) = self.header()?;
+        if major != 5 {
+            return None;
+        }
+
+        for _ in 0..entries {
+            let (major, length) = self.header()?;
+            if major != 3 {
+                return None;
+            }
+
+            if self.take(length)? == key.as_bytes() {
+                let (major, length) = self.header()?;
+                return if major == 2 { self.take(length) } else { None };
+            }
+
+            self.skip()?;
+        }
+
+        None
+    }
+
+    /// Reads the major type and argument of the next item.
+    ///
+    /// Indefinite lengths aren't supported, authenticators have to use the canonical encoding.
+    fn header(&mut self) -> Option<(u8, u64)> {
+        let initial = *self.bytes.get(self.position)?;
+        self.position += 1;
+
+        let major = initial >> 5;
+        let argument = match initial & 0x1f {
+            info @ 0..=23 => u64::from(info),
+            24 => u64::from(self.take(1)?[0]),
+            25 => u64::from(u16::from_be_bytes(self.take(2)?.try_into().ok()?)),
+            26 => u64::from(u32::from_be_bytes(self.take(4)?.try_into().ok()?)),
+            27 => u64::from_be_bytes(self.take(8)?.try_into().ok()?),
+            _ => return None,
+        };
+
+        Some((major, argument))
+    }
+
+    /// Skips over the next item, including anything nested inside of it.
+    fn skip(&mut self) -> Option<()> {
+        let (major, argument) = self.header()?;
+        match major {
+            2 | 3 => {
+                self.take(argument)?;
+            }
+            4 => {
+                for _ in 0..argument {
+                    self.skip()?;
+                }
+            }
+            5 => {
+                for _ in 0..argument.checked_mul(2)? {
+                    self.skip()?;
+                }
+            }
+            6 => self.skip()?,
+            // Integers, simple values and floats are entirely contained in the header.
+            _ => {}
+        }
+
+        Some(())
+    }
+
+    /// Takes the next `length` bytes.
+    fn take(&mut self, length: u64) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(usize::try_from(length).ok()?)?;
+        let bytes = self.bytes.get(self.position..end)?;
+        self.position = end;
+
+        Some(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ICLOUD_KEYCHAIN: &str = "fbfc3007-154e-4ecc-8c0b-6e020557d7bd";
+
+    /// Builds a `none` attestation object around authenticator data with the given AAGUID.
+    fn attestation_object(aaguid: Uuid) -> Vec<u8> {
+        let mut auth_data = vec![0; 32];
+        auth_data.push(ATTESTED_CREDENTIAL_DATA_FLAG | 0x01);
+        auth_data.extend([0, 0, 0, 0]);
+        auth_data.extend(aaguid.as_bytes());
+        // Credential ID length and ID, the public key would follow in a real authenticator.
+        auth_data.extend([0, 4, 1, 2, 3, 4]);
+
+        let mut object = vec![0xa3];
+        object.push(0x63);
+        object.extend(b"fmt");
+        object.push(0x64);
+        object.extend(b"none");
+        object.push(0x67);
+        object.extend(b"attStmt");
+        object.push(0xa0);
+        object.push(0x68);
+        object.extend(b"authData");
+        object.push(0x58);
+        object.push(auth_data.len() as u8);
+        object.extend(auth_data);
+        object
+    }
+
+    #[test]
+    fn aaguid_is_read_from_attestation_object() {
+        let aaguid = Uuid::parse_str(ICLOUD_KEYCHAIN).unwrap();
+        let object = attestation_object(aaguid);
+
+        assert_eq!(aaguid_from_attestation_object(&object), Some(aaguid));
+    }
+
+    #[test]
+    fn nil_aaguid_is_ignored() {
+        let object = attestation_object(Uuid::nil());
+        assert_eq!(aaguid_from_attestation_object(&object), None);
+    }
+
+    #[test]
+    fn malformed_attestation_object_is_ignored() {
+        assert_eq!(aaguid_from_attestation_object(&[]), None);
+        assert_eq!(aaguid_from_attestation_object(&[0xa1, 0x63]), None);
+    }
+
+    #[test]
+    fn known_authenticators_are_named() {
+        let aaguid = Uuid::parse_str(ICLOUD_KEYCHAIN).unwrap();
+        assert_eq!(authenticator_name(&aaguid), Some("iCloud Keychain"));
+        assert_eq!(authenticator_name(&Uuid::new_v4()), None);
+    }
+}
@@ -7,17 +7,24 @@ use axum::{
     Extension,
     Json,
 };
-
-use log::error;
+use axum_extra::extract::cookie::{
+    CookieJar,
+    Key,
+};
 use serde::{
     Deserialize,
     Serialize,
 };
+use sha2::{
+    Digest,
+    Sha256,
+};
 use sqlx::Postgres;
 use thiserror::Error;
 use tower_sessions::Session;
 use uuid::Uuid;
 use webauthn_rs::prelude::{
+    AllowCredentials,
     Passkey,
     PasskeyAuthentication,
     PublicKeyCredential,
@@ -30,19 +37,25 @@ use crate::{
         passkeys::{
             backend::{
                 AuthSession,
+                BackendAuthError,
                 BackendAuthParameters,
                 PasskeyBackend,
             },
             PasskeyAuthState,
         },
-        AuthParams,
+        LoginParams,
+        CHALLENGE_SESSION_COOKIE,
     },
     db::{
         credentials::Credential,
-        users::User,
+        users::get_user_by_identifier,
     },
     http::{
         error::ApiError,
+        metrics::{
+            self,
+            Ceremony,
+        },
         ApiContext,
     },
 };
@@ -50,17 +63,18 @@ use crate::{
 /// A set of errors that can occur while registering a new user.
 #[derive(Debug, Error)]
 pub enum PasskeyAuthError {
-    /// A user with the same email does not exist.
-    #[error("A user with that name does not exist")]
-    UserDoesNotExist,
-
     /// Could not create initial authentication challenge.
-    #[error("Could not create initial authentication challenge: {0}")]
-    ChallengeCreationFailure(WebauthnError),
+    #[error("Could not create initial authentication challenge")]
+    ChallengeCreationFailure(#[source] WebauthnError),
 
     /// Something went wrong when trying to store authentication state in the session.
-    #[error("Something went wrong when trying to store authentication state in the session: {0}")]
-    SessionFailure(tower_sessions::session::Error),
+    #[error("Something went wrong when trying to store authentication state in the session")]
+    SessionFailure(#[source] tower_sessions::session::Error),
+
+    /// The request didn't carry the session authentication was started in, most likely because it
+    /// expired or the browser isn't keeping cookies.
+    #[error("The login session has expired or wasn't kept, make sure cookies are enabled")]
+    MissingSession,
 
     /// Authentication state was missing from the session.
     #[error(
@@ -68,7 +82,7 @@ pub enum PasskeyAuthError {
     )]
     MissingSessionInfo,
 
-    #[error("Something went wrong whilst verifying and completing authentication: {0}")]
+    #[error("Something went wrong whilst verifying and completing authentication")]
     BackendAuthError(#[from] axum_login::Error<PasskeyBackend>),
 
     /// The backend checked the authentication challenge, but it was invalid.
@@ -76,35 +90,50 @@ pub enum PasskeyAuthError {
     BackendAuthInvalid,
 
     /// Could not log in user with auth backend.
-    #[error("Could not log in user with auth backend: {0}")]
-    AuthSessionFailure(axum_login::Error<PasskeyBackend>),
+    #[error("Could not log in user with auth backend")]
+    AuthSessionFailure(#[source] axum_login::Error<PasskeyBackend>),
 
     /// An error occurred while communicating with the database.
-    #[error("An error occurred while communicating with the database: {0}")]
+    #[error("An error occurred while communicating with the database")]
     DatabaseError(#[from] sqlx::Error),
 }
 
+impl PasskeyAuthError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PasskeyAuthError::ChallengeCreationFailure(_) => "challenge_creation_failure",
+            PasskeyAuthError::SessionFailure(_) => "session_failure",
+            PasskeyAuthError::MissingSession => "missing_session",
+            PasskeyAuthError::MissingSessionInfo => "missing_session_info",
+            PasskeyAuthError::BackendAuthError(_) => "backend_auth_error",
+            PasskeyAuthError::BackendAuthInvalid => "backend_auth_invalid",
+            PasskeyAuthError::AuthSessionFailure(_) => "auth_session_failure",
+            PasskeyAuthError::DatabaseError(_) => "database_error",
+        }
+    }
+}
+
 impl IntoResponse for PasskeyAuthError {
     /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
     fn into_response(self) -> Response {
         let status = match self {
-            PasskeyAuthError::UserDoesNotExist => StatusCode::NOT_FOUND,
             PasskeyAuthError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PasskeyAuthError::ChallengeCreationFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PasskeyAuthError::SessionFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PasskeyAuthError::AuthSessionFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PasskeyAuthError::MissingSession => StatusCode::UNAUTHORIZED,
             PasskeyAuthError::MissingSessionInfo => StatusCode::BAD_REQUEST,
+            // A credential that's behind on its counter was sent by the client, anything else
+            // that goes wrong in the backend is on our end.
+            PasskeyAuthError::BackendAuthError(axum_login::Error::Backend(
+                BackendAuthError::CounterDiscrepancy,
+            )) => StatusCode::BAD_REQUEST,
             PasskeyAuthError::BackendAuthError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PasskeyAuthError::BackendAuthInvalid => StatusCode::BAD_REQUEST,
         };
 
-        let error = ApiError {
-            message: self.to_string(),
-        };
-
-        error!("{}", error.message);
-
-        (status, Json(error)).into_response()
+        ApiError::respond(status, self.code(), &self)
     }
 }
 
@@ -114,14 +143,19 @@ impl IntoResponse for PasskeyAuthError {
 /// authentication state.
 type AuthenticationSession = Session;
 
-/// Registration info that is created in [start_register] and passed into [finish_register].
+/// Authentication info that is created in [start_authentication] and passed into
+/// [finish_authentication].
 ///
-/// Passing is done via a [RegisterSession].
+/// Passing is done via a [AuthenticationSession].
 #[derive(Serialize, Deserialize)]
 struct AuthenticationSessionInfo {
-    email: String,
+    /// The username or email the user is logging in with, exactly as they gave it.
+    identifier: String,
+    /// The UUID of the user, or a nil UUID if no user goes by the identifier.
     user_unique_id: Uuid,
-    auth_state: PasskeyAuthentication,
+    /// The in-progress authentication, or [None] if the client was handed a decoy challenge
+    /// because the user doesn't exist.
+    auth_state: Option<PasskeyAuthentication>,
 }
 
 /// Starts the passkey authentication process for a new user.
@@ -130,48 +164,164 @@ struct AuthenticationSessionInfo {
 /// [RequestChallengeResponse] is passed back to the client and the resulting authentication state
 /// will be passed to the [finish_authentication] endpoint via a [AuthenticationSession] to complete
 /// the authentication when the client calls it.
+///
+/// Users are looked up by either their username or their email, see [get_user_by_identifier].
+///
+/// Unknown identifiers are given a decoy challenge that looks just like a real one, so this
+/// endpoint can't be used to find out which accounts exist. Authentication will simply fail for
+/// them later in [finish_authentication].
+#[utoipa::path(
+    post,
+    path = "/api/users/start_authentication",
+    tag = "passkeys",
+    request_body = LoginParams,
+    responses(
+        (
+            status = 200, body = Object,
+            description = "The `RequestChallengeResponse` for `navigator.credentials.get()`"
+        ),
+    ),
+)]
 pub async fn start_authentication(
     ctx: Extension<ApiContext>,
     Extension(state): Extension<PasskeyAuthState>,
     session: AuthenticationSession,
-    Json(params): Json<AuthParams>,
+    Json(params): Json<LoginParams>,
 ) -> Result<Json<RequestChallengeResponse>, PasskeyAuthError> {
     // Get the user from the database if it exists.
-    let user = sqlx::query_file_as!(User, "sql/get_user_by_username.sql", params.username)
-        .fetch_optional(&ctx.db)
-        .await?
-        .map_or(Err(PasskeyAuthError::UserDoesNotExist), Ok)?;
+    let user = get_user_by_identifier(&ctx.db, &params.identifier).await?;
+    let user_unique_id = user.map_or_else(Uuid::nil, |user| user.uuid);
 
+    // Credentials are looked up even if the user doesn't exist so both cases take a similar
+    // amount of time.
     // language=postgresql
     let query = "SELECT * FROM credentials WHERE user_uuid = $1";
     let passkeys: Vec<Passkey> = sqlx::query_as::<Postgres, Credential>(query)
-        .bind(user.uuid)
+        .bind(user_unique_id)
         .fetch_all(&ctx.db)
         .await?
         .iter()
         .map(|cred| cred.passkey.0.clone())
         .collect();
 
-    let allow_credentials = passkeys.as_slice();
-    let (rcr, auth_state) = state
-        .webauthn
-        .start_passkey_authentication(allow_credentials)
-        .map_err(PasskeyAuthError::ChallengeCreationFailure)?;
+    let (rcr, auth_state) = if passkeys.is_empty() {
+        let rcr = decoy_challenge(&ctx, &state, &params.identifier).await?;
+        (rcr, None)
+    } else {
+        let (mut rcr, mut auth_state) = state
+            .webauthn
+            .start_passkey_authentication(&passkeys)
+            .map_err(PasskeyAuthError::ChallengeCreationFailure)?;
+
+        // Let older Apple devices find credentials they registered under the AppID.
+        state.apply_appid(&mut rcr, &mut auth_state);
+
+        (rcr, Some(auth_state))
+    };
 
     // Store necessary information in the session.
     let session_info = AuthenticationSessionInfo {
-        email: params.username,
-        user_unique_id: user.uuid,
+        identifier: params.identifier,
+        user_unique_id,
         auth_state,
     };
 
     session
         .insert("auth_state", session_info)
         .map_err(PasskeyAuthError::SessionFailure)?;
+    metrics::record_challenge(Ceremony::Authentication);
 
     Ok(Json(rcr))
 }
 
+/// Creates a challenge for a user that doesn't exist (or has no credentials) which can't be told
+/// apart from a real one.
+///
+/// The allow list is made from the credentials of a real user picked by the identifier, with each
+/// ID swapped out for one of the same length derived from it, so the number of credentials, their
+/// ID lengths and transports all look like an account's would. Asking for the same user twice
+/// gives the same list, just like it would for a real account.
+async fn decoy_challenge(
+    ctx: &ApiContext,
+    state: &PasskeyAuthState,
+    identifier: &str,
+) -> Result<RequestChallengeResponse, PasskeyAuthError> {
+    let seed = decoy_seed(&ctx.cookie_key, identifier);
+
+    // language=postgresql
+    let query = "SELECT * FROM credentials WHERE user_uuid = (
+        SELECT user_uuid FROM credentials ORDER BY md5($1::text || user_uuid::text) LIMIT 1
+    )";
+    let passkeys: Vec<Passkey> = sqlx::query_as::<Postgres, Credential>(query)
+        .bind(Uuid::from_slice(&seed[..16]).unwrap())
+        .fetch_all(&ctx.db)
+        .await?
+        .iter()
+        .map(|cred| cred.passkey.0.clone())
+        .collect();
+
+    let mut rcr = if passkeys.is_empty() {
+        // Nobody has a passkey yet, so there's no real account this could be told apart from.
+        let (mut rcr, _) = state
+            .webauthn
+            .start_discoverable_authentication()
+            .map_err(PasskeyAuthError::ChallengeCreationFailure)?;
+        rcr.public_key.allow_credentials = vec![AllowCredentials {
+            type_: "public-key".to_string(),
+            id: vec![0; 16].into(),
+            transports: None,
+        }];
+        rcr
+    } else {
+        let (rcr, _) = state
+            .webauthn
+            .start_passkey_authentication(&passkeys)
+            .map_err(PasskeyAuthError::ChallengeCreationFailure)?;
+        rcr
+    };
+
+    for (index, credential) in rcr.public_key.allow_credentials.iter_mut().enumerate() {
+        credential.id = decoy_credential_id(&seed, index, credential.id.0.len()).into();
+    }
+
+    state.add_appid_extension(&mut rcr);
+
+    Ok(rcr)
+}
+
+/// Derives the secret every decoy credential ID for an identifier is made from, see
+/// [decoy_challenge].
+///
+/// It's keyed with the cookie key so clients can't work the IDs out, and stays the same across
+/// restarts as long as [Config::cookie_key](crate::config::Config::cookie_key) is set. Identifiers
+/// are looked up regardless of their case, so they're lowercased to match.
+fn decoy_seed(key: &Key, identifier: &str) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(b"woof decoy credentials")
+        .chain_update(key.master())
+        .chain_update(identifier.to_lowercase())
+        .finalize()
+        .into()
+}
+
+/// Derives the ID of the `index`th credential in a decoy allow list, `len` bytes long.
+fn decoy_credential_id(seed: &[u8; 32], index: usize, len: usize) -> Vec<u8> {
+    let mut id = Vec::with_capacity(len);
+    let mut block: u32 = 0;
+    while id.len() < len {
+        id.extend(
+            Sha256::new()
+                .chain_update(seed)
+                .chain_update((index as u32).to_be_bytes())
+                .chain_update(block.to_be_bytes())
+                .finalize(),
+        );
+        block += 1;
+    }
+    id.truncate(len);
+    id
+}
+
 /// Finishes the passkey authentication process for a new user.
 ///
 /// This endpoint will verify the [PublicKeyCredential] passed back by the client and validate it
@@ -179,22 +329,73 @@ pub async fn start_authentication(
 /// [start_authentication].
 ///
 /// If the authentication is successful, the user will be logged in.
+#[utoipa::path(
+    post,
+    path = "/api/users/finish_authentication",
+    tag = "passkeys",
+    request_body(
+        content = Object,
+        description = "The `PublicKeyCredential` from `navigator.credentials.get()`"
+    ),
+    responses(
+        (status = 200, description = "The user was logged in"),
+        (status = 400, description = "The credential couldn't be verified", body = ApiError),
+        (status = 401, description = "The login session has expired", body = ApiError),
+    ),
+)]
 pub async fn finish_authentication(
     Extension(state): Extension<PasskeyAuthState>,
     session: AuthenticationSession,
     mut auth_session: AuthSession,
+    jar: CookieJar,
     Json(public_key): Json<PublicKeyCredential>,
+) -> Result<StatusCode, PasskeyAuthError> {
+    let has_session = jar.get(CHALLENGE_SESSION_COOKIE).is_some();
+    let result =
+        complete_authentication(state, session, has_session, &mut auth_session, public_key).await;
+
+    if let Err(PasskeyAuthError::BackendAuthError(axum_login::Error::Backend(
+        BackendAuthError::CounterDiscrepancy,
+    ))) = &result
+    {
+        metrics::record_counter_discrepancy();
+    }
+    metrics::record_authentication(result.is_ok());
+
+    result
+}
+
+/// Verifies the [PublicKeyCredential] against the authentication state and logs the user in,
+/// for [finish_authentication].
+///
+/// `has_session` says whether the request carried the cookie of the session authentication was
+/// started in, to tell a browser that didn't keep it apart from a client that never started.
+async fn complete_authentication(
+    state: PasskeyAuthState,
+    session: AuthenticationSession,
+    has_session: bool,
+    auth_session: &mut AuthSession,
+    public_key: PublicKeyCredential,
 ) -> Result<StatusCode, PasskeyAuthError> {
     // Get session info that should have been set in the start_register handler.
     // This can fail if the session info was never set, or if there was an error while
     // retrieving it.
+    let missing = match has_session {
+        true => PasskeyAuthError::MissingSessionInfo,
+        false => PasskeyAuthError::MissingSession,
+    };
     let session_info: AuthenticationSessionInfo = session
         .remove("auth_state")
         .map_err(PasskeyAuthError::SessionFailure)?
-        .ok_or(PasskeyAuthError::MissingSessionInfo)?;
+        .ok_or(missing)?;
+
+    // A decoy challenge was handed out, so there's nothing this could possibly be verified against.
+    let Some(auth_state) = session_info.auth_state else {
+        return Err(PasskeyAuthError::BackendAuthInvalid);
+    };
 
     let auth_params = BackendAuthParameters {
-        auth_state: session_info.auth_state,
+        auth_state,
         challenge_response: public_key,
         user: None,
         user_uuid: session_info.user_unique_id,
@@ -214,3 +415,250 @@ pub async fn finish_authentication(
 
     Ok(StatusCode::OK)
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::Request,
+    };
+    use serde_json::{
+        json,
+        Value,
+    };
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::http::{
+        error::ErrorChain,
+        testing::{
+            challenge_session,
+            error_code,
+            insert_user_with_passkey,
+            post_json,
+            with_auth,
+            PASSKEY_CREDENTIAL_ID,
+        },
+    };
+
+    /// Replaces every value in a JSON document with its type so responses can be compared by
+    /// shape alone.
+    fn shape(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), shape(v))).collect(),
+            Value::Array(items) => items.iter().map(shape).collect(),
+            Value::String(_) => json!("string"),
+            Value::Number(_) => json!("number"),
+            Value::Bool(_) => json!("bool"),
+            Value::Null => Value::Null,
+        }
+    }
+
+    async fn start(db: &PgPool, username: &str) -> (StatusCode, Value) {
+        start_with(db, json!({ "username": username })).await
+    }
+
+    async fn start_with(db: &PgPool, params: Value) -> (StatusCode, Value) {
+        let ctx = ApiContext::for_tests(db.clone());
+        let app = crate::auth::router(&ctx.config)
+            .unwrap()
+            .layer(Extension(ctx));
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/users/start_authentication")
+            .header("content-type", "application/json")
+            .body(Body::from(params.to_string()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn auth_errors_have_codes() {
+        assert_eq!(PasskeyAuthError::MissingSession.code(), "missing_session");
+        assert_eq!(
+            PasskeyAuthError::MissingSessionInfo.code(),
+            "missing_session_info"
+        );
+        assert_eq!(
+            PasskeyAuthError::BackendAuthInvalid.code(),
+            "backend_auth_invalid"
+        );
+    }
+
+    #[test]
+    fn verify_failures_are_client_errors() {
+        let status = |err: PasskeyAuthError| err.into_response().status();
+
+        assert_eq!(
+            status(PasskeyAuthError::BackendAuthInvalid),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(PasskeyAuthError::BackendAuthError(
+                axum_login::Error::Backend(BackendAuthError::CounterDiscrepancy)
+            )),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(PasskeyAuthError::BackendAuthError(
+                axum_login::Error::Backend(BackendAuthError::DatabaseFailure(
+                    sqlx::Error::PoolTimedOut
+                ))
+            )),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            status(PasskeyAuthError::ChallengeCreationFailure(
+                WebauthnError::Configuration
+            )),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn server_errors_log_their_cause_without_returning_it() {
+        let error = PasskeyAuthError::BackendAuthError(axum_login::Error::Backend(
+            BackendAuthError::CredentialUseFailure(sqlx::Error::PoolTimedOut),
+        ));
+        let cause = sqlx::Error::PoolTimedOut.to_string();
+        let logged = ErrorChain(&error).to_string();
+        assert!(logged.contains("Could not record when the passkey credential was last used"));
+        assert!(logged.ends_with(&cause));
+
+        let response = error.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert!(!body["message"].as_str().unwrap().contains(&cause));
+    }
+
+    /// Gets the IDs of the credentials a challenge allows.
+    fn allowed_credentials(challenge: &Value) -> Vec<&str> {
+        challenge["publicKey"]["allowCredentials"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|credential| credential["id"].as_str().unwrap())
+            .collect()
+    }
+
+    #[sqlx::test]
+    async fn unknown_users_cannot_be_told_apart(db: PgPool) -> sqlx::Result<()> {
+        insert_user_with_passkey(&db, "woof", None).await?;
+
+        let (known_status, known) = start(&db, "woof").await;
+        let (unknown_status, unknown) = start(&db, "bark").await;
+
+        assert_eq!(known_status, StatusCode::OK);
+        assert_eq!(unknown_status, known_status);
+        assert_eq!(shape(&unknown), shape(&known));
+
+        // The made up credentials have IDs as long as real ones and the same transports, but
+        // aren't the real ones. IDs are unpadded base64, so equal lengths mean equal byte lengths.
+        let credentials = |challenge: &Value| -> Vec<(usize, Value)> {
+            challenge["publicKey"]["allowCredentials"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|credential| {
+                    let id = credential["id"].as_str().unwrap();
+                    (id.len(), credential["transports"].clone())
+                })
+                .collect()
+        };
+        assert_eq!(credentials(&unknown), credentials(&known));
+        assert_eq!(credentials(&known)[0].1, json!(["internal", "hybrid"]));
+        assert_ne!(allowed_credentials(&unknown), allowed_credentials(&known));
+
+        // Asking again for the same unknown user must not give away that the list is made up, no
+        // matter how the identifier is capitalised.
+        let (_, again) = start(&db, "Bark").await;
+        assert_eq!(
+            again["publicKey"]["allowCredentials"],
+            unknown["publicKey"]["allowCredentials"]
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn appid_is_left_out_by_default(db: PgPool) -> sqlx::Result<()> {
+        insert_user_with_passkey(&db, "woof", None).await?;
+
+        for username in ["woof", "bark"] {
+            let (status, challenge) = start(&db, username).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(challenge["publicKey"]["extensions"]["appid"].is_null());
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn users_can_log_in_by_username(db: PgPool) -> sqlx::Result<()> {
+        insert_user_with_passkey(&db, "woof", Some("woof@woof.example")).await?;
+
+        let (status, challenge) = start_with(&db, json!({ "identifier": "Woof" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(allowed_credentials(&challenge), [PASSKEY_CREDENTIAL_ID]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn users_can_log_in_by_email(db: PgPool) -> sqlx::Result<()> {
+        insert_user_with_passkey(&db, "woof", Some("woof@woof.example")).await?;
+
+        let (status, challenge) =
+            start_with(&db, json!({ "identifier": "WOOF@woof.example" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(allowed_credentials(&challenge), [PASSKEY_CREDENTIAL_ID]);
+
+        // Someone else's email gets a decoy challenge, not the real credentials.
+        let (_, challenge) = start_with(&db, json!({ "identifier": "bark@woof.example" })).await;
+        assert_ne!(allowed_credentials(&challenge), [PASSKEY_CREDENTIAL_ID]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn finishing_without_a_session_is_told_apart_from_not_starting(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(crate::auth::router(&config).unwrap(), db);
+        let credential = json!({
+            "id": "d29vZg",
+            "rawId": "d29vZg",
+            "type": "public-key",
+            "extensions": {},
+            "response": {
+                "authenticatorData": "d29vZg",
+                "clientDataJSON": "d29vZg",
+                "signature": "d29vZg",
+                "userHandle": null,
+            },
+        });
+        let finish = |cookie: Option<&str>| {
+            post_json("/api/users/finish_authentication", cookie, &credential)
+        };
+
+        // Without the cookie the browser didn't keep the session, so there's nothing to finish.
+        let response = app.clone().oneshot(finish(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(error_code(response).await, "missing_session");
+
+        // A session that authentication was never started in is the client's mistake.
+        let cookie = challenge_session(&app).await;
+        let response = app.oneshot(finish(Some(&cookie))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_code(response).await, "missing_session_info");
+    }
+}
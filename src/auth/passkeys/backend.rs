@@ -6,6 +6,7 @@ use axum_login::{
     AuthnBackend,
     UserId,
 };
+use log::error;
 
 use sqlx::{
     PgPool,
@@ -14,9 +15,18 @@ use sqlx::{
 use thiserror::Error;
 use webauthn_rs::prelude::*;
 
-use crate::db::{
-    credentials::Credential,
-    users::User,
+use crate::{
+    db::{
+        credentials::{
+            update_credential_last_used,
+            StoredPasskey,
+        },
+        users::{
+            get_user_by_credential_id,
+            User,
+        },
+    },
+    http::error::ErrorChain,
 };
 
 impl AuthUser for User {
@@ -27,7 +37,7 @@ impl AuthUser for User {
     }
 
     fn session_auth_hash(&self) -> &[u8] {
-        self.username.as_bytes()
+        self.session_auth_hash.as_bytes()
     }
 }
 
@@ -41,7 +51,7 @@ pub struct BackendAuthParameters {
     /// The user that is attempting to authenticate.
     ///
     /// It's possible we don't know who this is yet, this can happen when an autofill requests
-    /// a passkey challenge before the user has entered an email.
+    /// a passkey challenge before the user has entered their username or email.
     pub user: Option<User>,
     /// The UUID of the user that is attempting to authenticate.
     pub user_uuid: Uuid,
@@ -65,16 +75,16 @@ impl PasskeyBackend {
 #[derive(Error, Debug)]
 pub enum BackendAuthError {
     /// Could not update passkey credential's counter.
-    #[error("Could not update passkey credential's counter: {0}")]
-    CredentialUpdateFailure(sqlx::Error),
+    #[error("Could not update passkey credential's counter")]
+    CredentialUpdateFailure(#[source] sqlx::Error),
 
     /// Could not grab a stored credential after validating the authentication.
     #[error("Could not grab a stored credential after validating the authentication.")]
-    StoredCredentialFailure(sqlx::Error),
+    StoredCredentialFailure(#[source] sqlx::Error),
 
-    /// The credential is orphaned and has no user associated with it.
-    #[error("The credential is orphaned and has no user associated with it.")]
-    OrphanedCredential(sqlx::Error),
+    /// Could not record when the passkey credential was last used.
+    #[error("Could not record when the passkey credential was last used")]
+    CredentialUseFailure(#[source] sqlx::Error),
 
     /// The credential counter is out of sync with the stored value.
     ///
@@ -125,6 +135,14 @@ impl AuthnBackend for PasskeyBackend {
         // In this case we need to look up the user from the provided credential.
         let id = auth_result.cred_id();
         let user = self.get_user_from_credentials(creds.user, id).await?;
+
+        // Not knowing which passkey was last used isn't worth turning the user away over.
+        if let Some(user) = &user {
+            if let Err(err) = self.record_credential_use(user.uuid, id).await {
+                error!("{}", ErrorChain(&err));
+            }
+        }
+
         Ok(user)
     }
 
@@ -144,12 +162,13 @@ impl PasskeyBackend {
         user_uuid: Uuid,
         auth_result: &AuthenticationResult,
     ) -> Result<(), BackendAuthError> {
-        let mut stored_creds =
-            sqlx::query_as::<Postgres, Credential>("SELECT * FROM credentials WHERE user_id = $1")
-                .bind(user_uuid)
-                .fetch_all(&self.db)
-                .await
-                .map_err(BackendAuthError::StoredCredentialFailure)?;
+        // language=postgresql
+        let query = "SELECT id, passkey FROM credentials WHERE user_uuid = $1";
+        let mut stored_creds = sqlx::query_as::<Postgres, StoredPasskey>(query)
+            .bind(user_uuid)
+            .fetch_all(&self.db)
+            .await
+            .map_err(BackendAuthError::StoredCredentialFailure)?;
 
         //TODO(videah): check counter discrepancies to detect cloning.
 
@@ -171,40 +190,39 @@ impl PasskeyBackend {
         Ok(())
     }
 
+    /// Marks the credential with the given ID as having just been used to log in.
+    ///
+    /// Only the credential that satisfied the challenge is touched, not every credential the user
+    /// has, so they can tell which of their passkeys they last logged in with.
+    async fn record_credential_use(
+        &self,
+        user_uuid: Uuid,
+        cred_id: &CredentialID,
+    ) -> Result<(), BackendAuthError> {
+        update_credential_last_used(&self.db, user_uuid, &cred_id.to_string())
+            .await
+            .map_err(BackendAuthError::CredentialUseFailure)
+    }
+
     /// Get a user assigned to a credential ID.
     ///
     /// If the user is already known, it will be returned. Otherwise, a user will be looked up
     /// from the database.
     ///
-    /// If the credential is orphaned and has no user assigned to it an error will be returned.
+    /// Returns `None` if no credential has the given ID. The lookup is a single query whether or
+    /// not the credential exists, so discoverable logins can't be used to find out which
+    /// credential IDs are registered by timing them.
     async fn get_user_from_credentials(
         &self,
         potential_user: Option<User>,
         cred_id: &CredentialID,
     ) -> Result<Option<User>, BackendAuthError> {
-        let user = match potential_user {
-            Some(user) => Some(user),
-            None => {
-                // language=postgresql
-                let query =
-                    "SELECT * FROM credentials WHERE passkey::json->'cred'->>'cred_id' = $1";
-                let cred = sqlx::query_as::<Postgres, Credential>(query)
-                    .bind(cred_id.to_string())
-                    .fetch_one(&self.db)
-                    .await
-                    .map_err(BackendAuthError::StoredCredentialFailure)?;
-
-                let user = sqlx::query_as::<Postgres, User>("SELECT * FROM users WHERE uuid = $1")
-                    .bind(cred.user_uuid)
-                    .fetch_one(&self.db)
-                    .await
-                    .map_err(BackendAuthError::OrphanedCredential)?;
-
-                Some(user)
-            }
-        };
-
-        Ok(user)
+        match potential_user {
+            Some(user) => Ok(Some(user)),
+            None => get_user_by_credential_id(&self.db, &cred_id.to_string())
+                .await
+                .map_err(BackendAuthError::StoredCredentialFailure),
+        }
     }
 }
 
@@ -213,3 +231,179 @@ impl PasskeyBackend {
 /// Not to be confused with [RegisterSession] or [AuthenticateSession] which are used for
 /// passing data between passkey register and auth handlers.
 pub type AuthSession = axum_login::AuthSession<PasskeyBackend>;
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::time::OffsetDateTime;
+    use webauthn_authenticator_rs::{
+        softpasskey::SoftPasskey,
+        WebauthnAuthenticator,
+    };
+
+    use super::*;
+    use crate::{
+        auth::passkeys::PasskeyAuthState,
+        db::credentials::insert_credential,
+        http::testing::{
+            insert_user,
+            insert_user_with_passkey,
+            PASSKEY_CREDENTIAL_ID,
+        },
+    };
+
+    #[sqlx::test]
+    async fn unknown_credential_has_no_user(db: PgPool) {
+        let backend = PasskeyBackend::new(db);
+        let cred_id = CredentialID::from(b"not a real credential".to_vec());
+
+        let user = backend
+            .get_user_from_credentials(None, &cred_id)
+            .await
+            .unwrap();
+        assert!(user.is_none());
+    }
+
+    #[sqlx::test]
+    async fn known_credential_resolves_to_its_user(db: PgPool) -> sqlx::Result<()> {
+        insert_user(&db, "bark").await?;
+        let user_uuid = insert_user_with_passkey(&db, "woof", None).await?;
+        let backend = PasskeyBackend::new(db);
+        let cred_id: CredentialID = serde_json::from_value(PASSKEY_CREDENTIAL_ID.into()).unwrap();
+
+        let user = backend
+            .get_user_from_credentials(None, &cred_id)
+            .await
+            .unwrap()
+            .expect("the credential should have a user");
+        assert_eq!(user.uuid, user_uuid);
+        assert_eq!(user.username, "woof");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn authenticating_updates_the_credential_counter(db: PgPool) -> sqlx::Result<()> {
+        let user_uuid = insert_user_with_passkey(&db, "woof", None).await?;
+        let backend = PasskeyBackend::new(db.clone());
+
+        // What webauthn-rs hands back after verifying an assertion that moved the counter on.
+        let auth_result: AuthenticationResult = serde_json::from_value(serde_json::json!({
+            "cred_id": PASSKEY_CREDENTIAL_ID,
+            "needs_update": true,
+            "user_verified": true,
+            "backup_state": false,
+            "backup_eligible": false,
+            "counter": 5,
+            "extensions": {}
+        }))
+        .unwrap();
+
+        backend
+            .update_credential_counter(user_uuid, &auth_result)
+            .await
+            .unwrap();
+
+        let counter: String = sqlx::query_scalar(
+            "SELECT passkey->'cred'->>'counter' FROM credentials WHERE user_uuid = $1",
+        )
+        .bind(user_uuid)
+        .fetch_one(&db)
+        .await?;
+        assert_eq!(counter, "5");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn logging_in_marks_only_the_used_credential(db: PgPool) -> sqlx::Result<()> {
+        let user_uuid = insert_user_with_passkey(&db, "woof", None).await?;
+        sqlx::query(
+            "INSERT INTO credentials (user_uuid, passkey) \
+             VALUES ($1, '{\"cred\": {\"cred_id\": \"YmFya2Jhcms\"}}')",
+        )
+        .bind(user_uuid)
+        .execute(&db)
+        .await?;
+        let backend = PasskeyBackend::new(db.clone());
+
+        let cred_id: CredentialID = serde_json::from_value(PASSKEY_CREDENTIAL_ID.into()).unwrap();
+        backend
+            .record_credential_use(user_uuid, &cred_id)
+            .await
+            .unwrap();
+
+        let last_used: Vec<(String, Option<OffsetDateTime>)> = sqlx::query_as(
+            "SELECT passkey->'cred'->>'cred_id', last_used_at FROM credentials \
+             WHERE user_uuid = $1",
+        )
+        .bind(user_uuid)
+        .fetch_all(&db)
+        .await?;
+        assert_eq!(last_used.len(), 2);
+        for (id, last_used_at) in last_used {
+            assert_eq!(last_used_at.is_some(), id == PASSKEY_CREDENTIAL_ID, "{id}");
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn logging_in_goes_ahead_when_the_use_cant_be_recorded(db: PgPool) -> sqlx::Result<()> {
+        let state = PasskeyAuthState::new("woof.example".to_string());
+        let origin = Url::parse("https://woof.example").unwrap();
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+
+        let user_uuid = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (username, uuid) VALUES ('woof', $1)")
+            .bind(user_uuid)
+            .execute(&db)
+            .await?;
+        let (ccr, registration) = state
+            .webauthn
+            .start_passkey_registration(user_uuid, "woof", "woof", None)
+            .unwrap();
+        let credential = authenticator.do_registration(origin.clone(), ccr).unwrap();
+        let passkey = state
+            .webauthn
+            .finish_passkey_registration(&credential, &registration)
+            .unwrap();
+        let passkey_json = serde_json::to_value(&passkey).unwrap();
+        let mut conn = db.acquire().await?;
+        insert_credential(&mut conn, user_uuid, passkey_json, None, None).await?;
+
+        // Recording when a credential was last used fails from here on.
+        sqlx::query(
+            "CREATE FUNCTION refuse_last_used() RETURNS trigger LANGUAGE plpgsql AS \
+             $$ BEGIN RAISE EXCEPTION 'not today'; END $$",
+        )
+        .execute(&db)
+        .await?;
+        sqlx::query(
+            "CREATE TRIGGER refuse_last_used BEFORE UPDATE ON credentials FOR EACH ROW \
+             WHEN (NEW.last_used_at IS DISTINCT FROM OLD.last_used_at) \
+             EXECUTE FUNCTION refuse_last_used()",
+        )
+        .execute(&db)
+        .await?;
+
+        let (rcr, auth_state) = state
+            .webauthn
+            .start_passkey_authentication(&[passkey])
+            .unwrap();
+        let challenge_response = authenticator.do_authentication(origin, rcr).unwrap();
+        let user = PasskeyBackend::new(db)
+            .authenticate(BackendAuthParameters {
+                auth_state,
+                challenge_response,
+                user: None,
+                user_uuid,
+                webauthn: state.webauthn.clone(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(user.map(|user| user.uuid), Some(user_uuid));
+
+        Ok(())
+    }
+}
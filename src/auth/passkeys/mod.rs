@@ -1,15 +1,89 @@
 use std::sync::Arc;
 
+use log::warn;
+use serde::{
+    de::DeserializeOwned,
+    Serialize,
+};
+use serde_json::Value;
+use thiserror::Error;
 use webauthn_rs::{
-    prelude::Url,
+    prelude::{
+        CreationChallengeResponse,
+        PasskeyAuthentication,
+        RequestChallengeResponse,
+        Url,
+        Uuid,
+        WebauthnError,
+    },
     Webauthn,
     WebauthnBuilder,
 };
 
+use crate::config::Config;
+
+pub mod aaguid;
 pub mod authentication;
 pub mod backend;
 pub mod registration;
 
+/// How much a registration challenge asks to be told about the authenticator creating the
+/// passkey, sent as its attestation conveyance preference.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum AttestationPreference {
+    /// No attestation, the authenticator stays anonymous.
+    #[default]
+    None,
+    /// Attestation is wanted, but the client is free to anonymize it.
+    Indirect,
+    /// The attestation exactly as the authenticator made it.
+    Direct,
+}
+
+impl AttestationPreference {
+    /// How the preference is spelled in the WebAuthn spec.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AttestationPreference::None => "none",
+            AttestationPreference::Indirect => "indirect",
+            AttestationPreference::Direct => "direct",
+        }
+    }
+}
+
+/// The kind of authenticator a registration challenge asks for, sent as its authenticator
+/// attachment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AttachmentPreference {
+    /// An authenticator built into the device, like Touch ID or Windows Hello.
+    Platform,
+    /// A roaming authenticator, like a security key or a phone.
+    CrossPlatform,
+}
+
+impl AttachmentPreference {
+    /// How the preference is spelled in the WebAuthn spec.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AttachmentPreference::Platform => "platform",
+            AttachmentPreference::CrossPlatform => "cross-platform",
+        }
+    }
+}
+
+/// A set of errors that can occur while setting up passkey authentication.
+#[derive(Debug, Error)]
+pub enum PasskeyConfigError {
+    /// The relying party origin isn't a valid URL.
+    #[error("The passkey origin `{0}` is not a valid URL: {1}")]
+    InvalidOrigin(String, url::ParseError),
+
+    /// webauthn-rs refused the relying party, usually because the RP ID isn't the origin's domain
+    /// or a parent of it.
+    #[error("The passkey RP ID `{0}` can't be used with the origin `{1}`: {2}")]
+    InvalidRelyingParty(String, Url, WebauthnError),
+}
+
 /// Configuration for the Webauthn instance used for passkey authentication.
 #[derive(Clone)]
 pub struct PasskeyAuthState {
@@ -17,21 +91,417 @@ pub struct PasskeyAuthState {
     pub webauthn: Arc<Webauthn>,
     /// The hostname of the server (i.e `videah.net`) used as the RP ID and origin.
     pub hostname: String,
-    /// The appid required for communicating with Apple devices.
-    pub appid: String,
+    /// The FIDO AppID legacy U2F credentials were registered under, if there are any to support.
+    pub appid: Option<String>,
+    /// The attestation registration challenges ask for.
+    pub attestation: AttestationPreference,
+    /// The AAGUIDs of the authenticators passkeys can be registered with, or empty to allow any.
+    pub allowed_authenticators: Vec<Uuid>,
+    /// Whether registration challenges require a discoverable credential.
+    pub require_resident_key: bool,
+    /// The kind of authenticator registration challenges ask for, or `None` to allow any.
+    pub authenticator_attachment: Option<AttachmentPreference>,
 }
 
 impl PasskeyAuthState {
-    pub fn new(rp_id: String, appid: String) -> PasskeyAuthState {
-        let rp_origin = Url::parse(&format!("https://{rp_id}")).unwrap();
-        let builder = WebauthnBuilder::new(&rp_id, &rp_origin).unwrap();
-        let builder = builder.rp_name("woof");
-
-        let webauthn = Arc::new(builder.build().unwrap());
-        PasskeyAuthState {
-            webauthn,
+    /// Sets up passkey authentication for the given relying party, served from `https://{rp_id}`.
+    ///
+    /// # Panics
+    ///
+    /// If the relying party is invalid, see [Self::try_new] for a version that doesn't panic.
+    pub fn new(rp_id: String) -> PasskeyAuthState {
+        let rp_origin = format!("https://{rp_id}");
+        PasskeyAuthState::try_new(rp_id, &rp_origin, &[], "woof")
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Sets up passkey authentication for the relying party with the given ID, origin and the name
+    /// authenticators show users.
+    ///
+    /// The RP ID has to be the domain of the origin, or a parent of it. Passkeys are also accepted
+    /// from each of the additional origins, which lets the same relying party be served from more
+    /// than one domain.
+    pub fn try_new(
+        rp_id: String,
+        rp_origin: &str,
+        additional_origins: &[String],
+        rp_name: &str,
+    ) -> Result<PasskeyAuthState, PasskeyConfigError> {
+        let rp_origin = parse_origin(rp_origin)?;
+        let additional_origins = additional_origins
+            .iter()
+            .map(|origin| parse_origin(origin))
+            .collect::<Result<Vec<_>, _>>()?;
+        let invalid_relying_party =
+            |err| PasskeyConfigError::InvalidRelyingParty(rp_id.clone(), rp_origin.clone(), err);
+
+        let webauthn = WebauthnBuilder::new(&rp_id, &rp_origin)
+            .and_then(|builder| {
+                additional_origins
+                    .iter()
+                    .fold(builder, |builder, origin| {
+                        builder.append_allowed_origin(origin)
+                    })
+                    .rp_name(rp_name)
+                    .build()
+            })
+            .map_err(invalid_relying_party)?;
+
+        Ok(PasskeyAuthState {
+            webauthn: Arc::new(webauthn),
             hostname: rp_id,
-            appid,
+            appid: None,
+            attestation: AttestationPreference::default(),
+            allowed_authenticators: Vec::new(),
+            require_resident_key: false,
+            authenticator_attachment: None,
+        })
+    }
+
+    /// Sets up passkey authentication for the relying party with the given ID and origin, with
+    /// everything else (its name, additional origins, AppID and what registration challenges ask
+    /// for) taken from the [Config].
+    pub fn from_config(
+        rp_id: String,
+        rp_origin: &str,
+        config: &Config,
+    ) -> Result<PasskeyAuthState, PasskeyConfigError> {
+        let state = PasskeyAuthState::try_new(
+            rp_id,
+            rp_origin,
+            &config.additional_origins,
+            &config.rp_name,
+        )?;
+
+        Ok(PasskeyAuthState {
+            appid: config.appid.clone(),
+            attestation: config.attestation,
+            allowed_authenticators: config.allowed_authenticators.clone(),
+            require_resident_key: config.require_resident_key,
+            authenticator_attachment: config.authenticator_attachment,
+            ..state
+        })
+    }
+}
+
+/// Parses an origin passkeys can be used from.
+fn parse_origin(origin: &str) -> Result<Url, PasskeyConfigError> {
+    Url::parse(origin).map_err(|err| PasskeyConfigError::InvalidOrigin(origin.to_string(), err))
+}
+
+/// Returns a copy of a challenge or ceremony state with `patch` applied to its serialized form.
+///
+/// webauthn-rs picks some options for passkeys itself (no attestation, no AppID, required user
+/// verification, etc.) without a way to change them, even though it handles them fine when
+/// verifying. Patching the JSON is the only way to set them. Returns `None` if the value doesn't
+/// look like `patch` expects, or no longer deserializes afterwards, in which case it should be
+/// left as webauthn-rs made it so the client and server never disagree.
+pub(crate) fn patched<T>(value: &T, patch: impl FnOnce(&mut Value) -> Option<()>) -> Option<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let mut json = serde_json::to_value(value).ok()?;
+    patch(&mut json)?;
+    serde_json::from_value(json).ok()
+}
+
+impl PasskeyAuthState {
+    /// Enables the FIDO AppID extension for an authentication ceremony if [Self::appid] is set.
+    ///
+    /// Credentials created through the legacy U2F API are scoped to the AppID rather than the RP
+    /// ID. The client needs to be told about the AppID to find them, and the authentication state
+    /// needs it to accept assertions signed over it, so it's [patched] in.
+    pub fn apply_appid(
+        &self,
+        rcr: &mut RequestChallengeResponse,
+        auth_state: &mut PasskeyAuthentication,
+    ) {
+        let Some(appid) = &self.appid else {
+            return;
+        };
+
+        let patched = patched(&*auth_state, |value| {
+            let state = value.get_mut("ast")?.as_object_mut()?;
+            state.insert("appid".to_string(), Value::from(appid.as_str()));
+            Some(())
+        });
+
+        match patched {
+            Some(patched) => {
+                *auth_state = patched;
+                self.add_appid_extension(rcr);
+            }
+            None => warn!("Could not enable the AppID extension for passkey authentication"),
+        }
+    }
+
+    /// Sets the attestation a registration challenge asks for to [Self::attestation].
+    ///
+    /// Whatever attestation comes back is verified when the registration is finished, so only the
+    /// challenge sent to the client is [patched].
+    pub fn apply_attestation(&self, ccr: &mut CreationChallengeResponse) {
+        let patched = patched(&*ccr, |value| {
+            let options = value.get_mut("publicKey")?.as_object_mut()?;
+            options.insert(
+                "attestation".to_string(),
+                Value::from(self.attestation.as_str()),
+            );
+            Some(())
+        });
+
+        match patched {
+            Some(patched) => *ccr = patched,
+            None => warn!("Could not set the attestation preference of a registration challenge"),
+        }
+    }
+
+    /// Sets the authenticator selection of a registration challenge to [Self::require_resident_key]
+    /// and [Self::authenticator_attachment].
+    ///
+    /// Neither is checked when the registration is finished, so only the challenge sent to the
+    /// client is [patched], and only if a preference is set.
+    pub fn apply_authenticator_selection(&self, ccr: &mut CreationChallengeResponse) {
+        if !self.require_resident_key && self.authenticator_attachment.is_none() {
+            return;
+        }
+
+        let patched = patched(&*ccr, |value| {
+            let selection = value
+                .get_mut("publicKey")?
+                .as_object_mut()?
+                .entry("authenticatorSelection")
+                .or_insert_with(|| Value::Object(Default::default()))
+                .as_object_mut()?;
+
+            if self.require_resident_key {
+                selection.insert("residentKey".to_string(), Value::from("required"));
+                selection.insert("requireResidentKey".to_string(), Value::Bool(true));
+            }
+            if let Some(attachment) = self.authenticator_attachment {
+                selection.insert(
+                    "authenticatorAttachment".to_string(),
+                    Value::from(attachment.as_str()),
+                );
+            }
+
+            Some(())
+        });
+
+        match patched {
+            Some(patched) => *ccr = patched,
+            None => warn!("Could not set the authenticator selection of a registration challenge"),
+        }
+    }
+
+    /// Whether passkeys can be registered with the authenticator that has the given AAGUID.
+    ///
+    /// Authenticators that didn't identify themselves are only allowed when any authenticator is.
+    pub fn is_authenticator_allowed(&self, aaguid: Option<Uuid>) -> bool {
+        self.allowed_authenticators.is_empty()
+            || aaguid.is_some_and(|aaguid| self.allowed_authenticators.contains(&aaguid))
+    }
+
+    /// Adds [Self::appid] to the extensions of an authentication challenge sent to the client, if
+    /// it's set.
+    fn add_appid_extension(&self, rcr: &mut RequestChallengeResponse) {
+        if let Some(appid) = &self.appid {
+            rcr.public_key
+                .extensions
+                .get_or_insert_with(Default::default)
+                .appid = Some(appid.clone());
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use webauthn_authenticator_rs::{
+        softpasskey::SoftPasskey,
+        WebauthnAuthenticator,
+    };
+
+    use super::*;
+
+    #[test]
+    fn appid_is_included_in_challenge_options() {
+        let state = PasskeyAuthState {
+            appid: Some("https://woof.example".to_string()),
+            ..PasskeyAuthState::new("woof.example".to_string())
+        };
+        let mut rcr: RequestChallengeResponse = serde_json::from_value(serde_json::json!({
+            "publicKey": {
+                "challenge": "d29vZg",
+                "rpId": "woof.example",
+                "allowCredentials": [],
+                "userVerification": "preferred"
+            }
+        }))
+        .unwrap();
+
+        state.add_appid_extension(&mut rcr);
+
+        let options = serde_json::to_value(&rcr).unwrap();
+        assert_eq!(
+            options["publicKey"]["extensions"]["appid"],
+            "https://woof.example"
+        );
+    }
+
+    #[test]
+    fn malformed_origin_is_described() {
+        let result =
+            PasskeyAuthState::try_new("woof.example".to_string(), "not a url", &[], "woof");
+
+        let err = result.err().expect("the origin to be rejected");
+        assert!(matches!(err, PasskeyConfigError::InvalidOrigin(..)));
+        assert!(err
+            .to_string()
+            .starts_with("The passkey origin `not a url` is not a valid URL"));
+    }
+
+    #[test]
+    fn origin_outside_the_rp_id_is_described() {
+        let result = PasskeyAuthState::try_new(
+            "woof.example".to_string(),
+            "https://bark.example",
+            &[],
+            "woof",
+        );
+
+        let err = result.err().expect("the origin to be rejected");
+        assert!(matches!(err, PasskeyConfigError::InvalidRelyingParty(..)));
+        assert!(err.to_string().starts_with(
+            "The passkey RP ID `woof.example` can't be used with the origin `https://bark.example/`"
+        ));
+    }
+
+    #[test]
+    fn valid_relying_party_is_accepted() {
+        let state = PasskeyAuthState::try_new(
+            "woof.example".to_string(),
+            "https://paste.woof.example",
+            &[],
+            "woof",
+        )
+        .unwrap();
+
+        assert_eq!(state.hostname, "woof.example");
+    }
+
+    #[test]
+    fn configured_settings_are_picked_up() {
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--appid",
+            "https://woof.example",
+            "--attestation",
+            "direct",
+            "--allowed-authenticators",
+            "fbfc3007-154e-4ecc-8c0b-6e020557d7bd",
+            "--require-resident-key",
+            "true",
+            "--authenticator-attachment",
+            "platform",
+            "--additional-origins",
+            "https://paste.woof.example",
+        ]);
+
+        let state = PasskeyAuthState::from_config(
+            "woof.example".to_string(),
+            "https://woof.example",
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(state.appid.as_deref(), Some("https://woof.example"));
+        assert_eq!(state.attestation, AttestationPreference::Direct);
+        assert_eq!(state.allowed_authenticators, config.allowed_authenticators);
+        assert!(state.require_resident_key);
+        assert_eq!(
+            state.authenticator_attachment,
+            Some(AttachmentPreference::Platform)
+        );
+
+        // The additional origins are checked just like they are by [PasskeyAuthState::try_new].
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--additional-origins",
+            "not a url",
+        ]);
+        let result = PasskeyAuthState::from_config(
+            "woof.example".to_string(),
+            "https://woof.example",
+            &config,
+        );
+        assert!(matches!(result, Err(PasskeyConfigError::InvalidOrigin(..))));
+    }
+
+    #[test]
+    fn malformed_additional_origin_is_described() {
+        let result = PasskeyAuthState::try_new(
+            "woof.example".to_string(),
+            "https://woof.example",
+            &[
+                "https://paste.woof.example".to_string(),
+                "not a url".to_string(),
+            ],
+            "woof",
+        );
+
+        let err = result.err().expect("the additional origin to be rejected");
+        assert!(
+            matches!(err, PasskeyConfigError::InvalidOrigin(origin, _) if origin == "not a url")
+        );
+    }
+
+    #[test]
+    fn credentials_from_any_allowed_origin_verify() {
+        let state = PasskeyAuthState::try_new(
+            "woof.example".to_string(),
+            "https://woof.example",
+            &["https://paste.woof.example".to_string()],
+            "woof",
+        )
+        .unwrap();
+        let origin = Url::parse("https://paste.woof.example").unwrap();
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+
+        let (ccr, registration) = state
+            .webauthn
+            .start_passkey_registration(Uuid::new_v4(), "woof", "woof", None)
+            .unwrap();
+        let credential = authenticator.do_registration(origin.clone(), ccr).unwrap();
+        let passkey = state
+            .webauthn
+            .finish_passkey_registration(&credential, &registration)
+            .unwrap();
+
+        let (rcr, authentication) = state
+            .webauthn
+            .start_passkey_authentication(&[passkey.clone()])
+            .unwrap();
+        let assertion = authenticator.do_authentication(origin, rcr).unwrap();
+        let result = state
+            .webauthn
+            .finish_passkey_authentication(&assertion, &authentication)
+            .unwrap();
+        assert_eq!(result.cred_id(), passkey.cred_id());
+
+        // Origins that weren't configured are still turned away.
+        let (rcr, authentication) = state
+            .webauthn
+            .start_passkey_authentication(&[passkey])
+            .unwrap();
+        let elsewhere = Url::parse("https://bark.woof.example").unwrap();
+        let assertion = authenticator.do_authentication(elsewhere, rcr).unwrap();
+        assert!(state
+            .webauthn
+            .finish_passkey_authentication(&assertion, &authentication)
+            .is_err());
+    }
+}
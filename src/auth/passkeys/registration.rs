@@ -7,11 +7,14 @@ use axum::{
     Extension,
     Json,
 };
-use log::error;
+use axum_extra::extract::cookie::CookieJar;
+use log::warn;
 use serde::{
     Deserialize,
     Serialize,
 };
+use serde_json::Value;
+use sqlx::PgPool;
 use thiserror::Error;
 use tower_sessions::Session;
 use webauthn_rs::prelude::*;
@@ -19,17 +22,32 @@ use webauthn_rs::prelude::*;
 use crate::{
     auth::{
         passkeys::{
+            aaguid::aaguid_from_attestation_object,
             backend::{
                 AuthSession,
                 PasskeyBackend,
             },
+            patched,
             PasskeyAuthState,
         },
         AuthParams,
+        CHALLENGE_SESSION_COOKIE,
+    },
+    db::{
+        credentials::insert_credential,
+        users::{
+            get_user_by_email,
+            get_user_by_username,
+            is_email,
+            User,
+        },
     },
-    db::users::User,
     http::{
         error::ApiError,
+        metrics::{
+            self,
+            Ceremony,
+        },
         ApiContext,
     },
 };
@@ -41,17 +59,39 @@ pub enum PasskeyRegisterError {
     #[error("A user with that name already exists")]
     UserAlreadyExists,
 
+    /// A user with the same email already exists.
+    #[error("A user with that email already exists")]
+    EmailAlreadyExists,
+
+    /// The username contains an `@`, which would make it impossible to tell apart from an email.
+    #[error("Usernames can't contain an @")]
+    InvalidUsername,
+
+    /// The email doesn't look like an email address.
+    #[error("That doesn't look like an email address")]
+    InvalidEmail,
+
+    /// The passkey was created by an authenticator that isn't on the configured allow-list.
+    #[error("Passkeys from that authenticator can't be registered here")]
+    AuthenticatorNotAllowed,
+
     /// An error occurred while creating a new challenge.
-    #[error("An error occurred while creating a new challenge: {0}")]
-    ChallengeCreationFailure(WebauthnError),
+    #[error("An error occurred while creating a new challenge")]
+    ChallengeCreationFailure(#[source] WebauthnError),
 
-    /// An error occurred while verifying and completing the registration.
+    /// The credential the client sent back couldn't be verified, which is down to the client
+    /// rather than the server.
     #[error("An error occurred while verifying and completing the registration: {0}")]
     RegistrationVerifyFailure(WebauthnError),
 
     /// An error occurred while storing registration state in the session.
     #[error("An error occurred while storing registration state in the session.")]
-    SessionFailure(tower_sessions::session::Error),
+    SessionFailure(#[source] tower_sessions::session::Error),
+
+    /// The request didn't carry the session registration was started in, most likely because it
+    /// expired or the browser isn't keeping cookies.
+    #[error("The registration session has expired or wasn't kept, make sure cookies are enabled")]
+    MissingSession,
 
     /// Registration state was missing from the session.
     #[error(
@@ -59,40 +99,66 @@ pub enum PasskeyRegisterError {
     )]
     MissingSessionInfo,
 
+    /// A passkey was being added to an account that isn't logged in anymore.
+    #[error("You need to be logged in to add a passkey to your account")]
+    NotLoggedIn,
+
     /// An error occurred while logging in the user.
-    #[error("An error occurred while logging in the user. {0}")]
-    AuthSessionFailure(axum_login::Error<PasskeyBackend>),
+    #[error("An error occurred while logging in the user.")]
+    AuthSessionFailure(#[source] axum_login::Error<PasskeyBackend>),
 
     /// An error occurred while encoding the passkey to JSON.
     #[error("An error occurred while encoding the passkey to JSON.")]
-    PasskeyJsonEncodeFailure(serde_json::Error),
+    PasskeyJsonEncodeFailure(#[source] serde_json::Error),
 
     /// An error occurred while communicating with the database.
     #[error("An error occurred while communicating with the database.")]
     DatabaseError(#[from] sqlx::Error),
 }
 
+impl PasskeyRegisterError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PasskeyRegisterError::UserAlreadyExists => "user_already_exists",
+            PasskeyRegisterError::EmailAlreadyExists => "email_already_exists",
+            PasskeyRegisterError::InvalidUsername => "invalid_username",
+            PasskeyRegisterError::InvalidEmail => "invalid_email",
+            PasskeyRegisterError::AuthenticatorNotAllowed => "authenticator_not_allowed",
+            PasskeyRegisterError::ChallengeCreationFailure(_) => "challenge_creation_failure",
+            PasskeyRegisterError::RegistrationVerifyFailure(_) => "registration_verify_failure",
+            PasskeyRegisterError::SessionFailure(_) => "session_failure",
+            PasskeyRegisterError::MissingSession => "missing_session",
+            PasskeyRegisterError::MissingSessionInfo => "missing_session_info",
+            PasskeyRegisterError::NotLoggedIn => "not_logged_in",
+            PasskeyRegisterError::AuthSessionFailure(_) => "auth_session_failure",
+            PasskeyRegisterError::PasskeyJsonEncodeFailure(_) => "passkey_json_encode_failure",
+            PasskeyRegisterError::DatabaseError(_) => "database_error",
+        }
+    }
+}
+
 impl IntoResponse for PasskeyRegisterError {
     /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
     fn into_response(self) -> Response {
         let status = match self {
             PasskeyRegisterError::UserAlreadyExists => StatusCode::CONFLICT,
+            PasskeyRegisterError::EmailAlreadyExists => StatusCode::CONFLICT,
+            PasskeyRegisterError::InvalidUsername => StatusCode::BAD_REQUEST,
+            PasskeyRegisterError::InvalidEmail => StatusCode::BAD_REQUEST,
+            PasskeyRegisterError::AuthenticatorNotAllowed => StatusCode::FORBIDDEN,
             PasskeyRegisterError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PasskeyRegisterError::ChallengeCreationFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            PasskeyRegisterError::RegistrationVerifyFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PasskeyRegisterError::RegistrationVerifyFailure(_) => StatusCode::BAD_REQUEST,
             PasskeyRegisterError::SessionFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PasskeyRegisterError::PasskeyJsonEncodeFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PasskeyRegisterError::AuthSessionFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PasskeyRegisterError::MissingSession => StatusCode::UNAUTHORIZED,
             PasskeyRegisterError::MissingSessionInfo => StatusCode::BAD_REQUEST,
+            PasskeyRegisterError::NotLoggedIn => StatusCode::UNAUTHORIZED,
         };
 
-        let error = ApiError {
-            message: self.to_string(),
-        };
-
-        error!("{}", error.message);
-
-        (status, Json(error)).into_response()
+        ApiError::respond(status, self.code(), &self)
     }
 }
 
@@ -105,8 +171,12 @@ pub type RegisterSession = Session;
 #[derive(Serialize, Deserialize)]
 struct RegistrationSessionInfo {
     username: String,
+    email: Option<String>,
     user_unique_id: Uuid,
     reg_state: PasskeyRegistration,
+    label: Option<String>,
+    /// Whether the passkey is being added to the logged in user rather than a new one.
+    existing_account: bool,
 }
 
 /// Starts the passkey registration process for a new user.
@@ -115,44 +185,234 @@ struct RegistrationSessionInfo {
 /// [CreationChallengeResponse] is passed back to the client and the resulting registration state
 /// will be passed to the [finish_register] endpoint via a [RegisterSession] to complete the
 /// registration when the client calls it.
+///
+/// A logged in user registering with their own username adds another passkey to their account
+/// instead, and the passkeys they already have are excluded from the challenge.
+#[utoipa::path(
+    post,
+    path = "/api/users/start_register",
+    tag = "passkeys",
+    request_body = AuthParams,
+    responses(
+        (
+            status = 200, body = Object,
+            description = "The `CreationChallengeResponse` for `navigator.credentials.create()`"
+        ),
+        (status = 400, description = "The username or email is invalid", body = ApiError),
+        (status = 409, description = "The username or email is already taken", body = ApiError),
+    ),
+)]
 pub async fn start_register(
     ctx: Extension<ApiContext>,
     Extension(state): Extension<PasskeyAuthState>,
     session: RegisterSession,
+    auth_session: AuthSession,
     Json(params): Json<AuthParams>,
 ) -> Result<impl IntoResponse, PasskeyRegisterError> {
+    begin_registration(&ctx.db, &state, &session, auth_session.user, params, false).await
+}
+
+/// Starts the passkey registration process for a new user through conditional create.
+///
+/// Conditional create lets the browser's password manager create a passkey without showing a
+/// modal prompt, so the challenge only prefers user verification rather than requiring it. It's
+/// otherwise the same as [start_register], and finishes through [finish_register] too.
+#[utoipa::path(
+    post,
+    path = "/api/users/start_conditional_register",
+    tag = "passkeys",
+    request_body = AuthParams,
+    responses(
+        (
+            status = 200, body = Object,
+            description = "The `CreationChallengeResponse` for `navigator.credentials.create()`"
+        ),
+        (status = 400, description = "The username or email is invalid", body = ApiError),
+        (status = 409, description = "The username or email is already taken", body = ApiError),
+    ),
+)]
+pub async fn start_conditional_register(
+    ctx: Extension<ApiContext>,
+    Extension(state): Extension<PasskeyAuthState>,
+    session: RegisterSession,
+    auth_session: AuthSession,
+    Json(params): Json<AuthParams>,
+) -> Result<impl IntoResponse, PasskeyRegisterError> {
+    begin_registration(&ctx.db, &state, &session, auth_session.user, params, true).await
+}
+
+/// Creates a registration challenge for [start_register] and [start_conditional_register],
+/// storing the registration state in the [RegisterSession] for [finish_register].
+async fn begin_registration(
+    db: &PgPool,
+    state: &PasskeyAuthState,
+    session: &RegisterSession,
+    current_user: Option<User>,
+    mut params: AuthParams,
+    conditional: bool,
+) -> Result<Json<CreationChallengeResponse>, PasskeyRegisterError> {
     // Clear any previous registration state that may have been set.
     session.clear();
 
-    let user_unique_id = Uuid::new_v4();
+    // Registering with the username of the logged in user adds a passkey to their account,
+    // anything else is a new account.
+    let existing_account = current_user.filter(|user| user.username == params.username);
+    let user_unique_id = match &existing_account {
+        Some(user) => {
+            params.email = user.email.clone();
+            user.uuid
+        }
+        None => {
+            check_identifiers(db, &params).await?;
+            Uuid::new_v4()
+        }
+    };
 
-    // Make sure the user doesn't already exist.
-    sqlx::query_file_as!(User, "sql/get_user_by_username.sql", params.username)
-        .fetch_optional(&ctx.db)
-        .await
-        .map_err(PasskeyRegisterError::DatabaseError)?
-        .map_or(Ok(()), |_| Err(PasskeyRegisterError::UserAlreadyExists))?;
+    // Any credentials this user already has are excluded so the same authenticator can't be
+    // registered twice. The browser will tell the user it's already registered instead.
+    let exclude_credentials = existing_credential_ids(db, user_unique_id).await?;
 
-    let (ccr, reg_state) = state
-        .webauthn
-        .start_passkey_registration(user_unique_id, &params.username, &params.username, None)
-        .map_err(PasskeyRegisterError::ChallengeCreationFailure)?;
+    let challenge = if conditional {
+        create_conditional_registration_challenge(
+            state,
+            user_unique_id,
+            &params,
+            exclude_credentials,
+        )
+    } else {
+        create_registration_challenge(state, user_unique_id, &params, exclude_credentials)
+    };
+    let (ccr, reg_state) = challenge.map_err(PasskeyRegisterError::ChallengeCreationFailure)?;
 
     // Construct the session info that will inevitably get passed to the finish_register handler.
     let session_info = RegistrationSessionInfo {
         username: params.username,
+        email: params.email,
         user_unique_id,
         reg_state,
+        label: params.label,
+        existing_account: existing_account.is_some(),
     };
 
     // Store the session info in the session.
     session
         .insert("reg_state", session_info)
         .map_err(PasskeyRegisterError::SessionFailure)?;
+    metrics::record_challenge(Ceremony::Registration);
 
     Ok(Json(ccr))
 }
 
+/// Makes sure the username and email a user is registering with are valid and not taken already.
+///
+/// Users can log in with either of them, so a username can't look like an email and vice versa.
+async fn check_identifiers(db: &PgPool, params: &AuthParams) -> Result<(), PasskeyRegisterError> {
+    if is_email(&params.username) {
+        return Err(PasskeyRegisterError::InvalidUsername);
+    }
+
+    if get_user_by_username(db, &params.username).await?.is_some() {
+        return Err(PasskeyRegisterError::UserAlreadyExists);
+    }
+
+    if let Some(email) = &params.email {
+        if !is_email(email) {
+            return Err(PasskeyRegisterError::InvalidEmail);
+        }
+
+        if get_user_by_email(db, email).await?.is_some() {
+            return Err(PasskeyRegisterError::EmailAlreadyExists);
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates a passkey registration challenge for a user.
+///
+/// The username is used as the stable account name, and the display name (if any) is what the
+/// authenticator shows the user. Authenticators holding any of the `exclude_credentials` will
+/// refuse to register again. The challenge asks for the attestation and authenticator selection
+/// the [PasskeyAuthState] is configured with.
+fn create_registration_challenge(
+    state: &PasskeyAuthState,
+    user_unique_id: Uuid,
+    params: &AuthParams,
+    exclude_credentials: Vec<CredentialID>,
+) -> Result<(CreationChallengeResponse, PasskeyRegistration), WebauthnError> {
+    let display_name = params.display_name.as_deref().unwrap_or(&params.username);
+    let exclude_credentials = (!exclude_credentials.is_empty()).then_some(exclude_credentials);
+
+    let (mut ccr, reg_state) = state.webauthn.start_passkey_registration(
+        user_unique_id,
+        &params.username,
+        display_name,
+        exclude_credentials,
+    )?;
+    state.apply_attestation(&mut ccr);
+    state.apply_authenticator_selection(&mut ccr);
+
+    Ok((ccr, reg_state))
+}
+
+/// Creates a passkey registration challenge suited to conditional create.
+///
+/// Password managers creating a passkey through conditional create don't ask the user to verify
+/// themselves, so user verification is only preferred. webauthn-rs always requires it for passkeys,
+/// so both the challenge and the registration state are [patched] to match, or neither is.
+fn create_conditional_registration_challenge(
+    state: &PasskeyAuthState,
+    user_unique_id: Uuid,
+    params: &AuthParams,
+    exclude_credentials: Vec<CredentialID>,
+) -> Result<(CreationChallengeResponse, PasskeyRegistration), WebauthnError> {
+    let (ccr, reg_state) =
+        create_registration_challenge(state, user_unique_id, params, exclude_credentials)?;
+
+    // "preferred" is how the WebAuthn spec spells it, which both sides are serialized with.
+    let preferred = || Value::from("preferred");
+    let patched_ccr = patched(&ccr, |value| {
+        let selection = value
+            .get_mut("publicKey")?
+            .get_mut("authenticatorSelection")?
+            .as_object_mut()?;
+        selection.insert("userVerification".to_string(), preferred());
+        Some(())
+    });
+    let patched_state = patched(&reg_state, |value| {
+        let registration = value.get_mut("rs")?.as_object_mut()?;
+        registration.insert("policy".to_string(), preferred());
+        Some(())
+    });
+
+    match (patched_ccr, patched_state) {
+        (Some(ccr), Some(reg_state)) => Ok((ccr, reg_state)),
+        _ => {
+            warn!("Could not relax user verification for conditional passkey registration");
+            Ok((ccr, reg_state))
+        }
+    }
+}
+
+/// Gets the IDs of every credential belonging to the user with the given UUID.
+async fn existing_credential_ids(
+    db: &PgPool,
+    user_uuid: Uuid,
+) -> Result<Vec<CredentialID>, sqlx::Error> {
+    let ids = sqlx::query_file_scalar!("sql/get_credential_ids_by_user_uuid.sql", user_uuid)
+        .fetch_all(db)
+        .await?;
+
+    // Credential IDs are stored in the same base64 encoding they're deserialized from.
+    let ids = ids
+        .into_iter()
+        .flatten()
+        .filter_map(|id| serde_json::from_value(serde_json::Value::String(id)).ok())
+        .collect();
+
+    Ok(ids)
+}
+
 /// Finishes the passkey registration process, creating a new user and associated credential.
 ///
 /// This endpoint will verify the [RegisterPublicKeyCredential] passed back by the client and
@@ -160,21 +420,46 @@ pub async fn start_register(
 /// [start_register].
 ///
 /// If the registration is successful, a new user and credential will be created in the database
-/// and the user will be automatically logged in.
+/// and the user will be automatically logged in. When [start_register] was adding a passkey to
+/// the logged in user, only the credential is created.
+#[utoipa::path(
+    post,
+    path = "/api/users/finish_register",
+    tag = "passkeys",
+    request_body(
+        content = Object,
+        description = "The `RegisterPublicKeyCredential` from `navigator.credentials.create()`"
+    ),
+    responses(
+        (status = 200, description = "The user was created and logged in"),
+        (status = 400, description = "The credential couldn't be verified", body = ApiError),
+        (
+            status = 401, body = ApiError,
+            description = "The registration session has expired or the user was logged out"
+        ),
+        (status = 403, description = "The authenticator isn't allowed", body = ApiError),
+    ),
+)]
 pub async fn finish_register(
     ctx: Extension<ApiContext>,
     Extension(state): Extension<PasskeyAuthState>,
     session: RegisterSession,
     mut auth_session: AuthSession,
+    jar: CookieJar,
     Json(reg): Json<RegisterPublicKeyCredential>,
 ) -> Result<impl IntoResponse, PasskeyRegisterError> {
     // Get session info that should have been set in the start_register handler.
     // This can fail if the session info was never set, or if there was an error while
-    // retrieving it.
+    // retrieving it. Without the session's cookie the browser never kept the session, which needs
+    // fixing on the user's end rather than the client's.
+    let missing = match jar.get(CHALLENGE_SESSION_COOKIE) {
+        Some(_) => PasskeyRegisterError::MissingSessionInfo,
+        None => PasskeyRegisterError::MissingSession,
+    };
     let session_info: RegistrationSessionInfo = session
         .remove("reg_state")
         .map_err(PasskeyRegisterError::SessionFailure)?
-        .ok_or(PasskeyRegisterError::MissingSessionInfo)?;
+        .ok_or(missing)?;
 
     // Verify the registration and get the completed passkey.
     let passkey = state
@@ -182,6 +467,39 @@ pub async fn finish_register(
         .finish_passkey_registration(&reg, &session_info.reg_state)
         .map_err(PasskeyRegisterError::RegistrationVerifyFailure)?;
 
+    // The authenticator's make and model isn't kept in the passkey, so grab it from the raw
+    // attestation before it's gone.
+    let aaguid = aaguid_from_attestation_object(reg.response.attestation_object.as_ref());
+
+    // The AAGUID is taken as the authenticator states it, the attestation isn't checked against
+    // its vendor's certificates. This keeps out authenticators that are honest about what they
+    // are, not ones set on pretending to be something else.
+    if !state.is_authenticator_allowed(aaguid) {
+        return Err(PasskeyRegisterError::AuthenticatorNotAllowed);
+    }
+
+    // Convert passkey to JSON so it can be inserted into the database.
+    let passkey =
+        serde_json::to_value(passkey).map_err(PasskeyRegisterError::PasskeyJsonEncodeFailure)?;
+
+    // The account a passkey is being added to has to still be the one logged in.
+    if session_info.existing_account {
+        let user = auth_session
+            .user
+            .filter(|user| user.uuid == session_info.user_unique_id)
+            .ok_or(PasskeyRegisterError::NotLoggedIn)?;
+        let mut conn = ctx
+            .db
+            .acquire()
+            .await
+            .map_err(PasskeyRegisterError::DatabaseError)?;
+        insert_credential(&mut conn, user.uuid, passkey, session_info.label, aaguid)
+            .await
+            .map_err(PasskeyRegisterError::DatabaseError)?;
+
+        return Ok(StatusCode::OK);
+    }
+
     // Time to insert the user into the database, we create a transaction to ensure that
     // the user and credential are inserted atomically.
     let mut tx = ctx
@@ -194,23 +512,21 @@ pub async fn finish_register(
         User,
         "sql/insert_user.sql",
         session_info.username,
-        session_info.user_unique_id
+        session_info.user_unique_id,
+        session_info.email
     )
     .fetch_one(&mut *tx)
     .await
     .map_err(PasskeyRegisterError::DatabaseError)?;
 
-    // Convert passkey to JSON and insert it into the database.
-    let passkey =
-        serde_json::to_value(passkey).map_err(PasskeyRegisterError::PasskeyJsonEncodeFailure)?;
-    sqlx::query_file_as!(Credential, "sql/insert_credential.sql", user.uuid, passkey)
-        .execute(&mut *tx)
+    insert_credential(&mut *tx, user.uuid, passkey, session_info.label, aaguid)
         .await
         .map_err(PasskeyRegisterError::DatabaseError)?;
 
     tx.commit()
         .await
         .map_err(PasskeyRegisterError::DatabaseError)?;
+    metrics::record_registration();
 
     // Automatically log the user in.
     auth_session
@@ -220,3 +536,438 @@ pub async fn finish_register(
 
     Ok(StatusCode::OK)
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::Request,
+    };
+    use clap::Parser;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        auth::passkeys::{
+            AttachmentPreference,
+            AttestationPreference,
+        },
+        config::Config,
+        http::{
+            csrf::CSRF_HEADER,
+            error::ErrorChain,
+            testing::{
+                challenge_session,
+                csrf_token,
+                error_code,
+                insert_user_with_passkey,
+                login_as,
+                post_json,
+                with_auth,
+                PASSKEY_CREDENTIAL_ID,
+            },
+        },
+    };
+
+    fn state() -> PasskeyAuthState {
+        PasskeyAuthState::new("woof.example".to_string())
+    }
+
+    #[test]
+    fn registration_challenge_uses_display_name() {
+        let params = AuthParams {
+            username: "woof".to_string(),
+            email: None,
+            display_name: Some("Woof Woof".to_string()),
+            label: None,
+        };
+        let (ccr, _) =
+            create_registration_challenge(&state(), Uuid::new_v4(), &params, vec![]).unwrap();
+
+        let options = serde_json::to_value(&ccr).unwrap();
+        assert_eq!(options["publicKey"]["user"]["name"], "woof");
+        assert_eq!(options["publicKey"]["user"]["displayName"], "Woof Woof");
+    }
+
+    #[test]
+    fn registration_challenge_defaults_display_name_to_username() {
+        let params = AuthParams {
+            username: "woof".to_string(),
+            email: None,
+            display_name: None,
+            label: None,
+        };
+        let (ccr, _) =
+            create_registration_challenge(&state(), Uuid::new_v4(), &params, vec![]).unwrap();
+
+        let options = serde_json::to_value(&ccr).unwrap();
+        assert_eq!(options["publicKey"]["user"]["displayName"], "woof");
+    }
+
+    #[test]
+    fn conditional_registration_challenge_prefers_user_verification() {
+        let params = AuthParams {
+            username: "woof".to_string(),
+            email: None,
+            display_name: None,
+            label: None,
+        };
+        let (ccr, reg_state) =
+            create_conditional_registration_challenge(&state(), Uuid::new_v4(), &params, vec![])
+                .unwrap();
+
+        let options = serde_json::to_value(&ccr).unwrap();
+        assert_eq!(
+            options["publicKey"]["authenticatorSelection"]["userVerification"],
+            "preferred"
+        );
+        assert_eq!(options["publicKey"]["user"]["name"], "woof");
+
+        let reg_state = serde_json::to_value(&reg_state).unwrap();
+        assert_eq!(reg_state["rs"]["policy"], "preferred");
+    }
+
+    #[sqlx::test]
+    async fn conditional_registration_returns_a_creation_challenge(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(crate::auth::router(&config).unwrap(), db);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/users/start_conditional_register")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"username":"woof"}"#))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let ccr: CreationChallengeResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(ccr.public_key.user.name, "woof");
+        assert!(ccr.public_key.authenticator_selection.is_some());
+    }
+
+    #[test]
+    fn registration_challenge_asks_for_configured_attestation() {
+        let params = AuthParams {
+            username: "woof".to_string(),
+            email: None,
+            display_name: None,
+            label: None,
+        };
+        let attestation = |state: &PasskeyAuthState| {
+            let (ccr, _) =
+                create_registration_challenge(state, Uuid::new_v4(), &params, vec![]).unwrap();
+            serde_json::to_value(&ccr).unwrap()["publicKey"]["attestation"].clone()
+        };
+
+        assert_eq!(attestation(&state()), "none");
+
+        let direct = PasskeyAuthState {
+            attestation: AttestationPreference::Direct,
+            ..state()
+        };
+        assert_eq!(attestation(&direct), "direct");
+    }
+
+    #[sqlx::test]
+    async fn configured_attestation_reaches_the_challenge(db: PgPool) {
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--attestation",
+            "direct",
+        ]);
+        let app = with_auth(crate::auth::router(&config).unwrap(), db);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/users/start_register")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"username":"woof"}"#))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let options: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(options["publicKey"]["attestation"], "direct");
+    }
+
+    #[test]
+    fn registration_challenge_asks_for_configured_authenticator_selection() {
+        let params = AuthParams {
+            username: "woof".to_string(),
+            email: None,
+            display_name: None,
+            label: None,
+        };
+        let selection = |state: &PasskeyAuthState| {
+            let (ccr, _) =
+                create_registration_challenge(state, Uuid::new_v4(), &params, vec![]).unwrap();
+            serde_json::to_value(&ccr).unwrap()["publicKey"]["authenticatorSelection"].clone()
+        };
+
+        let default = selection(&state());
+        assert_ne!(default["residentKey"], "required");
+        assert!(default["authenticatorAttachment"].is_null());
+
+        let discoverable = PasskeyAuthState {
+            require_resident_key: true,
+            authenticator_attachment: Some(AttachmentPreference::Platform),
+            ..state()
+        };
+        let selection = selection(&discoverable);
+        assert_eq!(selection["residentKey"], "required");
+        assert_eq!(selection["requireResidentKey"], true);
+        assert_eq!(selection["authenticatorAttachment"], "platform");
+
+        // The rest of the selection is left as webauthn-rs made it.
+        assert_eq!(selection["userVerification"], default["userVerification"]);
+    }
+
+    #[test]
+    fn only_allowed_authenticators_can_register() {
+        let allowed = Uuid::new_v4();
+        let state = PasskeyAuthState {
+            allowed_authenticators: vec![allowed],
+            ..state()
+        };
+
+        assert!(state.is_authenticator_allowed(Some(allowed)));
+        assert!(!state.is_authenticator_allowed(Some(Uuid::new_v4())));
+        // Authenticators that won't say what they are can't be on the list.
+        assert!(!state.is_authenticator_allowed(None));
+
+        // Without a list, anything goes.
+        assert!(state().is_authenticator_allowed(None));
+
+        let response = PasskeyRegisterError::AuthenticatorNotAllowed.into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn registration_challenge_excludes_existing_credentials(db: PgPool) -> sqlx::Result<()> {
+        let user_uuid = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (username, uuid) VALUES ('woof', $1)")
+            .bind(user_uuid)
+            .execute(&db)
+            .await?;
+        sqlx::query("INSERT INTO credentials (user_uuid, passkey) VALUES ($1, $2)")
+            .bind(user_uuid)
+            .bind(serde_json::json!({ "cred": { "cred_id": "d29vZg" } }))
+            .execute(&db)
+            .await?;
+
+        let exclude_credentials = existing_credential_ids(&db, user_uuid).await?;
+        assert_eq!(exclude_credentials.len(), 1);
+
+        let params = AuthParams {
+            username: "woof".to_string(),
+            email: None,
+            display_name: None,
+            label: None,
+        };
+        let (ccr, _) =
+            create_registration_challenge(&state(), user_uuid, &params, exclude_credentials)
+                .unwrap();
+
+        let options = serde_json::to_value(&ccr).unwrap();
+        let excluded = options["publicKey"]["excludeCredentials"]
+            .as_array()
+            .unwrap();
+        assert_eq!(excluded.len(), 1);
+        assert_eq!(excluded[0]["id"], "d29vZg");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn logged_in_users_adding_a_passkey_exclude_their_own(db: PgPool) -> sqlx::Result<()> {
+        insert_user_with_passkey(&db, "woof", None).await?;
+        let user_id: i32 = sqlx::query_scalar("SELECT id FROM users WHERE username = 'woof'")
+            .fetch_one(&db)
+            .await?;
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(crate::auth::router(&config).unwrap(), db);
+        // Logged in users send their CSRF token along with their session cookie.
+        let start = |session: Option<(&str, &str)>, username: &str| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/api/users/start_register")
+                .header("content-type", "application/json");
+            if let Some((cookie, token)) = session {
+                request = request.header("cookie", cookie).header(CSRF_HEADER, token);
+            }
+            let body = serde_json::json!({ "username": username }).to_string();
+            request.body(Body::from(body)).unwrap()
+        };
+        let excluded = |response: Response| async move {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let options: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            options["publicKey"]["excludeCredentials"].clone()
+        };
+
+        // Without being logged in it's someone else trying to take the username.
+        let response = app.clone().oneshot(start(None, "woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let cookie = login_as(&app, user_id).await;
+        let token = csrf_token(&app, &cookie).await;
+        let session = Some((cookie.as_str(), token.as_str()));
+        let response = app.clone().oneshot(start(session, "woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let own = excluded(response).await;
+        assert_eq!(own.as_array().unwrap().len(), 1);
+        assert_eq!(own[0]["id"], PASSKEY_CREDENTIAL_ID);
+
+        // A new account doesn't have any passkeys to exclude yet.
+        let response = app.oneshot(start(session, "bark")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(excluded(response).await.is_null());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn registration_identifiers_must_be_unambiguous(db: PgPool) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO users (username, uuid, email) \
+             VALUES ('woof', gen_random_uuid(), 'woof@woof.example')",
+        )
+        .execute(&db)
+        .await?;
+
+        let params = |username: &str, email: Option<&str>| AuthParams {
+            username: username.to_string(),
+            email: email.map(str::to_string),
+            display_name: None,
+            label: None,
+        };
+
+        let err = check_identifiers(&db, &params("bark@woof.example", None)).await;
+        assert!(matches!(err, Err(PasskeyRegisterError::InvalidUsername)));
+
+        let err = check_identifiers(&db, &params("bark", Some("bark"))).await;
+        assert!(matches!(err, Err(PasskeyRegisterError::InvalidEmail)));
+
+        let err = check_identifiers(&db, &params("bark", Some("WOOF@woof.example"))).await;
+        assert!(matches!(err, Err(PasskeyRegisterError::EmailAlreadyExists)));
+
+        assert!(
+            check_identifiers(&db, &params("bark", Some("bark@woof.example")))
+                .await
+                .is_ok()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn register_errors_have_codes() {
+        assert_eq!(
+            PasskeyRegisterError::MissingSession.code(),
+            "missing_session"
+        );
+        assert_eq!(
+            PasskeyRegisterError::UserAlreadyExists.code(),
+            "user_already_exists"
+        );
+        assert_eq!(
+            PasskeyRegisterError::MissingSessionInfo.code(),
+            "missing_session_info"
+        );
+    }
+
+    #[sqlx::test]
+    async fn finishing_without_a_session_is_told_apart_from_not_starting(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(crate::auth::router(&config).unwrap(), db);
+        let credential = serde_json::json!({
+            "id": "d29vZg",
+            "rawId": "d29vZg",
+            "type": "public-key",
+            "extensions": {},
+            "response": {
+                "attestationObject": "d29vZg",
+                "clientDataJSON": "d29vZg",
+            },
+        });
+        let finish =
+            |cookie: Option<&str>| post_json("/api/users/finish_register", cookie, &credential);
+
+        // Without the cookie the browser didn't keep the session, so there's nothing to finish.
+        let response = app.clone().oneshot(finish(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(error_code(response).await, "missing_session");
+
+        // The first attempt uses up the registration state, so the session is still there when
+        // it's retried but has nothing left to finish.
+        let cookie = challenge_session(&app).await;
+        app.clone().oneshot(finish(Some(&cookie))).await.unwrap();
+        let response = app.oneshot(finish(Some(&cookie))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_code(response).await, "missing_session_info");
+    }
+
+    #[test]
+    fn verify_failures_are_client_errors() {
+        let status = |err: PasskeyRegisterError| err.into_response().status();
+
+        assert_eq!(
+            status(PasskeyRegisterError::RegistrationVerifyFailure(
+                WebauthnError::MismatchedChallenge
+            )),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(PasskeyRegisterError::ChallengeCreationFailure(
+                WebauthnError::Configuration
+            )),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            status(PasskeyRegisterError::DatabaseError(
+                sqlx::Error::PoolTimedOut
+            )),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn register_error_response_includes_code() {
+        let response = PasskeyRegisterError::UserAlreadyExists.into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "user_already_exists");
+        assert_eq!(body["message"], "A user with that name already exists");
+    }
+
+    #[tokio::test]
+    async fn server_errors_log_their_cause_without_returning_it() {
+        let cause = WebauthnError::Configuration.to_string();
+        let error = PasskeyRegisterError::ChallengeCreationFailure(WebauthnError::Configuration);
+        assert!(ErrorChain(&error).to_string().ends_with(&cause));
+
+        let response = error.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["message"],
+            "An error occurred while creating a new challenge"
+        );
+    }
+}
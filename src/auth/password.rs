@@ -0,0 +1,52 @@
+//! Hashing and verification of the passwords that protect individual pastes.
+
+use argon2::{
+    password_hash::{
+        self,
+        rand_core::OsRng,
+        PasswordHash,
+        PasswordHasher,
+        PasswordVerifier,
+        SaltString,
+    },
+    Argon2,
+};
+
+/// Hashes a password with Argon2 and a random salt, returning it in the PHC string format so the
+/// parameters used are stored right alongside the hash.
+pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+
+    Ok(hash.to_string())
+}
+
+/// Checks a password against a hash created by [hash_password].
+///
+/// A malformed hash never matches anything.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashed_password_verifies() {
+        let hash = hash_password("correct horse battery staple").unwrap();
+
+        assert!(!hash.contains("correct horse battery staple"));
+        assert!(verify_password("correct horse battery staple", &hash));
+        assert!(!verify_password("incorrect horse battery staple", &hash));
+    }
+
+    #[test]
+    fn malformed_hash_never_verifies() {
+        assert!(!verify_password("woof", "woof"));
+    }
+}
@@ -8,6 +8,8 @@ use sqlx::{
         Json,
     },
     FromRow,
+    PgConnection,
+    PgPool,
 };
 use uuid::Uuid;
 use webauthn_rs::prelude::Passkey;
@@ -21,8 +23,109 @@ pub struct Credential {
     pub user_uuid: Uuid,
     /// The raw JSON passkey credential from webauthn-rs.
     pub passkey: Json<Passkey>,
+    /// A label given by the user to tell their credentials apart (e.g. "Work laptop").
+    pub label: Option<String>,
+    /// The AAGUID of the authenticator that created the credential, if it identified itself.
+    pub aaguid: Option<Uuid>,
     /// When the credential was created.
     pub created_at: OffsetDateTime,
     /// When the credential was last updated.
     pub updated_at: OffsetDateTime,
+    /// When the credential was last used to log in, or `None` if it never has been.
+    pub last_used_at: Option<OffsetDateTime>,
+}
+
+/// Just the passkey of a credential, along with the ID it's stored under so it can be written back.
+#[derive(Debug, FromRow)]
+pub struct StoredPasskey {
+    /// The ID of the credential.
+    pub id: i32,
+    /// The raw JSON passkey credential from webauthn-rs.
+    pub passkey: Json<Passkey>,
+}
+
+/// A credential's details without the passkey itself, safe to hand back to its owner.
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct CredentialInfo {
+    /// The ID of the credential.
+    pub id: i32,
+    /// A label given by the user to tell their credentials apart (e.g. "Work laptop").
+    pub label: Option<String>,
+    /// The AAGUID of the authenticator that created the credential, if it identified itself.
+    pub aaguid: Option<Uuid>,
+    /// When the credential was created.
+    pub created_at: OffsetDateTime,
+    /// When the credential was last updated.
+    pub updated_at: OffsetDateTime,
+    /// When the credential was last used to log in, or `None` if it never has been.
+    pub last_used_at: Option<OffsetDateTime>,
+}
+
+/// Inserts a new credential for the user with the given UUID.
+pub async fn insert_credential(
+    conn: &mut PgConnection,
+    user_uuid: Uuid,
+    passkey: serde_json::Value,
+    label: Option<String>,
+    aaguid: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query_file!(
+        "sql/insert_credential.sql",
+        user_uuid,
+        passkey,
+        label,
+        aaguid
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Gets every credential belonging to the user with the given UUID, oldest first.
+pub async fn get_credentials_by_user_uuid(
+    db: &PgPool,
+    user_uuid: Uuid,
+) -> Result<Vec<CredentialInfo>, sqlx::Error> {
+    sqlx::query_file_as!(
+        CredentialInfo,
+        "sql/get_credentials_by_user_uuid.sql",
+        user_uuid
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Marks the credential with the given ID as having just been used to log in, as long as it belongs
+/// to the user with the given UUID.
+pub async fn update_credential_last_used(
+    db: &PgPool,
+    user_uuid: Uuid,
+    cred_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query_file!("sql/update_credential_last_used.sql", user_uuid, cred_id)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Changes the label of a credential, as long as it belongs to the user with the given UUID.
+///
+/// Returns `None` if the credential doesn't exist or belongs to someone else.
+pub async fn update_credential_label(
+    db: &PgPool,
+    id: i32,
+    user_uuid: Uuid,
+    label: Option<String>,
+) -> Result<Option<CredentialInfo>, sqlx::Error> {
+    sqlx::query_file_as!(
+        CredentialInfo,
+        "sql/update_credential_label.sql",
+        id,
+        user_uuid,
+        label
+    )
+    .fetch_optional(db)
+    .await
 }
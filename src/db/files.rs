@@ -0,0 +1,333 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sqlx::{
+    types::time::OffsetDateTime,
+    FromRow,
+    PgPool,
+};
+use uuid::Uuid;
+
+/// The ID of a file upload, used to identify it in TUS URLs and in upload storage.
+///
+/// This keeps upload IDs from being mixed up with any other UUID. It's stored in the database as a
+/// plain UUID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct UploadId(Uuid);
+
+impl UploadId {
+    /// Generates a new, random upload ID.
+    pub fn generate() -> Self {
+        UploadId(Uuid::new_v4())
+    }
+
+    /// Returns the ID as the UUID it's stored as.
+    pub fn get(self) -> Uuid {
+        self.0
+    }
+}
+
+impl From<Uuid> for UploadId {
+    fn from(id: Uuid) -> Self {
+        UploadId(id)
+    }
+}
+
+impl Display for UploadId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for UploadId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(UploadId)
+    }
+}
+
+/// A file uploaded via TUS to be retrieved and stored in the database.
+///
+/// Files are created as soon as an upload starts and track how much of the file has been received
+/// so far, so the upload can be resumed if it's interrupted.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct File {
+    /// The ID of the file.
+    pub id: i32,
+    /// The UUID of the file, used to identify the upload in TUS URLs.
+    pub uuid: UploadId,
+    /// The ID of the user that uploaded the file, if any.
+    pub user_id: Option<i32>,
+    /// The name of the file given by the client, if any.
+    pub filename: Option<String>,
+    /// The MIME type of the file given by the client, if any.
+    pub content_type: Option<String>,
+    /// The total size of the file in bytes, or `None` if the client hasn't said yet.
+    pub size: Option<i64>,
+    /// How many bytes of the file have been uploaded so far.
+    pub offset: i64,
+    /// Whether every byte of the file has been uploaded.
+    pub completed: bool,
+    /// When the upload was created.
+    pub created_at: OffsetDateTime,
+    /// When the upload expires if it hasn't been finished by then.
+    pub expires_at: Option<OffsetDateTime>,
+    /// Whether the upload is a partial upload, to be concatenated into a final upload.
+    pub is_partial: bool,
+    /// If this is a final upload, the UUIDs of the partial uploads it's made of in order.
+    pub partials: Option<Vec<UploadId>>,
+}
+
+/// The details needed to start a new file upload.
+#[derive(Debug, Clone)]
+pub struct NewFile {
+    pub uuid: UploadId,
+    pub user_id: Option<i32>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub size: Option<i64>,
+    pub expires_at: Option<OffsetDateTime>,
+    pub is_partial: bool,
+    pub partials: Option<Vec<UploadId>>,
+}
+
+/// Inserts a new, empty file upload into the database.
+pub async fn insert_file(db: &PgPool, file: NewFile) -> Result<File, sqlx::Error> {
+    let partials = file
+        .partials
+        .map(|partials| partials.into_iter().map(UploadId::get).collect::<Vec<_>>());
+
+    sqlx::query_file_as!(
+        File,
+        "sql/insert_file.sql",
+        file.uuid.get(),
+        file.user_id,
+        file.filename,
+        file.content_type,
+        file.size,
+        file.expires_at,
+        file.is_partial,
+        partials.as_deref()
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Gets a file by its UUID, if it exists.
+pub async fn get_file_by_uuid(db: &PgPool, uuid: UploadId) -> Result<Option<File>, sqlx::Error> {
+    sqlx::query_file_as!(File, "sql/get_file_by_uuid.sql", uuid.get())
+        .fetch_optional(db)
+        .await
+}
+
+/// Gets the unfinished final uploads that the partial upload with the given UUID is part of.
+pub async fn get_unfinished_final_files(
+    db: &PgPool,
+    partial: UploadId,
+) -> Result<Vec<File>, sqlx::Error> {
+    sqlx::query_file_as!(File, "sql/get_unfinished_final_files.sql", partial.get())
+        .fetch_all(db)
+        .await
+}
+
+/// Moves the offset of a file on from `expected` to `offset` as more of it is uploaded, marking it
+/// as completed once the offset reaches its size. Files with a deferred size are never completed.
+///
+/// If `size` is given, the file's size is set in the same update, which only goes through if the
+/// file doesn't have one yet. The offset and size are never stored without each other.
+///
+/// Returns `None` if the file doesn't exist, its offset isn't `expected` anymore or its size was
+/// already set, which happens when another request got there first.
+pub async fn update_file_offset(
+    db: &PgPool,
+    uuid: UploadId,
+    expected: i64,
+    offset: i64,
+    size: Option<i64>,
+) -> Result<Option<File>, sqlx::Error> {
+    sqlx::query_file_as!(
+        File,
+        "sql/update_file_offset.sql",
+        uuid.get(),
+        offset,
+        expected,
+        size
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Deletes a file, returning whether it existed.
+pub async fn delete_file(db: &PgPool, uuid: UploadId) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query_file!("sql/delete_file.sql", uuid.get())
+        .execute(db)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Deletes every unfinished upload that has expired, returning the UUIDs of those removed so their
+/// contents can be cleaned up too.
+pub async fn delete_expired_files(db: &PgPool) -> Result<Vec<UploadId>, sqlx::Error> {
+    let uuids = sqlx::query_file_scalar!("sql/delete_expired_files.sql")
+        .fetch_all(db)
+        .await?;
+
+    Ok(uuids.into_iter().map(UploadId::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_id_display_and_parse_round_trip() {
+        let id = UploadId::generate();
+        assert_eq!(id.to_string().parse::<UploadId>().unwrap(), id);
+        assert_eq!(id.to_string(), id.get().to_string());
+    }
+
+    #[test]
+    fn upload_id_parse_rejects_non_uuids() {
+        assert!("".parse::<UploadId>().is_err());
+        assert!("42".parse::<UploadId>().is_err());
+        assert!("this-is-a-slug".parse::<UploadId>().is_err());
+    }
+
+    #[test]
+    fn upload_id_serializes_as_a_plain_uuid() {
+        let id = UploadId::generate();
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, format!("\"{id}\""));
+        assert_eq!(serde_json::from_str::<UploadId>(&json).unwrap(), id);
+    }
+
+    #[sqlx::test]
+    async fn upload_id_decodes_from_the_database(db: PgPool) -> sqlx::Result<()> {
+        let id = UploadId::generate();
+        let decoded: UploadId = sqlx::query_scalar("SELECT $1")
+            .bind(id.get())
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(decoded, id);
+
+        Ok(())
+    }
+
+    fn new_file(size: Option<i64>) -> NewFile {
+        NewFile {
+            uuid: UploadId::generate(),
+            user_id: None,
+            filename: Some("woof.txt".to_string()),
+            content_type: Some("text/plain".to_string()),
+            size,
+            expires_at: None,
+            is_partial: false,
+            partials: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn insert_file_starts_empty(db: PgPool) -> sqlx::Result<()> {
+        let new = new_file(Some(1024));
+        let file = insert_file(&db, new.clone()).await?;
+
+        assert_eq!(file.uuid, new.uuid);
+        assert_eq!(file.filename.as_deref(), Some("woof.txt"));
+        assert_eq!(file.size, Some(1024));
+        assert_eq!(file.offset, 0);
+        assert!(!file.completed);
+
+        let fetched = get_file_by_uuid(&db, new.uuid).await?.unwrap();
+        assert_eq!(fetched.id, file.id);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn update_file_offset_completes_file_at_its_size(db: PgPool) -> sqlx::Result<()> {
+        let file = insert_file(&db, new_file(Some(1024))).await?;
+
+        let partial = update_file_offset(&db, file.uuid, 0, 512, None)
+            .await?
+            .unwrap();
+        assert_eq!(partial.offset, 512);
+        assert!(!partial.completed);
+
+        let finished = update_file_offset(&db, file.uuid, 512, 1024, None)
+            .await?
+            .unwrap();
+        assert_eq!(finished.offset, 1024);
+        assert!(finished.completed);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn deferred_file_completes_once_its_length_is_set(db: PgPool) -> sqlx::Result<()> {
+        let file = insert_file(&db, new_file(None)).await?;
+        assert_eq!(file.size, None);
+
+        let unfinished = update_file_offset(&db, file.uuid, 0, 512, None)
+            .await?
+            .unwrap();
+        assert!(!unfinished.completed);
+
+        let sized = update_file_offset(&db, file.uuid, 512, 1024, Some(2048))
+            .await?
+            .unwrap();
+        assert_eq!(sized.size, Some(2048));
+        assert!(!sized.completed);
+
+        // The length can only be set once, and the offset doesn't move without it.
+        assert!(update_file_offset(&db, file.uuid, 1024, 1536, Some(4096))
+            .await?
+            .is_none());
+        let stored = get_file_by_uuid(&db, file.uuid).await?.unwrap();
+        assert_eq!((stored.offset, stored.size), (1024, Some(2048)));
+
+        let finished = update_file_offset(&db, file.uuid, 1024, 2048, None)
+            .await?
+            .unwrap();
+        assert!(finished.completed);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn update_file_offset_returns_none_for_unknown_file(db: PgPool) -> sqlx::Result<()> {
+        assert!(update_file_offset(&db, UploadId::generate(), 0, 512, None)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn update_file_offset_only_moves_on_from_the_expected_offset(
+        db: PgPool,
+    ) -> sqlx::Result<()> {
+        let file = insert_file(&db, new_file(Some(1024))).await?;
+        update_file_offset(&db, file.uuid, 0, 512, None)
+            .await?
+            .unwrap();
+
+        // A request that started from the old offset lost the race and can't overwrite it.
+        assert!(update_file_offset(&db, file.uuid, 0, 256, None)
+            .await?
+            .is_none());
+        let stored = get_file_by_uuid(&db, file.uuid).await?.unwrap();
+        assert_eq!(stored.offset, 512);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,96 @@
 pub mod credentials;
+pub mod files;
 pub mod pastes;
+pub mod reserved_slugs;
 pub mod slugs;
 pub mod users;
+
+use std::time::Duration;
+
+use anyhow::Context;
+use log::info;
+use sqlx::{
+    postgres::PgPoolOptions,
+    PgPool,
+};
+
+use crate::config::Config;
+
+/// Builds the options for the database connection pool out of the [Config].
+pub fn pool_options(config: &Config) -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .acquire_timeout(Duration::from_secs(config.db_acquire_timeout_secs))
+        .idle_timeout(Duration::from_secs(config.db_idle_timeout_secs))
+}
+
+/// Applies any un-applied migrations from the `migrations/` directory to the database.
+///
+/// The migrations are embedded in the application binary so we can ensure the database is migrated
+/// correctly on startup, even when deployed without the source tree.
+pub async fn migrate(db: &PgPool) -> anyhow::Result<()> {
+    info!("Applying database migrations...");
+    sqlx::migrate!()
+        .run(db)
+        .await
+        .context("could not apply database migrations")?;
+    info!("All un-applied migrations have been successfully executed!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn pool_options_apply_configured_limits() {
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--db-max-connections",
+            "3",
+            "--db-idle-timeout-secs",
+            "60",
+        ]);
+        let db = pool_options(&config)
+            .connect_lazy(&config.database_url)
+            .unwrap();
+
+        assert_eq!(db.options().get_max_connections(), 3);
+        assert_eq!(
+            db.options().get_idle_timeout(),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn migrations_apply_cleanly_to_fresh_database(db: PgPool) -> anyhow::Result<()> {
+        migrate(&db).await?;
+
+        // language=postgresql
+        let query = "SELECT table_name::text FROM information_schema.tables \
+                     WHERE table_schema = current_schema() AND table_name != '_sqlx_migrations' \
+                     ORDER BY table_name";
+        let tables: Vec<String> = sqlx::query_scalar(query).fetch_all(&db).await?;
+        assert_eq!(
+            tables,
+            vec![
+                "credentials",
+                "files",
+                "paste_idempotency_keys",
+                "pastes",
+                "slugs",
+                "users",
+            ]
+        );
+
+        // Running them again should be a no-op.
+        migrate(&db).await?;
+
+        Ok(())
+    }
+}
@@ -1,3 +1,9 @@
+use std::{
+    fmt::Display,
+    num::ParseIntError,
+    str::FromStr,
+};
+
 use serde::{
     Deserialize,
     Serialize,
@@ -5,15 +11,286 @@ use serde::{
 use sqlx::{
     types::time::OffsetDateTime,
     FromRow,
+    PgConnection,
+    PgPool,
 };
+use utoipa::ToSchema;
+
+use crate::db::slugs::SlugString;
+
+/// The ID of a paste.
+///
+/// This keeps paste IDs from being mixed up with the IDs of anything else, like the slugs pointing
+/// to them. It's stored in the database as a plain integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct PasteId(i32);
+
+impl PasteId {
+    /// Returns the ID as the integer it's stored as.
+    pub fn get(self) -> i32 {
+        self.0
+    }
+}
+
+impl From<i32> for PasteId {
+    fn from(id: i32) -> Self {
+        PasteId(id)
+    }
+}
+
+impl Display for PasteId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for PasteId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(PasteId)
+    }
+}
 
 /// A text paste to be retrieved and stored in the database.
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Paste {
-    pub id: i32,
+    pub id: PasteId,
     pub user_id: Option<i32>,
     pub title: Option<String>,
     pub content: String,
     pub created_at: OffsetDateTime,
     pub expires_at: Option<OffsetDateTime>,
+    pub language: Option<String>,
+    pub views: i64,
+    /// An Argon2 hash of the password needed to view the paste, if it's protected by one.
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    /// How the paste should be rendered, one of the [RenderMode]s.
+    pub render: Option<String>,
+}
+
+/// A paste along with the username of whoever created it.
+#[derive(Debug, Clone)]
+pub struct AuthoredPaste {
+    pub paste: Paste,
+    /// The username of the paste's author, or `None` if it was created anonymously or its author
+    /// has since been deleted.
+    pub author: Option<String>,
+}
+
+/// How a paste's content is presented on its page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderMode {
+    /// Shown exactly as it was written, without any highlighting.
+    Plain,
+    /// Syntax highlighted according to the paste's language.
+    Code,
+    /// Rendered from Markdown into HTML.
+    Markdown,
+}
+
+impl RenderMode {
+    /// The name the render mode is stored in the database as.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RenderMode::Plain => "plain",
+            RenderMode::Code => "code",
+            RenderMode::Markdown => "markdown",
+        }
+    }
+}
+
+impl FromStr for RenderMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(RenderMode::Plain),
+            "code" => Ok(RenderMode::Code),
+            "markdown" => Ok(RenderMode::Markdown),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A public paste listed among the most recent ones, without its content.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentPaste {
+    pub id: PasteId,
+    pub title: Option<String>,
+    pub language: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub expires_at: Option<OffsetDateTime>,
+    pub views: i64,
+    pub render: Option<String>,
+    /// The slug the paste can be viewed at.
+    pub slug: SlugString,
+}
+
+/// Fetches a single page of the most recent public pastes, newest first.
+///
+/// Only pastes that can be viewed by anyone are included, so ones that are protected by a password,
+/// have expired or whose slugs were all disabled are left out. Each paste is listed once, along
+/// with the first of its slugs that's enabled.
+///
+/// Passing the `created_at` and `id` of the last paste in a page as `before` and `before_id`
+/// fetches the next one.
+/// Pastes are ordered by their ID after their creation time, so ones created at the same time are
+/// neither skipped nor repeated between pages.
+pub async fn get_recent_pastes(
+    db: &PgPool,
+    before: Option<OffsetDateTime>,
+    before_id: Option<PasteId>,
+    page_size: i64,
+) -> Result<Vec<RecentPaste>, sqlx::Error> {
+    sqlx::query_file_as!(
+        RecentPaste,
+        "sql/get_recent_pastes.sql",
+        before,
+        before_id.map(PasteId::get),
+        page_size
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// A paste listed in a user's data export, with just enough to tell which paste it is.
+#[derive(Debug, Clone, Serialize)]
+pub struct PasteSummary {
+    /// The first slug the paste was given, if it still has one. Pastes with several slugs are
+    /// still only listed once.
+    pub slug: Option<SlugString>,
+    pub title: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+/// Fetches a summary of every paste belonging to the user with the given ID, expired or not,
+/// newest first.
+pub async fn get_paste_summaries_by_user_id(
+    db: &PgPool,
+    user_id: i32,
+) -> Result<Vec<PasteSummary>, sqlx::Error> {
+    sqlx::query_file_as!(
+        PasteSummary,
+        "sql/get_paste_summaries_by_user_id.sql",
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Fetches the paste with the given ID along with the username of its author.
+pub async fn get_paste_by_id(
+    db: &PgPool,
+    id: PasteId,
+) -> Result<Option<AuthoredPaste>, sqlx::Error> {
+    let row = sqlx::query_file!("sql/get_paste_by_id.sql", id.get())
+        .fetch_optional(db)
+        .await?;
+
+    Ok(row.map(|row| AuthoredPaste {
+        paste: Paste {
+            id: row.id.into(),
+            user_id: row.user_id,
+            title: row.title,
+            content: row.content,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            language: row.language,
+            views: row.views,
+            password_hash: row.password_hash,
+            render: row.render,
+        },
+        author: row.author,
+    }))
+}
+
+/// Counts a view of the paste with the given ID, returning the new view count.
+pub async fn increment_paste_views(db: &PgPool, id: PasteId) -> Result<i64, sqlx::Error> {
+    sqlx::query_file_scalar!("sql/increment_paste_views.sql", id.get())
+        .fetch_one(db)
+        .await
+}
+
+/// Remembers that the paste with the given ID was created with an idempotency key.
+///
+/// Keys are only remembered for so long, anything used before `fresh_since` is taken over by the
+/// new paste. Returns `false` if the key was used since then, in which case nothing is changed.
+pub async fn claim_idempotency_key(
+    conn: &mut PgConnection,
+    scope: &str,
+    key: &str,
+    paste_id: PasteId,
+    fresh_since: OffsetDateTime,
+) -> Result<bool, sqlx::Error> {
+    let claimed = sqlx::query_file_scalar!(
+        "sql/insert_paste_idempotency_key.sql",
+        scope,
+        key,
+        paste_id.get(),
+        fresh_since
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(claimed.is_some())
+}
+
+/// Gets the ID of the paste that was created with an idempotency key, as long as the key was used
+/// since `fresh_since`.
+pub async fn get_paste_id_by_idempotency_key(
+    db: &PgPool,
+    scope: &str,
+    key: &str,
+    fresh_since: OffsetDateTime,
+) -> Result<Option<PasteId>, sqlx::Error> {
+    let paste_id = sqlx::query_file_scalar!(
+        "sql/get_paste_id_by_idempotency_key.sql",
+        scope,
+        key,
+        fresh_since
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(paste_id.map(PasteId::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paste_id_display_and_parse_round_trip() {
+        let id = PasteId::from(42);
+        assert_eq!(id.to_string(), "42");
+        assert_eq!("42".parse::<PasteId>().unwrap(), id);
+    }
+
+    #[test]
+    fn paste_id_parse_rejects_non_integers() {
+        assert!("".parse::<PasteId>().is_err());
+        assert!("woof".parse::<PasteId>().is_err());
+        assert!("this-is-a-slug".parse::<PasteId>().is_err());
+    }
+
+    #[test]
+    fn paste_id_serializes_as_a_plain_integer() {
+        let id = PasteId::from(42);
+        assert_eq!(serde_json::to_string(&id).unwrap(), "42");
+        assert_eq!(serde_json::from_str::<PasteId>("42").unwrap(), id);
+    }
+
+    #[sqlx::test]
+    async fn paste_id_decodes_from_the_database(db: PgPool) -> sqlx::Result<()> {
+        let id: PasteId = sqlx::query_scalar("SELECT 42").fetch_one(&db).await?;
+        assert_eq!(id, PasteId::from(42));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,21 @@
+//! The words that can't be used in slugs.
+//!
+//! Slugs are only served under `/paste` for now, but a slug that's named after one of woof's own
+//! routes would shadow it if slugs were ever served from the root.
+
+/// The words every deployment reserves, which [crate::config::Config::reserved_slug_words] can add
+/// to.
+pub const RESERVED_SLUGS: &[&str] = &[
+    "api", "auth", "static", "paste", "logout", "me", "files", "healthz",
+];
+
+/// Whether the given word is reserved, either by [RESERVED_SLUGS] or one of the extra words.
+///
+/// Words are compared ignoring case, since slugs can be written in any case.
+pub fn is_reserved_word(word: &str, extra_words: &[String]) -> bool {
+    RESERVED_SLUGS
+        .iter()
+        .copied()
+        .chain(extra_words.iter().map(String::as_str))
+        .any(|reserved| reserved.eq_ignore_ascii_case(word))
+}
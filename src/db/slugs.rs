@@ -1,4 +1,7 @@
-use cool_id_generator::get_id;
+use cool_id_generator::{
+    get_id,
+    Size,
+};
 use serde::{
     Deserialize,
     Serialize,
@@ -11,10 +14,17 @@ use sqlx::{
     types::time::OffsetDateTime,
     Decode,
     FromRow,
+    PgPool,
     Postgres,
     Row,
 };
 use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::db::{
+    pastes::PasteId,
+    reserved_slugs::is_reserved_word,
+};
 
 /// A slug string, consisting of 4 words separated by dashes. (e.g. `this-is-a-slug`)
 /// This is used to identify a resource like a paste or a file.
@@ -24,39 +34,131 @@ use thiserror::Error;
 ///
 /// This is meant to be used with [`get_id`] using [`cool_id_generator::Size::Medium`], which
 /// generates a random slug with 1 billion possible combinations.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SlugString(String);
 
-#[derive(Error, Debug)]
+/// The ways a string can fail to be a valid slug.
+#[derive(Error, Debug, PartialEq, Eq)]
 pub enum SlugError {
-    #[error("Invalid slug format, expected 4 words separated by dashes, got: {0}")]
-    InvalidFormat(String),
+    /// The slug isn't made up of 4 words, holds how many words it has.
+    #[error("Invalid slug format, expected 4 words separated by dashes, got {0}")]
+    WrongWordCount(usize),
+
+    /// One of the words in the slug is empty, holds its position counting from 1.
+    #[error("Invalid slug format, word {0} is empty")]
+    EmptyWord(usize),
+
+    /// The slug contains something other than ASCII letters, digits and dashes.
+    #[error("Invalid slug format, `{0}` isn't allowed, only letters, digits and dashes are")]
+    InvalidCharacter(char),
 }
 
 impl SlugString {
     /// Creates a new slug from the given string.
     pub fn new(input: &str) -> Result<SlugString, SlugError> {
-        if SlugString::is_valid(input) {
-            Ok(SlugString(input.to_string()))
-        } else {
-            Err(SlugError::InvalidFormat(input.to_string()))
+        SlugString::validate(input)?;
+        Ok(SlugString(input.to_string()))
+    }
+
+    /// Generates a new random slug using [`get_id`] with [`Size::Medium`].
+    ///
+    /// Slugs that turn out to be reserved (see [SlugString::is_reserved]) are thrown away and
+    /// generated again.
+    pub fn generate(extra_words: &[String]) -> SlugString {
+        SlugString::generate_with(|| get_id(Size::Medium), extra_words)
+    }
+
+    /// Generates slugs with `next` until one of them isn't reserved.
+    fn generate_with(mut next: impl FnMut() -> String, extra_words: &[String]) -> SlugString {
+        loop {
+            let slug = SlugString(next());
+            debug_assert!(
+                SlugString::is_valid(slug.as_str()),
+                "generated slug `{}` is not valid",
+                slug.as_str()
+            );
+
+            if !slug.is_reserved(extra_words) {
+                return slug;
+            }
         }
     }
 
     /// Checks if the given string is a valid slug.
     /// A valid slug is 4 words separated by dashes (e.g. `this-is-a-slug`).
     pub fn is_valid(input: &str) -> bool {
-        let parts: Vec<&str> = input.split('-').collect();
-        parts.len() == 4 && parts.iter().all(|&part| !part.is_empty())
+        SlugString::validate(input).is_ok()
+    }
+
+    /// Checks if the given string has the shape of a slug, 4 words separated by dashes, whatever
+    /// the words are made of.
+    ///
+    /// Slugs made before words had to be ASCII still exist, so this is what should be checked
+    /// before looking a slug up rather than [SlugString::is_valid].
+    pub fn is_well_formed(input: &str) -> bool {
+        SlugString::validate_words(input).is_ok()
+    }
+
+    /// Checks if the given string is a valid slug, like [SlugString::is_valid], but says what's
+    /// wrong with it if it isn't.
+    ///
+    /// Words are made of ASCII letters and digits, so slugs can go in a URL as they are.
+    pub fn validate(input: &str) -> Result<(), SlugError> {
+        SlugString::validate_words(input)?;
+
+        let invalid = input
+            .chars()
+            .find(|&c| c != '-' && !c.is_ascii_alphanumeric());
+        match invalid {
+            Some(invalid) => Err(SlugError::InvalidCharacter(invalid)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that the given string is 4 words separated by dashes, like
+    /// [SlugString::is_well_formed], but says what's wrong with it if it isn't.
+    fn validate_words(input: &str) -> Result<(), SlugError> {
+        let mut words = 0;
+
+        for (position, word) in input.split('-').enumerate() {
+            if word.is_empty() {
+                return Err(SlugError::EmptyWord(position + 1));
+            }
+            words += 1;
+        }
+
+        match words {
+            4 => Ok(()),
+            words => Err(SlugError::WrongWordCount(words)),
+        }
+    }
+
+    /// Whether any of the slug's words are reserved, either by
+    /// [crate::db::reserved_slugs::RESERVED_SLUGS] or one of the extra words.
+    pub fn is_reserved(&self, extra_words: &[String]) -> bool {
+        self.reserved_word(extra_words).is_some()
+    }
+
+    /// The first of the slug's words that's reserved, like [SlugString::is_reserved] but says which
+    /// word it was.
+    pub fn reserved_word(&self, extra_words: &[String]) -> Option<&str> {
+        self.0
+            .split('-')
+            .find(|word| is_reserved_word(word, extra_words))
+    }
+
+    /// Returns the slug as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
     }
 }
 
 impl Decode<'_, Postgres> for SlugString {
     fn decode(value: PgValueRef<'_>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         let s = <String as Decode<Postgres>>::decode(value)?;
-        SlugString::new(&s).map_err(|_| {
-            Box::new(SlugError::InvalidFormat(s)) as Box<dyn std::error::Error + Send + Sync>
-        })
+        // Only the shape is checked, slugs made before words had to be ASCII are still around.
+        SlugString::validate_words(&s)?;
+        Ok(SlugString(s))
     }
 }
 
@@ -77,12 +179,22 @@ impl From<String> for SlugString {
 pub struct Slug {
     pub id: i32,
     pub file_id: Option<i32>,
-    pub paste_id: Option<i32>,
+    pub paste_id: Option<PasteId>,
     pub slug: SlugString,
     pub enabled: Option<OffsetDateTime>,
     pub created_at: OffsetDateTime,
 }
 
+/// Gets the slug the paste with the given ID was created with.
+pub async fn get_slug_by_paste_id(
+    db: &PgPool,
+    paste_id: PasteId,
+) -> Result<Option<Slug>, sqlx::Error> {
+    sqlx::query_file_as!(Slug, "sql/get_slug_by_paste_id.sql", paste_id.get())
+        .fetch_optional(db)
+        .await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -116,4 +228,115 @@ mod tests {
     fn slug_string_is_valid_returns_false_for_slug_with_more_than_four_words() {
         assert!(!SlugString::is_valid("this-is-a-very-long-slug"));
     }
+
+    #[test]
+    fn slug_string_new_reports_wrong_word_count() {
+        assert_eq!(
+            SlugString::new("thisisaslug").unwrap_err(),
+            SlugError::WrongWordCount(1)
+        );
+        assert_eq!(
+            SlugString::new("this-is-a-very-long-slug").unwrap_err(),
+            SlugError::WrongWordCount(6)
+        );
+    }
+
+    #[test]
+    fn slug_string_new_reports_empty_word() {
+        assert_eq!(
+            SlugString::new("this--is-a-slug").unwrap_err(),
+            SlugError::EmptyWord(2)
+        );
+        assert_eq!(
+            SlugString::new("this-is-a-").unwrap_err(),
+            SlugError::EmptyWord(4)
+        );
+        assert_eq!(SlugString::new("").unwrap_err(), SlugError::EmptyWord(1));
+    }
+
+    #[test]
+    fn slug_string_new_reports_invalid_character() {
+        assert_eq!(
+            SlugString::new("this-is-a-sl/ug").unwrap_err(),
+            SlugError::InvalidCharacter('/')
+        );
+        assert_eq!(
+            SlugString::new("this-is-a-slüg").unwrap_err(),
+            SlugError::InvalidCharacter('ü')
+        );
+        assert_eq!(
+            SlugString::new("this is-a-slug").unwrap_err(),
+            SlugError::InvalidCharacter(' ')
+        );
+    }
+
+    #[test]
+    fn slug_string_new_accepts_letters_and_digits() {
+        assert!(SlugString::new("this-is-slug-2").is_ok());
+        assert!(SlugString::new("This-Is-A-Slug").is_ok());
+    }
+
+    #[test]
+    fn slug_string_is_well_formed_ignores_what_words_are_made_of() {
+        assert!(SlugString::is_well_formed("this-is-a-slüg"));
+        assert!(!SlugString::is_valid("this-is-a-slüg"));
+        assert!(!SlugString::is_well_formed("this--is-a-slug"));
+        assert!(!SlugString::is_well_formed("thisisaslug"));
+    }
+
+    #[sqlx::test]
+    async fn slug_string_decodes_slugs_made_before_the_ascii_rule(db: PgPool) -> sqlx::Result<()> {
+        let slug: SlugString = sqlx::query_scalar("SELECT 'this-is-a-slüg'")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(slug.as_str(), "this-is-a-slüg");
+
+        let malformed = sqlx::query_scalar::<_, SlugString>("SELECT 'thisisaslug'")
+            .fetch_one(&db)
+            .await;
+        assert!(malformed.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn slug_string_generate_returns_valid_slug() {
+        for _ in 0..100 {
+            let slug = SlugString::generate(&[]);
+            assert!(SlugString::is_valid(slug.as_str()));
+        }
+    }
+
+    #[test]
+    fn slug_string_is_reserved_checks_every_word() {
+        let extra_words = vec!["admin".to_string()];
+        assert!(SlugString::new("my-cool-API-paste")
+            .unwrap()
+            .is_reserved(&[]));
+        assert!(SlugString::new("my-healthz-cool-paste")
+            .unwrap()
+            .is_reserved(&[]));
+        assert!(SlugString::new("my-cool-admin-paste")
+            .unwrap()
+            .is_reserved(&extra_words));
+
+        let slug = SlugString::new("my-very-cool-paste").unwrap();
+        assert!(!slug.is_reserved(&extra_words));
+        assert_eq!(slug.reserved_word(&extra_words), None);
+    }
+
+    #[test]
+    fn slug_string_generate_skips_reserved_slugs() {
+        let extra_words = vec!["admin".to_string()];
+        let mut candidates = [
+            "static-cool-paste-one",
+            "admin-cool-paste-two",
+            "cool-paste-three-four",
+        ]
+        .into_iter()
+        .map(str::to_string);
+
+        let slug = SlugString::generate_with(|| candidates.next().unwrap(), &extra_words);
+        assert_eq!(slug.as_str(), "cool-paste-three-four");
+    }
 }
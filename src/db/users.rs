@@ -5,6 +5,7 @@ use serde::{
 use sqlx::{
     types::time::OffsetDateTime,
     FromRow,
+    PgPool,
 };
 use uuid::Uuid;
 
@@ -17,8 +18,98 @@ pub struct User {
     pub uuid: Uuid,
     /// The unique username of the user.
     pub username: String,
+    /// An email address the user can log in with instead of their username, if they gave one.
+    pub email: Option<String>,
     /// When the user was created.
     pub created_at: OffsetDateTime,
     /// When the user last authenticated, if ever.
     pub last_authentication: Option<OffsetDateTime>,
+    /// Bumped whenever every session of the user should be logged out.
+    pub session_version: i32,
+    /// Derived by the database from the username and session version, sessions are only valid
+    /// while it matches the one they were logged in with.
+    #[serde(skip)]
+    pub session_auth_hash: String,
+}
+
+/// Gets the user with the given username, ignoring case.
+pub async fn get_user_by_username(
+    db: &PgPool,
+    username: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_file_as!(User, "sql/get_user_by_username.sql", username)
+        .fetch_optional(db)
+        .await
+}
+
+/// Gets the user with the given email address, ignoring case.
+pub async fn get_user_by_email(db: &PgPool, email: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_file_as!(User, "sql/get_user_by_email.sql", email)
+        .fetch_optional(db)
+        .await
+}
+
+/// Gets the user that registered the passkey credential with the given (base64url encoded) ID.
+pub async fn get_user_by_credential_id(
+    db: &PgPool,
+    cred_id: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_file_as!(User, "sql/get_user_by_credential_id.sql", cred_id)
+        .fetch_optional(db)
+        .await
+}
+
+/// Gets a user by the identifier they log in with, which is either their username or their email.
+///
+/// Usernames can't contain an `@`, so anything with one in it is looked up as an email.
+pub async fn get_user_by_identifier(
+    db: &PgPool,
+    identifier: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    if is_email(identifier) {
+        get_user_by_email(db, identifier).await
+    } else {
+        get_user_by_username(db, identifier).await
+    }
+}
+
+/// Whether a login identifier is an email address rather than a username.
+pub fn is_email(identifier: &str) -> bool {
+    identifier.contains('@')
+}
+
+/// Bumps the session version of the user with the given ID, which logs them out of every session
+/// they're currently logged in with. Returns the updated user, or `None` if they don't exist.
+pub async fn bump_session_version(db: &PgPool, id: i32) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_file_as!(User, "sql/bump_session_version.sql", id)
+        .fetch_optional(db)
+        .await
+}
+
+/// Deletes a user along with their credentials in a single transaction.
+///
+/// The user's pastes are deleted too when `delete_pastes` is set, otherwise they're kept around as
+/// anonymous pastes.
+pub async fn delete_user(db: &PgPool, user: &User, delete_pastes: bool) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query_file!("sql/delete_credentials_by_user_uuid.sql", user.uuid)
+        .execute(&mut *tx)
+        .await?;
+
+    if delete_pastes {
+        sqlx::query_file!("sql/delete_pastes_by_user_id.sql", user.id)
+            .execute(&mut *tx)
+            .await?;
+    } else {
+        sqlx::query_file!("sql/orphan_pastes_by_user_id.sql", user.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    sqlx::query_file!("sql/delete_user.sql", user.id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
 }
@@ -0,0 +1,61 @@
+//! Server-side syntax highlighting for pastes.
+
+use std::sync::OnceLock;
+
+use syntect::{
+    html::{
+        ClassStyle,
+        ClassedHTMLGenerator,
+    },
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+/// The set of syntaxes bundled with syntect. Loading this is fairly expensive so it's only done
+/// once, the first time a paste is highlighted.
+static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+
+/// Highlights the given content as the given language, returning HTML where each token is wrapped
+/// in a `<span>` with CSS classes describing its scope (e.g. `<span class="keyword">`).
+///
+/// The language can either be a name (`rust`) or a file extension (`rs`). Any HTML in the content
+/// is escaped, so the output is safe to render as is.
+///
+/// Returns `None` if the language isn't known or the content could not be highlighted.
+pub fn highlight(content: &str, language: &str) -> Option<String> {
+    let syntaxes = SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines);
+    let syntax = syntaxes.find_syntax_by_token(language)?;
+
+    let mut generator =
+        ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes, ClassStyle::Spaced);
+    for line in LinesWithEndings::from(content) {
+        generator
+            .parse_html_for_line_which_includes_newline(line)
+            .ok()?;
+    }
+
+    Some(generator.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_wraps_known_language_in_spans() {
+        let html = highlight("fn main() {}\n", "rust").unwrap();
+        assert!(html.contains("<span class="));
+    }
+
+    #[test]
+    fn highlight_escapes_angle_brackets() {
+        let html = highlight("let x = 1 < 2;\nlet y = \"<script>\";\n", "rs").unwrap();
+        assert!(html.contains("&lt;"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn highlight_returns_none_for_unknown_language() {
+        assert!(highlight("woof", "not-a-real-language").is_none());
+    }
+}
@@ -0,0 +1,46 @@
+//! Server-side Markdown rendering for pastes.
+
+use pulldown_cmark::{
+    html,
+    Options,
+    Parser,
+};
+
+/// Renders the given Markdown content into HTML.
+///
+/// Paste content is untrusted, and Markdown happily passes raw HTML straight through, so the
+/// output is sanitized with ammonia to strip out scripts, event handlers, `javascript:` links and
+/// the like. The result is safe to render as is.
+pub fn render(content: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let parser = Parser::new_ext(content, options);
+
+    let mut unsanitized = String::new();
+    html::push_html(&mut unsanitized, parser);
+
+    ammonia::clean(&unsanitized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_produces_html() {
+        let html = render("# Woof\n\nSome *very* **good** dogs.\n");
+        assert_eq!(
+            html,
+            "<h1>Woof</h1>\n<p>Some <em>very</em> <strong>good</strong> dogs.</p>\n"
+        );
+    }
+
+    #[test]
+    fn render_strips_scripts() {
+        let html =
+            render("Hello\n\n<script>alert('woof')</script>\n\n[click](javascript:alert(1))");
+        assert!(html.contains("<p>Hello</p>"));
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("alert('woof')"));
+        assert!(!html.contains("javascript:"));
+    }
+}
@@ -1,23 +1,48 @@
+mod highlight;
+mod markdown;
 mod paste;
+mod profile;
 
 use axum::{
     body::Body,
+    extract::{
+        DefaultBodyLimit,
+        Query,
+    },
+    middleware,
     response::{
         IntoResponse,
+        Redirect,
         Response,
     },
-    routing::get,
+    routing::{
+        get,
+        post,
+    },
+    Extension,
     Router,
 };
 use http::StatusCode;
+use serde::Deserialize;
 use thiserror::Error;
 
 use crate::{
     auth::passkeys::backend::AuthSession,
+    config::Config,
+    http::{
+        rate_limit::{
+            rate_limit,
+            RateLimiter,
+        },
+        redirect::validate_redirect,
+        security::CspNonce,
+        ApiContext,
+    },
     templates::{
         AuthTemplate,
         ErrorTemplate,
         IndexTemplate,
+        PasskeysInfoTemplate,
     },
 };
 
@@ -26,9 +51,36 @@ pub async fn index(session: AuthSession) -> IndexTemplate {
     IndexTemplate { user: session.user }
 }
 
+/// Parameters the authentication page can be opened with.
+#[derive(Deserialize)]
+pub struct AuthPageParams {
+    /// Where the login form sends the user once they've logged in.
+    #[serde(default)]
+    redirect: Option<String>,
+}
+
 /// The authentication page, presents a login form to the user.
-pub async fn auth() -> AuthTemplate {
-    AuthTemplate
+///
+/// The login form sends the user to the `redirect` parameter once they've logged in, so a target
+/// that isn't allowed by [validate_redirect] is dropped before the form ever sees it.
+pub async fn auth(
+    ctx: Extension<ApiContext>,
+    csp_nonce: CspNonce,
+    Query(params): Query<AuthPageParams>,
+) -> Response {
+    let disallowed = params
+        .redirect
+        .is_some_and(|target| validate_redirect(&ctx.config, &target).is_err());
+    if disallowed {
+        return Redirect::to("/auth").into_response();
+    }
+
+    AuthTemplate { csp_nonce }.into_response()
+}
+
+/// A page explaining what passkeys are, linked to from the authentication page.
+pub async fn passkeys_info() -> PasskeysInfoTemplate {
+    PasskeysInfoTemplate
 }
 
 /// An error that can occur in a context where a HTML page is expected to be returned.
@@ -39,8 +91,14 @@ pub enum HtmlPageError {
     InvalidPath(String),
     #[error("This resource could not be found.")]
     NotFound,
+    #[error("This resource is no longer available.")]
+    Gone,
+    #[error("This resource is password protected.")]
+    PasswordRequired,
     #[error("An error occurred while querying the database.")]
     DatabaseError,
+    #[error("An error occurred while accessing your session.")]
+    SessionError,
 }
 
 impl HtmlPageError {
@@ -49,7 +107,22 @@ impl HtmlPageError {
         match self {
             HtmlPageError::InvalidPath(_) => StatusCode::BAD_REQUEST,
             HtmlPageError::NotFound => StatusCode::NOT_FOUND,
+            HtmlPageError::Gone => StatusCode::GONE,
+            HtmlPageError::PasswordRequired => StatusCode::UNAUTHORIZED,
             HtmlPageError::DatabaseError => StatusCode::INTERNAL_SERVER_ERROR,
+            HtmlPageError::SessionError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// A short, human-friendly summary of the error, shown as the heading of the error page.
+    pub fn title(&self) -> &'static str {
+        match self {
+            HtmlPageError::InvalidPath(_) => "Invalid link",
+            HtmlPageError::NotFound => "Not found",
+            HtmlPageError::Gone => "Gone",
+            HtmlPageError::PasswordRequired => "Password required",
+            HtmlPageError::DatabaseError => "Something went wrong",
+            HtmlPageError::SessionError => "Something went wrong",
         }
     }
 }
@@ -57,18 +130,120 @@ impl HtmlPageError {
 impl IntoResponse for HtmlPageError {
     /// Converts this error into an axum HTTP response.
     fn into_response(self) -> Response<Body> {
+        let status = self.to_status_code();
         let template = ErrorTemplate {
+            status: status.as_u16(),
+            title: self.title().to_string(),
             error: self.to_string(),
         };
 
-        (self.to_status_code(), template).into_response()
+        (status, template).into_response()
     }
 }
 
-pub fn router() -> Router {
+/// The pages of the frontend, with paste creation throttled by the same [RateLimiter] as the paste
+/// API.
+pub fn router(config: &Config, paste_rate_limiter: RateLimiter) -> Router {
+    // Creating a paste through the form is held to the same limits as the paste API. A form
+    // percent-encodes the content, which can make it up to three times as large.
+    let body_limit = config.max_paste_bytes.saturating_mul(3);
+    let create_paste = post(paste::create)
+        .layer(middleware::from_fn_with_state(
+            paste_rate_limiter,
+            rate_limit,
+        ))
+        .layer(DefaultBodyLimit::max(body_limit));
+    // Guessing the password of a paste is held to the same limit as logging in.
+    let unlock = post(paste::unlock).layer(middleware::from_fn_with_state(
+        RateLimiter::per_minute(config.auth_rate_limit),
+        rate_limit,
+    ));
+
     Router::new()
         .route("/", get(index))
         .route("/auth", get(auth))
-        .route("/paste", get(paste::creation))
+        .route("/passkeys-info", get(passkeys_info))
+        .route("/me", get(profile::page))
+        .route("/paste", get(paste::creation).merge(create_paste))
         .route("/paste/:slug", get(paste::page))
+        .route("/paste/:slug/created", get(paste::created))
+        .route("/paste/:slug/raw", get(paste::raw))
+        .route("/paste/:slug/unlock", unlock)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{
+        header,
+        Request,
+    };
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Renders the error into the page it's served as, returning its status and body.
+    async fn render(err: HtmlPageError) -> (StatusCode, String) {
+        let response = err.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn passkeys_info_page_renders() {
+        let response = passkeys_info().await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("<title>What is a passkey? - woof</title>"));
+        assert!(body.contains(r#"href="/auth""#));
+    }
+
+    #[sqlx::test]
+    async fn auth_page_drops_disallowed_redirects(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = router(&config, RateLimiter::per_minute(config.paste_rate_limit))
+            .layer(Extension(ApiContext::for_tests(db)));
+        let auth = |query: &str| {
+            Request::builder()
+                .uri(format!("/auth{query}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(auth("?redirect=%2Fme")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let query = "?redirect=https%3A%2F%2Fevil.example";
+        let response = app.oneshot(auth(query)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/auth");
+    }
+
+    #[tokio::test]
+    async fn not_found_renders_a_not_found_page() {
+        let (status, body) = render(HtmlPageError::NotFound).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("<title>Not found - woof</title>"));
+        assert!(body.contains("we couldn't find"));
+        assert!(!body.contains("on our end"));
+    }
+
+    #[tokio::test]
+    async fn database_error_renders_a_server_error_page() {
+        let (status, body) = render(HtmlPageError::DatabaseError).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.contains("<title>Something went wrong - woof</title>"));
+        assert!(body.contains("on our end"));
+        assert!(!body.contains("we couldn't find"));
+    }
 }
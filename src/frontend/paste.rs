@@ -1,62 +1,944 @@
 use axum::{
     extract::Path,
+    http::{
+        header,
+        HeaderMap,
+        StatusCode,
+    },
+    response::{
+        IntoResponse,
+        Redirect,
+        Response,
+    },
     Extension,
+    Form,
+};
+use axum_extra::extract::cookie::{
+    Cookie,
+    Key,
+    SameSite,
+    SignedCookieJar,
+};
+use log::{
+    error,
+    warn,
+};
+use serde::Deserialize;
+use sqlx::{
+    types::time::{
+        Duration,
+        OffsetDateTime,
+    },
+    PgPool,
 };
 
 use crate::{
+    auth::{
+        passkeys::backend::AuthSession,
+        password::verify_password,
+    },
     db::{
-        pastes::Paste,
+        pastes::{
+            get_paste_by_id,
+            increment_paste_views,
+            AuthoredPaste,
+            Paste,
+            PasteId,
+            RenderMode,
+        },
         slugs::{
             Slug,
             SlugString,
         },
     },
-    frontend::HtmlPageError,
-    http::ApiContext,
+    frontend::{
+        highlight,
+        markdown,
+        HtmlPageError,
+    },
+    http::{
+        csrf::{
+            session_token,
+            verify_token,
+            CsrfError,
+        },
+        error::ErrorChain,
+        pastes::{
+            insert_paste,
+            NewPasteParams,
+        },
+        security::CspNonce,
+        ApiContext,
+    },
     templates::{
         PasteCard,
+        PasteCreatedTemplate,
         PasteCreationTemplate,
         PasteTemplate,
+        PasteUnlockTemplate,
     },
 };
 
+/// The name of the signed cookie that unlocks a password protected paste, followed by its ID.
+const UNLOCK_COOKIE_PREFIX: &str = "woof_unlock_";
+
+/// What's shown when visiting a paste, depending on whether it's been unlocked.
+pub enum PastePage {
+    /// The paste itself.
+    Content(PasteTemplate),
+    /// A form asking for the password of a password protected paste.
+    Locked(PasteUnlockTemplate),
+}
+
+impl IntoResponse for PastePage {
+    fn into_response(self) -> Response {
+        match self {
+            PastePage::Content(template) => template.into_response(),
+            PastePage::Locked(template) => template.into_response(),
+        }
+    }
+}
+
+/// The form submitted to unlock a password protected paste.
+#[derive(Deserialize)]
+pub struct UnlockParams {
+    password: String,
+}
+
+/// The paste creation form, as it's submitted by a browser without JavaScript.
+///
+/// Fields that are left empty are sent as empty strings, which are treated as not being given.
+#[derive(Deserialize)]
+pub struct NewPasteForm {
+    content: String,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    slug: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    /// The session's CSRF token, which logged in users have to send as a field since a form can't
+    /// send headers.
+    #[serde(default)]
+    csrf_token: Option<String>,
+}
+
+impl From<NewPasteForm> for NewPasteParams {
+    fn from(form: NewPasteForm) -> Self {
+        let non_empty = |field: Option<String>| field.filter(|field| !field.is_empty());
+
+        NewPasteParams {
+            title: non_empty(form.title),
+            content: form.content,
+            expires_at: None,
+            slug: non_empty(form.slug),
+            language: non_empty(form.language),
+            password: non_empty(form.password),
+            render: None,
+        }
+    }
+}
+
 /// The paste creation page, presents a form to the user to create a new paste.
-pub async fn creation() -> PasteCreationTemplate {
-    PasteCreationTemplate
+///
+/// Logged in users are given their session's CSRF token to send along with the paste.
+pub async fn creation(session: AuthSession) -> Result<PasteCreationTemplate, HtmlPageError> {
+    Ok(PasteCreationTemplate {
+        csrf_token: creation_token(&session)?,
+        content: String::new(),
+        error: None,
+    })
+}
+
+/// Creates a paste from the paste creation form and redirects to the [created] page with its link,
+/// so pastes can be created without JavaScript.
+///
+/// If the paste can't be created the form is shown again with the reason why, keeping what was
+/// pasted into it.
+pub async fn create(
+    ctx: Extension<ApiContext>,
+    session: AuthSession,
+    Form(form): Form<NewPasteForm>,
+) -> Result<Response, HtmlPageError> {
+    if session.user.is_some() {
+        match verify_token(&session.session, form.csrf_token.as_deref()) {
+            Ok(()) => {}
+            Err(CsrfError::InvalidToken) => {
+                let template = PasteCreationTemplate {
+                    csrf_token: creation_token(&session)?,
+                    content: form.content,
+                    error: Some(CsrfError::InvalidToken.to_string()),
+                };
+                return Ok((StatusCode::FORBIDDEN, template).into_response());
+            }
+            Err(CsrfError::SessionFailure(_)) => return Err(HtmlPageError::SessionError),
+        }
+    }
+
+    let csrf_token = creation_token(&session)?;
+    let content = form.content.clone();
+
+    match insert_paste(&ctx, session.user, form.into(), None).await {
+        Ok(created) => {
+            let location = format!("/paste/{}/created", created.slug.as_str());
+            Ok(Redirect::to(&location).into_response())
+        }
+        Err(err) => {
+            let status = err.status_code();
+            if status.is_server_error() {
+                error!("Could not create paste: {}", ErrorChain(&err));
+            }
+
+            let template = PasteCreationTemplate {
+                csrf_token,
+                content,
+                error: Some(err.to_string()),
+            };
+            Ok((status, template).into_response())
+        }
+    }
+}
+
+/// The CSRF token to put in the paste creation form, which only logged in users need.
+fn creation_token(session: &AuthSession) -> Result<Option<String>, HtmlPageError> {
+    match session.user {
+        Some(_) => session_token(&session.session)
+            .map(Some)
+            .map_err(|_| HtmlPageError::SessionError),
+        None => Ok(None),
+    }
+}
+
+/// The page shown once a paste has been created through the form, with its link ready to share.
+///
+/// It doesn't show anything about the paste besides its link, so it's fine for anyone to visit.
+pub async fn created(
+    ctx: Extension<ApiContext>,
+    csp_nonce: CspNonce,
+    Path(slug_path): Path<String>,
+) -> Result<PasteCreatedTemplate, HtmlPageError> {
+    // There's no sense in sharing a link to a paste that can't be seen.
+    find_paste_by_slug(&ctx.db, slug_path.clone()).await?;
+
+    Ok(PasteCreatedTemplate {
+        path: format!("/paste/{slug_path}"),
+        csp_nonce,
+    })
 }
 
 /// The paste page, retrieves a paste from the database and presents an HTML page with its content.
+///
+/// Password protected pastes present a form asking for the password instead, until they've been
+/// unlocked.
 pub async fn page(
     ctx: Extension<ApiContext>,
+    headers: HeaderMap,
+    Path(slug_path): Path<String>,
+) -> Result<PastePage, HtmlPageError> {
+    let AuthoredPaste { paste, author } = find_paste_by_slug(&ctx.db, slug_path.clone()).await?;
+
+    if !is_unlocked(&paste, &headers, &ctx.cookie_key) {
+        return Ok(PastePage::Locked(PasteUnlockTemplate {
+            slug: slug_path,
+            error: None,
+        }));
+    }
+
+    // Counting the view is best-effort, the paste is still worth showing if it fails.
+    let views = match increment_paste_views(&ctx.db, paste.id).await {
+        Ok(views) => views,
+        Err(err) => {
+            warn!("Could not count view of paste {}: {err}", paste.id);
+            paste.views
+        }
+    };
+
+    let render = paste
+        .render
+        .as_deref()
+        .and_then(|render| render.parse().ok());
+    let (highlighted, markdown) = match render {
+        Some(RenderMode::Plain) => (None, None),
+        Some(RenderMode::Markdown) => (None, Some(markdown::render(&paste.content))),
+        // Pastes without a language, or with one we don't know about, are shown as plain text.
+        Some(RenderMode::Code) | None => {
+            let highlighted = paste
+                .language
+                .as_deref()
+                .and_then(|language| highlight::highlight(&paste.content, language));
+            (highlighted, None)
+        }
+    };
+
+    Ok(PastePage::Content(PasteTemplate {
+        paste_card: PasteCard {
+            title: paste.title,
+            content: paste.content,
+            highlighted,
+            markdown,
+        },
+        author,
+        views,
+    }))
+}
+
+/// The raw paste endpoint, retrieves a paste from the database and returns its content as plain
+/// text without any HTML around it. Handy for `curl` and scripts.
+pub async fn raw(
+    ctx: Extension<ApiContext>,
+    headers: HeaderMap,
     Path(slug_path): Path<String>,
-) -> Result<PasteTemplate, HtmlPageError> {
+) -> Result<impl IntoResponse, HtmlPageError> {
+    let paste = find_paste_by_slug(&ctx.db, slug_path).await?.paste;
+
+    if !is_unlocked(&paste, &headers, &ctx.cookie_key) {
+        return Err(HtmlPageError::PasswordRequired);
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        paste.content,
+    ))
+}
+
+/// Checks a password protected paste's password, and if it's correct sets a short-lived signed
+/// cookie that unlocks the paste before redirecting back to it.
+pub async fn unlock(
+    ctx: Extension<ApiContext>,
+    Path(slug_path): Path<String>,
+    Form(params): Form<UnlockParams>,
+) -> Result<Response, HtmlPageError> {
+    let paste = find_paste_by_slug(&ctx.db, slug_path.clone()).await?.paste;
+    let location = format!("/paste/{slug_path}");
+
+    let Some(password_hash) = paste.password_hash.as_deref() else {
+        return Ok(Redirect::to(&location).into_response());
+    };
+
+    if !verify_password(&params.password, password_hash) {
+        let template = PasteUnlockTemplate {
+            slug: slug_path,
+            error: Some("That password is incorrect.".to_string()),
+        };
+        return Ok((StatusCode::UNAUTHORIZED, template).into_response());
+    }
+
+    let expiry = Duration::seconds(ctx.config.paste_unlock_expiry);
+    let expires_at = OffsetDateTime::now_utc() + expiry;
+
+    // The cookie's name isn't covered by its signature, so the paste's ID goes in the value too.
+    // The expiry is checked on our end, a cookie that outlived its max age is still signed.
+    let cookie = Cookie::build((
+        unlock_cookie_name(paste.id),
+        format!("{}:{}", paste.id, expires_at.unix_timestamp()),
+    ))
+    .path(location.clone())
+    .max_age(expiry)
+    .http_only(true)
+    .same_site(SameSite::Lax);
+
+    let jar = SignedCookieJar::new(ctx.cookie_key.clone()).add(cookie);
+
+    Ok((jar, Redirect::to(&location)).into_response())
+}
+
+/// The name of the cookie that unlocks the paste with the given ID.
+fn unlock_cookie_name(paste_id: PasteId) -> String {
+    format!("{UNLOCK_COOKIE_PREFIX}{paste_id}")
+}
+
+/// Whether the paste can be viewed, either because it doesn't have a password or because the
+/// request carries a valid unlock cookie for it.
+fn is_unlocked(paste: &Paste, headers: &HeaderMap, key: &Key) -> bool {
+    if paste.password_hash.is_none() {
+        return true;
+    }
+
+    let jar = SignedCookieJar::from_headers(headers, key.clone());
+    let Some(cookie) = jar.get(&unlock_cookie_name(paste.id)) else {
+        return false;
+    };
+
+    match cookie.value().split_once(':') {
+        Some((id, expires_at)) => {
+            id.parse::<PasteId>() == Ok(paste.id)
+                && expires_at.parse().is_ok_and(|expires_at: i64| {
+                    expires_at > OffsetDateTime::now_utc().unix_timestamp()
+                })
+        }
+        None => false,
+    }
+}
+
+/// Resolves a slug from a request path into the paste it points to, along with its author.
+///
+/// Returns [HtmlPageError::NotFound] if the slug doesn't exist, or [HtmlPageError::Gone] if it's
+/// been disabled or the paste it points to has expired.
+async fn find_paste_by_slug(
+    db: &PgPool,
+    slug_path: String,
+) -> Result<AuthoredPaste, HtmlPageError> {
     // First off, check if the given slug is actually valid.
-    if !SlugString::is_valid(&slug_path) {
+    if !SlugString::is_well_formed(&slug_path) {
         return Err(HtmlPageError::InvalidPath(slug_path));
     }
 
     // Attempt to get a paste with the given slug from the database.
     // If the paste doesn't exist, return a 404.
     let slug = sqlx::query_file_as!(Slug, "sql/get_slug_by_slug.sql", slug_path)
-        .fetch_optional(&ctx.db)
+        .fetch_optional(db)
         .await
         .map_err(|_| HtmlPageError::DatabaseError)?
         .map_or(Err(HtmlPageError::NotFound), Ok)?;
 
-    // Is the slug actually enabled? If not, return a 404.
+    // Is the slug actually enabled? If not, the paste has been taken down.
     if slug.enabled.is_none() {
-        return Err(HtmlPageError::NotFound);
+        return Err(HtmlPageError::Gone);
     }
 
-    let paste: Paste = sqlx::query_file_as!(Paste, "sql/get_paste_by_id.sql", slug.paste_id)
-        .fetch_optional(&ctx.db)
+    // Slugs can point to files too, which don't have a paste page.
+    let paste_id = slug.paste_id.ok_or(HtmlPageError::NotFound)?;
+    let authored = get_paste_by_id(db, paste_id)
         .await
         .map_err(|_| HtmlPageError::DatabaseError)?
         .map_or(Err(HtmlPageError::NotFound), Ok)?;
 
-    Ok(PasteTemplate {
-        paste_card: PasteCard {
-            content: paste.content,
+    // Expired pastes are gone, even if they haven't been swept from the database yet.
+    if authored
+        .paste
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= OffsetDateTime::now_utc())
+    {
+        return Err(HtmlPageError::Gone);
+    }
+
+    Ok(authored)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::Request,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        auth::password::hash_password,
+        db::users::{
+            delete_user,
+            get_user_by_username,
         },
-    })
+        http::{
+            rate_limit::RateLimiter,
+            slugs::set_slug_enabled,
+            testing::{
+                csrf_token,
+                insert_user,
+                login_as,
+                with_auth,
+            },
+        },
+    };
+
+    fn app(db: PgPool) -> Router {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let paste_rate_limiter = RateLimiter::per_minute(config.paste_rate_limit);
+        with_auth(crate::frontend::router(&config, paste_rate_limiter), db)
+    }
+
+    /// Submits the paste creation form like a browser would, with the given cookie if any.
+    async fn submit_form(app: &Router, cookie: Option<&str>, form: &str) -> Response {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/paste")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let request = request.body(Body::from(form.to_string())).unwrap();
+
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn body_text(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    /// Unwraps the paste from a page, panicking if it's still locked.
+    fn content(page: PastePage) -> PasteTemplate {
+        match page {
+            PastePage::Content(template) => template,
+            PastePage::Locked(_) => panic!("expected the paste to be unlocked"),
+        }
+    }
+
+    /// Inserts a paste protected by the given password, reachable through the given slug.
+    async fn insert_protected_paste(db: &PgPool, slug: &str, password: &str) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (content, password_hash) \
+                         VALUES ('top secret', $1) RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, $2 FROM paste";
+        sqlx::query(query)
+            .bind(hash_password(password).unwrap())
+            .bind(slug)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    fn unlock_form(password: &str) -> Form<UnlockParams> {
+        Form(UnlockParams {
+            password: password.to_string(),
+        })
+    }
+
+    #[sqlx::test]
+    async fn raw_returns_plain_text_without_html(db: PgPool) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (content) VALUES ('just some text') RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'plain-old-text-paste' FROM paste";
+        sqlx::query(query).execute(&db).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let response = raw(
+            ctx,
+            HeaderMap::new(),
+            Path("plain-old-text-paste".to_string()),
+        )
+        .await
+        .unwrap()
+        .into_response();
+
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(body, "just some text");
+        assert!(!body.contains('<'));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn expired_paste_responds_with_gone(db: PgPool) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (content, expires_at) \
+                         VALUES ('old news', now() - interval '1 hour') RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'a-long-expired-paste' FROM paste";
+        sqlx::query(query).execute(&db).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let err = page(
+            ctx,
+            HeaderMap::new(),
+            Path("a-long-expired-paste".to_string()),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert!(matches!(err, HtmlPageError::Gone));
+        assert_eq!(err.into_response().status(), StatusCode::GONE);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn disabled_paste_is_unavailable_until_enabled_again(db: PgPool) -> sqlx::Result<()> {
+        let owner = insert_user(&db, "woof").await?;
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (user_id, content) \
+                         VALUES ($1, 'now you see me') RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'now-you-see-me' FROM paste";
+        sqlx::query(query).bind(owner).execute(&db).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db.clone()));
+        let slug = || Path("now-you-see-me".to_string());
+
+        set_slug_enabled(&db, owner, "now-you-see-me", false)
+            .await
+            .unwrap();
+        let err = page(ctx.clone(), HeaderMap::new(), slug())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, HtmlPageError::Gone));
+
+        set_slug_enabled(&db, owner, "now-you-see-me", true)
+            .await
+            .unwrap();
+        assert!(page(ctx, HeaderMap::new(), slug()).await.is_ok());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn paste_made_before_slugs_had_to_be_ascii_can_still_be_viewed(
+        db: PgPool,
+    ) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (content) VALUES ('from long ago') RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'an-old-café-paste' FROM paste";
+        sqlx::query(query).execute(&db).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let response = raw(ctx, HeaderMap::new(), Path("an-old-café-paste".to_string()))
+            .await
+            .unwrap()
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn viewing_a_paste_counts_views(db: PgPool) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (content) VALUES ('look at me') RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'a-much-viewed-paste' FROM paste";
+        sqlx::query(query).execute(&db).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let slug = || Path("a-much-viewed-paste".to_string());
+
+        let first = content(page(ctx.clone(), HeaderMap::new(), slug()).await.unwrap());
+        assert_eq!(first.views, 1);
+
+        let second = content(page(ctx, HeaderMap::new(), slug()).await.unwrap());
+        assert_eq!(second.views, 2);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn paste_page_shows_its_author(db: PgPool) -> sqlx::Result<()> {
+        let owner = insert_user(&db, "woof").await?;
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (user_id, content) \
+                         VALUES ($1, 'written by a dog') RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'written-by-a-dog' FROM paste";
+        sqlx::query(query).bind(owner).execute(&db).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let slug = Path("written-by-a-dog".to_string());
+        let template = content(page(ctx, HeaderMap::new(), slug).await.unwrap());
+
+        assert_eq!(template.author.as_deref(), Some("woof"));
+        assert!(template.to_string().contains("by woof"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn paste_page_shows_anonymous_pastes_as_anonymous(db: PgPool) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (content) VALUES ('who wrote this') RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'who-wrote-this-paste' FROM paste";
+        sqlx::query(query).execute(&db).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let slug = Path("who-wrote-this-paste".to_string());
+        let template = content(page(ctx, HeaderMap::new(), slug).await.unwrap());
+
+        assert_eq!(template.author, None);
+        assert!(template.to_string().contains("anonymous"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn paste_page_outlives_its_deleted_author(db: PgPool) -> sqlx::Result<()> {
+        let owner = insert_user(&db, "woof").await?;
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (user_id, content) \
+                         VALUES ($1, 'left behind') RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'a-paste-left-behind' FROM paste";
+        sqlx::query(query).bind(owner).execute(&db).await?;
+        let owner = get_user_by_username(&db, "woof").await?.unwrap();
+        delete_user(&db, &owner, false).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let slug = Path("a-paste-left-behind".to_string());
+        let template = content(page(ctx, HeaderMap::new(), slug).await.unwrap());
+
+        assert_eq!(template.author, None);
+        assert!(template.to_string().contains("anonymous"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn protected_paste_is_locked_without_password(db: PgPool) -> sqlx::Result<()> {
+        insert_protected_paste(&db, "keep-it-secret", "hunter2").await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let slug = || Path("keep-it-secret".to_string());
+
+        let locked = page(ctx.clone(), HeaderMap::new(), slug()).await.unwrap();
+        assert!(matches!(locked, PastePage::Locked(_)));
+
+        let err = raw(ctx, HeaderMap::new(), slug()).await.err().unwrap();
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn incorrect_password_does_not_unlock_paste(db: PgPool) -> sqlx::Result<()> {
+        insert_protected_paste(&db, "keep-it-secret", "hunter2").await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let response = unlock(
+            ctx,
+            Path("keep-it-secret".to_string()),
+            unlock_form("hunter3"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!response.headers().contains_key(header::SET_COOKIE));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn correct_password_unlocks_paste(db: PgPool) -> sqlx::Result<()> {
+        insert_protected_paste(&db, "keep-it-secret", "hunter2").await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let slug = || Path("keep-it-secret".to_string());
+
+        let response = unlock(ctx.clone(), slug(), unlock_form("hunter2"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "/paste/keep-it-secret"
+        );
+
+        // Send the cookie back like a browser would.
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        let cookie = set_cookie.split(';').next().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, cookie.parse().unwrap());
+
+        let unlocked = content(page(ctx.clone(), headers.clone(), slug()).await.unwrap());
+        assert_eq!(unlocked.paste_card.content, "top secret");
+        assert!(raw(ctx, headers, slug()).await.is_ok());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn guessing_passwords_is_rate_limited(db: PgPool) -> sqlx::Result<()> {
+        insert_protected_paste(&db, "keep-it-secret", "hunter2").await?;
+        let limit = ApiContext::for_tests(db.clone()).config.auth_rate_limit;
+        let app = app(db);
+
+        let guess = || {
+            Request::builder()
+                .method("POST")
+                .uri("/paste/keep-it-secret/unlock")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from("password=hunter3"))
+                .unwrap()
+        };
+        for _ in 0..limit {
+            let response = app.clone().oneshot(guess()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let response = app.oneshot(guess()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn markdown_paste_is_rendered_as_sanitized_html(db: PgPool) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (content, render) \
+                         VALUES ('# Woof' || chr(10) || chr(10) || '<script>alert(1)</script>', \
+                                 'markdown') \
+                         RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'some-marked-down-words' FROM paste";
+        sqlx::query(query).execute(&db).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        let slug = Path("some-marked-down-words".to_string());
+        let template = content(page(ctx, HeaderMap::new(), slug).await.unwrap());
+
+        let markdown = template.paste_card.markdown.unwrap();
+        assert!(markdown.contains("<h1>Woof</h1>"));
+        assert!(!markdown.contains("<script"));
+        assert!(template.paste_card.highlighted.is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn paste_content_is_escaped_when_rendered(db: PgPool) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (content, language) \
+                         VALUES ('<script>alert(1)</script>', NULL), \
+                                ('<script>alert(1)</script>', 'html') \
+                         RETURNING id, language \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, CASE WHEN language IS NULL \
+                                     THEN 'a-scary-looking-paste' \
+                                     ELSE 'some-scary-looking-code' END \
+                     FROM paste";
+        sqlx::query(query).execute(&db).await?;
+
+        let ctx = Extension(ApiContext::for_tests(db));
+        for slug in ["a-scary-looking-paste", "some-scary-looking-code"] {
+            let page = page(ctx.clone(), HeaderMap::new(), Path(slug.to_string()))
+                .await
+                .unwrap();
+            let html = content(page).to_string();
+
+            // The page loads scripts of its own, just never the one that was pasted.
+            assert!(!html.contains("<script>alert(1)"), "{slug}");
+            assert!(!html.contains("alert(1)</script>"), "{slug}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn paste_title_is_escaped() {
+        let card = PasteCard {
+            title: Some("<script>alert(1)</script>".to_string()),
+            content: "woof".to_string(),
+            highlighted: None,
+            markdown: None,
+        };
+        let html = card.to_string();
+
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[sqlx::test]
+    async fn form_creates_paste_and_links_to_it(db: PgPool) {
+        let app = app(db);
+
+        let form = "title=&content=a+paste+without+javascript&slug=&language=&password=";
+        let response = submit_form(&app, None, form).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let path = location.strip_suffix("/created").unwrap();
+        assert!(path.starts_with("/paste/"));
+
+        // The page after creating the paste hands out its link...
+        let request = Request::builder()
+            .uri(location)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_text(response).await;
+        assert!(body.contains(&format!(r#"value="{path}""#)));
+        assert!(body.contains("navigator.clipboard"));
+
+        // ...which leads to the paste.
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_text(response)
+            .await
+            .contains("a paste without javascript"));
+    }
+
+    #[sqlx::test]
+    async fn created_page_needs_an_existing_paste(db: PgPool) {
+        let request = Request::builder()
+            .uri("/paste/no-such-paste-here/created")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(db).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn form_shows_why_a_paste_could_not_be_created(db: PgPool) {
+        let app = app(db);
+
+        let form = "content=woof&slug=my-cool-api-paste";
+        let response = submit_form(&app, None, form).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // What was pasted isn't lost.
+        let body = body_text(response).await;
+        assert!(body.contains(">woof</textarea>"));
+        assert!(body.contains("The slug contains the reserved word"));
+    }
+
+    #[sqlx::test]
+    async fn form_requires_the_csrf_token_when_logged_in(db: PgPool) -> sqlx::Result<()> {
+        let user_id = insert_user(&db, "woof").await?;
+        let app = app(db);
+        let cookie = login_as(&app, user_id).await;
+
+        let response = submit_form(&app, Some(&cookie), "content=woof").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let token = csrf_token(&app, &cookie).await;
+        let form = format!("content=woof&csrf_token={token}");
+        let response = submit_form(&app, Some(&cookie), &form).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        Ok(())
+    }
 }
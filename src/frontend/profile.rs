@@ -0,0 +1,124 @@
+use axum::{
+    response::{
+        IntoResponse,
+        Redirect,
+        Response,
+    },
+    Extension,
+};
+
+use crate::{
+    auth::passkeys::backend::AuthSession,
+    db::credentials::get_credentials_by_user_uuid,
+    frontend::HtmlPageError,
+    http::{
+        pastes::{
+            fetch_user_pastes,
+            ListPastesParams,
+        },
+        users::ListedCredential,
+        ApiContext,
+    },
+    templates::ProfileTemplate,
+};
+
+/// The profile page, shows the authenticated user their account details along with their
+/// credentials and most recent pastes.
+///
+/// Users that aren't logged in are sent to the authentication page instead.
+pub async fn page(
+    ctx: Extension<ApiContext>,
+    session: AuthSession,
+) -> Result<Response, HtmlPageError> {
+    let Some(user) = session.user else {
+        return Ok(Redirect::to("/auth").into_response());
+    };
+
+    let credentials = get_credentials_by_user_uuid(&ctx.db, user.uuid)
+        .await
+        .map_err(|_| HtmlPageError::DatabaseError)?
+        .into_iter()
+        .map(ListedCredential::from)
+        .collect();
+
+    let pastes = fetch_user_pastes(
+        &ctx.db,
+        user.id,
+        &ListPastesParams::default(),
+        ctx.config.paste_page_size,
+    )
+    .await
+    .map_err(|_| HtmlPageError::DatabaseError)?;
+
+    Ok(ProfileTemplate {
+        user,
+        credentials,
+        pastes,
+    }
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header,
+            Request,
+            StatusCode,
+        },
+        Router,
+    };
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use crate::http::{
+        rate_limit::RateLimiter,
+        testing::{
+            insert_user,
+            login_as,
+            with_auth,
+        },
+        ApiContext,
+    };
+
+    fn app(db: PgPool) -> Router {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let paste_rate_limiter = RateLimiter::per_minute(config.paste_rate_limit);
+        with_auth(crate::frontend::router(&config, paste_rate_limiter), db)
+    }
+
+    fn get_profile(cookie: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().uri("/me");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn unauthenticated_profile_redirects_to_auth(db: PgPool) {
+        let response = app(db).oneshot(get_profile(None)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/auth");
+    }
+
+    #[sqlx::test]
+    async fn authenticated_profile_shows_username(db: PgPool) -> sqlx::Result<()> {
+        let user_id = insert_user(&db, "woof").await?;
+        let app = app(db);
+
+        let cookie = login_as(&app, user_id).await;
+
+        let response = app.oneshot(get_profile(Some(&cookie))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<h1>woof</h1>"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+//! Protection against cross-site request forgery for requests authenticated by the session cookie.
+//!
+//! Each session is issued a random token that clients have to send back in the [CSRF_HEADER]
+//! header on anything that changes state. Browsers attach the session cookie to cross-site
+//! requests automatically, but another site has no way of reading the token to send along with it.
+
+use axum::{
+    extract::Request,
+    http::{
+        Method,
+        StatusCode,
+    },
+    middleware::Next,
+    response::{
+        IntoResponse,
+        Response,
+    },
+    routing::get,
+    Json,
+    Router,
+};
+use base64::{
+    engine::general_purpose::URL_SAFE_NO_PAD,
+    Engine,
+};
+use rand_core::{
+    OsRng,
+    RngCore,
+};
+use serde::Serialize;
+use thiserror::Error;
+use tower_sessions::Session;
+
+use crate::{
+    auth::passkeys::backend::AuthSession,
+    http::error::ApiError,
+};
+
+/// The header clients have to send the session's token in.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// The key the token is stored under in the session.
+const SESSION_KEY: &str = "csrf_token";
+
+pub fn router() -> Router {
+    Router::new().route("/api/csrf", get(csrf_token))
+}
+
+/// A set of errors that can occur while issuing or checking a CSRF token.
+#[derive(Debug, Error)]
+pub enum CsrfError {
+    /// The request didn't carry the session's token, or carried a different one.
+    #[error("The request is missing a valid CSRF token")]
+    InvalidToken,
+
+    /// The token couldn't be read from or stored in the session.
+    #[error("An error occurred while accessing the session")]
+    SessionFailure(#[from] tower_sessions::session::Error),
+}
+
+impl CsrfError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CsrfError::InvalidToken => "invalid_csrf_token",
+            CsrfError::SessionFailure(_) => "session_failure",
+        }
+    }
+}
+
+impl IntoResponse for CsrfError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            CsrfError::InvalidToken => StatusCode::FORBIDDEN,
+            CsrfError::SessionFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        ApiError::respond(status, self.code(), &self)
+    }
+}
+
+/// The response to a request for the session's CSRF token.
+#[derive(Debug, Serialize)]
+pub struct CsrfToken {
+    pub token: String,
+}
+
+/// Handler that returns the session's CSRF token, issuing one if it doesn't have one yet.
+pub async fn csrf_token(auth_session: AuthSession) -> Result<Json<CsrfToken>, CsrfError> {
+    let token = session_token(&auth_session.session)?;
+    Ok(Json(CsrfToken { token }))
+}
+
+/// Gets the CSRF token tied to the session, generating and storing a new one if needed.
+pub fn session_token(session: &Session) -> Result<String, CsrfError> {
+    if let Some(token) = session.get::<String>(SESSION_KEY)? {
+        return Ok(token);
+    }
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+    session.insert(SESSION_KEY, &token)?;
+
+    Ok(token)
+}
+
+/// Middleware that rejects state-changing requests made with a logged in session unless they
+/// carry the session's CSRF token.
+///
+/// Requests without a logged in user aren't authenticated by a cookie, so there is nothing for
+/// another site to forge and they're let through.
+pub async fn require_csrf_token(
+    auth_session: AuthSession,
+    request: Request,
+    next: Next,
+) -> Result<Response, CsrfError> {
+    let is_safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if is_safe || auth_session.user.is_none() {
+        return Ok(next.run(request).await);
+    }
+
+    let provided = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok());
+    verify_token(&auth_session.session, provided)?;
+
+    Ok(next.run(request).await)
+}
+
+/// Checks that a token sent with a request is the session's CSRF token.
+///
+/// This is for requests that can't send the token in the [CSRF_HEADER], like plain HTML forms
+/// which send it along as a field instead.
+pub fn verify_token(session: &Session, provided: Option<&str>) -> Result<(), CsrfError> {
+    let expected = session
+        .get::<String>(SESSION_KEY)?
+        .ok_or(CsrfError::InvalidToken)?;
+    let provided = provided.ok_or(CsrfError::InvalidToken)?;
+
+    if !constant_time_eq(expected.as_bytes(), provided.as_bytes()) {
+        return Err(CsrfError::InvalidToken);
+    }
+
+    Ok(())
+}
+
+/// Compares two byte strings without bailing out at the first difference, so the token can't be
+/// guessed a byte at a time by timing how long requests take to be rejected.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header,
+            Request,
+        },
+        middleware,
+        routing::post,
+    };
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::http::testing::{
+        csrf_token,
+        insert_user,
+        login_as,
+        with_auth,
+    };
+
+    fn app(db: PgPool) -> Router {
+        let protected = Router::new()
+            .route("/protected", post(|| async { StatusCode::OK }))
+            .route_layer(middleware::from_fn(require_csrf_token));
+
+        with_auth(protected, db)
+    }
+
+    async fn post_protected(app: &Router, cookie: &str, token: Option<&str>) -> StatusCode {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/protected")
+            .header(header::COOKIE, cookie);
+        if let Some(token) = token {
+            request = request.header(CSRF_HEADER, token);
+        }
+        let request = request.body(Body::empty()).unwrap();
+
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[sqlx::test]
+    async fn requests_without_a_token_are_rejected(db: PgPool) -> sqlx::Result<()> {
+        let user_id = insert_user(&db, "woof").await?;
+        let app = app(db);
+        let cookie = login_as(&app, user_id).await;
+
+        // Rejected before a token has been issued...
+        assert_eq!(
+            post_protected(&app, &cookie, None).await,
+            StatusCode::FORBIDDEN
+        );
+
+        // ...after it has, and when the wrong one is sent.
+        csrf_token(&app, &cookie).await;
+        assert_eq!(
+            post_protected(&app, &cookie, None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            post_protected(&app, &cookie, Some("not-the-token")).await,
+            StatusCode::FORBIDDEN
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn requests_with_the_token_succeed(db: PgPool) -> sqlx::Result<()> {
+        let user_id = insert_user(&db, "woof").await?;
+        let app = app(db);
+        let cookie = login_as(&app, user_id).await;
+
+        let token = csrf_token(&app, &cookie).await;
+        assert_eq!(csrf_token(&app, &cookie).await, token);
+        assert_eq!(
+            post_protected(&app, &cookie, Some(&token)).await,
+            StatusCode::OK
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn constant_time_eq_compares_contents() {
+        assert!(constant_time_eq(b"woof", b"woof"));
+        assert!(!constant_time_eq(b"woof", b"bark"));
+        assert!(!constant_time_eq(b"woof", b"woofwoof"));
+    }
+}
@@ -1,7 +1,65 @@
+use std::{
+    error::Error,
+    fmt,
+};
+
+use axum::{
+    http::StatusCode,
+    response::{
+        IntoResponse,
+        Response,
+    },
+    Json,
+};
+use log::error;
 use serde::Serialize;
+use utoipa::ToSchema;
 
 /// A generic error response for the API to return to clients.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ApiError {
+    /// A machine-readable code identifying the kind of error (e.g. `user_already_exists`).
+    pub code: String,
+    /// A human-readable description of the error.
     pub message: String,
 }
+
+impl ApiError {
+    /// Logs an error and responds with it as an [ApiError] with the given status code and `code`.
+    ///
+    /// The message of a server error doesn't say what caused it, so the whole [ErrorChain] is
+    /// logged for those instead.
+    pub fn respond(status: StatusCode, code: &str, error: &dyn Error) -> Response {
+        if status.is_server_error() {
+            error!("{}", ErrorChain(error));
+        } else {
+            error!("{error}");
+        }
+
+        let error = ApiError {
+            code: code.to_string(),
+            message: error.to_string(),
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// Displays an error followed by every error that caused it, separated by colons.
+///
+/// Server errors only tell clients that something went wrong, this is what gets logged so that
+/// the underlying cause (e.g. a [webauthn_rs::prelude::WebauthnError]) isn't lost.
+pub struct ErrorChain<'a>(pub &'a dyn Error);
+
+impl fmt::Display for ErrorChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+
+        let mut source = self.0.source();
+        while let Some(cause) = source {
+            write!(f, ": {cause}")?;
+            source = cause.source();
+        }
+
+        Ok(())
+    }
+}
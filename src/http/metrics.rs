@@ -0,0 +1,199 @@
+//! Counters and timings about what the server is up to, exposed in the Prometheus text format.
+//!
+//! Recording a metric before [install] has been called does nothing, so the rest of the application
+//! doesn't have to care whether metrics are enabled.
+
+use std::{
+    sync::OnceLock,
+    time::Instant,
+};
+
+use ::metrics::{
+    counter,
+    histogram,
+};
+use axum::{
+    extract::{
+        MatchedPath,
+        Request,
+        State,
+    },
+    middleware::Next,
+    response::Response,
+    routing::get,
+    Router,
+};
+use metrics_exporter_prometheus::{
+    PrometheusBuilder,
+    PrometheusHandle,
+};
+
+/// How many HTTP requests have been handled, by route, method and status code.
+const HTTP_REQUESTS: &str = "woof_http_requests_total";
+
+/// How long HTTP requests took to handle in seconds, by route and method.
+const HTTP_REQUEST_DURATION: &str = "woof_http_request_duration_seconds";
+
+/// How many passkey challenges have been handed out, by ceremony.
+const PASSKEY_CHALLENGES: &str = "woof_passkey_challenges_total";
+
+/// How many passkeys have been successfully registered.
+const PASSKEY_REGISTRATIONS: &str = "woof_passkey_registrations_total";
+
+/// How many passkey authentications have been attempted, by whether they succeeded.
+const PASSKEY_AUTHENTICATIONS: &str = "woof_passkey_authentications_total";
+
+/// How many authentications used a credential whose counter was behind, which is a sign it may
+/// have been cloned.
+const PASSKEY_COUNTER_DISCREPANCIES: &str = "woof_passkey_counter_discrepancies_total";
+
+/// Installs the global recorder that metrics are collected in, returning a handle to render them.
+///
+/// Only one recorder can ever be installed, so calling this again returns the same handle.
+pub fn install() -> PrometheusHandle {
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+    HANDLE
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .install_recorder()
+                .expect("failed to install the metrics recorder")
+        })
+        .clone()
+}
+
+/// Defines the [Router] that serves the collected metrics for Prometheus to scrape.
+pub fn router(handle: PrometheusHandle) -> Router {
+    Router::new()
+        .route("/metrics", get(render))
+        .with_state(handle)
+}
+
+/// Handler that renders every metric in the Prometheus text format.
+async fn render(State(handle): State<PrometheusHandle>) -> String {
+    handle.render()
+}
+
+/// Middleware that counts every request along with how long it took to handle.
+///
+/// Requests are labelled by the route they matched rather than their full path, so the number of
+/// distinct labels stays bounded.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    counter!(
+        HTTP_REQUESTS,
+        "route" => route.clone(),
+        "method" => method.clone(),
+        "status" => status
+    )
+    .increment(1);
+    histogram!(HTTP_REQUEST_DURATION, "route" => route, "method" => method)
+        .record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+/// The passkey ceremonies a challenge can be handed out for.
+#[derive(Debug, Clone, Copy)]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+/// Records that a challenge was handed out for the given ceremony.
+pub fn record_challenge(ceremony: Ceremony) {
+    let ceremony = match ceremony {
+        Ceremony::Registration => "registration",
+        Ceremony::Authentication => "authentication",
+    };
+    counter!(PASSKEY_CHALLENGES, "ceremony" => ceremony).increment(1);
+}
+
+/// Records that a passkey was successfully registered.
+pub fn record_registration() {
+    counter!(PASSKEY_REGISTRATIONS).increment(1);
+}
+
+/// Records the outcome of an attempt to authenticate with a passkey.
+pub fn record_authentication(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    counter!(PASSKEY_AUTHENTICATIONS, "result" => result).increment(1);
+}
+
+/// Records that a credential was used with a counter behind the one that's stored.
+pub fn record_counter_discrepancy() {
+    counter!(PASSKEY_COUNTER_DISCREPANCIES).increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            Request,
+            StatusCode,
+        },
+    };
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::http::{
+        testing::with_auth,
+        ApiContext,
+    };
+
+    #[sqlx::test]
+    async fn failed_authentications_are_counted(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(
+            crate::auth::router(&config)
+                .unwrap()
+                .merge(router(install())),
+            db,
+        );
+
+        // Finishing an authentication that was never started can only fail.
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/users/finish_authentication")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"id":"d29vZg","rawId":"d29vZg","type":"public-key","extensions":{},
+                "response":{"authenticatorData":"d29vZg","clientDataJSON":"d29vZg",
+                "signature":"d29vZg","userHandle":null}}"#,
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(!response.status().is_success());
+
+        let request = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let failures = body
+            .lines()
+            .find_map(|line| {
+                line.strip_prefix(r#"woof_passkey_authentications_total{result="failure"} "#)
+            })
+            .expect("the failure counter to be rendered");
+        assert!(failures.parse::<f64>().unwrap() > 0.0);
+    }
+}
@@ -1,33 +1,90 @@
+pub mod csrf;
 pub mod error;
+pub mod metrics;
+pub mod openapi;
 pub mod pastes;
+pub mod rate_limit;
+pub mod redirect;
+pub mod security;
+pub mod session;
+pub mod slugs;
+#[cfg(test)]
+pub mod testing;
+pub mod users;
 
-use std::sync::Arc;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+};
 
 use anyhow::Context;
 use axum::{
+    body::Body,
     error_handling::HandleErrorLayer,
-    http::StatusCode,
+    http::{
+        header,
+        HeaderName,
+        HeaderValue,
+        Method,
+        Request,
+        StatusCode,
+    },
+    middleware,
     BoxError,
     Extension,
     Router,
 };
+use axum_extra::extract::cookie::Key;
 use axum_login::AuthManagerLayerBuilder;
 use log::info;
 use sqlx::PgPool;
+use tokio::{
+    net::TcpListener,
+    sync::watch,
+};
 use tower::ServiceBuilder;
-use tower_http::services::ServeDir;
+use tower_http::{
+    cors::{
+        AllowOrigin,
+        CorsLayer,
+    },
+    request_id::{
+        MakeRequestUuid,
+        PropagateRequestIdLayer,
+        SetRequestIdLayer,
+    },
+    services::ServeDir,
+    trace::TraceLayer,
+};
 use tower_sessions::{
     cookie::time::Duration,
     Expiry,
     MemoryStore,
-    SessionManagerLayer,
 };
+use tracing::Span;
 
 use crate::{
-    auth::passkeys::backend::{
-        PasskeyBackend,
+    auth::passkeys::{
+        backend::PasskeyBackend,
+        PasskeyConfigError,
     },
     config::Config,
+    http::{
+        rate_limit::RateLimiter,
+        security::{
+            set_security_headers,
+            SecurityHeaders,
+        },
+        session::session_layer,
+    },
+    tus::{
+        lock::UploadLocks,
+        store::{
+            FilesystemUploadStore,
+            UploadStore,
+        },
+    },
 };
 
 /// The context that is passed to all handlers to provide access to the database and configuration.
@@ -35,15 +92,94 @@ use crate::{
 pub struct ApiContext {
     pub config: Arc<Config>,
     pub db: PgPool,
+    /// The key used to sign cookies that aren't tied to a session, like those that unlock
+    /// password protected pastes.
+    ///
+    /// It's read from [Config::cookie_key], or generated on startup if that isn't set.
+    pub cookie_key: Key,
+    /// Where the contents of TUS uploads are kept.
+    pub uploads: Arc<dyn UploadStore>,
+    /// The TUS uploads that requests are writing to at the moment.
+    pub upload_locks: UploadLocks,
+}
+
+#[cfg(test)]
+impl ApiContext {
+    /// Creates a context backed by the given pool and the default configuration, for use in tests.
+    pub fn for_tests(db: PgPool) -> Self {
+        use clap::Parser;
+
+        let config = Config::parse_from(["woof", "--database-url", "postgres://localhost/woof"]);
+        ApiContext {
+            config: Arc::new(config),
+            db,
+            cookie_key: Key::generate(),
+            uploads: Arc::new(crate::tus::store::MemoryUploadStore::default()),
+            upload_locks: UploadLocks::default(),
+        }
+    }
 }
 
 pub async fn serve(config: Config, db: PgPool) -> anyhow::Result<()> {
+    let listen_addr = SocketAddr::new(config.bind_address, config.port);
+    let uploads = FilesystemUploadStore::open(&config.upload_dir)
+        .await
+        .with_context(|| {
+            format!(
+                "upload directory `{}` could not be created or isn't writable",
+                config.upload_dir.display()
+            )
+        })?;
+    let uploads: Arc<dyn UploadStore> = Arc::new(uploads);
+
+    // Periodically clean up expired content in the background until the server stops.
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let sweeper = crate::sweeper::spawn(
+        db.clone(),
+        uploads.clone(),
+        std::time::Duration::from_secs(config.sweep_interval),
+        std::time::Duration::from_secs(config.idempotency_key_expiry as u64),
+        shutdown_rx,
+    );
+
+    let cookie_key = config.cookie_key.clone().unwrap_or_else(Key::generate);
+    let app = build_router(ApiContext {
+        config: Arc::new(config),
+        db: db.clone(),
+        cookie_key,
+        uploads,
+        upload_locks: UploadLocks::default(),
+    })?;
+
+    let listener = bind(listen_addr).await?;
+
+    info!("Listening on {}", listener.local_addr()?);
+    let result = run(listener, app, shutdown_signal()).await;
+
+    // Let any background tasks know we're done and wait for them to wind down before closing the
+    // database connections for good.
+    shutdown_tx.send(true).ok();
+    sweeper.await.ok();
+    db.close().await;
+    info!("Server has shut down");
+
+    result
+}
+
+/// Wires up the complete application served for the given context, with every route along with
+/// the session, security, CORS and tracing layers around them.
+///
+/// This is everything [serve] runs apart from the listener and background tasks, so tests can
+/// drive the real application without binding a port.
+pub fn build_router(ctx: ApiContext) -> Result<Router, PasskeyConfigError> {
     let auth_session_store = MemoryStore::default();
-    let auth_session_layer = SessionManagerLayer::new(auth_session_store)
-        .with_secure(false)
+    let auth_session_layer = session_layer(auth_session_store, &ctx.config)
         .with_expiry(Expiry::OnInactivity(Duration::days(7)));
 
-    let backend = PasskeyBackend::new(db.clone());
+    let backend = PasskeyBackend::new(ctx.db.clone());
+    let config = &ctx.config;
+    let cors = cors_layer(&config.allowed_origins);
+    let security_headers = SecurityHeaders::new(config);
 
     let auth_service = ServiceBuilder::new()
         .layer(HandleErrorLayer::new(|_: BoxError| async {
@@ -51,25 +187,434 @@ pub async fn serve(config: Config, db: PgPool) -> anyhow::Result<()> {
         }))
         .layer(AuthManagerLayerBuilder::new(backend, auth_session_layer).build());
 
-    let app = api_router()
+    let mut app = api_router(config)?
         .nest_service("/static", ServeDir::new("static"))
         .layer(auth_service)
-        .layer(ServiceBuilder::new().layer(Extension(ApiContext {
-            config: Arc::new(config),
-            db,
-        })));
+        .layer(middleware::from_fn(metrics::track_requests));
+
+    // Metrics are served outside the authentication layer, there's no session to load for them.
+    if config.metrics {
+        app = app.merge(metrics::router(metrics::install()));
+    }
+
+    let app = app
+        .layer(middleware::from_fn_with_state(
+            security_headers,
+            set_security_headers,
+        ))
+        .layer(ServiceBuilder::new().layer(Extension(ctx)))
+        .layer(cors)
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(TraceLayer::new_for_http().make_span_with(request_span))
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        );
+
+    Ok(app)
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+/// Creates the span everything logged while handling a request happens in, so log lines can be
+/// tied back to the request through its `x-request-id`.
+fn request_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        uri = %request.uri(),
+    )
+}
+
+/// Serves the given app on the listener until the `shutdown` future completes.
+///
+/// Once `shutdown` completes no new connections are accepted, but in-flight requests are allowed to
+/// finish before this returns.
+pub async fn run(
+    listener: TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    // Connection info is needed to tell clients apart when rate limiting.
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
 
-    info!("Listening on {}", listener.local_addr().unwrap());
     axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
         .await
         .context("error running HTTP server")
 }
 
+/// Completes once the process receives a SIGINT (i.e. Ctrl+C) or, on Unix, a SIGTERM.
+///
+/// SIGTERM is what systemd, Docker and Kubernetes send to ask a process to stop before they resort
+/// to killing it outright.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install the Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutdown signal received, finishing in-flight requests...");
+}
+
+/// Binds a TCP listener for the HTTP server to the given address.
+pub async fn bind(addr: SocketAddr) -> anyhow::Result<TcpListener> {
+    TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("could not bind HTTP server to {addr}"))
+}
+
+/// Builds a [CorsLayer] that allows the given origins to make credentialed requests to the API.
+///
+/// Credentials are needed so the session cookie is sent along with cross-origin requests. If no
+/// origins are given, no cross-origin requests are allowed.
+pub fn cors_layer(allowed_origins: &[HeaderValue]) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(allowed_origins.iter().cloned()))
+        .allow_credentials(true)
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            HeaderName::from_static(csrf::CSRF_HEADER),
+            HeaderName::from_static(pastes::IDEMPOTENCY_KEY_HEADER),
+        ])
+}
+
 /// Constructs the a [Router] that pulls in all the routes from the different modules.
-pub fn api_router() -> Router {
-    crate::auth::router()
-        .merge(pastes::router())
-        .merge(crate::frontend::router())
+pub fn api_router(config: &Config) -> Result<Router, PasskeyConfigError> {
+    let paste_rate_limiter = RateLimiter::per_minute(config.paste_rate_limit);
+    let router = crate::auth::router(config)?
+        .merge(csrf::router())
+        .merge(openapi::router())
+        .merge(pastes::router(config, paste_rate_limiter.clone()))
+        .merge(redirect::router())
+        .merge(slugs::router())
+        .merge(users::router())
+        .merge(crate::tus::router(config))
+        .merge(crate::frontend::router(config, paste_rate_limiter));
+
+    Ok(router)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::Request,
+        response::Response,
+        routing::{
+            delete,
+            post,
+        },
+    };
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Reads a response body as JSON.
+    async fn json_body(response: Response) -> Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Builds a JSON POST request, sent along with the given session cookie if there is one.
+    fn post_json(uri: &str, cookie: Option<&str>, body: Value) -> Request<Body> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+
+        request.body(Body::from(body.to_string())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_allows_configured_origin() {
+        let origin = HeaderValue::from_static("https://woof.example");
+        let app = Router::new()
+            .route("/api/users/start_register", post(|| async {}))
+            .layer(cors_layer(&[origin.clone()]));
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/users/start_register")
+            .header(header::ORIGIN, origin.clone())
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_allows_idempotency_keys() {
+        let origin = HeaderValue::from_static("https://woof.example");
+        let app = Router::new()
+            .route("/api/pastes", post(|| async {}))
+            .layer(cors_layer(&[origin.clone()]));
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/pastes")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "idempotency-key")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        let allowed = response.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(allowed.contains("idempotency-key"));
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_allows_every_api_method() {
+        let origin = HeaderValue::from_static("https://woof.example");
+        let app = Router::new()
+            .route("/api/users/me", delete(|| async {}))
+            .layer(cors_layer(&[origin.clone()]));
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/users/me")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        let allowed = response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap();
+        for method in ["GET", "POST", "PATCH", "DELETE"] {
+            assert!(allowed.contains(method), "{method} isn't allowed");
+        }
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_rejects_unknown_origin() {
+        let app = Router::new()
+            .route("/api/users/start_register", post(|| async {}))
+            .layer(cors_layer(&[]));
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/users/start_register")
+            .header(header::ORIGIN, "https://evil.example")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn bind_to_port_zero_assigns_a_port() {
+        let listener = bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        assert!(addr.ip().is_loopback());
+        assert_ne!(addr.port(), 0);
+    }
+
+    #[tokio::test]
+    async fn run_stops_accepting_connections_after_shutdown() {
+        let listener = bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let shutdown = async move {
+            shutdown_rx.await.ok();
+        };
+        let server = tokio::spawn(run(listener, Router::new(), shutdown));
+
+        // The server accepts connections while it's running...
+        assert!(tokio::net::TcpStream::connect(addr).await.is_ok());
+
+        // ...and stops once the shutdown signal resolves.
+        shutdown_tx.send(()).unwrap();
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("the server to shut down in time")
+            .unwrap();
+        assert!(result.is_ok());
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    #[sqlx::test]
+    async fn created_pastes_can_be_fetched(db: PgPool) {
+        let app = build_router(ApiContext::for_tests(db)).unwrap();
+
+        let request = post_json(
+            "/api/pastes",
+            None,
+            serde_json::json!({ "content": "woof" }),
+        );
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let created = json_body(response).await;
+        let slug = created["slug"].as_str().unwrap();
+
+        let request = Request::builder()
+            .uri(format!("/paste/{slug}/raw"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"woof");
+    }
+
+    #[sqlx::test]
+    async fn paste_api_and_form_share_a_rate_limit(db: PgPool) {
+        use clap::Parser;
+
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--paste-rate-limit",
+            "1",
+        ]);
+        let app = build_router(ApiContext {
+            config: Arc::new(config),
+            ..ApiContext::for_tests(db)
+        })
+        .unwrap();
+
+        let request = post_json(
+            "/api/pastes",
+            None,
+            serde_json::json!({ "content": "woof" }),
+        );
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/paste")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from("content=bark"))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[sqlx::test]
+    async fn page_scripts_carry_the_csp_nonce(db: PgPool) {
+        let app = build_router(ApiContext::for_tests(db)).unwrap();
+
+        let request = Request::builder().uri("/auth").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let policy = response.headers()[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let nonce = policy
+            .split_once("'nonce-")
+            .and_then(|(_, rest)| rest.split_once('\''))
+            .map(|(nonce, _)| nonce.to_string())
+            .expect("the policy to carry a nonce");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(&format!(r#"<script type="module" nonce="{nonce}">"#)));
+    }
+
+    #[sqlx::test]
+    async fn passkey_authentication_rejects_forged_credentials(db: PgPool) {
+        let app = build_router(ApiContext::for_tests(db)).unwrap();
+
+        let request = post_json(
+            "/api/users/start_authentication",
+            None,
+            serde_json::json!({ "identifier": "woof" }),
+        );
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let challenge = json_body(response).await;
+        assert!(challenge["publicKey"]["challenge"].is_string());
+
+        // A credential no authenticator ever signed, standing in for the browser's response.
+        let credential = serde_json::json!({
+            "id": "d29vZg",
+            "rawId": "d29vZg",
+            "type": "public-key",
+            "extensions": {},
+            "response": {
+                "authenticatorData": "d29vZg",
+                "clientDataJSON": "d29vZg",
+                "signature": "d29vZg",
+                "userHandle": null,
+            },
+        });
+
+        let request = post_json(
+            "/api/users/finish_authentication",
+            Some(&cookie),
+            credential.clone(),
+        );
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["code"], "backend_auth_invalid");
+
+        // The challenge is used up by the first attempt, so it can't be retried.
+        let request = post_json(
+            "/api/users/finish_authentication",
+            Some(&cookie),
+            credential,
+        );
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["code"], "missing_session_info");
+    }
 }
@@ -0,0 +1,117 @@
+//! A machine-readable description of the public API, for generating clients from.
+//!
+//! The schemas are derived from the same types the handlers (de)serialize, so they can't drift
+//! apart. The challenges and credentials passed through the passkey endpoints come straight from
+//! the WebAuthn browser API and are left as plain objects.
+
+use axum::{
+    routing::get,
+    Json,
+    Router,
+};
+use utoipa::OpenApi;
+
+use crate::{
+    auth::{
+        passkeys::{
+            authentication,
+            registration,
+        },
+        AuthParams,
+        LoginParams,
+    },
+    db::{
+        pastes::{
+            Paste,
+            PasteId,
+            RenderMode,
+        },
+        slugs::SlugString,
+    },
+    http::{
+        error::ApiError,
+        pastes::{
+            self,
+            CreatedPaste,
+            NewPasteParams,
+            UserPaste,
+        },
+    },
+};
+
+/// The OpenAPI document describing the paste and passkey endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        pastes::create_paste,
+        pastes::list_pastes,
+        registration::start_register,
+        registration::start_conditional_register,
+        registration::finish_register,
+        authentication::start_authentication,
+        authentication::finish_authentication,
+    ),
+    components(schemas(
+        ApiError,
+        AuthParams,
+        CreatedPaste,
+        LoginParams,
+        NewPasteParams,
+        Paste,
+        PasteId,
+        RenderMode,
+        SlugString,
+        UserPaste,
+    )),
+    tags(
+        (name = "pastes", description = "Creating and listing pastes"),
+        (name = "passkeys", description = "Registering and logging in with passkeys"),
+    )
+)]
+pub struct ApiDoc;
+
+pub fn router() -> Router {
+    Router::new().route("/api/openapi.json", get(openapi))
+}
+
+/// Serves the [ApiDoc] as JSON.
+pub async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            Request,
+            StatusCode,
+        },
+    };
+    use tower::ServiceExt;
+    use utoipa::openapi::PathItemType;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn served_document_describes_paste_creation() {
+        let request = Request::builder()
+            .uri("/api/openapi.json")
+            .body(Body::empty())
+            .unwrap();
+        let response = router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let document: utoipa::openapi::OpenApi = serde_json::from_slice(&body).unwrap();
+        assert!(document.paths.paths["/api/pastes"]
+            .operations
+            .contains_key(&PathItemType::Post));
+
+        let schemas = document.components.unwrap().schemas;
+        assert!(schemas.contains_key("NewPasteParams"));
+        assert!(schemas.contains_key("ApiError"));
+    }
+}
@@ -1,5 +1,21 @@
 use axum::{
-    routing::post,
+    extract::{
+        DefaultBodyLimit,
+        Query,
+    },
+    http::{
+        HeaderMap,
+        StatusCode,
+    },
+    middleware,
+    response::{
+        IntoResponse,
+        Response,
+    },
+    routing::{
+        get,
+        post,
+    },
     Extension,
     Json,
     Router,
@@ -8,34 +24,480 @@ use serde::{
     Deserialize,
     Serialize,
 };
-use sqlx::types::time::OffsetDateTime;
+use sqlx::{
+    types::time::{
+        Duration,
+        OffsetDateTime,
+    },
+    PgConnection,
+    PgPool,
+};
+use thiserror::Error;
+use tower_sessions::Session;
+use utoipa::{
+    IntoParams,
+    ToSchema,
+};
+use uuid::Uuid;
 
 use crate::{
-    auth::passkeys::backend::AuthSession,
-    db::pastes::Paste,
-    http::ApiContext,
+    auth::{
+        passkeys::backend::AuthSession,
+        password::hash_password,
+    },
+    config::Config,
+    db::{
+        pastes::{
+            claim_idempotency_key,
+            get_paste_by_id,
+            get_paste_id_by_idempotency_key,
+            get_recent_pastes,
+            Paste,
+            PasteId,
+            RecentPaste,
+            RenderMode,
+        },
+        slugs::{
+            get_slug_by_paste_id,
+            Slug,
+            SlugError,
+            SlugString,
+        },
+        users::User,
+    },
+    http::{
+        csrf::require_csrf_token,
+        error::ApiError,
+        rate_limit::{
+            rate_limit,
+            RateLimiter,
+        },
+        ApiContext,
+    },
 };
 
-pub fn router() -> Router {
-    Router::new().route("/api/pastes", post(create_paste))
+/// How many times we'll try to generate a unique slug for a paste before giving up.
+const SLUG_GENERATION_ATTEMPTS: usize = 5;
+
+/// The header clients can send to make retrying the creation of a paste safe.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// The longest an idempotency key can be.
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// The key an anonymous session's idempotency scope is stored under in the session.
+const IDEMPOTENCY_SCOPE_SESSION_KEY: &str = "idempotency_scope";
+
+/// The routes of the paste API, throttled by the given [RateLimiter].
+///
+/// The limiter is shared with the paste creation form, so a client can't get around the limit by
+/// switching between the two.
+pub fn router(config: &Config, rate_limiter: RateLimiter) -> Router {
+    // The request body also has to fit the rest of the JSON payload and any escaping of the
+    // content, so we give it some headroom over the content limit itself.
+    let body_limit = config.max_paste_bytes.saturating_mul(2);
+
+    let mut router = Router::new()
+        .route("/api/pastes", post(create_paste).get(list_pastes))
+        .route("/api/pastes/search", get(search_pastes));
+
+    // Not every deployment wants its public pastes listed for anyone to browse.
+    if config.recent_pastes {
+        router = router.route("/api/pastes/recent", get(recent_pastes));
+    }
+
+    router
+        .route_layer(middleware::from_fn(require_csrf_token))
+        .layer(middleware::from_fn_with_state(rate_limiter, rate_limit))
+        .layer(DefaultBodyLimit::max(body_limit))
 }
 
 /// Parameters for creating a new paste via the API.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct NewPasteParams {
-    title: Option<String>,
-    content: String,
-    expires_at: Option<OffsetDateTime>,
+    pub title: Option<String>,
+    pub content: String,
+    pub expires_at: Option<OffsetDateTime>,
+    /// A custom slug to use instead of a randomly generated one.
+    pub slug: Option<String>,
+    /// The language of the content used for syntax highlighting (e.g. `rust` or `rs`).
+    pub language: Option<String>,
+    /// A password that has to be entered before the paste can be viewed.
+    ///
+    /// Only a hash of it is ever stored.
+    pub password: Option<String>,
+    /// How the paste should be rendered, defaults to highlighting it if it has a language.
+    pub render: Option<RenderMode>,
+}
+
+/// A newly created paste, along with the slug that can be used to share it.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CreatedPaste {
+    #[serde(flatten)]
+    pub paste: Paste,
+    pub slug: SlugString,
+}
+
+/// An idempotency key sent along with a new paste, along with who sent it.
+#[derive(Debug, Clone)]
+pub struct IdempotencyKey {
+    /// The user or anonymous session the key belongs to, so the same key sent by someone else
+    /// never hands them another person's paste.
+    pub scope: String,
+    pub key: String,
+}
+
+/// Query parameters for listing the authenticated user's pastes.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListPastesParams {
+    /// Only return pastes created before this time, used to fetch the next page.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    before: Option<OffsetDateTime>,
+    /// The ID of the last paste in the previous page, so pastes created at the same time as it
+    /// aren't skipped.
+    before_id: Option<PasteId>,
+    /// Whether pastes that have already expired should be included.
+    #[serde(default)]
+    include_expired: bool,
+}
+
+/// Query parameters for searching the authenticated user's pastes.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SearchPastesParams {
+    /// The text to look for in the title or content of a paste, ignoring case.
+    q: String,
+    /// Only return pastes created before this time, used to fetch the next page.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    before: Option<OffsetDateTime>,
+    /// The ID of the last paste in the previous page, so pastes created at the same time as it
+    /// aren't skipped.
+    before_id: Option<PasteId>,
+    /// Whether pastes that have already expired should be included.
+    #[serde(default)]
+    include_expired: bool,
+}
+
+/// Query parameters for listing the most recent public pastes.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RecentPastesParams {
+    /// Only return pastes created before this time, used to fetch the next page.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    before: Option<OffsetDateTime>,
+    /// The ID of the last paste in the previous page, so pastes created at the same time as it
+    /// aren't skipped.
+    before_id: Option<PasteId>,
+}
+
+/// A paste belonging to a user, along with the slug that can be used to share it.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UserPaste {
+    #[serde(flatten)]
+    pub paste: Paste,
+    pub slug: Option<SlugString>,
+}
+
+/// A set of errors that can occur while listing a user's pastes.
+#[derive(Debug, Error)]
+pub enum ListPastesError {
+    /// The request was made without being logged in.
+    #[error("You must be logged in to list your pastes")]
+    Unauthenticated,
+
+    /// The search didn't say what to look for.
+    #[error("The search text can't be empty")]
+    EmptyQuery,
+
+    /// An error occurred while communicating with the database.
+    #[error("An error occurred while communicating with the database")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl ListPastesError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ListPastesError::Unauthenticated => "unauthenticated",
+            ListPastesError::EmptyQuery => "empty_query",
+            ListPastesError::DatabaseError(_) => "database_error",
+        }
+    }
+}
+
+impl IntoResponse for ListPastesError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            ListPastesError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            ListPastesError::EmptyQuery => StatusCode::BAD_REQUEST,
+            ListPastesError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        ApiError::respond(status, self.code(), &self)
+    }
+}
+
+/// A set of errors that can occur while creating a new paste.
+#[derive(Debug, Error)]
+pub enum CreatePasteError {
+    /// Anonymous pastes are disabled and the request was made without being logged in.
+    #[error("You must be logged in to create a paste")]
+    Unauthenticated,
+
+    /// The requested custom slug is not in a valid format.
+    #[error("{0}")]
+    InvalidSlug(#[from] SlugError),
+
+    /// The requested custom slug contains a reserved word.
+    #[error("The slug contains the reserved word `{0}`")]
+    ReservedSlug(String),
+
+    /// The requested custom slug is already in use by another resource.
+    #[error("That slug is already taken")]
+    SlugTaken,
+
+    /// The paste content is larger than the configured limit.
+    #[error("The paste content is larger than the maximum of {0} bytes")]
+    ContentTooLarge(usize),
+
+    /// The paste would already have expired by the time it's created.
+    #[error("The paste's expiry must be in the future")]
+    ExpiryInPast,
+
+    /// A unique slug could not be generated after several attempts.
+    #[error("Could not generate a unique slug for the paste")]
+    SlugGenerationFailure,
+
+    /// The `Idempotency-Key` header is empty, too long or not visible ASCII.
+    #[error(
+        "The Idempotency-Key header must be between 1 and {MAX_IDEMPOTENCY_KEY_LENGTH} visible \
+         ASCII characters"
+    )]
+    InvalidIdempotencyKey,
+
+    /// Another request with the same `Idempotency-Key` is still creating its paste.
+    #[error("A paste is already being created with that Idempotency-Key")]
+    IdempotencyKeyInUse,
+
+    /// The anonymous session's idempotency scope couldn't be read from or stored in the session.
+    #[error("An error occurred while accessing the session")]
+    SessionFailure(#[from] tower_sessions::session::Error),
+
+    /// The paste's password could not be hashed.
+    #[error("Could not hash the paste's password")]
+    PasswordHashFailure(#[source] argon2::password_hash::Error),
+
+    /// An error occurred while communicating with the database.
+    #[error("An error occurred while communicating with the database")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl CreatePasteError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CreatePasteError::Unauthenticated => "unauthenticated",
+            CreatePasteError::InvalidSlug(_) => "invalid_slug",
+            CreatePasteError::ReservedSlug(_) => "reserved_slug",
+            CreatePasteError::SlugTaken => "slug_taken",
+            CreatePasteError::ContentTooLarge(_) => "content_too_large",
+            CreatePasteError::ExpiryInPast => "expiry_in_past",
+            CreatePasteError::SlugGenerationFailure => "slug_generation_failure",
+            CreatePasteError::InvalidIdempotencyKey => "invalid_idempotency_key",
+            CreatePasteError::IdempotencyKeyInUse => "idempotency_key_in_use",
+            CreatePasteError::SessionFailure(_) => "session_failure",
+            CreatePasteError::PasswordHashFailure(_) => "password_hash_failure",
+            CreatePasteError::DatabaseError(_) => "database_error",
+        }
+    }
+
+    /// The status code the error is responded with, whether that's as JSON or as a page.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            CreatePasteError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            CreatePasteError::InvalidSlug(_) => StatusCode::BAD_REQUEST,
+            CreatePasteError::ReservedSlug(_) => StatusCode::BAD_REQUEST,
+            CreatePasteError::SlugTaken => StatusCode::CONFLICT,
+            CreatePasteError::ContentTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            CreatePasteError::ExpiryInPast => StatusCode::BAD_REQUEST,
+            CreatePasteError::SlugGenerationFailure => StatusCode::INTERNAL_SERVER_ERROR,
+            CreatePasteError::InvalidIdempotencyKey => StatusCode::BAD_REQUEST,
+            CreatePasteError::IdempotencyKeyInUse => StatusCode::CONFLICT,
+            CreatePasteError::SessionFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            CreatePasteError::PasswordHashFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            CreatePasteError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for CreatePasteError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+
+        ApiError::respond(status, self.code(), &self)
+    }
 }
 
 /// Create a new paste.
+///
+/// If a custom slug is given it will be used to share the paste, otherwise a random one is
+/// generated. Pastes are only created without a logged-in user if
+/// [Config::allow_anonymous_pastes] is enabled.
+///
+/// Requests sent with an [IDEMPOTENCY_KEY_HEADER] can be safely retried, a repeated request with
+/// the same key returns the paste the first one created for up to
+/// [Config::idempotency_key_expiry].
+#[utoipa::path(
+    post,
+    path = "/api/pastes",
+    tag = "pastes",
+    request_body = NewPasteParams,
+    params(
+        ("x-csrf-token" = String, Header, description = "The CSRF token of the session"),
+        (
+            "idempotency-key" = Option<String>, Header,
+            description = "Lets the request be retried without creating the paste twice"
+        ),
+    ),
+    responses(
+        (status = 200, description = "The paste was created", body = CreatedPaste),
+        (status = 400, description = "The paste or its slug is invalid", body = ApiError),
+        (status = 401, description = "Anonymous pastes are disabled", body = ApiError),
+        (status = 409, description = "The slug or idempotency key is in use", body = ApiError),
+        (status = 413, description = "The content is too large", body = ApiError),
+    ),
+)]
 pub async fn create_paste(
     ctx: Extension<ApiContext>,
     auth_session: AuthSession,
+    headers: HeaderMap,
     Json(paste): Json<NewPasteParams>,
-) -> Json<Paste> {
-    let user = auth_session.user;
-    let user_id = user.map(|u| u.id);
+) -> Result<Json<CreatedPaste>, CreatePasteError> {
+    let idempotency = idempotency_key(&headers, &auth_session)?;
+
+    if let Some(idempotency) = &idempotency {
+        if let Some(created) = find_idempotent_paste(&ctx, idempotency).await? {
+            return Ok(Json(created));
+        }
+    }
+
+    insert_paste(&ctx, auth_session.user, paste, idempotency.as_ref())
+        .await
+        .map(Json)
+}
+
+/// Reads the idempotency key a request was sent with, if any.
+///
+/// Keys belong to the logged in user, or otherwise to the anonymous session, which is given a
+/// random scope of its own the first time it sends one.
+fn idempotency_key(
+    headers: &HeaderMap,
+    auth_session: &AuthSession,
+) -> Result<Option<IdempotencyKey>, CreatePasteError> {
+    let Some(key) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH)
+        .ok_or(CreatePasteError::InvalidIdempotencyKey)?;
+
+    let scope = match &auth_session.user {
+        Some(user) => format!("user:{}", user.id),
+        None => format!("session:{}", anonymous_scope(&auth_session.session)?),
+    };
+
+    Ok(Some(IdempotencyKey {
+        scope,
+        key: key.to_string(),
+    }))
+}
+
+/// Gets the idempotency scope of an anonymous session, generating and storing a new one if needed.
+fn anonymous_scope(session: &Session) -> Result<String, tower_sessions::session::Error> {
+    if let Some(scope) = session.get::<String>(IDEMPOTENCY_SCOPE_SESSION_KEY)? {
+        return Ok(scope);
+    }
+
+    let scope = Uuid::new_v4().to_string();
+    session.insert(IDEMPOTENCY_SCOPE_SESSION_KEY, &scope)?;
+
+    Ok(scope)
+}
+
+/// Finds the paste that was created with an idempotency key, if the key hasn't expired yet.
+async fn find_idempotent_paste(
+    ctx: &ApiContext,
+    idempotency: &IdempotencyKey,
+) -> Result<Option<CreatedPaste>, sqlx::Error> {
+    let fresh_since = idempotency_fresh_since(&ctx.config);
+    let paste_id =
+        get_paste_id_by_idempotency_key(&ctx.db, &idempotency.scope, &idempotency.key, fresh_since)
+            .await?;
+    let Some(paste_id) = paste_id else {
+        return Ok(None);
+    };
+
+    let Some(paste) = get_paste_by_id(&ctx.db, paste_id).await? else {
+        return Ok(None);
+    };
+    let Some(slug) = get_slug_by_paste_id(&ctx.db, paste_id).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some(CreatedPaste {
+        paste: paste.paste,
+        slug: slug.slug,
+    }))
+}
+
+/// The earliest an idempotency key can have been used and still be remembered.
+fn idempotency_fresh_since(config: &Config) -> OffsetDateTime {
+    OffsetDateTime::now_utc() - Duration::seconds(config.idempotency_key_expiry)
+}
+
+/// Checks a new paste and inserts it along with its slug, on behalf of the given user if any.
+///
+/// This is shared by everything that can create pastes, so they're all held to the same rules.
+/// If an idempotency key is given and another request claims it first, the paste that request
+/// created is returned instead.
+pub async fn insert_paste(
+    ctx: &ApiContext,
+    user: Option<User>,
+    paste: NewPasteParams,
+    idempotency: Option<&IdempotencyKey>,
+) -> Result<CreatedPaste, CreatePasteError> {
+    let user_id = paste_owner(user, ctx.config.allow_anonymous_pastes)?;
+
+    check_content_length(&paste.content, ctx.config.max_paste_bytes)?;
+    let expires_at = paste_expiry(
+        paste.expires_at,
+        OffsetDateTime::now_utc(),
+        ctx.config.max_paste_lifetime,
+    )?;
+
+    // Make sure a custom slug is usable before we bother inserting anything.
+    let custom_slug = paste
+        .slug
+        .as_deref()
+        .map(|slug| parse_custom_slug(slug, &ctx.config.reserved_slug_words))
+        .transpose()?;
+
+    // An empty password wouldn't protect anything, so it's treated the same as no password.
+    let password_hash = paste
+        .password
+        .as_deref()
+        .filter(|password| !password.is_empty())
+        .map(hash_password)
+        .transpose()
+        .map_err(CreatePasteError::PasswordHashFailure)?;
+
+    // The paste and its slug are inserted in a transaction so we never end up with a paste that
+    // nobody can reach.
+    let mut tx = ctx.db.begin().await?;
 
     let paste = sqlx::query_file_as!(
         Paste,
@@ -43,11 +505,918 @@ pub async fn create_paste(
         user_id,
         paste.title,
         paste.content,
-        paste.expires_at
+        expires_at,
+        paste.language,
+        password_hash,
+        paste.render.map(|render| render.as_str())
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let slug = match custom_slug {
+        Some(slug) => insert_slug(&mut tx, paste.id, &slug)
+            .await?
+            .ok_or(CreatePasteError::SlugTaken)?,
+        None => insert_random_slug(&mut tx, paste.id, &ctx.config.reserved_slug_words)
+            .await?
+            .ok_or(CreatePasteError::SlugGenerationFailure)?,
+    };
+
+    if let Some(idempotency) = idempotency {
+        let fresh_since = idempotency_fresh_since(&ctx.config);
+        let claimed = claim_idempotency_key(
+            &mut tx,
+            &idempotency.scope,
+            &idempotency.key,
+            paste.id,
+            fresh_since,
+        )
+        .await?;
+
+        // A concurrent request with the same key got there first, so our paste is thrown away in
+        // favour of theirs.
+        if !claimed {
+            tx.rollback().await?;
+            return find_idempotent_paste(ctx, idempotency)
+                .await?
+                .ok_or(CreatePasteError::IdempotencyKeyInUse);
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(CreatedPaste {
+        paste,
+        slug: slug.slug,
+    })
+}
+
+/// Works out which user a new paste should belong to, if any.
+///
+/// Pastes belong to whoever is logged in. Anonymous pastes belong to nobody, and are rejected
+/// outright when `allow_anonymous` is disabled.
+pub fn paste_owner(
+    user: Option<User>,
+    allow_anonymous: bool,
+) -> Result<Option<i32>, CreatePasteError> {
+    match user {
+        Some(user) => Ok(Some(user.id)),
+        None if allow_anonymous => Ok(None),
+        None => Err(CreatePasteError::Unauthenticated),
+    }
+}
+
+/// List the authenticated user's pastes, newest first.
+///
+/// Results are paginated, the `created_at` and `id` of the last paste in a page can be passed as
+/// `before` and `before_id` to fetch the next one. Expired pastes are left out unless
+/// `include_expired` is set.
+#[utoipa::path(
+    get,
+    path = "/api/pastes",
+    tag = "pastes",
+    params(ListPastesParams),
+    responses(
+        (status = 200, description = "A page of the user's pastes", body = Vec<UserPaste>),
+        (status = 401, description = "The request isn't logged in", body = ApiError),
+    ),
+)]
+pub async fn list_pastes(
+    ctx: Extension<ApiContext>,
+    auth_session: AuthSession,
+    Query(params): Query<ListPastesParams>,
+) -> Result<Json<Vec<UserPaste>>, ListPastesError> {
+    let user = auth_session.user.ok_or(ListPastesError::Unauthenticated)?;
+    let pastes = fetch_user_pastes(&ctx.db, user.id, &params, ctx.config.paste_page_size).await?;
+
+    Ok(Json(pastes))
+}
+
+/// Fetches a single page of a user's pastes along with their slugs, newest first.
+pub async fn fetch_user_pastes(
+    db: &PgPool,
+    user_id: i32,
+    params: &ListPastesParams,
+    page_size: i64,
+) -> Result<Vec<UserPaste>, sqlx::Error> {
+    let page = PastesPage {
+        before: params.before,
+        before_id: params.before_id,
+        include_expired: params.include_expired,
+        page_size,
+    };
+
+    get_user_pastes(db, user_id, None, page).await
+}
+
+/// List the most recent public pastes, newest first.
+///
+/// Results are paginated the same way as [list_pastes]. Only routed when
+/// [Config::recent_pastes] is enabled.
+pub async fn recent_pastes(
+    ctx: Extension<ApiContext>,
+    Query(params): Query<RecentPastesParams>,
+) -> Result<Json<Vec<RecentPaste>>, ListPastesError> {
+    let page_size = ctx.config.paste_page_size;
+    let pastes = get_recent_pastes(&ctx.db, params.before, params.before_id, page_size).await?;
+
+    Ok(Json(pastes))
+}
+
+/// Searches the authenticated user's pastes for some text in their title or content, newest first.
+///
+/// Results are paginated the same way as [list_pastes].
+pub async fn search_pastes(
+    ctx: Extension<ApiContext>,
+    auth_session: AuthSession,
+    Query(params): Query<SearchPastesParams>,
+) -> Result<Json<Vec<UserPaste>>, ListPastesError> {
+    let user = auth_session.user.ok_or(ListPastesError::Unauthenticated)?;
+    if params.q.trim().is_empty() {
+        return Err(ListPastesError::EmptyQuery);
+    }
+    let pastes = search_user_pastes(&ctx.db, user.id, &params, ctx.config.paste_page_size).await?;
+
+    Ok(Json(pastes))
+}
+
+/// Fetches a single page of a user's pastes whose title or content contains the search text,
+/// ignoring case.
+pub async fn search_user_pastes(
+    db: &PgPool,
+    user_id: i32,
+    params: &SearchPastesParams,
+    page_size: i64,
+) -> Result<Vec<UserPaste>, sqlx::Error> {
+    let pattern = format!("%{}%", escape_like_pattern(&params.q));
+    let page = PastesPage {
+        before: params.before,
+        before_id: params.before_id,
+        include_expired: params.include_expired,
+        page_size,
+    };
+
+    get_user_pastes(db, user_id, Some(pattern), page).await
+}
+
+/// Which page of a user's pastes to fetch.
+struct PastesPage {
+    before: Option<OffsetDateTime>,
+    before_id: Option<PasteId>,
+    include_expired: bool,
+    page_size: i64,
+}
+
+/// Fetches a page of a user's pastes, newest first, optionally only those whose title or content
+/// matches a `LIKE` pattern.
+///
+/// Pastes are ordered by their ID after their creation time, so ones created at the same time are
+/// neither skipped nor repeated between pages. Each paste is listed once, along with the first
+/// slug it was given.
+async fn get_user_pastes(
+    db: &PgPool,
+    user_id: i32,
+    pattern: Option<String>,
+    page: PastesPage,
+) -> Result<Vec<UserPaste>, sqlx::Error> {
+    let rows = sqlx::query_file!(
+        "sql/get_pastes_by_user_id.sql",
+        user_id,
+        pattern,
+        page.before,
+        page.before_id.map(PasteId::get),
+        page.include_expired,
+        page.page_size
+    )
+    .fetch_all(db)
+    .await?;
+
+    let pastes = rows
+        .into_iter()
+        .map(|row| UserPaste {
+            paste: Paste {
+                id: row.id.into(),
+                user_id: row.user_id,
+                title: row.title,
+                content: row.content,
+                created_at: row.created_at,
+                expires_at: row.expires_at,
+                language: row.language,
+                views: row.views,
+                password_hash: row.password_hash,
+                render: row.render,
+            },
+            slug: row.slug,
+        })
+        .collect();
+
+    Ok(pastes)
+}
+
+/// Escapes the wildcards in some text so it's matched literally by `LIKE`/`ILIKE`.
+fn escape_like_pattern(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Makes sure the given paste content is no larger than `max_bytes`.
+pub fn check_content_length(content: &str, max_bytes: usize) -> Result<(), CreatePasteError> {
+    if content.len() > max_bytes {
+        return Err(CreatePasteError::ContentTooLarge(max_bytes));
+    }
+
+    Ok(())
+}
+
+/// Works out when a paste created at `now` should expire, given the expiry that was asked for.
+///
+/// Expiries that have already passed are rejected. If there's a `max_lifetime` in seconds, pastes
+/// that would outlive it (including ones that would never expire) are cut short to expire at the
+/// end of it instead.
+pub fn paste_expiry(
+    expires_at: Option<OffsetDateTime>,
+    now: OffsetDateTime,
+    max_lifetime: Option<i64>,
+) -> Result<Option<OffsetDateTime>, CreatePasteError> {
+    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(CreatePasteError::ExpiryInPast);
+    }
+
+    let Some(max_lifetime) = max_lifetime else {
+        return Ok(expires_at);
+    };
+
+    let latest = now + Duration::seconds(max_lifetime);
+    let expires_at = expires_at.map_or(latest, |expires_at| expires_at.min(latest));
+
+    Ok(Some(expires_at))
+}
+
+/// Validates a user-provided slug, making sure it's well-formed and doesn't contain a reserved
+/// word, either one of [crate::db::reserved_slugs::RESERVED_SLUGS] or the given extra ones.
+pub fn parse_custom_slug(
+    input: &str,
+    reserved_words: &[String],
+) -> Result<SlugString, CreatePasteError> {
+    let slug = SlugString::new(input)?;
+
+    match slug.reserved_word(reserved_words) {
+        Some(word) => Err(CreatePasteError::ReservedSlug(word.to_string())),
+        None => Ok(slug),
+    }
+}
+
+/// Links the given slug to the paste with the given ID.
+///
+/// Returns `None` if the slug is already taken.
+pub async fn insert_slug(
+    conn: &mut PgConnection,
+    paste_id: PasteId,
+    slug: &SlugString,
+) -> Result<Option<Slug>, sqlx::Error> {
+    sqlx::query_file_as!(
+        Slug,
+        "sql/insert_paste_slug.sql",
+        paste_id.get(),
+        slug.as_str()
     )
-    .fetch_one(&ctx.db)
+    .fetch_optional(&mut *conn)
     .await
-    .unwrap();
+}
+
+/// Generates a random slug that doesn't contain any of the given reserved words and links it to the
+/// paste with the given ID.
+///
+/// Slugs have around a billion possible combinations so collisions are rare, but if one does happen
+/// we retry with a fresh slug a few times. Returns `None` if every attempt collided.
+pub async fn insert_random_slug(
+    conn: &mut PgConnection,
+    paste_id: PasteId,
+    reserved_words: &[String],
+) -> Result<Option<Slug>, sqlx::Error> {
+    for _ in 0..SLUG_GENERATION_ATTEMPTS {
+        let slug = SlugString::generate(reserved_words);
+        let slug = insert_slug(conn, paste_id, &slug).await?;
+        if slug.is_some() {
+            return Ok(slug);
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{
+            header,
+            Request,
+        },
+    };
+    use clap::Parser;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::http::{
+        csrf::CSRF_HEADER,
+        testing::{
+            csrf_token,
+            insert_user,
+            login_as,
+            with_auth,
+        },
+    };
+
+    /// A fresh limiter for the paste routes, with the limit from the given config.
+    fn paste_rate_limiter(config: &Config) -> RateLimiter {
+        RateLimiter::per_minute(config.paste_rate_limit)
+    }
+
+    /// Inserts a bare paste to attach slugs to, returning its ID.
+    async fn insert_test_paste(conn: &mut PgConnection) -> sqlx::Result<PasteId> {
+        sqlx::query_scalar("INSERT INTO pastes (content) VALUES ('woof') RETURNING id")
+            .fetch_one(conn)
+            .await
+    }
+
+    fn reserved_words() -> Vec<String> {
+        vec!["api".to_string(), "static".to_string()]
+    }
+
+    #[sqlx::test]
+    async fn insert_random_slug_links_a_valid_slug(db: PgPool) -> sqlx::Result<()> {
+        let mut conn = db.acquire().await?;
+        let paste_id = insert_test_paste(&mut conn).await?;
+
+        let slug = insert_random_slug(&mut conn, paste_id, &reserved_words())
+            .await?
+            .expect("a slug to be generated");
+
+        assert_eq!(slug.paste_id, Some(paste_id));
+        assert!(SlugString::is_valid(slug.slug.as_str()));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn insert_slug_uses_valid_custom_slug(db: PgPool) -> sqlx::Result<()> {
+        let mut conn = db.acquire().await?;
+        let paste_id = insert_test_paste(&mut conn).await?;
+
+        let custom = parse_custom_slug("my-very-cool-paste", &reserved_words()).unwrap();
+        let slug = insert_slug(&mut conn, paste_id, &custom)
+            .await?
+            .expect("the custom slug to be free");
+
+        assert_eq!(slug.slug.as_str(), "my-very-cool-paste");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn insert_slug_returns_none_for_taken_slug(db: PgPool) -> sqlx::Result<()> {
+        let mut conn = db.acquire().await?;
+        let first_paste = insert_test_paste(&mut conn).await?;
+        let second_paste = insert_test_paste(&mut conn).await?;
+
+        let custom = parse_custom_slug("my-very-cool-paste", &reserved_words()).unwrap();
+        let first = insert_slug(&mut conn, first_paste, &custom).await?;
+        let second = insert_slug(&mut conn, second_paste, &custom).await?;
+        assert!(first.is_some());
+        assert!(second.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_custom_slug_rejects_reserved_words() {
+        let result = parse_custom_slug("my-cool-API-paste", &reserved_words());
+        assert!(matches!(result, Err(CreatePasteError::ReservedSlug(word)) if word == "API"));
+
+        // Route names are reserved even when no extra words are configured.
+        let result = parse_custom_slug("my-cool-healthz-paste", &[]);
+        assert!(matches!(result, Err(CreatePasteError::ReservedSlug(word)) if word == "healthz"));
+
+        assert!(parse_custom_slug("my-very-cool-paste", &[]).is_ok());
+    }
+
+    #[sqlx::test]
+    async fn fetch_user_pastes_paginates_newest_first(db: PgPool) -> sqlx::Result<()> {
+        let user_id = insert_user(&db, "woof").await?;
+
+        // Five pastes created a day apart, plus one that has already expired.
+        // language=postgresql
+        let query = "INSERT INTO pastes (user_id, content, created_at, expires_at) \
+                     SELECT $1, 'paste ' || n, now() - n * interval '1 day', NULL \
+                     FROM generate_series(1, 5) AS n \
+                     UNION ALL \
+                     SELECT $1, 'expired', now(), now() - interval '1 hour'";
+        sqlx::query(query).bind(user_id).execute(&db).await?;
+
+        let contents = |pastes: &[UserPaste]| -> Vec<String> {
+            pastes.iter().map(|p| p.paste.content.clone()).collect()
+        };
+
+        let mut params = ListPastesParams::default();
+        let first = fetch_user_pastes(&db, user_id, &params, 2).await?;
+        assert_eq!(contents(&first), vec!["paste 1", "paste 2"]);
+
+        params.before = Some(first[1].paste.created_at);
+        let second = fetch_user_pastes(&db, user_id, &params, 2).await?;
+        assert_eq!(contents(&second), vec!["paste 3", "paste 4"]);
+
+        params.before = Some(second[1].paste.created_at);
+        let last = fetch_user_pastes(&db, user_id, &params, 2).await?;
+        assert_eq!(contents(&last), vec!["paste 5"]);
+
+        let with_expired = ListPastesParams {
+            include_expired: true,
+            ..Default::default()
+        };
+        let first = fetch_user_pastes(&db, user_id, &with_expired, 2).await?;
+        assert_eq!(contents(&first), vec!["expired", "paste 1"]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn search_user_pastes_matches_title_and_content(db: PgPool) -> sqlx::Result<()> {
+        let user_id = insert_user(&db, "woof").await?;
+
+        // language=postgresql
+        let query = "INSERT INTO pastes (user_id, title, content, created_at) VALUES \
+                     ($1, 'Meatloaf Recipe', 'mix it all up', now() - interval '2 days'), \
+                     ($1, NULL, 'the best MEATLOAF ever', now() - interval '1 day'), \
+                     ($1, 'Shopping list', '100% beef', now())";
+        sqlx::query(query).bind(user_id).execute(&db).await?;
+
+        let search = |q: &str| SearchPastesParams {
+            q: q.to_string(),
+            ..Default::default()
+        };
+        let contents = |pastes: Vec<UserPaste>| -> Vec<String> {
+            pastes.into_iter().map(|p| p.paste.content).collect()
+        };
+
+        let found = search_user_pastes(&db, user_id, &search("meatloaf"), 10).await?;
+        assert_eq!(
+            contents(found),
+            vec!["the best MEATLOAF ever", "mix it all up"]
+        );
+
+        let found = search_user_pastes(&db, user_id, &search("woof"), 10).await?;
+        assert!(found.is_empty());
+
+        // Wildcards are matched literally.
+        let found = search_user_pastes(&db, user_id, &search("0%"), 10).await?;
+        assert_eq!(contents(found), vec!["100% beef"]);
+        let found = search_user_pastes(&db, user_id, &search("_"), 10).await?;
+        assert!(found.is_empty());
+
+        // Other users' pastes are never searched.
+        let found = search_user_pastes(&db, user_id + 1, &search("meatloaf"), 10).await?;
+        assert!(found.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn pastes_created_together_are_paged_once_each(db: PgPool) -> sqlx::Result<()> {
+        let user_id = insert_user(&db, "woof").await?;
+
+        // Three pastes created in the same instant, the first of which has two slugs.
+        // language=postgresql
+        let query = "WITH inserted AS ( \
+                         INSERT INTO pastes (user_id, content, created_at) \
+                         SELECT $1, 'paste ' || n, '2024-01-01T00:00:00Z' \
+                         FROM generate_series(1, 3) AS n \
+                         RETURNING id, content \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, 'paste-number-one-' || n FROM inserted, generate_series(1, 2) AS n \
+                     WHERE content = 'paste 1'";
+        sqlx::query(query).bind(user_id).execute(&db).await?;
+
+        let mut params = ListPastesParams::default();
+        let mut contents = Vec::new();
+        loop {
+            let page = fetch_user_pastes(&db, user_id, &params, 2).await?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            params.before = Some(last.paste.created_at);
+            params.before_id = Some(last.paste.id);
+            contents.extend(
+                page.into_iter()
+                    .map(|p| (p.paste.content, p.slug.map(|s| s.as_str().to_string()))),
+            );
+        }
+
+        let slug = Some("paste-number-one-1".to_string());
+        assert_eq!(
+            contents,
+            vec![
+                ("paste 3".to_string(), None),
+                ("paste 2".to_string(), None),
+                ("paste 1".to_string(), slug),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn searching_for_nothing_is_rejected(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(router(&config, paste_rate_limiter(&config)), db.clone());
+        let (_, cookie, _) = logged_in_user(&app, &db).await;
+
+        for q in ["", "%20%20"] {
+            let request = Request::builder()
+                .uri(format!("/api/pastes/search?q={q}"))
+                .header(header::COOKIE, &cookie)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{q:?}");
+        }
+    }
+
+    #[sqlx::test]
+    async fn recent_pastes_paginates_public_pastes_newest_first(db: PgPool) -> sqlx::Result<()> {
+        // Five public pastes created a day apart, plus ones that aren't public in different ways.
+        // language=postgresql
+        let query = "WITH inserted AS ( \
+                         INSERT INTO pastes \
+                             (title, content, created_at, expires_at, password_hash) \
+                         SELECT 'paste ' || n, '', now() - n * interval '1 day', NULL, NULL \
+                         FROM generate_series(1, 5) AS n \
+                         UNION ALL SELECT 'expired', '', now(), now() - interval '1 hour', NULL \
+                         UNION ALL SELECT 'password', '', now(), NULL, 'hash' \
+                         UNION ALL SELECT 'disabled', '', now(), NULL, NULL \
+                         RETURNING id, title \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug, enabled) \
+                     SELECT id, 'recent-paste-number-' || id, \
+                         CASE WHEN title = 'disabled' THEN NULL ELSE now() END \
+                     FROM inserted";
+        sqlx::query(query).execute(&db).await?;
+
+        let titles = |pastes: &[RecentPaste]| -> Vec<String> {
+            pastes.iter().filter_map(|p| p.title.clone()).collect()
+        };
+
+        let first = get_recent_pastes(&db, None, None, 2).await?;
+        assert_eq!(titles(&first), vec!["paste 1", "paste 2"]);
+
+        let second =
+            get_recent_pastes(&db, Some(first[1].created_at), Some(first[1].id), 2).await?;
+        assert_eq!(titles(&second), vec!["paste 3", "paste 4"]);
+
+        let last =
+            get_recent_pastes(&db, Some(second[1].created_at), Some(second[1].id), 2).await?;
+        assert_eq!(titles(&last), vec!["paste 5"]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn recent_pastes_route_lists_each_paste_once_across_pages(
+        db: PgPool,
+    ) -> sqlx::Result<()> {
+        // Three public pastes created in the same instant, the first of which has two slugs.
+        // language=postgresql
+        let query = "WITH inserted AS ( \
+                         INSERT INTO pastes (title, content, created_at) \
+                         SELECT 'paste ' || n, '', '2024-01-01T00:00:00Z' \
+                         FROM generate_series(1, 3) AS n \
+                         RETURNING id, title \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug, enabled) \
+                     SELECT id, 'recent-paste-' || id || '-' || n, now() \
+                     FROM inserted, generate_series(1, 2) AS n \
+                     WHERE title = 'paste 1' OR n = 1";
+        sqlx::query(query).execute(&db).await?;
+
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--recent-pastes",
+            "true",
+            "--paste-page-size",
+            "2",
+        ]);
+        let ctx = ApiContext {
+            config: Arc::new(config),
+            ..ApiContext::for_tests(db)
+        };
+        let app = router(&ctx.config, paste_rate_limiter(&ctx.config)).layer(Extension(ctx));
+
+        let mut uri = "/api/pastes/recent".to_string();
+        let mut titles = Vec::new();
+        loop {
+            let request = Request::builder().uri(&uri).body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+            let Some(last) = body.last() else {
+                break;
+            };
+            uri = format!(
+                "/api/pastes/recent?before=2024-01-01T00:00:00Z&before_id={}",
+                last["id"]
+            );
+            titles.extend(
+                body.iter()
+                    .map(|p| p["title"].as_str().unwrap().to_string()),
+            );
+        }
+
+        assert_eq!(titles, vec!["paste 3", "paste 2", "paste 1"]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn recent_pastes_route_is_off_by_default(db: PgPool) {
+        let ctx = ApiContext::for_tests(db);
+        let app = router(&ctx.config, paste_rate_limiter(&ctx.config)).layer(Extension(ctx));
+
+        let request = Request::builder()
+            .uri("/api/pastes/recent")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn check_content_length_accepts_content_at_the_limit() {
+        assert!(check_content_length(&"a".repeat(16), 16).is_ok());
+    }
+
+    #[test]
+    fn check_content_length_rejects_content_over_the_limit() {
+        let result = check_content_length(&"a".repeat(17), 16);
+        assert!(matches!(result, Err(CreatePasteError::ContentTooLarge(16))));
+    }
+
+    #[test]
+    fn paste_expiry_accepts_future_expiry() {
+        let now = OffsetDateTime::now_utc();
+        let expires_at = now + Duration::minutes(5);
+
+        assert_eq!(
+            paste_expiry(Some(expires_at), now, None).unwrap(),
+            Some(expires_at)
+        );
+        assert_eq!(paste_expiry(None, now, None).unwrap(), None);
+    }
+
+    #[test]
+    fn paste_expiry_rejects_past_expiry() {
+        let now = OffsetDateTime::now_utc();
+        let result = paste_expiry(Some(now - Duration::minutes(5)), now, None);
+        assert!(matches!(result, Err(CreatePasteError::ExpiryInPast)));
+
+        let result = paste_expiry(Some(now), now, None);
+        assert!(matches!(result, Err(CreatePasteError::ExpiryInPast)));
+    }
+
+    #[test]
+    fn paste_expiry_is_clamped_to_the_max_lifetime() {
+        let now = OffsetDateTime::now_utc();
+        let day = Duration::days(1);
+        let max_lifetime = Some(day.whole_seconds());
+
+        let soon = now + Duration::hours(1);
+        assert_eq!(
+            paste_expiry(Some(soon), now, max_lifetime).unwrap(),
+            Some(soon)
+        );
+
+        let later = now + Duration::days(2);
+        assert_eq!(
+            paste_expiry(Some(later), now, max_lifetime).unwrap(),
+            Some(now + day)
+        );
+
+        // A paste that would never expire is kept for as long as it's allowed to be.
+        assert_eq!(
+            paste_expiry(None, now, max_lifetime).unwrap(),
+            Some(now + day)
+        );
+    }
+
+    #[tokio::test]
+    async fn database_error_responds_with_api_error() {
+        let response = CreatePasteError::DatabaseError(sqlx::Error::PoolTimedOut).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("An error occurred while communicating with the database"));
+
+        // The cause is only logged, it could give away details about the database.
+        let cause = sqlx::Error::PoolTimedOut.to_string();
+        assert!(!body["message"].as_str().unwrap().contains(&cause));
+    }
+
+    fn user() -> User {
+        User {
+            id: 7,
+            uuid: uuid::Uuid::new_v4(),
+            username: "woof".to_string(),
+            email: None,
+            created_at: OffsetDateTime::now_utc(),
+            last_authentication: None,
+            session_version: 0,
+            session_auth_hash: String::new(),
+        }
+    }
+
+    #[test]
+    fn paste_owner_allows_anonymous_pastes_when_enabled() {
+        assert!(matches!(paste_owner(None, true), Ok(None)));
+        assert!(matches!(paste_owner(Some(user()), true), Ok(Some(7))));
+    }
+
+    #[test]
+    fn paste_owner_rejects_anonymous_pastes_when_disabled() {
+        let err = paste_owner(None, false).unwrap_err();
+        assert!(matches!(err, CreatePasteError::Unauthenticated));
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+
+        assert!(matches!(paste_owner(Some(user()), false), Ok(Some(7))));
+    }
+
+    #[test]
+    fn parse_custom_slug_rejects_invalid_format() {
+        let result = parse_custom_slug("not-a-slug", &reserved_words());
+        assert!(matches!(
+            result,
+            Err(CreatePasteError::InvalidSlug(SlugError::WrongWordCount(3)))
+        ));
+
+        let err = parse_custom_slug("not-a-good-slug!", &reserved_words()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid slug format, `!` isn't allowed, only letters, digits and dashes are"
+        );
+    }
+
+    /// Creates a paste as the logged in user with the given idempotency key, returning its slug.
+    async fn create_with_key(app: &Router, cookie: &str, token: &str, key: &str) -> String {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/pastes")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::COOKIE, cookie)
+            .header(CSRF_HEADER, token)
+            .header(IDEMPOTENCY_KEY_HEADER, key)
+            .body(Body::from(r#"{"content":"woof"}"#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        body["slug"].as_str().unwrap().to_string()
+    }
+
+    /// Inserts a user and logs in as them, returning their ID, session cookie and CSRF token.
+    async fn logged_in_user(app: &Router, db: &PgPool) -> (i32, String, String) {
+        let user_id = insert_user(db, "woof").await.unwrap();
+        let cookie = login_as(app, user_id).await;
+        let token = csrf_token(app, &cookie).await;
+
+        (user_id, cookie, token)
+    }
+
+    async fn count_user_pastes(db: &PgPool, user_id: i32) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM pastes WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn repeated_idempotency_key_creates_one_paste(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(router(&config, paste_rate_limiter(&config)), db.clone());
+        let (user_id, cookie, token) = logged_in_user(&app, &db).await;
+
+        let first = create_with_key(&app, &cookie, &token, "same-key").await;
+        let second = create_with_key(&app, &cookie, &token, "same-key").await;
+
+        assert_eq!(first, second);
+        assert_eq!(count_user_pastes(&db, user_id).await, 1);
+    }
+
+    /// Creates a paste without logging in, as the anonymous session with the given cookie if there
+    /// is one, returning its slug along with the session cookie that was set.
+    async fn create_anonymously(
+        app: &Router,
+        cookie: Option<&str>,
+        key: &str,
+    ) -> (String, Option<String>) {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/api/pastes")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(IDEMPOTENCY_KEY_HEADER, key);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let request = request.body(Body::from(r#"{"content":"woof"}"#)).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let set_cookie = response.headers().get(header::SET_COOKIE).map(|value| {
+            value
+                .to_str()
+                .unwrap()
+                .split(';')
+                .next()
+                .unwrap()
+                .to_string()
+        });
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        (body["slug"].as_str().unwrap().to_string(), set_cookie)
+    }
+
+    #[sqlx::test]
+    async fn idempotency_keys_are_scoped_to_the_anonymous_session(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(router(&config, paste_rate_limiter(&config)), db.clone());
+
+        let (first, cookie) = create_anonymously(&app, None, "same-key").await;
+        let cookie = cookie.expect("the session to be given a scope");
+        let (second, _) = create_anonymously(&app, Some(&cookie), "same-key").await;
+        assert_eq!(first, second);
+
+        // Another session sending the same key gets a paste of its own.
+        let (other, _) = create_anonymously(&app, None, "same-key").await;
+        assert_ne!(first, other);
+    }
+
+    #[sqlx::test]
+    async fn expired_idempotency_keys_create_a_new_paste(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(router(&config, paste_rate_limiter(&config)), db.clone());
+        let (user_id, cookie, token) = logged_in_user(&app, &db).await;
+
+        let first = create_with_key(&app, &cookie, &token, "same-key").await;
+        sqlx::query(
+            "UPDATE paste_idempotency_keys SET created_at = now() - make_interval(secs => $1)",
+        )
+        .bind(config.idempotency_key_expiry as f64 + 1.0)
+        .execute(&db)
+        .await
+        .unwrap();
+        let second = create_with_key(&app, &cookie, &token, "same-key").await;
+
+        assert_ne!(first, second);
+        assert_eq!(count_user_pastes(&db, user_id).await, 2);
+
+        // The key is remembered again from the second paste onwards.
+        let third = create_with_key(&app, &cookie, &token, "same-key").await;
+        assert_eq!(second, third);
+    }
+
+    #[sqlx::test]
+    async fn different_idempotency_keys_create_separate_pastes(db: PgPool) {
+        let config = ApiContext::for_tests(db.clone()).config;
+        let app = with_auth(router(&config, paste_rate_limiter(&config)), db.clone());
+        let (user_id, cookie, token) = logged_in_user(&app, &db).await;
+
+        let first = create_with_key(&app, &cookie, &token, "first-key").await;
+        let second = create_with_key(&app, &cookie, &token, "second-key").await;
 
-    Json(paste)
+        assert_ne!(first, second);
+        assert_eq!(count_user_pastes(&db, user_id).await, 2);
+    }
 }
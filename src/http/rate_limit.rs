@@ -0,0 +1,239 @@
+//! Middleware that throttles how often a single client can hit an endpoint.
+
+use std::{
+    collections::HashMap,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        SocketAddr,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use axum::{
+    extract::{
+        ConnectInfo,
+        Request,
+        State,
+    },
+    http::{
+        header,
+        StatusCode,
+    },
+    middleware::Next,
+    response::{
+        IntoResponse,
+        Response,
+    },
+};
+use thiserror::Error;
+
+use crate::http::error::ApiError;
+
+/// How many clients are tracked before buckets that have fully refilled are thrown away.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A token bucket rate limiter keyed by client IP address.
+///
+/// Every client starts with a full bucket of `capacity` tokens, each request takes one, and tokens
+/// trickle back in at a steady rate until the bucket is full again. This allows short bursts while
+/// still capping the sustained rate.
+#[derive(Clone)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+    capacity: f64,
+    refill_per_second: f64,
+}
+
+/// The tokens a single client has left, as of `updated`.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter that allows `requests` requests per minute from each client.
+    pub fn per_minute(requests: u32) -> RateLimiter {
+        RateLimiter {
+            buckets: Arc::default(),
+            capacity: f64::from(requests),
+            refill_per_second: f64::from(requests) / 60.0,
+        }
+    }
+
+    /// Takes a token from the client's bucket.
+    ///
+    /// If the bucket is empty the request should be rejected, and the returned [Duration] is how
+    /// long the client has to wait until it can try again.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.capacity);
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_second))
+        }
+    }
+
+    /// Works out how many tokens a bucket has at `now`, without going over capacity.
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity)
+    }
+}
+
+/// A set of errors that can occur while rate limiting a request.
+#[derive(Debug, Error)]
+pub enum RateLimitError {
+    /// The client has made too many requests, holds how many seconds until it can try again.
+    #[error("Too many requests, try again in {0} second(s)")]
+    TooManyRequests(u64),
+}
+
+impl RateLimitError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RateLimitError::TooManyRequests(_) => "too_many_requests",
+        }
+    }
+}
+
+impl IntoResponse for RateLimitError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let (status, retry_after) = match self {
+            RateLimitError::TooManyRequests(seconds) => (StatusCode::TOO_MANY_REQUESTS, seconds),
+        };
+
+        (
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            ApiError::respond(status, self.code(), &self),
+        )
+            .into_response()
+    }
+}
+
+/// Middleware that rejects requests from clients that have gone over the [RateLimiter]'s limit.
+///
+/// Clients are told how long to back off for with a `Retry-After` header. Requests without
+/// connection info (i.e. the app wasn't served with it) all share a single bucket.
+pub async fn rate_limit(
+    State(limiter): State<RateLimiter>,
+    request: Request,
+    next: Next,
+) -> Result<Response, RateLimitError> {
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |info| info.0.ip());
+
+    limiter.check(client).map_err(|retry_after| {
+        let seconds = retry_after.as_secs_f64().ceil() as u64;
+        RateLimitError::TooManyRequests(seconds.max(1))
+    })?;
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http,
+        middleware,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn request_from(ip: [u8; 4]) -> http::Request<Body> {
+        let mut request = http::Request::builder()
+            .uri("/")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+        request
+    }
+
+    #[test]
+    fn limiter_allows_a_burst_up_to_capacity() {
+        let limiter = RateLimiter::per_minute(3);
+        let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        for _ in 0..3 {
+            assert!(limiter.check(client).is_ok());
+        }
+
+        let retry_after = limiter.check(client).unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(20));
+    }
+
+    #[test]
+    fn limiter_tracks_clients_separately() {
+        let limiter = RateLimiter::per_minute(1);
+
+        assert!(limiter
+            .check(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+            .is_ok());
+        assert!(limiter
+            .check(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+            .is_err());
+        assert!(limiter
+            .check(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_rejected() {
+        let app = Router::new().route("/", get(|| async { "woof" })).layer(
+            middleware::from_fn_with_state(RateLimiter::per_minute(2), rate_limit),
+        );
+
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(request_from([10, 0, 0, 1]))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(request_from([10, 0, 0, 1]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+
+        // Somebody else shouldn't be punished for it.
+        let response = app.oneshot(request_from([10, 0, 0, 2])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,302 @@
+//! Deciding where users can be sent after logging in or out.
+//!
+//! Redirect targets come from query parameters, so anyone can craft a link that would send a user
+//! off to another site once they're done. The server has the final say on where that can be: a
+//! path on woof itself, or a URL on one of [Config::allowed_redirect_hosts], and either way under
+//! one of [Config::allowed_redirect_paths].
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{
+        IntoResponse,
+        Response,
+    },
+    routing::get,
+    Extension,
+    Json,
+    Router,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use thiserror::Error;
+use url::{
+    Position,
+    Url,
+};
+
+use crate::{
+    config::Config,
+    http::{
+        error::ApiError,
+        ApiContext,
+    },
+};
+
+/// Stands in for woof's own origin while resolving relative targets, it can never be a real host.
+const PLACEHOLDER_ORIGIN: &str = "http://woof.invalid";
+
+pub fn router() -> Router {
+    Router::new().route("/api/redirect/validate", get(validate))
+}
+
+/// A set of errors that can occur while checking where a redirect goes.
+#[derive(Debug, Error)]
+pub enum RedirectError {
+    /// The target isn't a path or an `http`/`https` URL.
+    #[error("`{0}` is not a valid redirect target")]
+    Invalid(String),
+
+    /// The target is on a host that users can't be redirected to.
+    #[error("Redirects to `{0}` are not allowed")]
+    DisallowedHost(String),
+
+    /// The target is on a path that users can't be redirected to.
+    #[error("Redirects to `{0}` are not allowed")]
+    DisallowedPath(String),
+}
+
+impl RedirectError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RedirectError::Invalid(_) => "invalid_redirect",
+            RedirectError::DisallowedHost(_) => "disallowed_redirect_host",
+            RedirectError::DisallowedPath(_) => "disallowed_redirect_path",
+        }
+    }
+}
+
+impl IntoResponse for RedirectError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            RedirectError::Invalid(_) => StatusCode::BAD_REQUEST,
+            RedirectError::DisallowedHost(_) => StatusCode::BAD_REQUEST,
+            RedirectError::DisallowedPath(_) => StatusCode::BAD_REQUEST,
+        };
+
+        ApiError::respond(status, self.code(), &self)
+    }
+}
+
+/// Makes sure users can be redirected to the given target, returning it in the form it should be
+/// redirected to.
+///
+/// Relative targets have to be absolute paths (e.g. `/paste/woof`), and come back that way with
+/// any `.` or `..` segments resolved. Anything else has to be an `http` or `https` URL on one of
+/// [Config::allowed_redirect_hosts].
+pub fn validate_redirect(config: &Config, target: &str) -> Result<String, RedirectError> {
+    let invalid = || RedirectError::Invalid(target.to_string());
+    let base = Url::parse(PLACEHOLDER_ORIGIN).unwrap();
+
+    // A relative target that isn't a path (e.g. `woof`) depends on the page it's followed from.
+    let is_relative = target.starts_with('/');
+    let url = if is_relative {
+        base.join(target)
+    } else {
+        Url::parse(target)
+    }
+    .map_err(|_| invalid())?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid());
+    }
+
+    let host = url.host_str().ok_or_else(invalid)?;
+    if is_relative {
+        // Targets like `//evil.example` or `/\evil.example` look like paths, but browsers take
+        // them to another host.
+        if url.origin() != base.origin() {
+            return Err(RedirectError::DisallowedHost(host.to_string()));
+        }
+    } else if !url.username().is_empty() || url.password().is_some() {
+        return Err(invalid());
+    } else if !config
+        .allowed_redirect_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+        return Err(RedirectError::DisallowedHost(host.to_string()));
+    }
+
+    let path = url.path();
+    if !config
+        .allowed_redirect_paths
+        .iter()
+        .any(|allowed| is_under_path(path, allowed))
+    {
+        return Err(RedirectError::DisallowedPath(path.to_string()));
+    }
+
+    if is_relative {
+        Ok(url[Position::BeforePath..].to_string())
+    } else {
+        Ok(url.to_string())
+    }
+}
+
+/// Whether a path is the given path or somewhere underneath it, going by whole segments so that
+/// `/paste` covers `/paste/woof` but not `/pastebin`.
+fn is_under_path(path: &str, allowed: &str) -> bool {
+    let allowed = allowed.trim_end_matches('/');
+    path.strip_prefix(allowed)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Parameters for checking a redirect target.
+#[derive(Deserialize)]
+pub struct ValidateParams {
+    /// The path or URL a user is about to be redirected to.
+    target: String,
+}
+
+/// A redirect target that users can be sent to.
+#[derive(Serialize)]
+pub struct ValidatedRedirect {
+    /// The target in the form it should be redirected to.
+    target: String,
+}
+
+/// Checks whether users can be redirected to the given target, so clients can find out before
+/// sending them there.
+pub async fn validate(
+    ctx: Extension<ApiContext>,
+    Query(params): Query<ValidateParams>,
+) -> Result<Json<ValidatedRedirect>, RedirectError> {
+    let target = validate_redirect(&ctx.config, &params.target)?;
+    Ok(Json(ValidatedRedirect { target }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::Request,
+    };
+    use clap::Parser;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn config() -> Config {
+        Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--allowed-redirect-hosts",
+            "woof.example",
+            "--allowed-redirect-paths",
+            "/,/paste",
+        ])
+    }
+
+    #[test]
+    fn paths_and_allowed_hosts_can_be_redirected_to() {
+        let config = config();
+
+        for (target, expected) in [
+            ("/", "/"),
+            ("/paste/woof?raw=1#top", "/paste/woof?raw=1#top"),
+            ("/me/../paste", "/paste"),
+            ("https://woof.example/me", "https://woof.example/me"),
+            ("https://WOOF.example", "https://woof.example/"),
+        ] {
+            assert_eq!(validate_redirect(&config, target).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn other_hosts_cant_be_redirected_to() {
+        let config = config();
+
+        for target in [
+            "//evil.example",
+            "/\\evil.example",
+            "/\t/evil.example",
+            "https://evil.example/paste",
+            "https://woof.example.evil.example",
+        ] {
+            assert!(
+                matches!(
+                    validate_redirect(&config, target),
+                    Err(RedirectError::DisallowedHost(_))
+                ),
+                "{target}"
+            );
+        }
+
+        for target in [
+            "javascript:alert(1)",
+            "data:text/html,woof",
+            "paste/woof",
+            "https://woof@woof.example",
+            "",
+        ] {
+            assert!(
+                matches!(
+                    validate_redirect(&config, target),
+                    Err(RedirectError::Invalid(_))
+                ),
+                "{target}"
+            );
+        }
+    }
+
+    #[test]
+    fn only_allowed_paths_can_be_redirected_to() {
+        let mut config = config();
+        config.allowed_redirect_paths = vec!["/paste".to_string(), "/me/".to_string()];
+
+        assert!(validate_redirect(&config, "/paste").is_ok());
+        assert!(validate_redirect(&config, "/paste/woof").is_ok());
+        assert!(validate_redirect(&config, "/me").is_ok());
+        assert!(validate_redirect(&config, "https://woof.example/paste/woof").is_ok());
+
+        for target in [
+            "/",
+            "/pastebin",
+            "/paste/../auth",
+            "https://woof.example/auth",
+        ] {
+            assert!(
+                matches!(
+                    validate_redirect(&config, target),
+                    Err(RedirectError::DisallowedPath(_))
+                ),
+                "{target}"
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn validate_endpoint_reports_whether_a_target_is_allowed(db: PgPool) {
+        let app = router().layer(Extension(ApiContext::for_tests(db)));
+        let validate = |target: &str| {
+            Request::builder()
+                .uri(format!("/api/redirect/validate?target={target}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(validate("%2Fme")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["target"], "/me");
+
+        let target = "https%3A%2F%2Fevil.example";
+        let response = app.oneshot(validate(target)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["code"], "disallowed_redirect_host");
+    }
+}
@@ -0,0 +1,294 @@
+//! Middleware that adds security related headers to every response.
+
+use std::{
+    convert::Infallible,
+    fmt,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{
+        FromRequestParts,
+        Request,
+        State,
+    },
+    http::{
+        header,
+        request::Parts,
+        HeaderName,
+        HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+};
+use base64::{
+    engine::general_purpose::URL_SAFE_NO_PAD,
+    Engine,
+};
+use rand_core::{
+    OsRng,
+    RngCore,
+};
+
+use crate::config::Config;
+
+/// A random value generated for every response, which inline scripts are tagged with so the
+/// `Content-Security-Policy` can allow them without allowing every inline script.
+///
+/// Handlers rendering a page with an inline script extract it and pass it to their template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CspNonce(String);
+
+impl CspNonce {
+    /// Generates a new nonce from 16 random bytes.
+    pub fn generate() -> CspNonce {
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+        CspNonce(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CspNonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CspNonce
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    /// Gets the nonce [set_security_headers] generated for the request.
+    ///
+    /// Without the middleware there's no policy for the nonce to match, so any nonce will do.
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let nonce = parts.extensions.get::<CspNonce>().cloned();
+        Ok(nonce.unwrap_or_else(CspNonce::generate))
+    }
+}
+
+/// The set of security headers added to every response by [set_security_headers].
+#[derive(Clone, Default)]
+pub struct SecurityHeaders {
+    headers: Arc<Vec<(HeaderName, HeaderValue)>>,
+    /// Whether the `Content-Security-Policy` allows scripts tagged with the response's [CspNonce].
+    csp_nonce: bool,
+}
+
+impl SecurityHeaders {
+    /// Builds the set of security headers from the application [Config].
+    ///
+    /// If security headers are disabled in the config, the set will be empty.
+    pub fn new(config: &Config) -> SecurityHeaders {
+        if !config.security_headers {
+            return SecurityHeaders::default();
+        }
+
+        let headers = Arc::new(vec![
+            (
+                header::CONTENT_SECURITY_POLICY,
+                config.content_security_policy.clone(),
+            ),
+            (
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_static("max-age=63072000; includeSubDomains"),
+            ),
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+            (header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+            (
+                header::REFERRER_POLICY,
+                HeaderValue::from_static("strict-origin-when-cross-origin"),
+            ),
+        ]);
+
+        SecurityHeaders {
+            headers,
+            csp_nonce: config.csp_nonce,
+        }
+    }
+}
+
+/// Middleware that adds the given [SecurityHeaders] to a response.
+///
+/// A fresh [CspNonce] is handed to the handler for every request, and added to the
+/// `Content-Security-Policy` if [Config::csp_nonce] is enabled. Headers that were already set by
+/// a handler are left alone, so individual routes can still override them.
+pub async fn set_security_headers(
+    State(headers): State<SecurityHeaders>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let nonce = CspNonce::generate();
+    request.extensions_mut().insert(nonce.clone());
+
+    let mut response = next.run(request).await;
+
+    for (name, value) in headers.headers.iter() {
+        response.headers_mut().entry(name).or_insert_with(|| {
+            if headers.csp_nonce && name == header::CONTENT_SECURITY_POLICY {
+                add_nonce(value, &nonce)
+            } else {
+                value.clone()
+            }
+        });
+    }
+
+    response
+}
+
+/// Allows scripts tagged with the given nonce in a `Content-Security-Policy`.
+///
+/// The nonce is added to `script-src`, or to `default-src` when scripts fall back to it. Policies
+/// with neither don't restrict scripts at all, so they're left alone.
+fn add_nonce(policy: &HeaderValue, nonce: &CspNonce) -> HeaderValue {
+    let Ok(policy_str) = policy.to_str() else {
+        return policy.clone();
+    };
+
+    let directives: Vec<&str> = policy_str
+        .split(';')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .collect();
+    let has_directive = |name: &str| {
+        directives
+            .iter()
+            .any(|directive| directive_name(directive).eq_ignore_ascii_case(name))
+    };
+
+    let target = if has_directive("script-src") {
+        "script-src"
+    } else if has_directive("default-src") {
+        "default-src"
+    } else {
+        return policy.clone();
+    };
+
+    let patched = directives
+        .iter()
+        .map(|directive| {
+            if directive_name(directive).eq_ignore_ascii_case(target) {
+                format!("{directive} 'nonce-{nonce}'")
+            } else {
+                directive.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    HeaderValue::from_str(&patched).unwrap_or_else(|_| policy.clone())
+}
+
+/// The name of a `Content-Security-Policy` directive, e.g. `script-src`.
+fn directive_name(directive: &str) -> &str {
+    directive.split_whitespace().next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http,
+        middleware,
+        routing::get,
+        Router,
+    };
+    use clap::Parser;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn app(config: &Config) -> Router {
+        Router::new()
+            .route("/", get(|| async { "woof" }))
+            .layer(middleware::from_fn_with_state(
+                SecurityHeaders::new(config),
+                set_security_headers,
+            ))
+    }
+
+    async fn get_index(app: Router) -> Response {
+        let request = http::Request::builder()
+            .uri("/")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn security_headers_are_added_to_responses() {
+        let config = Config::parse_from(["woof", "--database-url", "postgres://localhost/woof"]);
+        let response = get_index(app(&config)).await;
+
+        let headers = response.headers();
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert!(headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+        assert!(headers.contains_key(header::CONTENT_SECURITY_POLICY));
+    }
+
+    #[tokio::test]
+    async fn security_headers_can_be_disabled() {
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--security-headers",
+            "false",
+        ]);
+        let response = get_index(app(&config)).await;
+
+        assert!(!response
+            .headers()
+            .contains_key(header::X_CONTENT_TYPE_OPTIONS));
+    }
+
+    #[test]
+    fn nonce_is_added_to_the_script_sources() {
+        let nonce = CspNonce("d29vZg".to_string());
+
+        let policy = HeaderValue::from_static("default-src 'self'; script-src 'self'");
+        assert_eq!(
+            add_nonce(&policy, &nonce),
+            "default-src 'self'; script-src 'self' 'nonce-d29vZg'"
+        );
+
+        // Scripts fall back to `default-src` without a `script-src` of their own.
+        let policy = HeaderValue::from_static("default-src 'self'; img-src data:");
+        assert_eq!(
+            add_nonce(&policy, &nonce),
+            "default-src 'self' 'nonce-d29vZg'; img-src data:"
+        );
+
+        let policy = HeaderValue::from_static("frame-ancestors 'none'");
+        assert_eq!(add_nonce(&policy, &nonce), "frame-ancestors 'none'");
+    }
+
+    #[tokio::test]
+    async fn every_response_gets_a_fresh_nonce() {
+        let config = Config::parse_from(["woof", "--database-url", "postgres://localhost/woof"]);
+        let app = app(&config);
+
+        let first = get_index(app.clone()).await;
+        let second = get_index(app).await;
+
+        let policy = |response: &Response| {
+            response.headers()[header::CONTENT_SECURITY_POLICY]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert!(policy(&first).contains("'nonce-"));
+        assert_ne!(policy(&first), policy(&second));
+    }
+}
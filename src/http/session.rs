@@ -0,0 +1,112 @@
+//! The cookie attributes shared by every session the server hands out.
+
+use tower_sessions::{
+    cookie::SameSite,
+    SessionManagerLayer,
+    SessionStore,
+};
+
+use crate::config::Config;
+
+/// The `SameSite` policy session cookies are set with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SameSitePolicy {
+    /// Only sent along with requests that come from woof itself.
+    Strict,
+    /// Also sent when following a link to woof from another site.
+    Lax,
+    /// Sent with every request, including ones made by other sites.
+    None,
+}
+
+impl From<SameSitePolicy> for SameSite {
+    fn from(policy: SameSitePolicy) -> Self {
+        match policy {
+            SameSitePolicy::Strict => SameSite::Strict,
+            SameSitePolicy::Lax => SameSite::Lax,
+            SameSitePolicy::None => SameSite::None,
+        }
+    }
+}
+
+/// Creates a [SessionManagerLayer] for the given store with its cookie set up as configured by
+/// [Config::cookie_secure], [Config::cookie_domain] and [Config::cookie_same_site].
+pub fn session_layer<Store: SessionStore>(
+    store: Store,
+    config: &Config,
+) -> SessionManagerLayer<Store> {
+    let layer = SessionManagerLayer::new(store)
+        .with_secure(config.cookie_secure)
+        .with_same_site(config.cookie_same_site.into());
+
+    match &config.cookie_domain {
+        Some(domain) => layer.with_domain(domain),
+        None => layer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header,
+            Request,
+        },
+        routing::get,
+        Router,
+    };
+    use clap::Parser;
+    use tower::ServiceExt;
+    use tower_sessions::{
+        MemoryStore,
+        Session,
+    };
+
+    use super::*;
+
+    /// Gets the `Set-Cookie` header of a response from a route that starts a session.
+    async fn set_cookie(config: &Config) -> String {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|session: Session| async move {
+                    session.insert("woof", true).unwrap();
+                }),
+            )
+            .layer(session_layer(MemoryStore::default(), config));
+
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn session_cookies_have_configured_attributes() {
+        let config = Config::parse_from(["woof", "--database-url", "postgres://localhost/woof"]);
+        let cookie = set_cookie(&config).await;
+        assert!(cookie.contains("Secure"));
+        assert!(cookie.contains("SameSite=Lax"));
+        assert!(!cookie.contains("Domain="));
+
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--cookie-secure",
+            "false",
+            "--cookie-domain",
+            "woof.example",
+            "--cookie-same-site",
+            "strict",
+        ]);
+        let cookie = set_cookie(&config).await;
+        assert!(!cookie.contains("Secure"));
+        assert!(cookie.contains("SameSite=Strict"));
+        assert!(cookie.contains("Domain=woof.example"));
+    }
+}
@@ -0,0 +1,208 @@
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    middleware,
+    response::{
+        IntoResponse,
+        Response,
+    },
+    routing::post,
+    Extension,
+    Json,
+    Router,
+};
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::{
+    auth::passkeys::backend::AuthSession,
+    db::slugs::{
+        Slug,
+        SlugString,
+    },
+    http::{
+        csrf::require_csrf_token,
+        error::ApiError,
+        ApiContext,
+    },
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/slugs/:slug/enable", post(enable_slug))
+        .route("/api/slugs/:slug/disable", post(disable_slug))
+        .route_layer(middleware::from_fn(require_csrf_token))
+}
+
+/// A set of errors that can occur while enabling or disabling a slug.
+#[derive(Debug, Error)]
+pub enum SlugStateError {
+    /// The request was made without being logged in.
+    #[error("You must be logged in to change a slug")]
+    Unauthenticated,
+
+    /// The slug doesn't exist.
+    #[error("That slug does not exist")]
+    NotFound,
+
+    /// The slug points to a resource that belongs to somebody else, or to nobody at all.
+    #[error("You don't own the resource this slug points to")]
+    Forbidden,
+
+    /// An error occurred while communicating with the database.
+    #[error("An error occurred while communicating with the database")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl SlugStateError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SlugStateError::Unauthenticated => "unauthenticated",
+            SlugStateError::NotFound => "not_found",
+            SlugStateError::Forbidden => "forbidden",
+            SlugStateError::DatabaseError(_) => "database_error",
+        }
+    }
+}
+
+impl IntoResponse for SlugStateError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            SlugStateError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            SlugStateError::NotFound => StatusCode::NOT_FOUND,
+            SlugStateError::Forbidden => StatusCode::FORBIDDEN,
+            SlugStateError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        ApiError::respond(status, self.code(), &self)
+    }
+}
+
+/// Enables a slug, bringing the resource it points to back online.
+pub async fn enable_slug(
+    ctx: Extension<ApiContext>,
+    auth_session: AuthSession,
+    Path(slug): Path<String>,
+) -> Result<Json<Slug>, SlugStateError> {
+    let user = auth_session.user.ok_or(SlugStateError::Unauthenticated)?;
+    let slug = set_slug_enabled(&ctx.db, user.id, &slug, true).await?;
+
+    Ok(Json(slug))
+}
+
+/// Disables a slug, taking the resource it points to offline without deleting it.
+pub async fn disable_slug(
+    ctx: Extension<ApiContext>,
+    auth_session: AuthSession,
+    Path(slug): Path<String>,
+) -> Result<Json<Slug>, SlugStateError> {
+    let user = auth_session.user.ok_or(SlugStateError::Unauthenticated)?;
+    let slug = set_slug_enabled(&ctx.db, user.id, &slug, false).await?;
+
+    Ok(Json(slug))
+}
+
+/// Enables or disables a slug on behalf of the user with the given ID.
+///
+/// Only the owner of the paste or file the slug points to can change it. Resources created
+/// anonymously don't belong to anybody, so their slugs can't be changed at all.
+pub async fn set_slug_enabled(
+    db: &PgPool,
+    user_id: i32,
+    slug: &str,
+    enabled: bool,
+) -> Result<Slug, SlugStateError> {
+    // Anything that isn't a valid slug can't possibly exist.
+    if !SlugString::is_well_formed(slug) {
+        return Err(SlugStateError::NotFound);
+    }
+
+    let owner = sqlx::query_file_scalar!("sql/get_slug_owner.sql", slug)
+        .fetch_optional(db)
+        .await?
+        .ok_or(SlugStateError::NotFound)?;
+
+    if owner != Some(user_id) {
+        return Err(SlugStateError::Forbidden);
+    }
+
+    let slug = if enabled {
+        sqlx::query_file_as!(Slug, "sql/enable_slug.sql", slug)
+            .fetch_one(db)
+            .await?
+    } else {
+        sqlx::query_file_as!(Slug, "sql/disable_slug.sql", slug)
+            .fetch_one(db)
+            .await?
+    };
+
+    Ok(slug)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::testing::insert_user;
+
+    /// Inserts a paste with the given slug owned by a new user, returning the user's ID.
+    async fn insert_owned_paste(db: &PgPool, slug: &str) -> sqlx::Result<i32> {
+        let user_id = insert_user(db, "woof").await?;
+
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (user_id, content) VALUES ($1, 'woof') RETURNING id \
+                     ) \
+                     INSERT INTO slugs (paste_id, slug) \
+                     SELECT id, $2 FROM paste";
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(slug)
+            .execute(db)
+            .await?;
+
+        Ok(user_id)
+    }
+
+    #[sqlx::test]
+    async fn owner_can_disable_and_enable_slug(db: PgPool) -> sqlx::Result<()> {
+        let owner = insert_owned_paste(&db, "toggle-me-on-off").await?;
+
+        let slug = set_slug_enabled(&db, owner, "toggle-me-on-off", false)
+            .await
+            .unwrap();
+        assert!(slug.enabled.is_none());
+
+        let slug = set_slug_enabled(&db, owner, "toggle-me-on-off", true)
+            .await
+            .unwrap();
+        assert!(slug.enabled.is_some());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn non_owner_cannot_change_slug(db: PgPool) -> sqlx::Result<()> {
+        let owner = insert_owned_paste(&db, "not-yours-to-touch").await?;
+
+        let err = set_slug_enabled(&db, owner + 1, "not-yours-to-touch", false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SlugStateError::Forbidden));
+        assert_eq!(err.into_response().status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn unknown_slug_is_not_found(db: PgPool) -> sqlx::Result<()> {
+        let err = set_slug_enabled(&db, 1, "this-does-not-exist", false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SlugStateError::NotFound));
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+}
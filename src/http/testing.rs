@@ -0,0 +1,208 @@
+//! Helpers for testing handlers that need a logged in user, along with fixtures for them.
+
+use axum::{
+    body::Body,
+    error_handling::HandleErrorLayer,
+    extract::Path,
+    http::{
+        header,
+        Request,
+        StatusCode,
+    },
+    response::Response,
+    routing::post,
+    BoxError,
+    Extension,
+    Router,
+};
+use axum_login::AuthManagerLayerBuilder;
+use sqlx::PgPool;
+use tower::{
+    ServiceBuilder,
+    ServiceExt,
+};
+use tower_sessions::{
+    MemoryStore,
+    SessionManagerLayer,
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        passkeys::backend::{
+            AuthSession,
+            PasskeyBackend,
+        },
+        CHALLENGE_SESSION_COOKIE,
+    },
+    db::users::User,
+    http::{
+        csrf,
+        ApiContext,
+    },
+};
+
+/// Logs in the user with the given ID, standing in for a passkey ceremony.
+async fn login(
+    ctx: Extension<ApiContext>,
+    mut auth_session: AuthSession,
+    Path(id): Path<i32>,
+) -> StatusCode {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(id)
+        .fetch_one(&ctx.db)
+        .await
+        .unwrap();
+    auth_session.login(&user).await.unwrap();
+    StatusCode::OK
+}
+
+/// Wraps a router with the authentication layers and a test context, along with a route that
+/// logs users in for [login_as] and the route that hands out CSRF tokens for [csrf_token].
+pub fn with_auth(router: Router, db: PgPool) -> Router {
+    let session_layer = SessionManagerLayer::new(MemoryStore::default()).with_secure(false);
+    let auth_service = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(|_: BoxError| async {
+            StatusCode::BAD_REQUEST
+        }))
+        .layer(
+            AuthManagerLayerBuilder::new(PasskeyBackend::new(db.clone()), session_layer).build(),
+        );
+
+    router
+        .merge(csrf::router())
+        .route("/test/login/:id", post(login))
+        .layer(auth_service)
+        .layer(Extension(ApiContext::for_tests(db)))
+}
+
+/// Logs in as the given user, returning the session cookie.
+pub async fn login_as(app: &Router, user_id: i32) -> String {
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/test/login/{user_id}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+/// Fetches the CSRF token of the session with the given cookie, to send along with requests that
+/// change state.
+pub async fn csrf_token(app: &Router, cookie: &str) -> String {
+    let request = Request::builder()
+        .uri("/api/csrf")
+        .header(header::COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    json["token"].as_str().unwrap().to_string()
+}
+
+/// Builds a JSON `POST` request, sending along the given session cookie if there is one.
+pub fn post_json(uri: &str, cookie: Option<&str>, body: &serde_json::Value) -> Request<Body> {
+    let mut request = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json");
+    if let Some(cookie) = cookie {
+        request = request.header(header::COOKIE, cookie);
+    }
+
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+/// Gets the `code` of an error response.
+pub async fn error_code(response: Response) -> serde_json::Value {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    json["code"].clone()
+}
+
+/// Starts registering a passkey for `woof`, returning the cookie of the challenge session it
+/// opens.
+pub async fn challenge_session(app: &Router) -> String {
+    let params = serde_json::json!({ "username": "woof" });
+    let request = post_json("/api/users/start_register", None, &params);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+    assert!(cookie.starts_with(CHALLENGE_SESSION_COOKIE));
+
+    cookie
+}
+
+/// Inserts a user without any credentials, returning their ID.
+pub async fn insert_user(db: &PgPool, username: &str) -> sqlx::Result<i32> {
+    sqlx::query_scalar(
+        "INSERT INTO users (username, uuid) VALUES ($1, gen_random_uuid()) RETURNING id",
+    )
+    .bind(username)
+    .fetch_one(db)
+    .await
+}
+
+/// The ID of the credential inserted by [insert_user_with_passkey].
+pub const PASSKEY_CREDENTIAL_ID: &str = "d29vZndvb2Z3b29md29vZg";
+
+/// Inserts a user with a single passkey credential, returning their UUID.
+pub async fn insert_user_with_passkey(
+    db: &PgPool,
+    username: &str,
+    email: Option<&str>,
+) -> sqlx::Result<Uuid> {
+    let user_uuid = Uuid::new_v4();
+    sqlx::query("INSERT INTO users (username, uuid, email) VALUES ($1, $2, $3)")
+        .bind(username)
+        .bind(user_uuid)
+        .bind(email)
+        .execute(db)
+        .await?;
+
+    let passkey = serde_json::json!({
+        "cred": {
+            "cred_id": PASSKEY_CREDENTIAL_ID,
+            "cred": {
+                "type_": "ES256",
+                "key": {
+                    "EC_EC2": {
+                        "curve": "SECP256R1",
+                        "x": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                        "y": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
+                    }
+                }
+            },
+            "counter": 0,
+            "transports": ["internal", "hybrid"],
+            "user_verified": true,
+            "backup_eligible": false,
+            "backup_state": false,
+            "registration_policy": "required",
+            "extensions": {
+                "cred_protect": "NotRequested",
+                "hmac_create_secret": "NotRequested",
+                "appid": "NotRequested",
+                "cred_props": "Ignored"
+            },
+            "attestation": { "data": "None", "metadata": "None" },
+            "attestation_format": "none"
+        }
+    });
+    sqlx::query("INSERT INTO credentials (user_uuid, passkey) VALUES ($1, $2)")
+        .bind(user_uuid)
+        .bind(passkey)
+        .execute(db)
+        .await?;
+
+    Ok(user_uuid)
+}
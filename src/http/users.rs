@@ -0,0 +1,763 @@
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    middleware,
+    response::{
+        IntoResponse,
+        Response,
+    },
+    routing::{
+        delete,
+        get,
+        patch,
+        post,
+    },
+    Extension,
+    Json,
+    Router,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sqlx::types::time::OffsetDateTime;
+use thiserror::Error;
+
+use crate::{
+    auth::passkeys::{
+        aaguid::authenticator_name,
+        backend::{
+            AuthSession,
+            PasskeyBackend,
+        },
+    },
+    db::{
+        credentials::{
+            get_credentials_by_user_uuid,
+            update_credential_label,
+            CredentialInfo,
+        },
+        pastes::{
+            get_paste_summaries_by_user_id,
+            PasteSummary,
+        },
+        users::{
+            bump_session_version,
+            delete_user,
+            User,
+        },
+    },
+    http::{
+        csrf::require_csrf_token,
+        error::ApiError,
+        ApiContext,
+    },
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/users/me", get(current_user).delete(delete_account))
+        .route("/api/users/me/logout-all", post(logout_all))
+        .route("/api/users/me/export", get(export_user_data))
+        .route("/api/users/credentials", get(list_credentials))
+        .route("/api/users/credentials/:id", patch(rename_credential))
+        .route_layer(middleware::from_fn(require_csrf_token))
+}
+
+/// Parameters for renaming one of the user's credentials.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RenameCredentialParams {
+    /// The new label for the credential, or `None` to remove it.
+    label: Option<String>,
+}
+
+/// The authenticated user, as they're shown to themselves.
+///
+/// Only the fields listed here are ever sent, so internal details of a [User] like their IDs or
+/// session auth hash can't end up in a response by accident.
+#[derive(Debug, Serialize)]
+pub struct CurrentUser {
+    pub username: String,
+    pub created_at: OffsetDateTime,
+    pub last_authentication: Option<OffsetDateTime>,
+}
+
+impl From<User> for CurrentUser {
+    fn from(user: User) -> Self {
+        CurrentUser {
+            username: user.username,
+            created_at: user.created_at,
+            last_authentication: user.last_authentication,
+        }
+    }
+}
+
+/// A set of errors that can occur while getting the authenticated user.
+#[derive(Debug, Error)]
+pub enum CurrentUserError {
+    /// The request was made without being logged in.
+    #[error("You are not logged in")]
+    Unauthenticated,
+}
+
+impl CurrentUserError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CurrentUserError::Unauthenticated => "unauthenticated",
+        }
+    }
+}
+
+impl IntoResponse for CurrentUserError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            CurrentUserError::Unauthenticated => StatusCode::UNAUTHORIZED,
+        };
+
+        ApiError::respond(status, self.code(), &self)
+    }
+}
+
+/// Everything about the authenticated user that's safe to hand back to them, so they can take
+/// their data elsewhere.
+///
+/// Credentials are exported as [ListedCredential]s, which leave out the passkeys themselves, so no
+/// key material or signature counters can end up in an export.
+#[derive(Debug, Serialize)]
+pub struct UserExport {
+    pub profile: ExportedProfile,
+    pub credentials: Vec<ListedCredential>,
+    pub pastes: Vec<PasteSummary>,
+}
+
+/// The authenticated user as they're shown to themselves, along with their email address.
+#[derive(Debug, Serialize)]
+pub struct ExportedProfile {
+    #[serde(flatten)]
+    pub user: CurrentUser,
+    pub email: Option<String>,
+}
+
+/// A set of errors that can occur while exporting a user's data.
+#[derive(Debug, Error)]
+pub enum ExportError {
+    /// The request was made without being logged in.
+    #[error("You must be logged in to export your data")]
+    Unauthenticated,
+
+    /// An error occurred while communicating with the database.
+    #[error("An error occurred while communicating with the database")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl ExportError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExportError::Unauthenticated => "unauthenticated",
+            ExportError::DatabaseError(_) => "database_error",
+        }
+    }
+}
+
+impl IntoResponse for ExportError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            ExportError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            ExportError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        ApiError::respond(status, self.code(), &self)
+    }
+}
+
+/// A set of errors that can occur while deleting a user's account.
+#[derive(Debug, Error)]
+pub enum DeleteAccountError {
+    /// The request was made without being logged in.
+    #[error("You must be logged in to delete your account")]
+    Unauthenticated,
+
+    /// The account was deleted, but the user couldn't be logged out.
+    #[error("Your account was deleted, but you could not be logged out")]
+    LogoutFailure(#[source] axum_login::Error<PasskeyBackend>),
+
+    /// An error occurred while communicating with the database.
+    #[error("An error occurred while communicating with the database")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl DeleteAccountError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DeleteAccountError::Unauthenticated => "unauthenticated",
+            DeleteAccountError::LogoutFailure(_) => "logout_failure",
+            DeleteAccountError::DatabaseError(_) => "database_error",
+        }
+    }
+}
+
+impl IntoResponse for DeleteAccountError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            DeleteAccountError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            DeleteAccountError::LogoutFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            DeleteAccountError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        ApiError::respond(status, self.code(), &self)
+    }
+}
+
+/// A set of errors that can occur while logging a user out of all of their sessions.
+#[derive(Debug, Error)]
+pub enum LogoutAllError {
+    /// The request was made without being logged in.
+    #[error("You must be logged in to log out of your sessions")]
+    Unauthenticated,
+
+    /// Every other session was logged out, but the current one couldn't be kept logged in.
+    #[error("Your other sessions were logged out, but you could not be logged back in")]
+    LoginFailure(#[source] axum_login::Error<PasskeyBackend>),
+
+    /// An error occurred while communicating with the database.
+    #[error("An error occurred while communicating with the database")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl LogoutAllError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LogoutAllError::Unauthenticated => "unauthenticated",
+            LogoutAllError::LoginFailure(_) => "login_failure",
+            LogoutAllError::DatabaseError(_) => "database_error",
+        }
+    }
+}
+
+impl IntoResponse for LogoutAllError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            LogoutAllError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            LogoutAllError::LoginFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            LogoutAllError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        ApiError::respond(status, self.code(), &self)
+    }
+}
+
+/// One of the user's credentials, along with the name of the authenticator that created it if
+/// it's a well-known one.
+#[derive(Debug, Serialize)]
+pub struct ListedCredential {
+    #[serde(flatten)]
+    pub credential: CredentialInfo,
+    pub authenticator: Option<&'static str>,
+}
+
+impl From<CredentialInfo> for ListedCredential {
+    fn from(credential: CredentialInfo) -> Self {
+        let authenticator = credential.aaguid.as_ref().and_then(authenticator_name);
+        ListedCredential {
+            credential,
+            authenticator,
+        }
+    }
+}
+
+/// A set of errors that can occur while managing a user's credentials.
+#[derive(Debug, Error)]
+pub enum CredentialsError {
+    /// The request was made without being logged in.
+    #[error("You must be logged in to manage your credentials")]
+    Unauthenticated,
+
+    /// The credential doesn't exist, or belongs to someone else.
+    #[error("That credential does not exist")]
+    NotFound,
+
+    /// An error occurred while communicating with the database.
+    #[error("An error occurred while communicating with the database")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl CredentialsError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CredentialsError::Unauthenticated => "unauthenticated",
+            CredentialsError::NotFound => "not_found",
+            CredentialsError::DatabaseError(_) => "database_error",
+        }
+    }
+}
+
+impl IntoResponse for CredentialsError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            CredentialsError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            CredentialsError::NotFound => StatusCode::NOT_FOUND,
+            CredentialsError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        ApiError::respond(status, self.code(), &self)
+    }
+}
+
+/// Tells the client who they're logged in as, so they can find out without scraping a page.
+pub async fn current_user(
+    auth_session: AuthSession,
+) -> Result<Json<CurrentUser>, CurrentUserError> {
+    let user = auth_session.user.ok_or(CurrentUserError::Unauthenticated)?;
+
+    Ok(Json(user.into()))
+}
+
+/// Lists the authenticated user's credentials along with their labels and authenticators.
+pub async fn list_credentials(
+    ctx: Extension<ApiContext>,
+    auth_session: AuthSession,
+) -> Result<Json<Vec<ListedCredential>>, CredentialsError> {
+    let user = auth_session.user.ok_or(CredentialsError::Unauthenticated)?;
+    let credentials = get_credentials_by_user_uuid(&ctx.db, user.uuid)
+        .await?
+        .into_iter()
+        .map(ListedCredential::from)
+        .collect();
+
+    Ok(Json(credentials))
+}
+
+/// Renames one of the authenticated user's credentials.
+///
+/// Credentials belonging to other users are treated as if they don't exist.
+pub async fn rename_credential(
+    ctx: Extension<ApiContext>,
+    auth_session: AuthSession,
+    Path(id): Path<i32>,
+    Json(params): Json<RenameCredentialParams>,
+) -> Result<Json<ListedCredential>, CredentialsError> {
+    let user = auth_session.user.ok_or(CredentialsError::Unauthenticated)?;
+    let credential = update_credential_label(&ctx.db, id, user.uuid, params.label)
+        .await?
+        .ok_or(CredentialsError::NotFound)?;
+
+    Ok(Json(credential.into()))
+}
+
+/// Exports the authenticated user's profile along with the metadata of their credentials and
+/// pastes, for when they want a copy of what woof knows about them.
+pub async fn export_user_data(
+    ctx: Extension<ApiContext>,
+    auth_session: AuthSession,
+) -> Result<Json<UserExport>, ExportError> {
+    let user = auth_session.user.ok_or(ExportError::Unauthenticated)?;
+
+    let credentials = get_credentials_by_user_uuid(&ctx.db, user.uuid)
+        .await?
+        .into_iter()
+        .map(ListedCredential::from)
+        .collect();
+    let pastes = get_paste_summaries_by_user_id(&ctx.db, user.id).await?;
+
+    Ok(Json(UserExport {
+        profile: ExportedProfile {
+            email: user.email.clone(),
+            user: user.into(),
+        },
+        credentials,
+        pastes,
+    }))
+}
+
+/// Deletes the authenticated user's account and credentials, then logs them out.
+///
+/// Whether their pastes are deleted too or kept around anonymously is decided by
+/// [crate::config::Config::delete_user_pastes].
+pub async fn delete_account(
+    ctx: Extension<ApiContext>,
+    mut auth_session: AuthSession,
+) -> Result<StatusCode, DeleteAccountError> {
+    let user = auth_session
+        .user
+        .clone()
+        .ok_or(DeleteAccountError::Unauthenticated)?;
+
+    delete_user(&ctx.db, &user, ctx.config.delete_user_pastes).await?;
+
+    auth_session
+        .logout()
+        .map_err(DeleteAccountError::LogoutFailure)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Logs the authenticated user out of every session they have, for when their account might be
+/// compromised.
+///
+/// Bumping the user's session version changes their session auth hash, so every session stops
+/// authenticating on its next request. The current session is logged back in with the new hash so
+/// the user isn't kicked out of the one they made the request from.
+pub async fn logout_all(
+    ctx: Extension<ApiContext>,
+    mut auth_session: AuthSession,
+) -> Result<StatusCode, LogoutAllError> {
+    let user = auth_session
+        .user
+        .clone()
+        .ok_or(LogoutAllError::Unauthenticated)?;
+
+    let user = bump_session_version(&ctx.db, user.id)
+        .await?
+        .ok_or(LogoutAllError::Unauthenticated)?;
+
+    auth_session
+        .login(&user)
+        .await
+        .map_err(LogoutAllError::LoginFailure)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header,
+            Request,
+        },
+    };
+    use serde_json::{
+        json,
+        Value,
+    };
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        db::credentials::insert_credential,
+        http::{
+            csrf::CSRF_HEADER,
+            testing::{
+                csrf_token,
+                insert_user,
+                insert_user_with_passkey,
+                login_as,
+                with_auth,
+                PASSKEY_CREDENTIAL_ID,
+            },
+        },
+    };
+
+    fn app(db: PgPool) -> Router {
+        with_auth(router(), db)
+    }
+
+    /// The AAGUID of iCloud Keychain.
+    const ICLOUD_KEYCHAIN: &str = "fbfc3007-154e-4ecc-8c0b-6e020557d7bd";
+
+    /// Creates a user with a single labelled credential, returning the user's ID and UUID.
+    async fn create_user(db: &PgPool, username: &str) -> sqlx::Result<(i32, Uuid)> {
+        let user_id = insert_user(db, username).await?;
+        let user_uuid: Uuid = sqlx::query_scalar("SELECT uuid FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(db)
+            .await?;
+
+        let mut conn = db.acquire().await?;
+        insert_credential(
+            &mut conn,
+            user_uuid,
+            json!({}),
+            Some("Work laptop".to_string()),
+            Uuid::parse_str(ICLOUD_KEYCHAIN).ok(),
+        )
+        .await?;
+
+        Ok((user_id, user_uuid))
+    }
+
+    async fn rename(app: &Router, cookie: &str, id: i32, label: &str) -> Response {
+        let token = csrf_token(app, cookie).await;
+        let request = Request::builder()
+            .method("PATCH")
+            .uri(format!("/api/users/credentials/{id}"))
+            .header(header::COOKIE, cookie)
+            .header(CSRF_HEADER, token)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "label": label }).to_string()))
+            .unwrap();
+
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn list(app: &Router, cookie: &str) -> Value {
+        let request = Request::builder()
+            .uri("/api/users/credentials")
+            .header(header::COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn current_user_is_only_shown_to_themselves(db: PgPool) -> sqlx::Result<()> {
+        let (user_id, _) = create_user(&db, "woof").await?;
+        let app = app(db);
+
+        let request = Request::builder()
+            .uri("/api/users/me")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let cookie = login_as(&app, user_id).await;
+        let request = Request::builder()
+            .uri("/api/users/me")
+            .header(header::COOKIE, &cookie)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let user: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user["username"], "woof");
+
+        let mut fields: Vec<_> = user.as_object().unwrap().keys().collect();
+        fields.sort();
+        assert_eq!(fields, ["created_at", "last_authentication", "username"]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn export_has_metadata_but_no_passkey_secrets(db: PgPool) -> sqlx::Result<()> {
+        let user_uuid = insert_user_with_passkey(&db, "woof", Some("woof@woof.example")).await?;
+        let user_id: i32 = sqlx::query_scalar("SELECT id FROM users WHERE uuid = $1")
+            .bind(user_uuid)
+            .fetch_one(&db)
+            .await?;
+        let paste_id: i32 = sqlx::query_scalar(
+            "INSERT INTO pastes (user_id, title, content) VALUES ($1, 'Bones', 'woof') \
+             RETURNING id",
+        )
+        .bind(user_id)
+        .fetch_one(&db)
+        .await?;
+        sqlx::query(
+            "INSERT INTO slugs (paste_id, slug) \
+             VALUES ($1, 'my-very-cool-paste'), ($1, 'another-name-for-it')",
+        )
+        .bind(paste_id)
+        .execute(&db)
+        .await?;
+
+        let app = app(db);
+        let export = || Request::builder().uri("/api/users/me/export");
+
+        let response = app
+            .clone()
+            .oneshot(export().body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let cookie = login_as(&app, user_id).await;
+        let request = export()
+            .header(header::COOKIE, &cookie)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let export: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(export["profile"]["username"], "woof");
+        assert_eq!(export["profile"]["email"], "woof@woof.example");
+        assert_eq!(export["credentials"].as_array().unwrap().len(), 1);
+        // The paste is only listed once however many slugs it has.
+        assert_eq!(export["pastes"].as_array().unwrap().len(), 1);
+        assert_eq!(export["pastes"][0]["title"], "Bones");
+        assert_eq!(export["pastes"][0]["slug"], "my-very-cool-paste");
+
+        // None of the stored passkey should make it into the export.
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!body.contains(PASSKEY_CREDENTIAL_ID));
+        let secrets = [
+            r#""passkey""#,
+            r#""cred""#,
+            r#""counter""#,
+            "EC_EC2",
+            "AAAAAAAAAAAA",
+        ];
+        for secret in secrets {
+            assert!(!body.contains(secret), "the export contains `{secret}`");
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn credential_label_is_set_and_renamed(db: PgPool) -> sqlx::Result<()> {
+        let (user_id, _) = create_user(&db, "woof").await?;
+        let app = app(db.clone());
+        let cookie = login_as(&app, user_id).await;
+
+        let credentials = list(&app, &cookie).await;
+        assert_eq!(credentials[0]["label"], "Work laptop");
+
+        let id = credentials[0]["id"].as_i64().unwrap() as i32;
+        let response = rename(&app, &cookie, id, "Phone").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let credentials = list(&app, &cookie).await;
+        assert_eq!(credentials[0]["label"], "Phone");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn credential_aaguid_is_persisted_and_named(db: PgPool) -> sqlx::Result<()> {
+        let (user_id, _) = create_user(&db, "woof").await?;
+        let app = app(db.clone());
+        let cookie = login_as(&app, user_id).await;
+
+        let credentials = list(&app, &cookie).await;
+        assert_eq!(credentials[0]["aaguid"], ICLOUD_KEYCHAIN);
+        assert_eq!(credentials[0]["authenticator"], "iCloud Keychain");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn credentials_of_other_users_cannot_be_renamed(db: PgPool) -> sqlx::Result<()> {
+        let (_, owner_uuid) = create_user(&db, "woof").await?;
+        let (other_id, _) = create_user(&db, "bark").await?;
+        let app = app(db.clone());
+        let cookie = login_as(&app, other_id).await;
+
+        let id: i32 = sqlx::query_scalar("SELECT id FROM credentials WHERE user_uuid = $1")
+            .bind(owner_uuid)
+            .fetch_one(&db)
+            .await?;
+        let response = rename(&app, &cookie, id, "Stolen").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let label: Option<String> =
+            sqlx::query_scalar("SELECT label FROM credentials WHERE id = $1")
+                .bind(id)
+                .fetch_one(&db)
+                .await?;
+        assert_eq!(label.as_deref(), Some("Work laptop"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn delete_account_removes_user_and_logs_out(db: PgPool) -> sqlx::Result<()> {
+        let user_id = insert_user(&db, "woof").await?;
+        sqlx::query("INSERT INTO credentials (user_uuid, passkey) SELECT uuid, '{}' FROM users")
+            .execute(&db)
+            .await?;
+
+        let app = app(db.clone());
+
+        let cookie = login_as(&app, user_id).await;
+        let token = csrf_token(&app, &cookie).await;
+
+        let delete = || {
+            Request::builder()
+                .method("DELETE")
+                .uri("/api/users/me")
+                .header(header::COOKIE, cookie.clone())
+                .header(CSRF_HEADER, token.clone())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(delete()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let users: i64 = sqlx::query_scalar("SELECT count(*) FROM users")
+            .fetch_one(&db)
+            .await?;
+        let credentials: i64 = sqlx::query_scalar("SELECT count(*) FROM credentials")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(users, 0);
+        assert_eq!(credentials, 0);
+
+        // The old session cookie should no longer be logged in.
+        let response = app.oneshot(delete()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn logout_all_revokes_other_sessions(db: PgPool) -> sqlx::Result<()> {
+        let (user_id, _) = create_user(&db, "woof").await?;
+        let app = app(db.clone());
+
+        let cookie = login_as(&app, user_id).await;
+        let other_cookie = login_as(&app, user_id).await;
+        let token = csrf_token(&app, &cookie).await;
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/users/me/logout-all")
+            .header(header::COOKIE, &cookie)
+            .header(CSRF_HEADER, token)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+        let credentials = |cookie: &str| {
+            Request::builder()
+                .uri("/api/users/credentials")
+                .header(header::COOKIE, cookie)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // The other session was logged in with the old session version, so it no longer counts.
+        let response = app
+            .clone()
+            .oneshot(credentials(&other_cookie))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // The session that asked for it stays logged in.
+        let response = app.oneshot(credentials(&cookie)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Sets up where log output goes and what it looks like.
+//!
+//! Everything is routed through `tracing`, with the `log` macros used throughout the application
+//! bridged over so nothing is lost. Which messages are shown can be tuned with `RUST_LOG`.
+
+use tracing::{
+    level_filters::LevelFilter,
+    Subscriber,
+};
+use tracing_subscriber::{
+    fmt::MakeWriter,
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter,
+    Layer,
+};
+
+/// The format log lines are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines, for reading in a terminal.
+    Pretty,
+    /// One JSON object per line, for ingestion into log aggregators.
+    Json,
+}
+
+/// Installs the global logger, writing to stderr in the given format.
+pub fn init(format: LogFormat) -> anyhow::Result<()> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer(format, std::io::stderr))
+        .try_init()?;
+
+    Ok(())
+}
+
+/// Builds the layer that formats log lines and hands them to `writer`.
+///
+/// JSON lines have the timestamp, level, target and message at the top level, along with the
+/// fields of the span the event happened in (like the `request_id` of an HTTP request).
+fn layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Pretty => layer.boxed(),
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{
+            Arc,
+            Mutex,
+        },
+    };
+
+    use super::*;
+
+    /// Collects everything that's logged so it can be inspected.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'w> MakeWriter<'w> for Output {
+        type Writer = Output;
+
+        fn make_writer(&'w self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn json_format_writes_json_lines() {
+        // Make sure messages from the `log` macros are forwarded, like `init` does.
+        tracing_log::LogTracer::init().ok();
+
+        let output = Output::default();
+        let subscriber =
+            tracing_subscriber::registry().with(layer(LogFormat::Json, output.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "a-request-id");
+            let _entered = span.enter();
+
+            log::info!("first message");
+            tracing::warn!("second message");
+        });
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["message"], "first message");
+        assert_eq!(lines[0]["span"]["request_id"], "a-request-id");
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["message"], "second message");
+
+        for line in &lines {
+            assert!(line["timestamp"].is_string());
+            assert!(line["target"].as_str().unwrap().starts_with("woof_backend"));
+        }
+    }
+}
@@ -3,13 +3,14 @@ mod config;
 mod db;
 mod frontend;
 mod http;
+mod logging;
+mod sweeper;
 mod templates;
 mod tus;
 
 use anyhow::Context;
 use clap::Parser;
 use log::info;
-use sqlx::postgres::PgPoolOptions;
 
 use crate::config::Config;
 
@@ -19,23 +20,25 @@ async fn main() -> anyhow::Result<()> {
     // since we're not going to use a `.env` file if we deploy this application.
     dotenv::dotenv().ok();
 
-    // Initialize the logger.
-    env_logger::init();
-
     let config = Config::parse();
 
+    // Initialize the logger.
+    logging::init(config.log_format)?;
+
     // We create a single connection pool for SQLx that's shared across the whole application.
     // This saves us from opening a new connection for every API call, which is wasteful.
-    let db = PgPoolOptions::new()
+    info!(
+        "Connecting to the database with at most {} connections, a {}s acquire timeout and a {}s \
+         idle timeout",
+        config.db_max_connections, config.db_acquire_timeout_secs, config.db_idle_timeout_secs
+    );
+    let db = db::pool_options(&config)
         .connect(&config.database_url)
         .await
         .context("could not connect to database_url")?;
 
-    // This embeds database migrations in the application binary so we can ensure the database
-    // is migrated correctly on startup
-    info!("Applying database migrations...");
-    sqlx::migrate!().run(&db).await?;
-    info!("All un-applied migrations have been successfully executed!");
+    // Make sure the database schema is up to date before we start serving anything.
+    db::migrate(&db).await?;
 
     http::serve(config, db).await?;
 
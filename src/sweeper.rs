@@ -0,0 +1,204 @@
+//! Background task that periodically removes expired content from the database.
+
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use log::{
+    error,
+    info,
+    warn,
+};
+use sqlx::{
+    types::time::OffsetDateTime,
+    PgPool,
+};
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+
+use crate::{
+    db::files::delete_expired_files,
+    tus::store::UploadStore,
+};
+
+/// Spawns a background task that deletes expired pastes, unfinished uploads and idempotency keys
+/// every `interval`. Idempotency keys are kept for `idempotency_key_expiry`.
+///
+/// The task keeps running until a value is sent on `shutdown` (or its sender is dropped), at which
+/// point it finishes the sweep it's currently running, if any, and exits.
+pub fn spawn(
+    db: PgPool,
+    uploads: Arc<dyn UploadStore>,
+    interval: Duration,
+    idempotency_key_expiry: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    match sweep_expired_pastes(&db).await {
+                        Ok(count) => info!("Swept {count} expired paste(s) from the database"),
+                        Err(err) => error!("Could not sweep expired pastes: {err}"),
+                    }
+                    match sweep_expired_uploads(&db, uploads.as_ref()).await {
+                        Ok(count) => info!("Swept {count} expired upload(s)"),
+                        Err(err) => error!("Could not sweep expired uploads: {err}"),
+                    }
+                    match sweep_expired_idempotency_keys(&db, idempotency_key_expiry).await {
+                        Ok(count) => info!("Swept {count} expired idempotency key(s)"),
+                        Err(err) => error!("Could not sweep expired idempotency keys: {err}"),
+                    }
+                },
+                _ = shutdown.changed() => break,
+            }
+        }
+
+        info!("Expired content sweeper has stopped");
+    })
+}
+
+/// Deletes every paste that has expired, returning how many were removed.
+pub async fn sweep_expired_pastes(db: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query_file!("sql/delete_expired_pastes.sql")
+        .execute(db)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Deletes every unfinished upload that has expired along with its contents, returning how many
+/// were removed.
+///
+/// Removing the contents is best-effort, anything left behind is only logged since the upload can't
+/// be reached anymore once it's gone from the database.
+pub async fn sweep_expired_uploads(
+    db: &PgPool,
+    uploads: &dyn UploadStore,
+) -> Result<u64, sqlx::Error> {
+    let expired = delete_expired_files(db).await?;
+
+    for uuid in &expired {
+        if let Err(err) = uploads.delete(*uuid).await {
+            warn!("Could not remove the contents of expired upload {uuid}: {err}");
+        }
+    }
+
+    Ok(expired.len() as u64)
+}
+
+/// Deletes every idempotency key that was used longer than `expiry` ago, returning how many were
+/// removed.
+///
+/// They're already ignored once they've expired, this keeps them from piling up.
+pub async fn sweep_expired_idempotency_keys(
+    db: &PgPool,
+    expiry: Duration,
+) -> Result<u64, sqlx::Error> {
+    let fresh_since = OffsetDateTime::now_utc() - expiry;
+    let result = sqlx::query_file!("sql/delete_expired_idempotency_keys.sql", fresh_since)
+        .execute(db)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::files::UploadId,
+        tus::store::MemoryUploadStore,
+    };
+
+    #[sqlx::test]
+    async fn sweep_removes_only_expired_pastes(db: PgPool) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "INSERT INTO pastes (content, expires_at) VALUES \
+                     ('expired', now() - interval '1 hour'), \
+                     ('pending', now() + interval '1 hour'), \
+                     ('forever', NULL)";
+        sqlx::query(query).execute(&db).await?;
+
+        let removed = sweep_expired_pastes(&db).await?;
+        assert_eq!(removed, 1);
+
+        let remaining: Vec<String> = sqlx::query_scalar("SELECT content FROM pastes ORDER BY id")
+            .fetch_all(&db)
+            .await?;
+        assert_eq!(remaining, vec!["pending", "forever"]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn sweep_removes_only_expired_unfinished_uploads(db: PgPool) -> sqlx::Result<()> {
+        let uploads = MemoryUploadStore::default();
+        let (expired, pending, finished) = (
+            UploadId::generate(),
+            UploadId::generate(),
+            UploadId::generate(),
+        );
+        for uuid in [expired, pending, finished] {
+            uploads.create(uuid).await?;
+        }
+
+        // language=postgresql
+        let query = "INSERT INTO files (uuid, size, \"offset\", completed, expires_at) VALUES \
+                     ($1, 4, 0, false, now() - interval '1 hour'), \
+                     ($2, 4, 0, false, now() + interval '1 hour'), \
+                     ($3, 4, 4, true, now() - interval '1 hour')";
+        sqlx::query(query)
+            .bind(expired)
+            .bind(pending)
+            .bind(finished)
+            .execute(&db)
+            .await?;
+
+        let removed = sweep_expired_uploads(&db, &uploads).await?;
+        assert_eq!(removed, 1);
+        assert!(uploads.contents(expired).is_none());
+        assert!(uploads.contents(pending).is_some());
+        assert!(uploads.contents(finished).is_some());
+
+        let remaining: Vec<UploadId> = sqlx::query_scalar("SELECT uuid FROM files ORDER BY id")
+            .fetch_all(&db)
+            .await?;
+        assert_eq!(remaining, vec![pending, finished]);
+
+        Ok(())
+    }
+    #[sqlx::test]
+    async fn sweep_removes_only_expired_idempotency_keys(db: PgPool) -> sqlx::Result<()> {
+        // language=postgresql
+        let query = "WITH paste AS ( \
+                         INSERT INTO pastes (content) VALUES ('woof') RETURNING id \
+                     ) \
+                     INSERT INTO paste_idempotency_keys \
+                         (scope, idempotency_key, paste_id, created_at) \
+                     SELECT 'user:1', used.idempotency_key, paste.id, used.created_at \
+                     FROM paste, (VALUES \
+                         ('expired', now() - interval '2 hours'), \
+                         ('fresh', now() - interval '30 minutes') \
+                     ) AS used (idempotency_key, created_at)";
+        sqlx::query(query).execute(&db).await?;
+
+        let removed = sweep_expired_idempotency_keys(&db, Duration::from_secs(60 * 60)).await?;
+        assert_eq!(removed, 1);
+
+        let remaining: Vec<String> =
+            sqlx::query_scalar("SELECT idempotency_key FROM paste_idempotency_keys")
+                .fetch_all(&db)
+                .await?;
+        assert_eq!(remaining, vec!["fresh"]);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,13 @@
 use askama::Template;
 
-use crate::db::users::User;
+use crate::{
+    db::users::User,
+    http::{
+        pastes::UserPaste,
+        security::CspNonce,
+        users::ListedCredential,
+    },
+};
 
 #[derive(Template)]
 #[template(path = "index.html")]
@@ -10,26 +17,94 @@ pub struct IndexTemplate {
 
 #[derive(Template)]
 #[template(path = "auth.html")]
-pub struct AuthTemplate;
+pub struct AuthTemplate {
+    /// The nonce the script loading the passkey login component is tagged with.
+    pub csp_nonce: CspNonce,
+}
+
+#[derive(Template)]
+#[template(path = "passkeys_info.html")]
+pub struct PasskeysInfoTemplate;
 
 #[derive(Template)]
 #[template(path = "new_paste.html")]
-pub struct PasteCreationTemplate;
+pub struct PasteCreationTemplate {
+    /// The CSRF token to send with the paste, if the user is logged in.
+    pub csrf_token: Option<String>,
+    /// The content to fill the form back in with, when it's shown again after an error.
+    pub content: String,
+    /// Why the paste couldn't be created, if the form was already submitted.
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "paste_created.html")]
+pub struct PasteCreatedTemplate {
+    /// The path of the new paste, which the page turns into a full URL to share.
+    pub path: String,
+    /// The nonce the script that copies the link is tagged with.
+    pub csp_nonce: CspNonce,
+}
 
 #[derive(Template)]
 #[template(path = "components/paste_card.html")]
 pub struct PasteCard {
+    /// The title of the paste, if it has one.
+    pub title: Option<String>,
+    /// The content of the paste exactly as it was submitted.
+    ///
+    /// This is shown whenever the paste isn't highlighted or rendered as Markdown, and is always
+    /// escaped. Content can be anything anyone cared to paste, so it must never be rendered with
+    /// `|safe`.
     pub content: String,
+    /// Syntax highlighted HTML of the content, if the paste's language is known.
+    ///
+    /// This is rendered without escaping, so it must only ever come from the syntax highlighter,
+    /// which takes care of escaping the content itself.
+    pub highlighted: Option<String>,
+    /// HTML rendered from the content if the paste is Markdown.
+    ///
+    /// This is also rendered without escaping, so it must only ever come from the Markdown
+    /// renderer, which sanitizes its output.
+    pub markdown: Option<String>,
 }
 
 #[derive(Template)]
 #[template(path = "paste.html")]
 pub struct PasteTemplate {
+    /// The paste itself, rendered without escaping since it's a template that escapes its own
+    /// fields.
     pub paste_card: PasteCard,
+    /// The username of the paste's author, or `None` if it's anonymous.
+    pub author: Option<String>,
+    /// How many times the paste has been viewed, including this view.
+    pub views: i64,
+}
+
+#[derive(Template)]
+#[template(path = "paste_unlock.html")]
+pub struct PasteUnlockTemplate {
+    /// The slug of the password protected paste, used to send the password back to it.
+    pub slug: String,
+    /// Why the last attempt at unlocking the paste failed, if there was one.
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "profile.html")]
+pub struct ProfileTemplate {
+    pub user: User,
+    pub credentials: Vec<ListedCredential>,
+    /// The user's most recent pastes, newest first.
+    pub pastes: Vec<UserPaste>,
 }
 
 #[derive(Template)]
 #[template(path = "error.html")]
 pub struct ErrorTemplate {
+    /// The HTTP status code the page is served with, which picks the copy shown on it.
+    pub status: u16,
+    /// A short summary of the error, used as the heading and document title.
+    pub title: String,
     pub error: String,
 }
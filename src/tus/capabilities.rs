@@ -0,0 +1,102 @@
+//! The parts of the TUS protocol the server supports, declared in one place.
+
+use crate::{
+    config::Config,
+    tus::{
+        checksum::ChecksumAlgorithm,
+        extensions::Extension,
+        headers::{
+            TusVersionHeader,
+            Version,
+        },
+    },
+};
+
+/// Which versions and extensions of the protocol the server supports, as set up by the [Config].
+///
+/// This is shared with the handlers as an axum `Extension`. What's advertised to clients and what
+/// the handlers actually accept are both read from it, so the two can't drift apart.
+#[derive(Debug, Clone)]
+pub struct TusCapabilities {
+    /// The versions of the protocol the server speaks.
+    pub versions: Vec<Version>,
+    /// The extensions that are enabled, in the order they're advertised.
+    pub extensions: Vec<Extension>,
+    /// The largest upload that's accepted, in bytes.
+    pub max_size: u64,
+    /// The algorithms upload chunks can be verified with, in the order they're advertised.
+    ///
+    /// This is empty when the checksum extension is disabled.
+    pub checksum_algorithms: Vec<ChecksumAlgorithm>,
+}
+
+impl TusCapabilities {
+    /// Works out what the server supports from its configuration.
+    pub fn from_config(config: &Config) -> Self {
+        let mut extensions = vec![
+            Extension::Creation,
+            Extension::CreationWithUpload,
+            Extension::CreationDeferLength,
+            Extension::Expiration,
+            Extension::Termination,
+            Extension::Concatenation,
+        ];
+
+        if config.concatenation_unfinished {
+            extensions.push(Extension::ConcatenationUnfinished);
+        }
+
+        let checksum = config.checksum && !config.checksum_algorithms.is_empty();
+        if checksum {
+            extensions.push(Extension::Checksum);
+            if config.checksum_trailer {
+                extensions.push(Extension::ChecksumTrailer);
+            }
+        }
+
+        TusCapabilities {
+            versions: Version::SUPPORTED.to_vec(),
+            extensions,
+            max_size: config.max_upload_bytes,
+            checksum_algorithms: if checksum {
+                config.checksum_algorithms.clone()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Whether the given extension is enabled.
+    pub fn supports(&self, extension: Extension) -> bool {
+        self.extensions.contains(&extension)
+    }
+
+    /// The [TusVersionHeader] listing the supported versions, most preferred first.
+    pub fn version_header(&self) -> TusVersionHeader {
+        TusVersionHeader::by_preference(&self.versions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        let defaults = ["woof", "--database-url", "postgres://localhost/woof"];
+        Config::parse_from(defaults.iter().chain(args))
+    }
+
+    #[test]
+    fn checksum_trailer_depends_on_checksum() {
+        let capabilities = TusCapabilities::from_config(&config(&[]));
+        assert!(capabilities.supports(Extension::Checksum));
+        assert!(capabilities.supports(Extension::ChecksumTrailer));
+
+        let capabilities = TusCapabilities::from_config(&config(&["--checksum", "false"]));
+        assert!(!capabilities.supports(Extension::Checksum));
+        assert!(!capabilities.supports(Extension::ChecksumTrailer));
+        assert!(capabilities.checksum_algorithms.is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+//! The checksum algorithms the server can verify upload chunks with.
+//!
+//! See [Extension::Checksum](crate::tus::extensions::Extension::Checksum).
+
+use std::{
+    fmt::Display,
+    str::FromStr,
+};
+
+use md5::Md5;
+use sha1::{
+    digest::DynDigest,
+    Sha1,
+};
+use sha2::Sha256;
+
+/// A checksum algorithm, named by lowercase ASCII as required by the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ChecksumAlgorithm {
+    /// SHA-1, which every Server MUST support.
+    Sha1,
+    Sha256,
+    Md5,
+}
+
+impl ChecksumAlgorithm {
+    /// Creates a hasher that can be fed the bytes of a chunk a piece at a time.
+    pub fn hasher(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            ChecksumAlgorithm::Sha1 => Box::<Sha1>::default(),
+            ChecksumAlgorithm::Sha256 => Box::<Sha256>::default(),
+            ChecksumAlgorithm::Md5 => Box::<Md5>::default(),
+        }
+    }
+}
+
+impl Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksumAlgorithm::Sha1 => write!(f, "sha1"),
+            ChecksumAlgorithm::Sha256 => write!(f, "sha256"),
+            ChecksumAlgorithm::Md5 => write!(f, "md5"),
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = ();
+
+    /// Parses the name of an algorithm. Names are case-sensitive, since uppercase characters aren't
+    /// allowed in them.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha1" => Ok(ChecksumAlgorithm::Sha1),
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "md5" => Ok(ChecksumAlgorithm::Md5),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for algorithm in [
+            ChecksumAlgorithm::Sha1,
+            ChecksumAlgorithm::Sha256,
+            ChecksumAlgorithm::Md5,
+        ] {
+            assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+        }
+    }
+
+    #[test]
+    fn names_are_case_sensitive() {
+        assert!("SHA1".parse::<ChecksumAlgorithm>().is_err());
+        assert!("crc32".parse::<ChecksumAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn hashers_produce_known_digests() {
+        let digest = |algorithm: ChecksumAlgorithm| {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"woof");
+            hasher.finalize().len()
+        };
+
+        assert_eq!(digest(ChecksumAlgorithm::Sha1), 20);
+        assert_eq!(digest(ChecksumAlgorithm::Sha256), 32);
+        assert_eq!(digest(ChecksumAlgorithm::Md5), 16);
+    }
+}
@@ -15,6 +15,7 @@ use crate::tus::headers::{
     UploadOffsetHeader,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Extension {
     /// The Client and the Server SHOULD implement the upload creation extension. If the Server
     /// supports this extension, it MUST add creation to the Tus-Extension header.
@@ -39,6 +40,13 @@ pub enum Extension {
     /// Expect: 100-continue header in the request to receive early feedback from the Server on
     /// whether it will accept the creation request, before attempting to transfer the first chunk.
     CreationWithUpload,
+    /// The Client MAY create an upload without knowing its size yet by sending the
+    /// Upload-Defer-Length header instead of Upload-Length. If the Server supports this, it MUST
+    /// add `creation-defer-length` to the [TusExtensionHeader] header.
+    ///
+    /// Once the Client knows the size, it MUST set it by including the Upload-Length header in the
+    /// next PATCH request. Once set, the length MUST NOT be changed.
+    CreationDeferLength,
     /// The Server MAY remove unfinished uploads once they expire. In order to indicate this
     /// behavior to the Client, the Server MUST add expiration to the [TusExtensionHeader] header.
     Expiration,
@@ -68,6 +76,10 @@ pub enum Extension {
     /// already. Following RFC 7230 they MUST be announced using the Trailer header and are
     /// only allowed in chunked transfers.
     Checksum,
+    /// The Server can read the checksum of a PATCH request from a trailer sent after its chunked
+    /// body, for Clients that can't calculate it before they start sending. See
+    /// [Extension::Checksum].
+    ChecksumTrailer,
     /// This extension defines a way for the Client to terminate completed and unfinished uploads
     /// allowing the Server to free up used resources.
     ///
@@ -121,6 +133,10 @@ pub enum Extension {
     /// upload MUST include the Upload-Concat header and its value as received in the upload
     /// creation request.
     Concatenation,
+    /// The Client MAY send the concatenation request while the partial uploads are still in
+    /// progress, see [Extension::Concatenation]. If the Server supports this, it MUST add
+    /// `concatenation-unfinished` to the [TusExtensionHeader] header.
+    ConcatenationUnfinished,
 }
 
 impl Display for Extension {
@@ -128,10 +144,13 @@ impl Display for Extension {
         match self {
             Extension::Creation => write!(f, "creation"),
             Extension::CreationWithUpload => write!(f, "creation-with-upload"),
+            Extension::CreationDeferLength => write!(f, "creation-defer-length"),
             Extension::Expiration => write!(f, "expiration"),
             Extension::Checksum => write!(f, "checksum"),
+            Extension::ChecksumTrailer => write!(f, "checksum-trailer"),
             Extension::Termination => write!(f, "termination"),
             Extension::Concatenation => write!(f, "concatenation"),
+            Extension::ConcatenationUnfinished => write!(f, "concatenation-unfinished"),
         }
     }
 }
@@ -0,0 +1,2269 @@
+//! Handlers for the core TUS protocol, along with the extensions the server supports.
+
+use std::{
+    io,
+    ops::Range,
+};
+
+use axum::{
+    body::{
+        Body,
+        Bytes,
+    },
+    extract::{
+        Path,
+        Request,
+    },
+    http::{
+        header,
+        HeaderMap,
+        HeaderValue,
+        Method,
+        StatusCode,
+    },
+    middleware::Next,
+    response::{
+        IntoResponse,
+        Response,
+    },
+    Extension,
+    Json,
+};
+use axum_extra::TypedHeader;
+use futures_util::{
+    stream,
+    Stream,
+};
+use headers::{
+    ContentLength,
+    Header,
+    HeaderMapExt,
+};
+use http_body_util::{
+    BodyExt,
+    LengthLimitError,
+    Limited,
+};
+use log::error;
+use sqlx::types::time::{
+    Duration,
+    OffsetDateTime,
+};
+use thiserror::Error;
+use tokio::io::{
+    AsyncRead,
+    AsyncReadExt,
+};
+
+use crate::{
+    auth::passkeys::backend::AuthSession,
+    db::files::{
+        delete_file,
+        get_file_by_uuid,
+        get_unfinished_final_files,
+        insert_file,
+        update_file_offset,
+        NewFile,
+        UploadId,
+    },
+    http::{
+        error::{
+            ApiError,
+            ErrorChain,
+        },
+        ApiContext,
+    },
+    tus::{
+        capabilities::TusCapabilities,
+        checksum::ChecksumAlgorithm,
+        extensions::Extension as TusExtension,
+        headers::{
+            TusChecksumAlgorithmHeader,
+            TusExtensionHeader,
+            TusMaxSizeHeader,
+            TusResumableHeader,
+            UploadChecksumHeader,
+            UploadConcatHeader,
+            UploadDeferLengthHeader,
+            UploadExpiresHeader,
+            UploadLengthHeader,
+            UploadMetadataHeader,
+            UploadOffsetHeader,
+            Version,
+        },
+        state::UploadState,
+    },
+};
+
+/// The only content type PATCH and creation requests are allowed to send their bytes as.
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+/// A set of errors that can occur while handling a TUS request.
+#[derive(Debug, Error)]
+pub enum TusError {
+    /// The creation request didn't say how big the upload is going to be.
+    #[error("The Upload-Length or Upload-Defer-Length header is required")]
+    MissingLength,
+
+    /// The creation request sent both the length of the upload and that it's deferred.
+    #[error("The Upload-Length and Upload-Defer-Length headers can't be sent together")]
+    ConflictingLength,
+
+    /// The PATCH request tried to change the length of an upload after it was set.
+    #[error("The length of the upload is already set to {0} bytes")]
+    LengthAlreadySet(u64),
+
+    /// The PATCH request set the length of an upload to less than what's already been received.
+    #[error("The length of the upload can't be less than the {0} bytes already received")]
+    LengthBelowOffset(u64),
+
+    /// The upload is larger than the configured limit.
+    #[error("The upload is larger than the maximum of {0} bytes")]
+    UploadTooLarge(u64),
+
+    /// The PATCH request didn't say where its bytes go.
+    #[error("The Upload-Offset header is required")]
+    MissingOffset,
+
+    /// The PATCH request body wasn't sent as `application/offset+octet-stream`.
+    #[error("The request body must be sent as {OFFSET_OCTET_STREAM}")]
+    InvalidContentType,
+
+    /// One of the uploads a final upload is to be made of isn't a partial upload.
+    #[error("`{0}` is not a partial upload")]
+    InvalidPartial(String),
+
+    /// One of the partial uploads a final upload is to be made of hasn't been finished yet.
+    #[error("The partial upload `{0}` hasn't been finished yet")]
+    PartialUnfinished(String),
+
+    /// The creation request expected something of the server other than `100-continue`.
+    #[error("Only the 100-continue expectation is supported")]
+    UnsupportedExpectation,
+
+    /// The upload doesn't exist.
+    #[error("That upload does not exist")]
+    NotFound,
+
+    /// Another PATCH request is already writing to the upload.
+    #[error("Another request is already writing to that upload")]
+    Locked,
+
+    /// The PATCH request was made against a final upload, which is put together by the server.
+    #[error("Final uploads can't be modified")]
+    FinalUpload,
+
+    /// The upload can't be downloaded, because not every byte of it has been received yet.
+    #[error("That upload hasn't been finished yet")]
+    Unfinished,
+
+    /// The upload wasn't finished in time and can't be resumed anymore.
+    #[error("That upload has expired")]
+    Expired,
+
+    /// The PATCH request's offset doesn't line up with how much has been uploaded so far.
+    #[error("The upload is at offset {0}, which doesn't match the Upload-Offset")]
+    OffsetMismatch(u64),
+
+    /// The PATCH request has more bytes than are left in the upload.
+    #[error("The request body goes past the end of the upload")]
+    BodyTooLarge,
+
+    /// The [UploadChecksumHeader] sent with the PATCH request is malformed.
+    #[error("The Upload-Checksum header is malformed")]
+    InvalidChecksum,
+
+    /// The PATCH request's checksum uses an algorithm the server doesn't support.
+    #[error("The checksum algorithm `{0}` is not supported")]
+    UnsupportedChecksumAlgorithm(String),
+
+    /// The PATCH request body doesn't match the checksum that was sent along with it.
+    #[error("The request body doesn't match the Upload-Checksum")]
+    ChecksumMismatch,
+
+    /// The PATCH request body could not be read.
+    #[error("Could not read the request body")]
+    BodyReadFailure(#[source] axum::Error),
+
+    /// An error occurred while writing to the upload store.
+    #[error("An error occurred while storing the upload")]
+    StorageError(#[from] std::io::Error),
+
+    /// An error occurred while communicating with the database.
+    #[error("An error occurred while communicating with the database")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl TusError {
+    /// A machine-readable code for the error that clients can branch on, unlike the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TusError::MissingLength => "missing_length",
+            TusError::ConflictingLength => "conflicting_length",
+            TusError::LengthAlreadySet(_) => "length_already_set",
+            TusError::LengthBelowOffset(_) => "length_below_offset",
+            TusError::UploadTooLarge(_) => "upload_too_large",
+            TusError::MissingOffset => "missing_offset",
+            TusError::InvalidContentType => "invalid_content_type",
+            TusError::InvalidPartial(_) => "invalid_partial",
+            TusError::PartialUnfinished(_) => "partial_unfinished",
+            TusError::UnsupportedExpectation => "unsupported_expectation",
+            TusError::NotFound => "not_found",
+            TusError::Locked => "locked",
+            TusError::FinalUpload => "final_upload",
+            TusError::Unfinished => "unfinished",
+            TusError::Expired => "expired",
+            TusError::OffsetMismatch(_) => "offset_mismatch",
+            TusError::BodyTooLarge => "body_too_large",
+            TusError::InvalidChecksum => "invalid_checksum",
+            TusError::UnsupportedChecksumAlgorithm(_) => "unsupported_checksum_algorithm",
+            TusError::ChecksumMismatch => "checksum_mismatch",
+            TusError::BodyReadFailure(_) => "body_read_failure",
+            TusError::StorageError(_) => "storage_error",
+            TusError::DatabaseError(_) => "database_error",
+        }
+    }
+}
+
+impl IntoResponse for TusError {
+    /// Converts the error into an [ApiError] and then a [Response] with an appropriate status code.
+    fn into_response(self) -> Response {
+        let status = match self {
+            TusError::MissingLength => StatusCode::BAD_REQUEST,
+            TusError::ConflictingLength => StatusCode::BAD_REQUEST,
+            TusError::LengthAlreadySet(_) => StatusCode::CONFLICT,
+            TusError::LengthBelowOffset(_) => StatusCode::BAD_REQUEST,
+            TusError::UploadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            TusError::MissingOffset => StatusCode::BAD_REQUEST,
+            TusError::InvalidContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            TusError::InvalidPartial(_) => StatusCode::BAD_REQUEST,
+            TusError::PartialUnfinished(_) => StatusCode::BAD_REQUEST,
+            TusError::UnsupportedExpectation => StatusCode::EXPECTATION_FAILED,
+            TusError::NotFound => StatusCode::NOT_FOUND,
+            TusError::Locked => StatusCode::LOCKED,
+            TusError::FinalUpload => StatusCode::FORBIDDEN,
+            TusError::Unfinished => StatusCode::CONFLICT,
+            TusError::Expired => StatusCode::GONE,
+            TusError::OffsetMismatch(_) => StatusCode::CONFLICT,
+            TusError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            TusError::InvalidChecksum => StatusCode::BAD_REQUEST,
+            TusError::UnsupportedChecksumAlgorithm(_) => StatusCode::BAD_REQUEST,
+            // Defined by the checksum extension, it isn't a standard HTTP status code.
+            TusError::ChecksumMismatch => StatusCode::from_u16(460).unwrap(),
+            TusError::BodyReadFailure(_) => StatusCode::BAD_REQUEST,
+            TusError::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            TusError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let error = ApiError {
+            code: self.code().to_string(),
+            message: self.to_string(),
+        };
+
+        // The message doesn't say what caused the error, the log has to.
+        error!("{}", ErrorChain(&self));
+
+        (status, Json(error)).into_response()
+    }
+}
+
+/// Middleware that makes sure the client speaks a version of the protocol we support.
+///
+/// Every request except OPTIONS has to include the [TusResumableHeader]. If it's missing or the
+/// version isn't one of the [TusCapabilities] the request is rejected with
+/// [StatusCode::PRECONDITION_FAILED] and the versions we do support, without being processed.
+///
+/// GET requests aren't part of the protocol, they download finished uploads and are let through
+/// so that browsers, which don't know to send the header, can make them.
+pub async fn require_tus_resumable(
+    Extension(capabilities): Extension<TusCapabilities>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() == Method::OPTIONS || request.method() == Method::GET {
+        return next.run(request).await;
+    }
+
+    match request.headers().typed_get::<TusResumableHeader>() {
+        Some(TusResumableHeader(version)) if capabilities.versions.contains(&version) => {
+            next.run(request).await
+        }
+        // The supported versions are listed so the client can switch to one of them.
+        _ => (
+            StatusCode::PRECONDITION_FAILED,
+            TypedHeader(capabilities.version_header()),
+        )
+            .into_response(),
+    }
+}
+
+/// Middleware that adds the [TusResumableHeader] to every response except those to OPTIONS
+/// requests, which the protocol requires of the server.
+///
+/// Responses that already carry the header are left alone.
+pub async fn set_tus_resumable(request: Request, next: Next) -> Response {
+    if request.method() == Method::OPTIONS {
+        return next.run(request).await;
+    }
+
+    let mut response = next.run(request).await;
+    if !response.headers().contains_key(TusResumableHeader::name()) {
+        response
+            .headers_mut()
+            .typed_insert(TusResumableHeader(Version::CURRENT));
+    }
+
+    response
+}
+
+/// Tells the client which version of the protocol, extensions and upload sizes we support, as
+/// declared by the [TusCapabilities].
+pub async fn options(Extension(capabilities): Extension<TusCapabilities>) -> impl IntoResponse {
+    // Both headers have to be left out entirely rather than sent empty.
+    let checksum_algorithms = capabilities
+        .supports(TusExtension::Checksum)
+        .then(|| TusChecksumAlgorithmHeader(capabilities.checksum_algorithms.clone()));
+    let extensions = (!capabilities.extensions.is_empty())
+        .then(|| TusExtensionHeader(capabilities.extensions.clone()));
+
+    (
+        StatusCode::NO_CONTENT,
+        checksum_algorithms.map(TypedHeader),
+        TypedHeader(capabilities.version_header()),
+        TypedHeader(TusMaxSizeHeader(capabilities.max_size)),
+        extensions.map(TypedHeader),
+    )
+}
+
+/// Creates a new, empty upload that bytes can then be sent to with PATCH requests.
+///
+/// The upload expires if it isn't finished within [Config::upload_expiry] seconds.
+///
+/// If the client doesn't know how big the upload is going to be yet, it can send the
+/// [UploadDeferLengthHeader] instead of the [UploadLengthHeader] and set the length in a later
+/// PATCH request.
+///
+/// If the [UploadConcatHeader] marks this as a final upload, it's instead put together right away
+/// out of the partial uploads it lists, which all have to be finished. With the
+/// `concatenation-unfinished` extension, it can also list partial uploads that are still in
+/// progress, and is put together once the last of them finishes.
+///
+/// With the `creation-with-upload` extension, the first bytes of an upload that isn't a final one
+/// can be sent in the body of the request, as if by [upload_chunk].
+///
+/// Clients that send `Expect: 100-continue` are only told to go ahead once the body starts being
+/// read, which only happens after every other check has passed. A creation request that's
+/// rejected (e.g. for being too large) is therefore rejected before the client has sent any of its
+/// body. Any other expectation can't be met and is rejected outright.
+///
+/// [Config::upload_expiry]: crate::config::Config::upload_expiry
+pub async fn create_upload(
+    ctx: Extension<ApiContext>,
+    Extension(capabilities): Extension<TusCapabilities>,
+    auth_session: AuthSession,
+    concat: Option<TypedHeader<UploadConcatHeader>>,
+    metadata: Option<TypedHeader<UploadMetadataHeader>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, TusError> {
+    let expect = headers.get(header::EXPECT);
+    if expect.is_some_and(|expect| !expect.as_bytes().eq_ignore_ascii_case(b"100-continue")) {
+        return Err(TusError::UnsupportedExpectation);
+    }
+
+    let content_type = headers.get(header::CONTENT_TYPE);
+    let with_upload = capabilities.supports(TusExtension::CreationWithUpload)
+        && content_type.is_some_and(|content_type| content_type == OFFSET_OCTET_STREAM);
+
+    let metadata = metadata.map(|TypedHeader(metadata)| metadata);
+    let metadata_value = |key| {
+        metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(key))
+            .map(str::to_string)
+    };
+
+    let user_id = auth_session.user.map(|user| user.id);
+    let uuid = UploadId::generate();
+    let new_file = NewFile {
+        uuid,
+        user_id,
+        filename: metadata_value("filename"),
+        content_type: metadata_value("filetype"),
+        size: None,
+        expires_at: None,
+        is_partial: false,
+        partials: None,
+    };
+
+    let upload: UploadState = match concat.map(|TypedHeader(concat)| concat) {
+        Some(UploadConcatHeader::Final(urls)) => {
+            let partials = urls
+                .iter()
+                .map(|url| partial_uuid(url))
+                .collect::<Result<Vec<_>, _>>()?;
+            let found = find_partials(&ctx, user_id, &partials).await?;
+
+            if !capabilities.supports(TusExtension::ConcatenationUnfinished) {
+                if let Some(partial) = found.iter().find(|partial| !partial.is_complete()) {
+                    return Err(TusError::PartialUnfinished(upload_url(partial.id)));
+                }
+            }
+
+            let length = final_upload_length(&found);
+            let max_size = ctx.config.max_upload_bytes;
+            if length.is_some_and(|length| length > max_size) {
+                return Err(TusError::UploadTooLarge(max_size));
+            }
+
+            let new_file = NewFile {
+                size: length.map(|length| length as i64),
+                // This only matters while it's waiting on its partial uploads to finish.
+                expires_at: Some(
+                    OffsetDateTime::now_utc() + Duration::seconds(ctx.config.upload_expiry),
+                ),
+                partials: Some(partials),
+                ..new_file
+            };
+            let upload = insert_file(&ctx.db, new_file).await?.into();
+
+            match complete_final_upload(&ctx, upload, &found).await {
+                Ok(upload) => upload,
+                // A final upload that couldn't be put together isn't kept around for the client
+                // to find later.
+                Err(err) => {
+                    delete_file(&ctx.db, uuid).await?;
+                    return Err(err);
+                }
+            }
+        }
+        concat => {
+            let length = headers.typed_get::<UploadLengthHeader>();
+            let defer_length = headers.typed_get::<UploadDeferLengthHeader>();
+            let length = match (length, defer_length) {
+                (Some(UploadLengthHeader(length)), None) => Some(length),
+                (None, Some(_)) => None,
+                (Some(_), Some(_)) => return Err(TusError::ConflictingLength),
+                (None, None) => return Err(TusError::MissingLength),
+            };
+
+            let max_size = ctx.config.max_upload_bytes;
+            if length.is_some_and(|length| length > max_size) {
+                return Err(TusError::UploadTooLarge(max_size));
+            }
+
+            ctx.uploads.create(uuid).await?;
+            let new_file = NewFile {
+                // Anything over the maximum size was rejected above, which is far below `i64::MAX`.
+                size: length.map(|length| length as i64),
+                expires_at: Some(
+                    OffsetDateTime::now_utc() + Duration::seconds(ctx.config.upload_expiry),
+                ),
+                is_partial: concat == Some(UploadConcatHeader::Partial),
+                ..new_file
+            };
+            let upload = insert_file(&ctx.db, new_file).await?.into();
+
+            if !with_upload {
+                upload
+            } else {
+                match receive_body(&ctx, &capabilities, upload, 0, &headers, body).await {
+                    Ok(upload) => upload,
+                    // The client never found out where the upload is, so it can't be resumed.
+                    Err(err) => {
+                        delete_file(&ctx.db, uuid).await?;
+                        ctx.uploads.delete(uuid).await?;
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    };
+
+    // The body of a final upload is never read, so it's left as if no body had been sent.
+    let offset = (with_upload && !upload.is_final()).then_some(UploadOffsetHeader(upload.offset));
+
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, upload_url(upload.id))],
+        offset.map(TypedHeader),
+        upload_expires(&upload).map(TypedHeader),
+    ))
+}
+
+/// Tells the client how much of an upload we've received, so it knows where to resume from.
+pub async fn upload_info(
+    ctx: Extension<ApiContext>,
+    Path(uuid): Path<UploadId>,
+) -> Result<impl IntoResponse, TusError> {
+    let upload = find_upload(&ctx, uuid).await?;
+    let metadata =
+        (!upload.metadata.is_empty()).then(|| UploadMetadataHeader(upload.metadata.clone()));
+
+    // A final upload has no offset until it's been put together, and its length is worked out
+    // from its partial uploads rather than deferred by the client.
+    let offset = (!upload.is_final() || upload.is_complete()).then_some(upload.offset);
+    let defer_length = upload.is_deferred() && !upload.is_final();
+
+    Ok((
+        [(header::CACHE_CONTROL, "no-store")],
+        offset.map(|offset| TypedHeader(UploadOffsetHeader(offset))),
+        upload
+            .total_length
+            .map(|length| TypedHeader(UploadLengthHeader(length))),
+        defer_length.then_some(TypedHeader(UploadDeferLengthHeader)),
+        metadata.map(TypedHeader),
+        upload_concat(&upload).map(TypedHeader),
+        upload_expires(&upload).map(TypedHeader),
+    ))
+}
+
+/// Downloads a finished upload, or just the part of it asked for with a `Range` header.
+///
+/// The upload is sent as an attachment with the content type and filename the client gave it when
+/// it was created, so that browsers save it rather than displaying it on our origin.
+pub async fn download_upload(
+    ctx: Extension<ApiContext>,
+    Path(uuid): Path<UploadId>,
+    headers: HeaderMap,
+) -> Result<Response, TusError> {
+    let upload = find_upload(&ctx, uuid).await?;
+    if !upload.is_complete() {
+        return Err(TusError::Unfinished);
+    }
+
+    let length = upload.offset;
+    let (status, range) = match requested_range(&headers, length) {
+        RequestedRange::Full => (StatusCode::OK, 0..length),
+        RequestedRange::Partial(range) => (StatusCode::PARTIAL_CONTENT, range),
+        RequestedRange::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{length}"))],
+            )
+                .into_response());
+        }
+    };
+
+    let reader = ctx
+        .uploads
+        .read(uuid, range.start)
+        .await?
+        .take(range.end - range.start);
+    let body = Body::from_stream(read_chunks(reader, ctx.config.upload_chunk_bytes as usize));
+
+    let mut response = (
+        status,
+        [
+            (header::ACCEPT_RANGES, HeaderValue::from_static("bytes")),
+            (header::CONTENT_TYPE, download_content_type(&upload)),
+            (header::CONTENT_DISPOSITION, content_disposition(&upload)),
+        ],
+        TypedHeader(ContentLength(range.end - range.start)),
+        body,
+    )
+        .into_response();
+
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{length}", range.start, range.end - 1);
+        response.headers_mut().insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&content_range).unwrap(),
+        );
+    }
+
+    Ok(response)
+}
+
+/// Terminates an upload, whether it's been finished or not, deleting everything that was received
+/// of it. Requests for it afterwards are answered with [TusError::NotFound].
+///
+/// Uploads made by a user can only be terminated by that same user, anyone else is told they don't
+/// exist. Anonymous uploads can be terminated by anyone who has their URL, just like they can be
+/// resumed.
+pub async fn terminate_upload(
+    ctx: Extension<ApiContext>,
+    auth_session: AuthSession,
+    Path(uuid): Path<UploadId>,
+) -> Result<impl IntoResponse, TusError> {
+    let file = get_file_by_uuid(&ctx.db, uuid)
+        .await?
+        .ok_or(TusError::NotFound)?;
+
+    let user_id = auth_session.user.map(|user| user.id);
+    if file.user_id.is_some() && file.user_id != user_id {
+        return Err(TusError::NotFound);
+    }
+
+    if !delete_file(&ctx.db, uuid).await? {
+        return Err(TusError::NotFound);
+    }
+    ctx.uploads.delete(uuid).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Appends the bytes in the request body to an upload, starting from the given offset.
+///
+/// The body is streamed into the upload a chunk at a time rather than held in memory. If it turns
+/// out to be bad, whatever was written of it is thrown away again.
+///
+/// If an [UploadChecksumHeader] is sent, either as a header or as a trailer after a chunked body,
+/// the bytes are only kept if they match it. It's ignored when the [TusCapabilities] don't include
+/// the checksum extension, just like it would be by a server that never supported it.
+///
+/// The length of an upload that was created with the [UploadDeferLengthHeader] is set by the first
+/// request that sends the [UploadLengthHeader] along with it.
+///
+/// Only one request at a time can write to an upload, any others are rejected with
+/// [TusError::Locked] before they write anything.
+pub async fn upload_chunk(
+    ctx: Extension<ApiContext>,
+    Extension(capabilities): Extension<TusCapabilities>,
+    Path(uuid): Path<UploadId>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, TusError> {
+    let content_type = headers.get(header::CONTENT_TYPE);
+    if !content_type.is_some_and(|content_type| content_type == OFFSET_OCTET_STREAM) {
+        return Err(TusError::InvalidContentType);
+    }
+
+    let UploadOffsetHeader(offset) = headers
+        .typed_get::<UploadOffsetHeader>()
+        .ok_or(TusError::MissingOffset)?;
+
+    // Held until the offset is stored, so the upload is read as the request before left it.
+    let _lock = ctx.upload_locks.try_lock(uuid).ok_or(TusError::Locked)?;
+    let upload = find_upload(&ctx, uuid).await?;
+    let upload = receive_body(&ctx, &capabilities, upload, offset, &headers, body).await?;
+
+    Ok((
+        StatusCode::NO_CONTENT,
+        TypedHeader(UploadOffsetHeader(upload.offset)),
+        upload_expires(&upload).map(TypedHeader),
+    ))
+}
+
+/// Appends a request body to an upload at the given offset, as described for [upload_chunk], and
+/// returns the upload as it's left afterwards.
+///
+/// Nothing else may be writing to the upload, either because its lock in
+/// [ApiContext::upload_locks] is held or because it was only just created.
+async fn receive_body(
+    ctx: &ApiContext,
+    capabilities: &TusCapabilities,
+    mut upload: UploadState,
+    offset: u64,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<UploadState, TusError> {
+    let uuid = upload.id;
+    let checksum = if capabilities.supports(TusExtension::Checksum) {
+        upload_checksum(headers)?
+    } else {
+        None
+    };
+
+    // With the checksum known upfront, only its algorithm has to be calculated. Otherwise it could
+    // still turn up in a trailer using any of them.
+    let algorithms = match &checksum {
+        Some(checksum) => vec![checksum_algorithm(capabilities, checksum)?],
+        None if capabilities.supports(TusExtension::ChecksumTrailer) => {
+            capabilities.checksum_algorithms.clone()
+        }
+        None => Vec::new(),
+    };
+
+    upload.can_accept(offset)?;
+
+    let was_deferred = upload.is_deferred();
+    if let Some(UploadLengthHeader(length)) = headers.typed_get() {
+        let max_size = ctx.config.max_upload_bytes;
+        if length > max_size {
+            return Err(TusError::UploadTooLarge(max_size));
+        }
+        upload.set_length(length)?;
+    }
+
+    // The body can't be any bigger than what's left of the upload, which can be told upfront if
+    // the client says how big it is. Until the length is known, the upload can still grow up to
+    // the maximum size.
+    let remaining = upload
+        .remaining()
+        .unwrap_or_else(|| ctx.config.max_upload_bytes.saturating_sub(upload.offset));
+    let content_length = headers
+        .typed_get::<ContentLength>()
+        .map(|ContentLength(length)| length);
+    if content_length.is_some_and(|length| length > remaining) {
+        return Err(TusError::BodyTooLarge);
+    }
+
+    let chunk_size = ctx.config.upload_chunk_bytes as usize;
+    let received = match append_body(ctx, uuid, body, remaining, chunk_size, &algorithms).await {
+        Ok(received) => received,
+        Err(err) => return Err(discard_body(ctx, uuid, offset, err).await),
+    };
+
+    // Trailers only ever come after a chunked body, and are only looked at when the checksum
+    // wasn't already sent as a header.
+    let checksum = match (checksum, received.trailers.as_ref()) {
+        (None, Some(trailers)) if capabilities.supports(TusExtension::ChecksumTrailer) => {
+            upload_checksum(trailers)
+        }
+        (checksum, _) => Ok(checksum),
+    };
+    let verified = checksum.and_then(|checksum| match checksum {
+        Some(checksum) => verify_checksum(capabilities, &checksum, &received),
+        None => Ok(()),
+    });
+    if let Err(err) = verified.and_then(|()| upload.advance(received.length)) {
+        return Err(discard_body(ctx, uuid, offset, err).await);
+    }
+
+    // Uploads are capped at the maximum size, which is far below `i64::MAX`. The length is only
+    // stored along with the offset if it's still unset, so a request that set it at the same time
+    // can't be overruled.
+    let length = upload
+        .total_length
+        .filter(|_| was_deferred)
+        .map(|length| length as i64);
+    let (expected, new_offset) = (offset as i64, upload.offset as i64);
+    let stored = update_file_offset(&ctx.db, uuid, expected, new_offset, length).await?;
+    if stored.is_none() {
+        // Another request got there first, which the lock only rules out within this process. It
+        // has already written its bytes, so anything past where it left the upload is from this
+        // one.
+        let Some(file) = get_file_by_uuid(&ctx.db, uuid).await? else {
+            return Err(TusError::NotFound);
+        };
+        let current = file.offset as u64;
+        let err = match file.size {
+            Some(size) if current == offset && length.is_some() => {
+                TusError::LengthAlreadySet(size as u64)
+            }
+            _ => TusError::OffsetMismatch(current),
+        };
+        return Err(discard_body(ctx, uuid, current, err).await);
+    }
+
+    // The partial upload has been received either way, a final upload that can't be put together
+    // is left unfinished until it expires.
+    if upload.is_partial && upload.is_complete() {
+        if let Err(err) = complete_final_uploads(ctx, uuid).await {
+            let err = ErrorChain(&err);
+            error!("Could not complete the final uploads waiting on {uuid}: {err}");
+        }
+    }
+
+    Ok(upload)
+}
+
+/// What was received in the body of a PATCH request.
+struct ReceivedBody {
+    /// How many bytes were appended to the upload.
+    length: u64,
+    /// The digest of the bytes for each of the algorithms that were calculated.
+    digests: Vec<(ChecksumAlgorithm, Vec<u8>)>,
+    /// The trailers sent after the body, if it was chunked.
+    trailers: Option<HeaderMap>,
+}
+
+/// Streams a PATCH request body into an upload, holding at most `chunk_size` bytes in memory at a
+/// time.
+///
+/// The body is cut off with [TusError::BodyTooLarge] as soon as it goes past `limit` bytes. Its
+/// digest is calculated along the way with each of the given `algorithms`.
+async fn append_body(
+    ctx: &ApiContext,
+    uuid: UploadId,
+    body: Body,
+    limit: u64,
+    chunk_size: usize,
+    algorithms: &[ChecksumAlgorithm],
+) -> Result<ReceivedBody, TusError> {
+    let mut body = Limited::new(body, limit as usize);
+    let mut buffer = Vec::with_capacity(chunk_size);
+    let mut hashers: Vec<_> = algorithms
+        .iter()
+        .map(|&algorithm| (algorithm, algorithm.hasher()))
+        .collect();
+    let mut length = 0;
+    let mut trailers = None;
+
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|err| match err.downcast::<LengthLimitError>() {
+            Ok(_) => TusError::BodyTooLarge,
+            Err(err) => TusError::BodyReadFailure(axum::Error::new(err)),
+        })?;
+        let data = match frame.into_data() {
+            Ok(data) => data,
+            Err(frame) => {
+                trailers = frame.into_trailers().ok();
+                continue;
+            }
+        };
+
+        for (_, hasher) in &mut hashers {
+            hasher.update(&data);
+        }
+        length += data.len() as u64;
+
+        // Frames can be any size, so big ones are split up and small ones gathered together.
+        for piece in data.chunks(chunk_size) {
+            buffer.extend_from_slice(piece);
+            if buffer.len() >= chunk_size {
+                ctx.uploads.append(uuid, &buffer).await?;
+                buffer.clear();
+            }
+        }
+    }
+
+    if !buffer.is_empty() {
+        ctx.uploads.append(uuid, &buffer).await?;
+    }
+
+    Ok(ReceivedBody {
+        length,
+        digests: hashers
+            .into_iter()
+            .map(|(algorithm, hasher)| (algorithm, hasher.finalize().to_vec()))
+            .collect(),
+        trailers,
+    })
+}
+
+/// Throws away whatever part of a PATCH request body was written past `offset` before `err`
+/// happened, so the upload is left just as it was before the request.
+async fn discard_body(ctx: &ApiContext, uuid: UploadId, offset: u64, err: TusError) -> TusError {
+    match ctx.uploads.truncate(uuid, offset).await {
+        Ok(()) => err,
+        Err(truncate_err) => TusError::StorageError(truncate_err),
+    }
+}
+
+/// Gets the [UploadChecksumHeader] out of the given headers or trailers, if one was sent.
+fn upload_checksum(headers: &HeaderMap) -> Result<Option<UploadChecksumHeader>, TusError> {
+    headers
+        .typed_try_get::<UploadChecksumHeader>()
+        .map_err(|_| TusError::InvalidChecksum)
+}
+
+/// Gets the algorithm of a checksum, as long as it's one the server is configured to support.
+fn checksum_algorithm(
+    capabilities: &TusCapabilities,
+    checksum: &UploadChecksumHeader,
+) -> Result<ChecksumAlgorithm, TusError> {
+    checksum
+        .algorithm
+        .parse()
+        .ok()
+        .filter(|algorithm| capabilities.checksum_algorithms.contains(algorithm))
+        .ok_or_else(|| TusError::UnsupportedChecksumAlgorithm(checksum.algorithm.clone()))
+}
+
+/// Makes sure the received body matches the checksum the client sent along with it.
+fn verify_checksum(
+    capabilities: &TusCapabilities,
+    checksum: &UploadChecksumHeader,
+    body: &ReceivedBody,
+) -> Result<(), TusError> {
+    let algorithm = checksum_algorithm(capabilities, checksum)?;
+    let digest = body
+        .digests
+        .iter()
+        .find_map(|(calculated, digest)| (*calculated == algorithm).then_some(digest))
+        .ok_or_else(|| TusError::UnsupportedChecksumAlgorithm(checksum.algorithm.clone()))?;
+
+    if *digest != checksum.checksum {
+        return Err(TusError::ChecksumMismatch);
+    }
+
+    Ok(())
+}
+
+/// Streams everything a reader has to give, at most `chunk_size` bytes at a time.
+fn read_chunks(
+    reader: impl AsyncRead + Send + Unpin + 'static,
+    chunk_size: usize,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+    stream::try_unfold(reader, move |mut reader| async move {
+        let mut chunk = vec![0; chunk_size];
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+
+        chunk.truncate(read);
+        Ok(Some((Bytes::from(chunk), reader)))
+    })
+}
+
+/// Which bytes of an upload a download asked for.
+#[derive(Debug, PartialEq)]
+enum RequestedRange {
+    /// The whole upload.
+    Full,
+    /// Just the bytes in the given range.
+    Partial(Range<u64>),
+    /// Bytes that are past the end of the upload.
+    Unsatisfiable,
+}
+
+/// Works out which bytes of an upload of the given length the `Range` header asks for.
+///
+/// Only a single range of bytes is supported. A header asking for anything else, or that can't be
+/// made sense of, is ignored and the whole upload is sent, which HTTP allows for.
+fn requested_range(headers: &HeaderMap, length: u64) -> RequestedRange {
+    let Some(range) = headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.trim().split_once('-'))
+    else {
+        return RequestedRange::Full;
+    };
+
+    let (start, end) = match range {
+        // A suffix range, asking for the last however many bytes.
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return RequestedRange::Unsatisfiable,
+            Ok(suffix) => (length.saturating_sub(suffix), None),
+            Err(_) => return RequestedRange::Full,
+        },
+        (start, "") => match start.parse() {
+            Ok(start) => (start, None),
+            Err(_) => return RequestedRange::Full,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, Some(end)),
+            _ => return RequestedRange::Full,
+        },
+    };
+
+    if start >= length {
+        return RequestedRange::Unsatisfiable;
+    }
+
+    // The end of a range is inclusive, and is allowed to go past the end of the upload.
+    let end = end.map_or(length, |end| end.saturating_add(1).min(length));
+    RequestedRange::Partial(start..end)
+}
+
+/// The content type a finished upload is downloaded as, going by the `filetype` it was given.
+fn download_content_type(upload: &UploadState) -> HeaderValue {
+    upload
+        .metadata_value("filetype")
+        .and_then(|filetype| HeaderValue::from_str(filetype).ok())
+        .unwrap_or_else(|| HeaderValue::from_static("application/octet-stream"))
+}
+
+/// The `Content-Disposition` a finished upload is downloaded with, going by the `filename` it was
+/// given.
+///
+/// The filename is sent both as plain ASCII for older clients, with anything that isn't swapped
+/// out for an underscore, and percent-encoded as UTF-8 for everything else.
+fn content_disposition(upload: &UploadState) -> HeaderValue {
+    let Some(filename) = upload.metadata_value("filename") else {
+        return HeaderValue::from_static("attachment");
+    };
+
+    let ascii: String = filename
+        .chars()
+        .map(|c| match c {
+            ' ' => c,
+            '"' | '\\' => '_',
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (byte as char).to_string(),
+            b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect();
+
+    HeaderValue::from_str(&format!(
+        "attachment; filename=\"{ascii}\"; filename*=UTF-8''{encoded}"
+    ))
+    .unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
+/// Finds an upload that can still be resumed.
+async fn find_upload(ctx: &ApiContext, uuid: UploadId) -> Result<UploadState, TusError> {
+    let upload: UploadState = get_file_by_uuid(&ctx.db, uuid)
+        .await?
+        .ok_or(TusError::NotFound)?
+        .into();
+
+    if upload.is_expired() {
+        return Err(TusError::Expired);
+    }
+
+    Ok(upload)
+}
+
+/// Finds the partial uploads a final upload is made of, making sure they're all partial uploads
+/// that can still be used.
+///
+/// Only the partial uploads made by the same user as the final upload can be used, or ones made
+/// anonymously for an anonymous final upload. Anyone else's are treated as if they don't exist.
+async fn find_partials(
+    ctx: &ApiContext,
+    user_id: Option<i32>,
+    partials: &[UploadId],
+) -> Result<Vec<UploadState>, TusError> {
+    let mut found = Vec::with_capacity(partials.len());
+
+    for uuid in partials {
+        let partial = get_file_by_uuid(&ctx.db, *uuid)
+            .await?
+            .map(UploadState::from)
+            .filter(|partial| partial.is_partial && !partial.is_expired())
+            .filter(|partial| partial.user_id == user_id)
+            .ok_or_else(|| TusError::InvalidPartial(upload_url(*uuid)))?;
+        found.push(partial);
+    }
+
+    Ok(found)
+}
+
+/// The length of a final upload made of the given partial uploads, or `None` if any of their
+/// lengths are still deferred.
+fn final_upload_length(partials: &[UploadState]) -> Option<u64> {
+    partials.iter().map(|partial| partial.total_length).sum()
+}
+
+/// Puts a final upload together out of its partial uploads, returning it as it is afterwards.
+///
+/// Nothing happens until every one of the partial uploads is finished. Once they are, the final
+/// upload is finished too, with its offset and length both set to the sum of theirs.
+async fn complete_final_upload(
+    ctx: &ApiContext,
+    upload: UploadState,
+    partials: &[UploadState],
+) -> Result<UploadState, TusError> {
+    if !partials.iter().all(UploadState::is_complete) {
+        return Ok(upload);
+    }
+
+    // Finished partial uploads always have a length, but their sum can only be checked against
+    // the maximum size once it's known.
+    let length = final_upload_length(partials).unwrap_or_default();
+    let max_size = ctx.config.max_upload_bytes;
+    if length > max_size {
+        return Err(TusError::UploadTooLarge(max_size));
+    }
+
+    // Another request finishing a partial upload at the same time may be putting it together too.
+    // Both end up with the same contents, and only the first to get there finishes it.
+    let ids: Vec<_> = partials.iter().map(|partial| partial.id).collect();
+    ctx.uploads.concatenate(upload.id, &ids).await?;
+
+    // Uploads are capped at the maximum size, which is far below `i64::MAX`.
+    let length = length as i64;
+    let size = upload.is_deferred().then_some(length);
+    let finished = match update_file_offset(&ctx.db, upload.id, 0, length, size).await? {
+        Some(finished) => finished,
+        None => get_file_by_uuid(&ctx.db, upload.id)
+            .await?
+            .ok_or(TusError::NotFound)?,
+    };
+
+    Ok(finished.into())
+}
+
+/// Puts together the unfinished final uploads that the given partial upload is part of, now that
+/// it's finished. The ones still waiting on other partial uploads are left as they are.
+async fn complete_final_uploads(ctx: &ApiContext, partial: UploadId) -> Result<(), TusError> {
+    for upload in get_unfinished_final_files(&ctx.db, partial).await? {
+        let upload = UploadState::from(upload);
+        let partials = upload.partials.as_deref().unwrap_or_default();
+        let partials = find_partials(ctx, upload.user_id, partials).await?;
+        complete_final_upload(ctx, upload, &partials).await?;
+    }
+
+    Ok(())
+}
+
+/// Gets the ID of a partial upload out of its URL, which may be absolute or relative.
+fn partial_uuid(url: &str) -> Result<UploadId, TusError> {
+    url.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| TusError::InvalidPartial(url.to_string()))
+}
+
+/// The URL an upload can be reached at.
+fn upload_url(uuid: UploadId) -> String {
+    format!("/files/{uuid}")
+}
+
+/// The [UploadConcatHeader] for an upload, if it's a partial or final upload.
+fn upload_concat(upload: &UploadState) -> Option<UploadConcatHeader> {
+    if upload.is_partial {
+        return Some(UploadConcatHeader::Partial);
+    }
+
+    let partials = upload.partials.as_ref()?;
+    Some(UploadConcatHeader::Final(
+        partials.iter().copied().map(upload_url).collect(),
+    ))
+}
+
+/// The [UploadExpiresHeader] for an upload, if it's unfinished and going to expire.
+fn upload_expires(upload: &UploadState) -> Option<UploadExpiresHeader> {
+    if upload.is_complete() {
+        return None;
+    }
+
+    upload.expires_at.map(UploadExpiresHeader)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{
+                AtomicBool,
+                Ordering,
+            },
+            Arc,
+        },
+    };
+
+    use axum::{
+        body::{
+            Body,
+            Bytes,
+        },
+        http::Request,
+        Router,
+    };
+    use clap::Parser;
+    use http_body_util::Full;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        http::testing::{
+            insert_user,
+            login_as,
+            with_auth,
+        },
+        tus::store::{
+            MemoryUploadStore,
+            UploadStore,
+        },
+    };
+
+    fn app(db: PgPool) -> Router {
+        app_with_context(ApiContext::for_tests(db))
+    }
+
+    /// Builds the app around an upload store the test can look inside of.
+    fn app_with_store(db: PgPool, uploads: Arc<MemoryUploadStore>) -> Router {
+        app_with_context(ApiContext {
+            uploads,
+            ..ApiContext::for_tests(db)
+        })
+    }
+
+    /// Builds the app for the given context, with sessions so that uploads can be made by users
+    /// who are logged in.
+    fn app_with_context(ctx: ApiContext) -> Router {
+        let db = ctx.db.clone();
+        with_auth(crate::tus::router(&ctx.config).layer(Extension(ctx)), db)
+    }
+
+    /// Builds a creation request with the given headers.
+    fn post(headers: &[(&str, String)]) -> Request<Body> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri("/files")
+            .header("tus-resumable", "1.0.0");
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    /// Creates an upload with the given length, returning its ID.
+    async fn create(app: &Router, length: u64) -> UploadId {
+        create_with(app, &[("upload-length", length.to_string())]).await
+    }
+
+    /// Creates an upload with the given headers, returning its ID.
+    async fn create_with(app: &Router, headers: &[(&str, String)]) -> UploadId {
+        let response = app.clone().oneshot(post(headers)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        location.trim_start_matches("/files/").parse().unwrap()
+    }
+
+    fn patch(uuid: UploadId, offset: u64, body: &'static [u8]) -> Request<Body> {
+        Request::builder()
+            .method(Method::PATCH)
+            .uri(format!("/files/{uuid}"))
+            .header("tus-resumable", "1.0.0")
+            .header("upload-offset", offset)
+            .header(header::CONTENT_TYPE, OFFSET_OCTET_STREAM)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    /// The SHA-1 checksum of `woof`.
+    const WOOF_SHA1: &str = "sha1 6P6dREGQie3dU8FbBqsk3P6yDXA=";
+
+    /// The SHA-256 checksum of `woof`.
+    const WOOF_SHA256: &str = "sha256 GBG90p8s/pXm4jQC4jkPoQEnCPxS74uKKe5UCxxIFTQ=";
+
+    /// Builds a PATCH request with a chunked body followed by an Upload-Checksum trailer.
+    fn patch_with_trailer(
+        uuid: UploadId,
+        offset: u64,
+        body: &'static [u8],
+        checksum: &'static str,
+    ) -> Request<Body> {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("upload-checksum", checksum.parse().unwrap());
+        let body = Full::new(Bytes::from_static(body)).with_trailers(async { Some(Ok(trailers)) });
+
+        Request::builder()
+            .method(Method::PATCH)
+            .uri(format!("/files/{uuid}"))
+            .header("tus-resumable", "1.0.0")
+            .header("upload-offset", offset)
+            .header(header::CONTENT_TYPE, OFFSET_OCTET_STREAM)
+            .header(header::TRANSFER_ENCODING, "chunked")
+            .header(header::TRAILER, "upload-checksum")
+            .body(Body::new(body))
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn options_advertises_expiration(db: PgPool) {
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/files")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(db).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["tus-version"], "1.0.0");
+        assert_eq!(
+            response.headers()["tus-extension"],
+            "creation,creation-with-upload,creation-defer-length,expiration,termination,\
+             concatenation,checksum,checksum-trailer"
+        );
+        assert_eq!(
+            response.headers()["tus-checksum-algorithm"],
+            "sha1,sha256,md5"
+        );
+    }
+
+    #[sqlx::test]
+    async fn disabled_extensions_are_not_advertised(db: PgPool) {
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--checksum",
+            "false",
+        ]);
+        let ctx = ApiContext {
+            config: Arc::new(config),
+            ..ApiContext::for_tests(db)
+        };
+        let app = app_with_context(ctx);
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/files")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()["tus-extension"],
+            "creation,creation-with-upload,creation-defer-length,expiration,termination,\
+             concatenation"
+        );
+        assert!(!response.headers().contains_key("tus-checksum-algorithm"));
+
+        // Checksums sent anyway are ignored, rather than the chunk being checked against them.
+        let uuid = create(&app, 4).await;
+        let mut request = patch(uuid, 0, b"bark");
+        request
+            .headers_mut()
+            .insert("upload-checksum", WOOF_SHA1.parse().unwrap());
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[sqlx::test]
+    async fn options_lists_versions_by_preference(db: PgPool) {
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/files")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(db).oneshot(request).await.unwrap();
+
+        let versions = response.headers()["tus-version"].to_str().unwrap();
+        let versions: Vec<Version> = versions
+            .split(',')
+            .map(|version| Version::new(version).unwrap())
+            .collect();
+
+        assert_eq!(versions.len(), Version::SUPPORTED.len());
+        assert!(versions.windows(2).all(|pair| pair[0] > pair[1]));
+        assert_eq!(versions[0], *Version::SUPPORTED.iter().max().unwrap());
+    }
+
+    #[sqlx::test]
+    async fn unsupported_version_is_rejected(db: PgPool) {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/files")
+            .header("tus-resumable", "0.2.2")
+            .header("upload-length", 4)
+            .body(Body::empty())
+            .unwrap();
+        let response = app(db).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(response.headers()["tus-version"], "1.0.0");
+    }
+
+    fn head(uuid: UploadId) -> Request<Body> {
+        Request::builder()
+            .method(Method::HEAD)
+            .uri(format!("/files/{uuid}"))
+            .header("tus-resumable", "1.0.0")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn delete(uuid: UploadId) -> Request<Body> {
+        Request::builder()
+            .method(Method::DELETE)
+            .uri(format!("/files/{uuid}"))
+            .header("tus-resumable", "1.0.0")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn responses_carry_tus_resumable(db: PgPool) {
+        let app = app(db);
+        let uuid = create(&app, 4).await;
+
+        let response = app.clone().oneshot(patch(uuid, 0, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["tus-resumable"], "1.0.0");
+
+        let response = app.clone().oneshot(head(uuid)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["tus-resumable"], "1.0.0");
+
+        // Errors count as responses too.
+        let response = app
+            .clone()
+            .oneshot(head(UploadId::generate()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["tus-resumable"], "1.0.0");
+
+        // OPTIONS is the only exception.
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/files")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(!response.headers().contains_key("tus-resumable"));
+    }
+
+    #[sqlx::test]
+    async fn upload_ids_cannot_escape_the_upload_directory(db: PgPool) {
+        let app = app(db);
+
+        for id in ["..%2F..%2Fetc%2Fpasswd", "..", "%2Fetc%2Fpasswd"] {
+            let request = Request::builder()
+                .method(Method::PATCH)
+                .uri(format!("/files/{id}"))
+                .header("tus-resumable", "1.0.0")
+                .header("upload-offset", 0)
+                .header(header::CONTENT_TYPE, OFFSET_OCTET_STREAM)
+                .body(Body::from("woof"))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{id}");
+        }
+    }
+
+    #[sqlx::test]
+    async fn interrupted_upload_is_resumed_until_complete(db: PgPool) -> sqlx::Result<()> {
+        let uploads = Arc::new(MemoryUploadStore::default());
+        let app = app_with_store(db.clone(), uploads.clone());
+        let uuid = create(&app, 8).await;
+
+        let response = app.clone().oneshot(patch(uuid, 0, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["upload-offset"], "4");
+
+        // A client that lost its connection asks where to pick back up from.
+        let response = app.clone().oneshot(head(uuid)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["upload-offset"], "4");
+        assert_eq!(response.headers()["upload-length"], "8");
+        assert!(!get_file_by_uuid(&db, uuid).await?.unwrap().completed);
+
+        let response = app.clone().oneshot(patch(uuid, 4, b"bark")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["upload-offset"], "8");
+
+        let file = get_file_by_uuid(&db, uuid).await?.unwrap();
+        assert!(file.completed);
+        assert_eq!(file.size, Some(file.offset));
+
+        let response = app.clone().oneshot(head(uuid)).await.unwrap();
+        assert_eq!(response.headers()["upload-offset"], "8");
+        assert_eq!(uploads.contents(uuid).unwrap(), b"woofbark");
+
+        // Once it's terminated, there's nothing left of it to ask about.
+        let response = app.clone().oneshot(delete(uuid)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(uploads.contents(uuid).is_none());
+
+        let response = app.oneshot(head(uuid)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn deferred_upload_length_is_set_by_a_later_patch(db: PgPool) -> sqlx::Result<()> {
+        let app = app(db.clone());
+        let uuid = create_with(&app, &[("upload-defer-length", "1".to_string())]).await;
+
+        let response = app.clone().oneshot(head(uuid)).await.unwrap();
+        assert_eq!(response.headers()["upload-defer-length"], "1");
+        assert!(!response.headers().contains_key("upload-length"));
+
+        // Bytes can be sent before the length is known...
+        let response = app.clone().oneshot(patch(uuid, 0, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!get_file_by_uuid(&db, uuid).await?.unwrap().completed);
+
+        // ...until the length is set along with the rest of them.
+        let mut request = patch(uuid, 4, b"woof");
+        request
+            .headers_mut()
+            .insert("upload-length", "8".parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let file = get_file_by_uuid(&db, uuid).await?.unwrap();
+        assert_eq!(file.size, Some(8));
+        assert!(file.completed);
+
+        let response = app.oneshot(head(uuid)).await.unwrap();
+        assert_eq!(response.headers()["upload-length"], "8");
+        assert!(!response.headers().contains_key("upload-defer-length"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn deferred_upload_length_cant_be_changed(db: PgPool) -> sqlx::Result<()> {
+        let app = app(db.clone());
+        let uuid = create_with(&app, &[("upload-defer-length", "1".to_string())]).await;
+
+        let mut request = patch(uuid, 0, b"woof");
+        request
+            .headers_mut()
+            .insert("upload-length", "8".parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let mut request = patch(uuid, 4, b"woof");
+        request
+            .headers_mut()
+            .insert("upload-length", "16".parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(get_file_by_uuid(&db, uuid).await?.unwrap().size, Some(8));
+
+        // The upload carries on with the length it was given, and can't go past it.
+        let request = patch(uuid, 4, b"woofwoof");
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let file = get_file_by_uuid(&db, uuid).await?.unwrap();
+        assert_eq!(file.offset, 4);
+        assert!(!file.completed);
+
+        let response = app.oneshot(patch(uuid, 4, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(get_file_by_uuid(&db, uuid).await?.unwrap().completed);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn deferred_upload_length_cant_be_less_than_whats_received(
+        db: PgPool,
+    ) -> sqlx::Result<()> {
+        let app = app(db.clone());
+        let uuid = create_with(&app, &[("upload-defer-length", "1".to_string())]).await;
+
+        let response = app.clone().oneshot(patch(uuid, 0, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let mut request = patch(uuid, 4, b"");
+        request
+            .headers_mut()
+            .insert("upload-length", "2".parse().unwrap());
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let file = get_file_by_uuid(&db, uuid).await?.unwrap();
+        assert_eq!(file.size, None);
+        assert_eq!(file.offset, 4);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn creation_needs_exactly_one_of_length_and_defer_length(db: PgPool) {
+        let app = app(db);
+
+        let response = app.clone().oneshot(post(&[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let headers = [
+            ("upload-length", "8".to_string()),
+            ("upload-defer-length", "1".to_string()),
+        ];
+        let response = app.oneshot(post(&headers)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn creation_and_patch_include_upload_expires(db: PgPool) -> sqlx::Result<()> {
+        let app = app(db.clone());
+        let uuid = create(&app, 8).await;
+
+        let file = get_file_by_uuid(&db, uuid).await?.unwrap();
+        assert!(file.expires_at.unwrap() > OffsetDateTime::now_utc());
+
+        let response = app.clone().oneshot(patch(uuid, 0, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["upload-offset"], "4");
+        assert!(response.headers().contains_key("upload-expires"));
+
+        // Finished uploads don't expire anymore.
+        let response = app.oneshot(patch(uuid, 4, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!response.headers().contains_key("upload-expires"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn patch_against_expired_upload_is_gone(db: PgPool) -> sqlx::Result<()> {
+        let app = app(db.clone());
+        let uuid = create(&app, 8).await;
+
+        sqlx::query("UPDATE files SET expires_at = now() - interval '1 minute' WHERE uuid = $1")
+            .bind(uuid)
+            .execute(&db)
+            .await?;
+
+        let response = app.oneshot(patch(uuid, 0, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+
+        Ok(())
+    }
+
+    /// Creates and finishes a partial upload with the given contents, returning its ID.
+    async fn partial(app: &Router, contents: &'static [u8]) -> UploadId {
+        let uuid = create_with(
+            app,
+            &[
+                ("upload-length", contents.len().to_string()),
+                ("upload-concat", "partial".to_string()),
+            ],
+        )
+        .await;
+
+        let response = app.clone().oneshot(patch(uuid, 0, contents)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        uuid
+    }
+
+    #[sqlx::test]
+    async fn final_upload_concatenates_partials(db: PgPool) -> sqlx::Result<()> {
+        let uploads = Arc::new(MemoryUploadStore::default());
+        let app = app_with_store(db.clone(), uploads.clone());
+
+        let first = partial(&app, b"woof ").await;
+        let second = partial(&app, b"bark").await;
+        let concat = format!("final;/files/{first} https://woof.example/files/{second}");
+        let uuid = create_with(&app, &[("upload-concat", concat)]).await;
+
+        assert_eq!(uploads.contents(uuid).unwrap(), b"woof bark");
+
+        let file = get_file_by_uuid(&db, uuid).await?.unwrap();
+        assert_eq!(file.size, Some(9));
+        assert_eq!(Some(file.offset), file.size);
+        assert!(file.completed);
+        assert_eq!(file.partials, Some(vec![first, second]));
+
+        let response = app.oneshot(head(uuid)).await.unwrap();
+        assert_eq!(
+            response.headers()["upload-concat"].to_str().unwrap(),
+            format!("final;/files/{first} /files/{second}")
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn final_upload_that_cant_be_put_together_is_not_kept(db: PgPool) -> sqlx::Result<()> {
+        let uploads = Arc::new(MemoryUploadStore::default());
+        let app = app_with_store(db.clone(), uploads.clone());
+
+        // The contents of a finished partial upload going missing makes concatenation fail.
+        let first = partial(&app, b"woof ").await;
+        let second = partial(&app, b"bark").await;
+        uploads.delete(second).await?;
+        let concat = format!("final;/files/{first} /files/{second}");
+        let response = app
+            .clone()
+            .oneshot(post(&[("upload-concat", concat.clone())]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let finals: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM files WHERE partials IS NOT NULL")
+                .fetch_one(&db)
+                .await?;
+        assert_eq!(finals, 0);
+
+        // Nothing is left in the way of trying again once the contents are back.
+        uploads.create(second).await?;
+        uploads.append(second, b"bark").await?;
+        let uuid = create_with(&app, &[("upload-concat", concat)]).await;
+        assert_eq!(uploads.contents(uuid).unwrap(), b"woof bark");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn final_upload_only_uses_the_users_own_partials(db: PgPool) -> sqlx::Result<()> {
+        let app = app(db.clone());
+        let mut cookies = Vec::new();
+        for username in ["woof", "bark"] {
+            let user_id = insert_user(&db, username).await?;
+            cookies.push(login_as(&app, user_id).await);
+        }
+
+        let owned = create_with(
+            &app,
+            &[
+                ("upload-length", "4".to_string()),
+                ("upload-concat", "partial".to_string()),
+                ("cookie", cookies[0].clone()),
+            ],
+        )
+        .await;
+        let response = app.clone().oneshot(patch(owned, 0, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Neither someone else nor someone anonymous can put it into a final upload of their own.
+        let concat = format!("final;/files/{owned}");
+        for headers in [
+            vec![
+                ("upload-concat", concat.clone()),
+                ("cookie", cookies[1].clone()),
+            ],
+            vec![("upload-concat", concat.clone())],
+        ] {
+            let response = app.clone().oneshot(post(&headers)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let headers = [("upload-concat", concat), ("cookie", cookies[0].clone())];
+        let uuid = create_with(&app, &headers).await;
+        let file = get_file_by_uuid(&db, uuid).await?.unwrap();
+        assert!(file.completed);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn uploads_are_only_terminated_by_whoever_made_them(db: PgPool) -> sqlx::Result<()> {
+        let app = app(db.clone());
+        let mut cookies = Vec::new();
+        for username in ["woof", "bark"] {
+            let user_id = insert_user(&db, username).await?;
+            cookies.push(login_as(&app, user_id).await);
+        }
+        let delete_as = |uuid, cookie: Option<&str>| {
+            let mut request = delete(uuid);
+            if let Some(cookie) = cookie {
+                request
+                    .headers_mut()
+                    .insert(header::COOKIE, cookie.parse().unwrap());
+            }
+            request
+        };
+
+        let headers = [
+            ("upload-length", "4".to_string()),
+            ("cookie", cookies[0].clone()),
+        ];
+        let owned = create_with(&app, &headers).await;
+        for cookie in [Some(cookies[1].as_str()), None] {
+            let response = app.clone().oneshot(delete_as(owned, cookie)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+        assert!(get_file_by_uuid(&db, owned).await?.is_some());
+
+        let response = app
+            .clone()
+            .oneshot(delete_as(owned, Some(&cookies[0])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(get_file_by_uuid(&db, owned).await?.is_none());
+
+        // Anonymous uploads can be terminated by anyone who has their URL.
+        let anonymous = create(&app, 4).await;
+        let response = app.oneshot(delete_as(anonymous, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn final_upload_rejects_unfinished_partials(db: PgPool) {
+        let app = app(db);
+
+        let unfinished = create_with(
+            &app,
+            &[
+                ("upload-length", "4".to_string()),
+                ("upload-concat", "partial".to_string()),
+            ],
+        )
+        .await;
+        let concat = format!("final;/files/{unfinished}");
+        let response = app
+            .oneshot(post(&[("upload-concat", concat)]))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn final_upload_waits_for_unfinished_partials(db: PgPool) -> sqlx::Result<()> {
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--concatenation-unfinished",
+            "true",
+        ]);
+        let uploads = Arc::new(MemoryUploadStore::default());
+        let ctx = ApiContext {
+            config: Arc::new(config),
+            uploads: uploads.clone(),
+            ..ApiContext::for_tests(db.clone())
+        };
+        let app = app_with_context(ctx);
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/files")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let extensions = response.headers()["tus-extension"].to_str().unwrap();
+        let extensions: Vec<_> = extensions.split(',').collect();
+        assert!(extensions.contains(&"concatenation-unfinished"));
+
+        let first = partial(&app, b"woof ").await;
+        let second = create_with(
+            &app,
+            &[
+                ("upload-length", "4".to_string()),
+                ("upload-concat", "partial".to_string()),
+            ],
+        )
+        .await;
+        let concat = format!("final;/files/{first} /files/{second}");
+        let uuid = create_with(&app, &[("upload-concat", concat)]).await;
+
+        // Until the last partial upload finishes, there's nothing to put together yet.
+        let response = app.clone().oneshot(head(uuid)).await.unwrap();
+        assert!(!response.headers().contains_key("upload-offset"));
+        assert_eq!(response.headers()["upload-length"], "9");
+        assert!(uploads.contents(uuid).is_none());
+        assert!(!get_file_by_uuid(&db, uuid).await?.unwrap().completed);
+
+        let request = patch(second, 0, b"bark");
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        assert_eq!(uploads.contents(uuid).unwrap(), b"woof bark");
+        let response = app.oneshot(head(uuid)).await.unwrap();
+        assert_eq!(response.headers()["upload-offset"], "9");
+        assert_eq!(response.headers()["upload-length"], "9");
+        assert!(get_file_by_uuid(&db, uuid).await?.unwrap().completed);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn patch_against_final_upload_is_forbidden(db: PgPool) -> sqlx::Result<()> {
+        let app = app(db.clone());
+
+        let first = partial(&app, b"woof").await;
+        let uuid = create_with(&app, &[("upload-concat", format!("final;/files/{first}"))]).await;
+
+        let response = app.oneshot(patch(uuid, 4, b"woof")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Neither the final upload nor its partial were touched.
+        let file = get_file_by_uuid(&db, uuid).await?.unwrap();
+        assert_eq!(file.offset, 4);
+        let first = get_file_by_uuid(&db, first).await?.unwrap();
+        assert_eq!(first.offset, 4);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn checksum_header_is_verified(db: PgPool) -> sqlx::Result<()> {
+        let app = app(db.clone());
+        let uuid = create(&app, 8).await;
+
+        let mut request = patch(uuid, 0, b"bark");
+        request
+            .headers_mut()
+            .insert("upload-checksum", WOOF_SHA1.parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status().as_u16(), 460);
+
+        let mut request = patch(uuid, 0, b"woof");
+        request
+            .headers_mut()
+            .insert("upload-checksum", WOOF_SHA1.parse().unwrap());
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn checksum_header_is_verified_with_sha256(db: PgPool) -> sqlx::Result<()> {
+        let app = app(db.clone());
+        let uuid = create(&app, 8).await;
+
+        let mut request = patch(uuid, 0, b"bark");
+        request
+            .headers_mut()
+            .insert("upload-checksum", WOOF_SHA256.parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status().as_u16(), 460);
+
+        let mut request = patch(uuid, 0, b"woof");
+        request
+            .headers_mut()
+            .insert("upload-checksum", WOOF_SHA256.parse().unwrap());
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn checksum_trailer_is_verified(db: PgPool) -> sqlx::Result<()> {
+        let uploads = Arc::new(MemoryUploadStore::default());
+        let app = app_with_store(db.clone(), uploads.clone());
+        let uuid = create(&app, 12).await;
+
+        // The chunk is thrown away if it doesn't match the digest in the trailer.
+        let request = patch_with_trailer(uuid, 0, b"bark", WOOF_SHA1);
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status().as_u16(), 460);
+        assert_eq!(get_file_by_uuid(&db, uuid).await?.unwrap().offset, 0);
+
+        let request = patch_with_trailer(uuid, 0, b"woof", WOOF_SHA1);
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["upload-offset"], "4");
+        assert_eq!(uploads.contents(uuid).unwrap(), b"woof");
+
+        // Any of the configured algorithms can be used in the trailer.
+        let request = patch_with_trailer(uuid, 4, b"woof", WOOF_SHA256);
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["upload-offset"], "8");
+
+        // Algorithms we don't know about can't be verified.
+        let request = patch_with_trailer(uuid, 8, b"woof", "md4 d29vZg==");
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    /// A body of `woof` that records whether it was ever read, which a client sending
+    /// `Expect: 100-continue` only lets happen once the server tells it to go ahead.
+    fn watched_body() -> (Body, Arc<AtomicBool>) {
+        let polled = Arc::new(AtomicBool::new(false));
+        let body = futures_util::stream::once({
+            let polled = polled.clone();
+            async move {
+                polled.store(true, Ordering::SeqCst);
+                Ok::<_, Infallible>(Bytes::from_static(b"woof"))
+            }
+        });
+
+        (Body::from_stream(body), polled)
+    }
+
+    /// Builds a creation request that sends the first bytes of the upload along with it.
+    fn post_with_upload(length: u64, body: Body) -> Request<Body> {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/files")
+            .header("tus-resumable", "1.0.0")
+            .header("upload-length", length)
+            .header(header::EXPECT, "100-continue")
+            .header(header::CONTENT_TYPE, OFFSET_OCTET_STREAM)
+            .body(body)
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn oversized_creation_is_rejected_before_the_body_is_sent(db: PgPool) {
+        let max_size = ApiContext::for_tests(db.clone()).config.max_upload_bytes;
+
+        let (body, polled) = watched_body();
+        let response = app(db)
+            .oneshot(post_with_upload(max_size + 1, body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!polled.load(Ordering::SeqCst));
+    }
+
+    #[sqlx::test]
+    async fn creation_with_upload_stores_the_first_bytes(db: PgPool) {
+        let uploads = Arc::new(MemoryUploadStore::default());
+        let app = app_with_store(db, uploads.clone());
+
+        let (body, polled) = watched_body();
+        let response = app.oneshot(post_with_upload(8, body)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["upload-offset"], "4");
+        assert!(polled.load(Ordering::SeqCst));
+
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let uuid = location.trim_start_matches("/files/").parse().unwrap();
+        assert_eq!(uploads.contents(uuid).unwrap(), b"woof");
+    }
+
+    #[sqlx::test]
+    async fn creation_with_upload_that_fails_is_not_kept(db: PgPool) -> sqlx::Result<()> {
+        // The body is longer than the upload it's for.
+        let (body, _) = watched_body();
+        let response = app(db.clone())
+            .oneshot(post_with_upload(2, body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let files: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM files")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(files, 0);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn unsupported_expectations_are_rejected(db: PgPool) {
+        let request = post(&[
+            ("upload-length", "4".to_string()),
+            ("expect", "something-else".to_string()),
+        ]);
+        let response = app(db).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::EXPECTATION_FAILED);
+    }
+
+    /// Builds a PATCH request with a body that arrives in the given frames.
+    fn patch_streamed(uuid: UploadId, offset: u64, frames: &[&'static [u8]]) -> Request<Body> {
+        let frames: Vec<Result<Bytes, Infallible>> = frames
+            .iter()
+            .map(|frame| Ok(Bytes::from_static(frame)))
+            .collect();
+
+        Request::builder()
+            .method(Method::PATCH)
+            .uri(format!("/files/{uuid}"))
+            .header("tus-resumable", "1.0.0")
+            .header("upload-offset", offset)
+            .header(header::CONTENT_TYPE, OFFSET_OCTET_STREAM)
+            .body(Body::from_stream(futures_util::stream::iter(frames)))
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn patch_body_is_written_in_chunks(db: PgPool) -> sqlx::Result<()> {
+        let uploads = Arc::new(MemoryUploadStore::default());
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--upload-chunk-bytes",
+            "4",
+        ]);
+        let ctx = ApiContext {
+            config: Arc::new(config),
+            uploads: uploads.clone(),
+            ..ApiContext::for_tests(db.clone())
+        };
+        let app = app_with_context(ctx);
+        let uuid = create(&app, 33).await;
+
+        // Frames both smaller and much larger than a chunk, over more than one request.
+        let request = patch_streamed(uuid, 0, &[b"wo", b"of woof woof", b"woof"]);
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["upload-offset"], "18");
+
+        let request = patch_streamed(uuid, 18, &[b" bark bark bark"]);
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["upload-offset"], "33");
+
+        assert_eq!(
+            uploads.contents(uuid).unwrap(),
+            b"woof woof woofwoof bark bark bark"
+        );
+        assert!(uploads.largest_append() <= 4);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn overlapping_patches_dont_mix_their_bytes(db: PgPool) -> sqlx::Result<()> {
+        let uploads = Arc::new(MemoryUploadStore::default());
+        let config = Config::parse_from([
+            "woof",
+            "--database-url",
+            "postgres://localhost/woof",
+            "--upload-chunk-bytes",
+            "2",
+        ]);
+        let ctx = ApiContext {
+            config: Arc::new(config),
+            uploads: uploads.clone(),
+            ..ApiContext::for_tests(db.clone())
+        };
+        let app = app_with_context(ctx);
+        let uuid = create(&app, 8).await;
+
+        // The first request's body is sent bit by bit, so it's still being written to storage
+        // when the second one comes in for the same offset.
+        let (frames, receiver) = tokio::sync::mpsc::channel::<&'static [u8]>(1);
+        let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+            let frame = receiver.recv().await?;
+            Some((Ok::<_, Infallible>(Bytes::from_static(frame)), receiver))
+        });
+        let mut request = patch(uuid, 0, b"");
+        *request.body_mut() = Body::from_stream(body);
+        frames.send(b"wo").await.unwrap();
+        let first = tokio::spawn(app.clone().oneshot(request));
+        while uploads.contents(uuid).unwrap() != b"wo" {
+            tokio::task::yield_now().await;
+        }
+
+        let response = app.clone().oneshot(patch(uuid, 0, b"bark")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::LOCKED);
+
+        frames.send(b"of").await.unwrap();
+        drop(frames);
+        let response = first.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(uploads.contents(uuid).unwrap(), b"woof");
+
+        // Once the first request is done, the rest of the upload can be sent.
+        let response = app.oneshot(patch(uuid, 4, b"bark")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(uploads.contents(uuid).unwrap(), b"woofbark");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn oversized_patch_body_is_discarded(db: PgPool) -> sqlx::Result<()> {
+        let uploads = Arc::new(MemoryUploadStore::default());
+        let app = app_with_store(db.clone(), uploads.clone());
+        let uuid = create(&app, 8).await;
+
+        // Without a Content-Length this is only caught once the body goes past the end.
+        let request = patch_streamed(uuid, 0, &[b"woof", b"woof", b"woof"]);
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(uploads.contents(uuid).unwrap(), b"");
+
+        let file = get_file_by_uuid(&db, uuid).await?.unwrap();
+        assert_eq!(file.offset, 0);
+
+        // With one it's turned away before anything is read.
+        let mut request = patch(uuid, 0, b"woof woof");
+        request
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, "9".parse().unwrap());
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        Ok(())
+    }
+
+    /// Creates an upload of `woof bark.txt` as `text/plain` and sends all of it.
+    async fn finished_text_upload(app: &Router) -> UploadId {
+        let metadata = "filename d29vZiBiYXJrLnR4dA==,filetype dGV4dC9wbGFpbg==".to_string();
+        let uuid = create_with(
+            app,
+            &[
+                ("upload-length", "9".to_string()),
+                ("upload-metadata", metadata),
+            ],
+        )
+        .await;
+
+        let response = app
+            .clone()
+            .oneshot(patch(uuid, 0, b"woof bark"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        uuid
+    }
+
+    fn download(uuid: UploadId) -> Request<Body> {
+        Request::builder()
+            .uri(format!("/files/{uuid}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn finished_upload_can_be_downloaded(db: PgPool) {
+        let app = app_with_store(db, Arc::new(MemoryUploadStore::default()));
+        let uuid = finished_text_upload(&app).await;
+
+        // Browsers won't send Tus-Resumable, so downloads can't need it.
+        let response = app.oneshot(download(uuid)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "9");
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"woof bark.txt\"; filename*=UTF-8''woof%20bark.txt"
+        );
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "woof bark");
+    }
+
+    #[sqlx::test]
+    async fn finished_upload_can_be_downloaded_in_ranges(db: PgPool) {
+        let app = app_with_store(db, Arc::new(MemoryUploadStore::default()));
+        let uuid = finished_text_upload(&app).await;
+
+        for (range, content_range, expected) in [
+            ("bytes=5-8", "bytes 5-8/9", "bark"),
+            ("bytes=2-", "bytes 2-8/9", "of bark"),
+            ("bytes=-4", "bytes 5-8/9", "bark"),
+            ("bytes=0-100", "bytes 0-8/9", "woof bark"),
+        ] {
+            let mut request = download(uuid);
+            request
+                .headers_mut()
+                .insert(header::RANGE, range.parse().unwrap());
+            let response = app.clone().oneshot(request).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{range}");
+            assert_eq!(response.headers()[header::CONTENT_RANGE], content_range);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, expected);
+        }
+
+        let mut request = download(uuid);
+        request
+            .headers_mut()
+            .insert(header::RANGE, "bytes=9-".parse().unwrap());
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */9");
+    }
+
+    #[sqlx::test]
+    async fn unfinished_upload_cant_be_downloaded(db: PgPool) {
+        let app = app(db);
+        let uuid = create(&app, 4).await;
+
+        let response = app.clone().oneshot(download(uuid)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = app.oneshot(download(UploadId::generate())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn server_errors_dont_return_their_cause() {
+        let cause = io::Error::other("/var/lib/woof/uploads is full");
+        let error = TusError::StorageError(cause);
+        assert!(ErrorChain(&error).to_string().ends_with("is full"));
+
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["message"],
+            "An error occurred while storing the upload"
+        );
+    }
+}
@@ -1,20 +1,37 @@
 use std::fmt::Display;
 
+pub mod tus_checksum_algorithm;
 pub mod tus_extension;
+pub mod tus_max_size;
 pub mod tus_resumable;
 pub mod tus_version;
+pub mod upload_checksum;
+pub mod upload_concat;
+pub mod upload_defer_length;
+pub mod upload_expires;
 pub mod upload_length;
+pub mod upload_metadata;
 pub mod upload_offset;
 
 pub use crate::tus::headers::{
+    tus_checksum_algorithm::TusChecksumAlgorithmHeader,
     tus_extension::TusExtensionHeader,
+    tus_max_size::TusMaxSizeHeader,
     tus_resumable::TusResumableHeader,
     tus_version::TusVersionHeader,
+    upload_checksum::UploadChecksumHeader,
+    upload_concat::UploadConcatHeader,
+    upload_defer_length::UploadDeferLengthHeader,
+    upload_expires::UploadExpiresHeader,
     upload_length::UploadLengthHeader,
+    upload_metadata::UploadMetadataHeader,
     upload_offset::UploadOffsetHeader,
 };
 
-#[derive(Debug, PartialEq)]
+/// A version of the TUS protocol.
+///
+/// Versions are ordered by their major, then minor, then patch number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Version {
     major: u32,
     minor: u32,
@@ -22,6 +39,16 @@ pub struct Version {
 }
 
 impl Version {
+    /// The version of the protocol implemented by the server.
+    pub const CURRENT: Version = Version {
+        major: 1,
+        minor: 0,
+        patch: 0,
+    };
+
+    /// Every version of the protocol the server supports, in no particular order.
+    pub const SUPPORTED: &'static [Version] = &[Version::CURRENT];
+
     pub fn new(s: &str) -> Result<Self, &'static str> {
         let parts: Vec<&str> = s.split('.').collect();
 
@@ -77,6 +104,14 @@ mod tests {
         assert!(version.is_err());
     }
 
+    #[test]
+    fn versions_are_ordered_by_significance() {
+        let version = |s| Version::new(s).unwrap();
+        assert!(version("1.0.0") > version("0.2.2"));
+        assert!(version("0.2.2") > version("0.2.1"));
+        assert!(version("0.10.0") > version("0.2.9"));
+    }
+
     #[test]
     fn version_display_format() {
         let version = Version::new("1.2.3").unwrap();
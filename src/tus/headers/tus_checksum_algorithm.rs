@@ -0,0 +1,88 @@
+use axum::http::HeaderName;
+use headers::Header;
+
+use crate::tus::checksum::ChecksumAlgorithm;
+
+static CUSTOM_HEADER: &str = "tus-checksum-algorithm";
+static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
+
+/// # Tus-Checksum-Algorithm
+/// The [TusChecksumAlgorithmHeader] response header MUST be a comma-separated list of the checksum
+/// algorithms supported by the server. It MUST be included in the response to an OPTIONS request
+/// if the Server supports the checksum extension.
+#[derive(Debug, PartialEq)]
+pub struct TusChecksumAlgorithmHeader(pub Vec<ChecksumAlgorithm>);
+
+impl Header for TusChecksumAlgorithmHeader {
+    fn name() -> &'static HeaderName {
+        &HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i http::HeaderValue>,
+    {
+        let mut algorithms = Vec::new();
+
+        for value in values {
+            let value_str = value.to_str().map_err(|_| headers::Error::invalid())?;
+            for algorithm in value_str.split(',') {
+                let algorithm = algorithm
+                    .trim()
+                    .parse()
+                    .map_err(|_| headers::Error::invalid())?;
+                algorithms.push(algorithm);
+            }
+        }
+
+        Ok(TusChecksumAlgorithmHeader(algorithms))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<http::HeaderValue>,
+    {
+        let algorithm_strings: Vec<String> =
+            self.0.iter().map(ChecksumAlgorithm::to_string).collect();
+        let combined = algorithm_strings.join(",");
+        if let Ok(header_value) = http::HeaderValue::from_str(&combined) {
+            values.extend(std::iter::once(header_value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        let header = TusChecksumAlgorithmHeader(vec![
+            ChecksumAlgorithm::Sha1,
+            ChecksumAlgorithm::Sha256,
+            ChecksumAlgorithm::Md5,
+        ]);
+        let mut values = Vec::new();
+        header.encode(&mut values);
+        assert_eq!(values[0], "sha1,sha256,md5");
+
+        let decoded = TusChecksumAlgorithmHeader::decode(&mut values.iter()).unwrap();
+        assert_eq!(decoded, header);
+    }
+
+    #[test]
+    fn decode_with_spaces_between_algorithms() {
+        let value = http::HeaderValue::from_static("sha1, md5");
+        let decoded = TusChecksumAlgorithmHeader::decode(&mut std::iter::once(&value)).unwrap();
+        assert_eq!(
+            decoded.0,
+            vec![ChecksumAlgorithm::Sha1, ChecksumAlgorithm::Md5]
+        );
+    }
+
+    #[test]
+    fn decode_with_unknown_algorithm() {
+        let value = http::HeaderValue::from_static("sha1,crc32");
+        assert!(TusChecksumAlgorithmHeader::decode(&mut std::iter::once(&value)).is_err());
+    }
+}
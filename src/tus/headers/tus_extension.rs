@@ -12,7 +12,7 @@ static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
 /// The [TusExtensionHeader] response header MUST be a comma-separated list of the extensions
 /// supported by the Server. If no extensions are supported, the [TusExtensionHeader] header MUST be
 /// omitted.
-pub struct TusExtensionHeader(Vec<Extension>);
+pub struct TusExtensionHeader(pub Vec<Extension>);
 
 impl Header for TusExtensionHeader {
     fn name() -> &'static HeaderName {
@@ -32,6 +32,13 @@ impl Header for TusExtensionHeader {
             for ext in extension_strings {
                 let extension = match ext.trim() {
                     "creation" => Extension::Creation,
+                    "creation-with-upload" => Extension::CreationWithUpload,
+                    "creation-defer-length" => Extension::CreationDeferLength,
+                    "expiration" => Extension::Expiration,
+                    "checksum" => Extension::Checksum,
+                    "termination" => Extension::Termination,
+                    "concatenation" => Extension::Concatenation,
+                    "concatenation-unfinished" => Extension::ConcatenationUnfinished,
                     _ => return Err(headers::Error::invalid()),
                 };
                 extensions.push(extension);
@@ -0,0 +1,36 @@
+use axum::http::HeaderName;
+use headers::Header;
+
+static CUSTOM_HEADER: &str = "tus-max-size";
+static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
+
+/// # Tus-Max-Size
+/// The [TusMaxSizeHeader] response header MUST be a non-negative integer indicating the allowed
+/// maximum size of a single upload in bytes. If such a limit is not set, the header MUST be
+/// omitted.
+pub struct TusMaxSizeHeader(pub u64);
+
+impl Header for TusMaxSizeHeader {
+    fn name() -> &'static HeaderName {
+        &HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i http::HeaderValue>,
+    {
+        values
+            .next()
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(TusMaxSizeHeader)
+            .ok_or_else(headers::Error::invalid)
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<http::HeaderValue>,
+    {
+        let value = http::HeaderValue::from_str(&self.0.to_string()).unwrap();
+        values.extend(std::iter::once(value));
+    }
+}
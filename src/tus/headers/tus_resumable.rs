@@ -20,7 +20,7 @@ static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
 /// If the version specified by the Client is not supported by the Server, it MUST respond with the
 /// [StatusCode::PRECONDITION_FAILED] status and MUST include the [TusVersionHeader] header into the
 /// response. In addition, the Server MUST NOT process the request.
-pub struct TusResumableHeader(Version);
+pub struct TusResumableHeader(pub Version);
 
 impl Header for TusResumableHeader {
     fn name() -> &'static HeaderName {
@@ -12,7 +12,16 @@ static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
 /// The [TusVersionHeader] response header MUST be a comma-separated list of protocol versions
 /// supported by the Server. The list MUST be sorted by Server’s preference where the first one is
 /// the most preferred one.
-pub struct TusVersionHeader(Vec<Version>);
+pub struct TusVersionHeader(pub Vec<Version>);
+
+impl TusVersionHeader {
+    /// Lists the given versions sorted by preference, which is the highest version first.
+    pub fn by_preference(versions: &[Version]) -> Self {
+        let mut versions = versions.to_vec();
+        versions.sort_by(|a, b| b.cmp(a));
+        TusVersionHeader(versions)
+    }
+}
 
 impl Header for TusVersionHeader {
     fn name() -> &'static HeaderName {
@@ -75,6 +84,18 @@ mod tests {
         assert!(tus_version.is_err());
     }
 
+    #[test]
+    fn versions_are_listed_highest_first() {
+        let tus_version = TusVersionHeader::by_preference(&[
+            Version::new("0.2.1").unwrap(),
+            Version::new("1.0.0").unwrap(),
+            Version::new("0.2.2").unwrap(),
+        ]);
+        let mut values = Vec::new();
+        tus_version.encode(&mut values);
+        assert_eq!(values[0].to_str().unwrap(), "1.0.0,0.2.2,0.2.1");
+    }
+
     #[test]
     fn version_encode() {
         let tus_version = TusVersionHeader(vec![
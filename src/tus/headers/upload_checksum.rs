@@ -0,0 +1,110 @@
+use axum::http::HeaderName;
+use base64::{
+    engine::general_purpose::STANDARD,
+    Engine,
+};
+use headers::Header;
+
+static CUSTOM_HEADER: &str = "upload-checksum";
+static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
+
+/// # Upload-Checksum
+/// The [UploadChecksumHeader] request header contains information about the checksum of the
+/// current body payload. The header MUST consist of the name of the used checksum algorithm and
+/// the Base64 encoded checksum separated by a space.
+///
+/// It can either be sent as a regular header, or as a trailer after the body of a chunked request
+/// if the checksum can't be calculated upfront.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadChecksumHeader {
+    /// The name of the checksum algorithm, like `sha1`.
+    pub algorithm: String,
+    /// The decoded checksum of the body.
+    pub checksum: Vec<u8>,
+}
+
+impl Header for UploadChecksumHeader {
+    fn name() -> &'static HeaderName {
+        &HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i http::HeaderValue>,
+    {
+        let value_str = values
+            .next()
+            .ok_or_else(headers::Error::invalid)?
+            .to_str()
+            .map_err(|_| headers::Error::invalid())?;
+
+        let (algorithm, checksum) = value_str
+            .trim()
+            .split_once(' ')
+            .ok_or_else(headers::Error::invalid)?;
+
+        // Algorithm names are only allowed to use lowercase ASCII characters.
+        let is_valid_name = |c: char| c.is_ascii() && !c.is_ascii_uppercase() && c != ' ';
+        if algorithm.is_empty() || !algorithm.chars().all(is_valid_name) {
+            return Err(headers::Error::invalid());
+        }
+
+        let checksum = STANDARD
+            .decode(checksum.trim())
+            .map_err(|_| headers::Error::invalid())?;
+
+        Ok(UploadChecksumHeader {
+            algorithm: algorithm.to_string(),
+            checksum,
+        })
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<http::HeaderValue>,
+    {
+        let value = format!("{} {}", self.algorithm, STANDARD.encode(&self.checksum));
+        if let Ok(header_value) = http::HeaderValue::from_str(&value) {
+            values.extend(std::iter::once(header_value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_with_valid_header() {
+        let value = http::HeaderValue::from_static("sha1 Kq5sNclPz7QV2+lfQIuc6R7oRu0=");
+        let mut values = vec![&value].into_iter();
+        let checksum = UploadChecksumHeader::decode(&mut values).unwrap();
+
+        assert_eq!(checksum.algorithm, "sha1");
+        assert_eq!(checksum.checksum.len(), 20);
+    }
+
+    #[test]
+    fn decode_with_invalid_header() {
+        for value in [
+            "sha1",
+            "SHA1 Kq5sNclPz7QV2+lfQIuc6R7oRu0=",
+            "sha1 not-base64!",
+        ] {
+            let value = http::HeaderValue::from_static(value);
+            let mut values = vec![&value].into_iter();
+            assert!(UploadChecksumHeader::decode(&mut values).is_err());
+        }
+    }
+
+    #[test]
+    fn checksum_encode() {
+        let checksum = UploadChecksumHeader {
+            algorithm: "sha1".to_string(),
+            checksum: b"woof".to_vec(),
+        };
+        let mut values = Vec::new();
+        checksum.encode(&mut values);
+        assert_eq!(values[0], "sha1 d29vZg==");
+    }
+}
@@ -0,0 +1,114 @@
+use axum::http::HeaderName;
+use headers::Header;
+
+static CUSTOM_HEADER: &str = "upload-concat";
+static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
+
+/// # Upload-Concat
+/// The [UploadConcatHeader] request and response header MUST be set in both partial and final
+/// upload creation requests. It indicates whether the upload is either a partial or final upload.
+/// If the upload is a partial one, the header value MUST be `partial`. In the case of a final
+/// upload, its value MUST be `final` followed by a semicolon and a space-separated list of partial
+/// upload URLs that will be concatenated. The partial uploads URLs MAY be absolute or relative and
+/// MUST NOT contain spaces as defined in RFC 3986.
+#[derive(Debug, PartialEq)]
+pub enum UploadConcatHeader {
+    /// The upload is a chunk of a file, to be concatenated into a final upload later.
+    Partial,
+    /// The upload is made of the partial uploads at the given URLs, in order.
+    Final(Vec<String>),
+}
+
+impl Header for UploadConcatHeader {
+    fn name() -> &'static HeaderName {
+        &HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i http::HeaderValue>,
+    {
+        let value_str = values
+            .next()
+            .ok_or_else(headers::Error::invalid)?
+            .to_str()
+            .map_err(|_| headers::Error::invalid())?
+            .trim();
+
+        if value_str == "partial" {
+            return Ok(UploadConcatHeader::Partial);
+        }
+
+        let urls: Vec<String> = value_str
+            .strip_prefix("final;")
+            .ok_or_else(headers::Error::invalid)?
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+
+        if urls.is_empty() {
+            return Err(headers::Error::invalid());
+        }
+
+        Ok(UploadConcatHeader::Final(urls))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<http::HeaderValue>,
+    {
+        let value = match self {
+            UploadConcatHeader::Partial => "partial".to_string(),
+            UploadConcatHeader::Final(urls) => format!("final;{}", urls.join(" ")),
+        };
+        if let Ok(header_value) = http::HeaderValue::from_str(&value) {
+            values.extend(std::iter::once(header_value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(value: &'static str) -> Result<UploadConcatHeader, headers::Error> {
+        let value = http::HeaderValue::from_static(value);
+        UploadConcatHeader::decode(&mut vec![&value].into_iter())
+    }
+
+    #[test]
+    fn decode_partial() {
+        assert_eq!(decode("partial").unwrap(), UploadConcatHeader::Partial);
+    }
+
+    #[test]
+    fn decode_final_with_urls() {
+        let concat = decode("final;/files/a https://woof.example/files/b").unwrap();
+        assert_eq!(
+            concat,
+            UploadConcatHeader::Final(vec![
+                "/files/a".to_string(),
+                "https://woof.example/files/b".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn decode_with_invalid_header() {
+        assert!(decode("final;").is_err());
+        assert!(decode("final /files/a").is_err());
+        assert!(decode("partially").is_err());
+    }
+
+    #[test]
+    fn concat_encode() {
+        let mut values = Vec::new();
+        UploadConcatHeader::Final(vec!["/files/a".to_string(), "/files/b".to_string()])
+            .encode(&mut values);
+        assert_eq!(values[0], "final;/files/a /files/b");
+
+        let mut values = Vec::new();
+        UploadConcatHeader::Partial.encode(&mut values);
+        assert_eq!(values[0], "partial");
+    }
+}
@@ -0,0 +1,63 @@
+use axum::http::HeaderName;
+use headers::Header;
+
+static CUSTOM_HEADER: &str = "upload-defer-length";
+static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
+
+/// # Upload-Defer-Length
+/// The [UploadDeferLengthHeader] request and response header indicates that the size of the upload
+/// is not known currently and will be transferred later. Its value MUST be 1. If the length of an
+/// upload is not deferred, this header MUST be omitted.
+#[derive(Debug, PartialEq)]
+pub struct UploadDeferLengthHeader;
+
+impl Header for UploadDeferLengthHeader {
+    fn name() -> &'static HeaderName {
+        &HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i http::HeaderValue>,
+    {
+        values
+            .next()
+            .filter(|value| *value == "1")
+            .map(|_| UploadDeferLengthHeader)
+            .ok_or_else(headers::Error::invalid)
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<http::HeaderValue>,
+    {
+        values.extend(std::iter::once(http::HeaderValue::from_static("1")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defer_length_decode() {
+        let value = http::HeaderValue::from_static("1");
+        let decoded = UploadDeferLengthHeader::decode(&mut std::iter::once(&value));
+        assert_eq!(decoded.unwrap(), UploadDeferLengthHeader);
+    }
+
+    #[test]
+    fn defer_length_decode_with_other_values() {
+        for value in ["0", "true", ""] {
+            let value = http::HeaderValue::from_static(value);
+            assert!(UploadDeferLengthHeader::decode(&mut std::iter::once(&value)).is_err());
+        }
+    }
+
+    #[test]
+    fn defer_length_encode() {
+        let mut values = Vec::new();
+        UploadDeferLengthHeader.encode(&mut values);
+        assert_eq!(values[0], "1");
+    }
+}
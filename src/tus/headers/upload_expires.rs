@@ -0,0 +1,80 @@
+use std::time::SystemTime;
+
+use axum::http::HeaderName;
+use headers::Header;
+use sqlx::types::time::OffsetDateTime;
+
+static CUSTOM_HEADER: &str = "upload-expires";
+static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
+
+/// # Upload-Expires
+/// The [UploadExpiresHeader] response header indicates the time after which the unfinished upload
+/// expires. A Server MAY wish to remove incomplete uploads after a given period of time to prevent
+/// abandoned uploads from taking up extra storage. The Client SHOULD use this header to determine
+/// if an upload is still valid before attempting to resume the upload.
+///
+/// This header MUST be included in every PATCH response if the upload is going to expire. If the
+/// expiration is known at the creation, the [UploadExpiresHeader] header MUST be included in the
+/// response to the initial POST request. Its value MAY change over time.
+///
+/// The value is a datetime in the format defined in RFC 7231 (e.g.
+/// `Wed, 25 Jun 2014 16:00:00 GMT`), which only has a precision of whole seconds.
+pub struct UploadExpiresHeader(pub OffsetDateTime);
+
+impl Header for UploadExpiresHeader {
+    fn name() -> &'static HeaderName {
+        &HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i http::HeaderValue>,
+    {
+        values
+            .next()
+            .and_then(|value| httpdate::parse_http_date(value.to_str().ok()?).ok())
+            .map(|expires| UploadExpiresHeader(expires.into()))
+            .ok_or_else(headers::Error::invalid)
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<http::HeaderValue>,
+    {
+        let date = httpdate::fmt_http_date(SystemTime::from(self.0));
+        let value = http::HeaderValue::from_str(&date).unwrap();
+        values.extend(std::iter::once(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_uses_http_date_format() {
+        let expires = OffsetDateTime::from_unix_timestamp(1403712000).unwrap();
+        let mut values = Vec::new();
+        UploadExpiresHeader(expires).encode(&mut values);
+
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0], "Wed, 25 Jun 2014 16:00:00 GMT");
+    }
+
+    #[test]
+    fn http_date_round_trips() {
+        let expires = OffsetDateTime::from_unix_timestamp(1703000000).unwrap();
+        let mut values = Vec::new();
+        UploadExpiresHeader(expires).encode(&mut values);
+
+        let decoded = UploadExpiresHeader::decode(&mut values.iter()).unwrap();
+        assert_eq!(decoded.0, expires);
+    }
+
+    #[test]
+    fn decode_with_invalid_date() {
+        let value = http::HeaderValue::from_static("2014-06-25T16:00:00Z");
+        let mut values = vec![&value].into_iter();
+        assert!(UploadExpiresHeader::decode(&mut values).is_err());
+    }
+}
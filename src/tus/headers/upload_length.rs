@@ -7,7 +7,7 @@ static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
 /// # Upload-Length
 /// The [UploadLengthHeader] request and response header indicates the size of the entire upload in
 /// bytes. The value MUST be a non-negative integer.
-pub struct UploadLengthHeader(u64);
+pub struct UploadLengthHeader(pub u64);
 
 impl Header for UploadLengthHeader {
     fn name() -> &'static HeaderName {
@@ -0,0 +1,125 @@
+use axum::http::HeaderName;
+use base64::{
+    engine::general_purpose::STANDARD,
+    Engine,
+};
+use headers::Header;
+
+static CUSTOM_HEADER: &str = "upload-metadata";
+static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
+
+/// # Upload-Metadata
+/// The Client MAY supply the [UploadMetadataHeader] header to add additional metadata to the
+/// upload creation request. The Server MAY decide to ignore or use this information to further
+/// process the request or to reject it. If an upload contains additional metadata, responses to
+/// HEAD requests MUST include the [UploadMetadataHeader] header and its value as specified by the
+/// Client during the creation.
+///
+/// The [UploadMetadataHeader] MUST consist of one or more comma-separated key-value pairs. The key
+/// and value MUST be separated by a space. The key MUST NOT contain spaces and commas and MUST NOT
+/// be empty. The key SHOULD be ASCII encoded and all keys MUST be unique. The value MAY be empty.
+/// In these cases, the space, which would normally separate the key and the value, MAY be left
+/// out. Since metadata can contain arbitrary binary values, the Server MUST encode the value using
+/// Base64.
+pub struct UploadMetadataHeader(pub Vec<(String, Option<Vec<u8>>)>);
+
+impl UploadMetadataHeader {
+    /// Gets the value with the given key, if it's present and valid UTF-8.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, value)| std::str::from_utf8(value.as_deref()?).ok())
+    }
+}
+
+impl Header for UploadMetadataHeader {
+    fn name() -> &'static HeaderName {
+        &HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i http::HeaderValue>,
+    {
+        let value_str = values
+            .next()
+            .ok_or_else(headers::Error::invalid)?
+            .to_str()
+            .map_err(|_| headers::Error::invalid())?;
+
+        let mut pairs: Vec<(String, Option<Vec<u8>>)> = Vec::new();
+
+        for pair in value_str.split(',') {
+            let mut parts = pair.trim().splitn(2, ' ');
+            let key = parts.next().unwrap_or_default();
+            if key.is_empty() || pairs.iter().any(|(existing, _)| existing == key) {
+                return Err(headers::Error::invalid());
+            }
+
+            let value = match parts.next().map(str::trim) {
+                Some(value) if !value.is_empty() => Some(
+                    STANDARD
+                        .decode(value)
+                        .map_err(|_| headers::Error::invalid())?,
+                ),
+                _ => None,
+            };
+
+            pairs.push((key.to_string(), value));
+        }
+
+        Ok(UploadMetadataHeader(pairs))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<http::HeaderValue>,
+    {
+        let pair_strings: Vec<String> = self
+            .0
+            .iter()
+            .map(|(key, value)| match value {
+                Some(value) => format!("{key} {}", STANDARD.encode(value)),
+                None => key.clone(),
+            })
+            .collect();
+        let combined = pair_strings.join(",");
+        if let Ok(header_value) = http::HeaderValue::from_str(&combined) {
+            values.extend(std::iter::once(header_value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_with_valid_header() {
+        let value = http::HeaderValue::from_static("filename d29vZi50eHQ=,is_confidential");
+        let mut values = vec![&value].into_iter();
+        let metadata = UploadMetadataHeader::decode(&mut values).unwrap();
+
+        assert_eq!(metadata.get("filename"), Some("woof.txt"));
+        assert_eq!(metadata.0[1], ("is_confidential".to_string(), None));
+    }
+
+    #[test]
+    fn decode_with_duplicate_keys() {
+        let value = http::HeaderValue::from_static("filename d29vZg==,filename d29vZg==");
+        let mut values = vec![&value].into_iter();
+        assert!(UploadMetadataHeader::decode(&mut values).is_err());
+    }
+
+    #[test]
+    fn metadata_encode() {
+        let metadata = UploadMetadataHeader(vec![
+            ("filename".to_string(), Some(b"woof.txt".to_vec())),
+            ("is_confidential".to_string(), None),
+        ]);
+        let mut values = Vec::new();
+        metadata.encode(&mut values);
+        assert_eq!(values[0], "filename d29vZi50eHQ=,is_confidential");
+    }
+}
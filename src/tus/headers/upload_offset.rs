@@ -7,7 +7,7 @@ static HEADER_NAME: HeaderName = HeaderName::from_static(CUSTOM_HEADER);
 /// # Upload-Offset
 /// The [UploadOffsetHeader] request and response header indicates a byte offset within a resource.
 /// The value MUST be a non-negative integer.
-pub struct UploadOffsetHeader(u64);
+pub struct UploadOffsetHeader(pub u64);
 
 impl Header for UploadOffsetHeader {
     fn name() -> &'static HeaderName {
@@ -0,0 +1,67 @@
+//! Keeps more than one request from writing to the same upload at once.
+//!
+//! Bytes are appended to the end of an upload as they arrive, so two PATCH requests writing to
+//! the same upload at the same time would have their bytes interleaved in storage. Whichever one
+//! gets the lock first writes its body, and the other is turned away before it writes anything.
+
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use crate::db::files::UploadId;
+
+/// The uploads that are being written to at the moment, shared between every request.
+#[derive(Debug, Clone, Default)]
+pub struct UploadLocks {
+    locked: Arc<Mutex<HashSet<UploadId>>>,
+}
+
+impl UploadLocks {
+    /// Locks the given upload for as long as the returned [UploadLock] is held.
+    ///
+    /// Returns `None` if another request is already holding the lock, rather than waiting for it.
+    pub fn try_lock(&self, id: UploadId) -> Option<UploadLock> {
+        let mut locked = self.locked.lock().unwrap();
+        locked.insert(id).then(|| UploadLock {
+            locks: self.clone(),
+            id,
+        })
+    }
+}
+
+/// The lock on an upload from [UploadLocks::try_lock], released when it's dropped.
+#[must_use]
+#[derive(Debug)]
+pub struct UploadLock {
+    locks: UploadLocks,
+    id: UploadId,
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        self.locks.locked.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_can_only_be_locked_once_at_a_time() {
+        let locks = UploadLocks::default();
+        let id = UploadId::generate();
+
+        let lock = locks.try_lock(id).unwrap();
+        assert!(locks.try_lock(id).is_none());
+        // Other uploads aren't held up by it.
+        assert!(locks.try_lock(UploadId::generate()).is_some());
+
+        drop(lock);
+        assert!(locks.try_lock(id).is_some());
+    }
+}
@@ -1,3 +1,53 @@
 //! Axum implementation of the [TUS protocol](https://tus.io) for resumable file uploads.
+pub mod capabilities;
+pub mod checksum;
 pub mod extensions;
+pub mod handlers;
 pub mod headers;
+pub mod lock;
+pub mod state;
+pub mod store;
+
+use axum::{
+    middleware,
+    routing::{
+        get,
+        post,
+    },
+    Extension,
+    Router,
+};
+
+use crate::{
+    config::Config,
+    tus::{
+        capabilities::TusCapabilities,
+        handlers::{
+            create_upload,
+            download_upload,
+            options,
+            require_tus_resumable,
+            set_tus_resumable,
+            terminate_upload,
+            upload_chunk,
+            upload_info,
+        },
+    },
+};
+
+/// Defines the [Router] for TUS uploads, supporting what the [Config] enables.
+pub fn router(config: &Config) -> Router {
+    Router::new()
+        .route("/files", post(create_upload).options(options))
+        .route(
+            "/files/:id",
+            get(download_upload)
+                .head(upload_info)
+                .patch(upload_chunk)
+                .delete(terminate_upload),
+        )
+        .layer(middleware::from_fn(require_tus_resumable))
+        // Outside the version check, so the responses it rejects requests with get the header too.
+        .layer(middleware::from_fn(set_tus_resumable))
+        .layer(Extension(TusCapabilities::from_config(config)))
+}
@@ -0,0 +1,268 @@
+//! The lifecycle of a single upload, from creation until every byte of it has been received.
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::{
+    db::files::{
+        File,
+        UploadId,
+    },
+    tus::handlers::TusError,
+};
+
+/// Where an upload is at, and what it's allowed to receive next.
+///
+/// This is what the handlers work with rather than the database row, so the rules about how an
+/// upload moves along only live in one place.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadState {
+    /// The ID identifying the upload in its URL.
+    pub id: UploadId,
+    /// The ID of the user who created the upload, or `None` if it was created anonymously.
+    pub user_id: Option<i32>,
+    /// How big the whole upload is going to be, or `None` if the client hasn't said yet.
+    pub total_length: Option<u64>,
+    /// How many bytes of the upload have been received so far.
+    pub offset: u64,
+    /// Whether the upload is a partial upload, to be concatenated into a final upload.
+    pub is_partial: bool,
+    /// If this is a final upload, the IDs of the partial uploads it's made of in order.
+    pub partials: Option<Vec<UploadId>>,
+    /// The metadata the client sent when creating the upload, as key and value pairs.
+    pub metadata: Vec<(String, Option<Vec<u8>>)>,
+    /// When the upload expires if it hasn't been finished by then.
+    pub expires_at: Option<OffsetDateTime>,
+    /// Whether every byte of the upload has been received.
+    pub completed: bool,
+}
+
+impl UploadState {
+    /// Whether this is a final upload, which is put together by the server out of partial uploads
+    /// rather than receiving bytes of its own.
+    pub fn is_final(&self) -> bool {
+        self.partials.is_some()
+    }
+
+    /// Whether the client hasn't said how big the upload is going to be yet.
+    pub fn is_deferred(&self) -> bool {
+        self.total_length.is_none()
+    }
+
+    /// How many bytes are left to be received, or `None` if the length of the upload is deferred.
+    pub fn remaining(&self) -> Option<u64> {
+        self.total_length
+            .map(|total_length| total_length.saturating_sub(self.offset))
+    }
+
+    /// Whether every byte of the upload has been received.
+    pub fn is_complete(&self) -> bool {
+        self.completed || self.total_length == Some(self.offset)
+    }
+
+    /// The value the client gave a piece of metadata, if it sent one that's valid UTF-8.
+    pub fn metadata_value(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(name, _)| name == key)
+            .and_then(|(_, value)| std::str::from_utf8(value.as_deref()?).ok())
+    }
+
+    /// Whether the upload was left unfinished past its expiry, and can't be resumed anymore.
+    pub fn is_expired(&self) -> bool {
+        !self.is_complete()
+            && self
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= OffsetDateTime::now_utc())
+    }
+
+    /// Makes sure the upload can take bytes starting at the given offset.
+    pub fn can_accept(&self, offset: u64) -> Result<(), TusError> {
+        if self.is_final() {
+            return Err(TusError::FinalUpload);
+        }
+
+        if self.is_expired() {
+            return Err(TusError::Expired);
+        }
+
+        if offset != self.offset {
+            return Err(TusError::OffsetMismatch(self.offset));
+        }
+
+        Ok(())
+    }
+
+    /// Sets how big the upload is going to be, which can only be done once for a deferred upload.
+    ///
+    /// Setting the length it already has again is allowed, so clients can keep sending it.
+    pub fn set_length(&mut self, length: u64) -> Result<(), TusError> {
+        match self.total_length {
+            Some(total_length) if total_length != length => {
+                Err(TusError::LengthAlreadySet(total_length))
+            }
+            _ if length < self.offset => Err(TusError::LengthBelowOffset(self.offset)),
+            _ => {
+                self.total_length = Some(length);
+                self.completed = self.is_complete();
+                Ok(())
+            }
+        }
+    }
+
+    /// Moves the upload along by the given number of received bytes, completing it once they've
+    /// all been received.
+    pub fn advance(&mut self, length: u64) -> Result<(), TusError> {
+        let offset = self.offset.saturating_add(length);
+        if self
+            .total_length
+            .is_some_and(|total_length| offset > total_length)
+        {
+            return Err(TusError::BodyTooLarge);
+        }
+
+        self.offset = offset;
+        self.completed = self.is_complete();
+
+        Ok(())
+    }
+}
+
+impl From<File> for UploadState {
+    fn from(file: File) -> Self {
+        // Only the metadata the server makes use of is kept around.
+        let metadata = [("filename", file.filename), ("filetype", file.content_type)]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.to_string(), Some(value?.into_bytes()))))
+            .collect();
+
+        UploadState {
+            id: file.uuid,
+            user_id: file.user_id,
+            total_length: file.size.map(|size| size as u64),
+            offset: file.offset as u64,
+            is_partial: file.is_partial,
+            partials: file.partials,
+            metadata,
+            expires_at: file.expires_at,
+            completed: file.completed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::time::Duration;
+
+    use super::*;
+
+    fn state(total_length: Option<u64>) -> UploadState {
+        UploadState {
+            id: UploadId::generate(),
+            user_id: None,
+            total_length,
+            offset: 0,
+            is_partial: false,
+            partials: None,
+            metadata: vec![("filename".to_string(), Some(b"woof.txt".to_vec()))],
+            expires_at: Some(OffsetDateTime::now_utc() + Duration::hours(1)),
+            completed: false,
+        }
+    }
+
+    #[test]
+    fn advancing_completes_the_upload_at_its_length() {
+        let mut upload = state(Some(8));
+        assert_eq!(upload.remaining(), Some(8));
+
+        upload.advance(4).unwrap();
+        assert_eq!(upload.offset, 4);
+        assert_eq!(upload.remaining(), Some(4));
+        assert!(!upload.is_complete());
+
+        upload.advance(4).unwrap();
+        assert_eq!(upload.remaining(), Some(0));
+        assert!(upload.is_complete());
+        assert!(upload.completed);
+    }
+
+    #[test]
+    fn advancing_past_the_length_is_rejected() {
+        let mut upload = state(Some(8));
+
+        assert!(matches!(upload.advance(9), Err(TusError::BodyTooLarge)));
+        assert_eq!(upload.offset, 0);
+    }
+
+    #[test]
+    fn deferred_length_has_nothing_remaining_to_check() {
+        let mut upload = state(None);
+
+        upload.advance(1024).unwrap();
+        assert_eq!(upload.remaining(), None);
+        assert!(!upload.is_complete());
+    }
+
+    #[test]
+    fn deferred_length_can_only_be_set_once() {
+        let mut upload = state(None);
+        assert!(upload.is_deferred());
+        upload.advance(4).unwrap();
+
+        assert!(matches!(
+            upload.set_length(2),
+            Err(TusError::LengthBelowOffset(4))
+        ));
+        upload.set_length(8).unwrap();
+        assert!(!upload.is_deferred());
+        assert_eq!(upload.remaining(), Some(4));
+
+        upload.set_length(8).unwrap();
+        assert!(matches!(
+            upload.set_length(16),
+            Err(TusError::LengthAlreadySet(8))
+        ));
+    }
+
+    #[test]
+    fn only_the_current_offset_is_accepted() {
+        let mut upload = state(Some(8));
+        upload.advance(4).unwrap();
+
+        assert!(upload.can_accept(4).is_ok());
+        assert!(matches!(
+            upload.can_accept(0),
+            Err(TusError::OffsetMismatch(4))
+        ));
+    }
+
+    #[test]
+    fn final_and_expired_uploads_accept_nothing() {
+        let final_upload = UploadState {
+            partials: Some(vec![UploadId::generate()]),
+            ..state(Some(8))
+        };
+        assert!(matches!(
+            final_upload.can_accept(0),
+            Err(TusError::FinalUpload)
+        ));
+
+        let expired = UploadState {
+            expires_at: Some(OffsetDateTime::now_utc() - Duration::seconds(1)),
+            ..state(Some(8))
+        };
+        assert!(expired.is_expired());
+        assert!(matches!(expired.can_accept(0), Err(TusError::Expired)));
+    }
+
+    #[test]
+    fn state_round_trips_through_json() {
+        let upload = state(Some(8));
+        let json = serde_json::to_string(&upload).unwrap();
+
+        assert_eq!(serde_json::from_str::<UploadState>(&json).unwrap(), upload);
+    }
+}
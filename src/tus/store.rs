@@ -0,0 +1,352 @@
+//! Storage for the contents of TUS uploads.
+//!
+//! The database only tracks an upload's progress, the bytes themselves are kept by an
+//! [UploadStore] so they can live somewhere more suitable than a table.
+
+use std::{
+    io::{
+        self,
+        SeekFrom,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use async_trait::async_trait;
+use tokio::{
+    fs::{
+        self,
+        OpenOptions,
+    },
+    io::{
+        AsyncRead,
+        AsyncSeekExt,
+        AsyncWriteExt,
+    },
+};
+use uuid::Uuid;
+
+use crate::db::files::UploadId;
+
+/// Reads back the contents of an upload.
+pub type UploadReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Somewhere the contents of uploads can be written to as they come in, and read back from.
+#[async_trait]
+pub trait UploadStore: Send + Sync {
+    /// Creates a new, empty upload.
+    async fn create(&self, id: UploadId) -> io::Result<()>;
+
+    /// Appends bytes to the end of an existing upload.
+    async fn append(&self, id: UploadId, bytes: &[u8]) -> io::Result<()>;
+
+    /// Cuts an upload back down to the given length, throwing away anything written past it.
+    async fn truncate(&self, id: UploadId, length: u64) -> io::Result<()>;
+
+    /// Creates a new upload out of the contents of existing uploads, joined together in order.
+    ///
+    /// Nothing is left behind if it fails part way through, and doing it again replaces whatever
+    /// an earlier attempt created.
+    async fn concatenate(&self, id: UploadId, parts: &[UploadId]) -> io::Result<()>;
+
+    /// Opens an upload for reading, starting the given number of bytes in.
+    async fn read(&self, id: UploadId, offset: u64) -> io::Result<UploadReader>;
+
+    /// Deletes an upload and everything written to it.
+    ///
+    /// Deleting an upload that doesn't exist is not an error.
+    async fn delete(&self, id: UploadId) -> io::Result<()>;
+}
+
+/// An [UploadStore] that keeps each upload in its own file inside a directory.
+pub struct FilesystemUploadStore {
+    root: PathBuf,
+}
+
+impl FilesystemUploadStore {
+    /// Creates a store that keeps uploads in the given directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FilesystemUploadStore { root: root.into() }
+    }
+
+    /// Opens a store that keeps uploads in the given directory, creating it if it doesn't exist.
+    ///
+    /// A file is briefly written to the directory to make sure it's writable, so a misconfigured
+    /// directory is caught on startup rather than when the first upload comes in.
+    pub async fn open(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let store = FilesystemUploadStore::new(root);
+        fs::create_dir_all(&store.root).await?;
+
+        let probe = store.path(UploadId::generate());
+        fs::write(&probe, b"").await?;
+        fs::remove_file(&probe).await?;
+
+        Ok(store)
+    }
+
+    /// The path of the file an upload is kept in.
+    ///
+    /// Upload IDs are always UUIDs, which can't contain a path separator or `..`, so an upload can
+    /// never end up outside of the root directory.
+    fn path(&self, id: UploadId) -> PathBuf {
+        self.root.join(id.to_string())
+    }
+
+    /// Writes the contents of the given uploads, joined together in order, to a new file.
+    async fn join_parts(&self, path: &Path, parts: &[UploadId]) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await?;
+
+        // Parts are copied over a bit at a time rather than read into memory, they could be huge.
+        for part in parts {
+            let mut part = fs::File::open(self.path(*part)).await?;
+            tokio::io::copy(&mut part, &mut file).await?;
+        }
+
+        file.sync_data().await
+    }
+}
+
+#[async_trait]
+impl UploadStore for FilesystemUploadStore {
+    async fn create(&self, id: UploadId) -> io::Result<()> {
+        fs::create_dir_all(&self.root).await?;
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.path(id))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn append(&self, id: UploadId, bytes: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(self.path(id)).await?;
+        file.write_all(bytes).await?;
+        file.sync_data().await
+    }
+
+    async fn truncate(&self, id: UploadId, length: u64) -> io::Result<()> {
+        let file = OpenOptions::new().write(true).open(self.path(id)).await?;
+        file.set_len(length).await?;
+        file.sync_data().await
+    }
+
+    async fn concatenate(&self, id: UploadId, parts: &[UploadId]) -> io::Result<()> {
+        fs::create_dir_all(&self.root).await?;
+
+        // The parts are joined in a file of their own that's only moved into place once it's
+        // complete, so the upload never exists half put together.
+        let joined = self.root.join(format!("{id}.{}.part", Uuid::new_v4()));
+        let result = match self.join_parts(&joined, parts).await {
+            Ok(()) => fs::rename(&joined, self.path(id)).await,
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            fs::remove_file(&joined).await.ok();
+        }
+
+        result
+    }
+
+    async fn read(&self, id: UploadId, offset: u64) -> io::Result<UploadReader> {
+        let mut file = fs::File::open(self.path(id)).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        Ok(Box::new(file))
+    }
+
+    async fn delete(&self, id: UploadId) -> io::Result<()> {
+        match fs::remove_file(self.path(id)).await {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// An [UploadStore] that keeps uploads in memory, so tests don't need a filesystem.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryUploadStore {
+    uploads: std::sync::Mutex<std::collections::HashMap<UploadId, Vec<u8>>>,
+    largest_append: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl MemoryUploadStore {
+    /// Gets a copy of everything written to an upload so far.
+    pub fn contents(&self, id: UploadId) -> Option<Vec<u8>> {
+        self.uploads.lock().unwrap().get(&id).cloned()
+    }
+
+    /// The most bytes that have been appended to any upload in one go.
+    pub fn largest_append(&self) -> usize {
+        self.largest_append
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl UploadStore for MemoryUploadStore {
+    async fn create(&self, id: UploadId) -> io::Result<()> {
+        self.uploads.lock().unwrap().insert(id, Vec::new());
+        Ok(())
+    }
+
+    async fn append(&self, id: UploadId, bytes: &[u8]) -> io::Result<()> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?
+            .extend_from_slice(bytes);
+        self.largest_append
+            .fetch_max(bytes.len(), std::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn truncate(&self, id: UploadId, length: u64) -> io::Result<()> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?
+            .truncate(length as usize);
+        Ok(())
+    }
+
+    async fn concatenate(&self, id: UploadId, parts: &[UploadId]) -> io::Result<()> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let mut contents = Vec::new();
+        for part in parts {
+            let part = uploads
+                .get(part)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+            contents.extend_from_slice(part);
+        }
+
+        uploads.insert(id, contents);
+        Ok(())
+    }
+
+    async fn read(&self, id: UploadId, offset: u64) -> io::Result<UploadReader> {
+        let contents = self
+            .contents(id)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        let offset = (offset as usize).min(contents.len());
+
+        Ok(Box::new(io::Cursor::new(contents[offset..].to_vec())))
+    }
+
+    async fn delete(&self, id: UploadId) -> io::Result<()> {
+        self.uploads.lock().unwrap().remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn filesystem_store_appends_to_uploads() {
+        let root = std::env::temp_dir().join(format!("woof-uploads-{}", Uuid::new_v4()));
+        let store = FilesystemUploadStore::new(&root);
+        let id = UploadId::generate();
+
+        store.create(id).await.unwrap();
+        store.append(id, b"woof ").await.unwrap();
+        store.append(id, b"woof").await.unwrap();
+        assert_eq!(
+            fs::read(root.join(id.to_string())).await.unwrap(),
+            b"woof woof"
+        );
+
+        store.append(id, b"bark").await.unwrap();
+        store.truncate(id, 9).await.unwrap();
+        assert_eq!(
+            fs::read(root.join(id.to_string())).await.unwrap(),
+            b"woof woof"
+        );
+
+        let mut contents = String::new();
+        let mut reader = store.read(id, 5).await.unwrap();
+        reader.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(contents, "woof");
+
+        let concatenated = UploadId::generate();
+        store.concatenate(concatenated, &[id, id]).await.unwrap();
+        assert_eq!(
+            fs::read(root.join(concatenated.to_string())).await.unwrap(),
+            b"woof woofwoof woof"
+        );
+        store.delete(concatenated).await.unwrap();
+
+        store.delete(id).await.unwrap();
+        assert!(!root.join(id.to_string()).exists());
+        assert!(store.delete(id).await.is_ok());
+
+        fs::remove_dir(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_concatenation_leaves_nothing_behind() {
+        let root = std::env::temp_dir().join(format!("woof-uploads-{}", Uuid::new_v4()));
+        let store = FilesystemUploadStore::new(&root);
+        let (id, missing) = (UploadId::generate(), UploadId::generate());
+        store.create(id).await.unwrap();
+        store.append(id, b"woof").await.unwrap();
+
+        let concatenated = UploadId::generate();
+        let result = store.concatenate(concatenated, &[id, missing]).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 1);
+
+        // Once the missing part turns up it can be tried again.
+        store.create(missing).await.unwrap();
+        store.append(missing, b" bark").await.unwrap();
+        store
+            .concatenate(concatenated, &[id, missing])
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read(root.join(concatenated.to_string())).await.unwrap(),
+            b"woof bark"
+        );
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn filesystem_store_creates_missing_root() {
+        let root = std::env::temp_dir()
+            .join(format!("woof-uploads-{}", Uuid::new_v4()))
+            .join("nested");
+
+        FilesystemUploadStore::open(&root).await.unwrap();
+        assert!(root.is_dir());
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+
+        fs::remove_dir_all(root.parent().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn filesystem_store_rejects_unusable_root() {
+        // A directory can't be created underneath a file.
+        let file = std::env::temp_dir().join(format!("woof-uploads-{}", Uuid::new_v4()));
+        fs::write(&file, b"woof").await.unwrap();
+
+        let result = FilesystemUploadStore::open(file.join("uploads")).await;
+        assert!(result.is_err());
+
+        fs::remove_file(&file).await.unwrap();
+    }
+}
@@ -1,6 +1,16 @@
 //! This module contains all the logic for the WebAuthn authentication flow.
 
-use gloo_net::http::Request;
+use std::{
+    future::Future,
+    task::Poll,
+};
+
+use gloo_net::http::{
+    Request,
+    Response,
+};
+use gloo_timers::future::TimeoutFuture;
+use js_sys::Reflect;
 use seed::{
     prelude::*,
     *,
@@ -11,6 +21,10 @@ use serde::{
     Serialize,
 };
 use thiserror::Error;
+use web_sys::{
+    AbortController,
+    AbortSignal,
+};
 use webauthn_rs_proto::{
     CreationChallengeResponse,
     PublicKeyCredential,
@@ -19,24 +33,109 @@ use webauthn_rs_proto::{
 };
 
 use crate::{
+    i18n::Strings,
+    theme::Theme,
+    utils::store_last_username,
     views::ViewState,
     Msg,
 };
 
 /// Holds all the state for the authentication component.
 pub struct AuthModel {
+    /// The text shown to the user, in their language.
+    pub strings: &'static Strings,
     /// The current value of the username input box.
     pub input_value: String,
     /// The last username that was used to start the registration/authentication process.
     pub last_username: String,
     /// The current state of the view.
     pub view_state: ViewState,
+    /// The color scheme the component is shown in.
+    pub theme: Theme,
+    /// The server API endpoints used during registration/authentication.
+    pub endpoints: ApiEndpoints,
+    /// How long to wait for the server to send a challenge before giving up, in milliseconds.
+    pub challenge_timeout: u32,
+    /// Whether the browser can create passkeys through conditional create, which is tried before
+    /// falling back to a regular registration.
+    pub conditional_create: bool,
+    /// The process to start again when the user retries, set when the server didn't respond.
+    pub retry: Option<Flow>,
+    /// The step of the registration/authentication process that's currently running, if any.
+    pub in_flight: Option<InFlight>,
+}
+
+/// A request to the server or a prompt from the browser that's still running.
+///
+/// Dropping it cancels the request, and aborts the fetch or prompt behind it so nothing is left
+/// running in the background.
+pub struct InFlight {
+    _handle: CmdHandle,
+    abort_controller: Option<AbortController>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Some(abort_controller) = &self.abort_controller {
+            abort_controller.abort();
+        }
+    }
+}
+
+/// The processes the component can take a user through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    Register,
+    Authenticate,
+}
+
+/// The URLs of the server API endpoints the component talks to.
+///
+/// These are all built from a common base so the component keeps working when woof is mounted
+/// under a path prefix, or the API lives on a different host.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiEndpoints {
+    pub csrf: String,
+    pub start_register: String,
+    pub start_conditional_register: String,
+    pub finish_register: String,
+    pub start_authentication: String,
+    pub finish_authentication: String,
+    pub validate_redirect: String,
+}
+
+impl ApiEndpoints {
+    /// Builds the endpoint URLs on top of the given base (e.g. `/woof` or
+    /// `https://api.woof.example`).
+    pub fn from_base(base: &str) -> ApiEndpoints {
+        let base = base.trim_end_matches('/');
+        ApiEndpoints {
+            csrf: format!("{base}/api/csrf"),
+            start_register: format!("{base}/api/users/start_register"),
+            start_conditional_register: format!("{base}/api/users/start_conditional_register"),
+            finish_register: format!("{base}/api/users/finish_register"),
+            start_authentication: format!("{base}/api/users/start_authentication"),
+            finish_authentication: format!("{base}/api/users/finish_authentication"),
+            validate_redirect: format!("{base}/api/redirect/validate"),
+        }
+    }
+}
+
+impl Default for ApiEndpoints {
+    /// Uses absolute paths on the current host, which is where woof serves the API by default.
+    fn default() -> Self {
+        ApiEndpoints::from_base("")
+    }
 }
 
 /// Parameters sent to the server to start the registration/authentication process.
 #[derive(Serialize)]
 pub struct AuthServerParams {
     pub username: String,
+    /// A human-friendly name shown in the authenticator's UI during registration.
+    /// The server falls back to the username if this is omitted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
 }
 
 impl AuthModel {
@@ -48,13 +147,54 @@ impl AuthModel {
     ///
     /// If the server responds with an error, it will be displayed to the user.
     pub fn start_register(&mut self, username: String, orders: &mut impl Orders<Msg>) {
+        let endpoint = self.endpoints.start_register.clone();
+        self.request_register_challenge(username, endpoint, Msg::SignRegisterChallenge, orders);
+    }
+
+    /// Start the registration process for a user through conditional create.
+    ///
+    /// This works just like [start_register], except the server's [CreationChallengeResponse]
+    /// should be passed to [sign_conditional_register_challenge] instead.
+    pub fn start_conditional_register(&mut self, username: String, orders: &mut impl Orders<Msg>) {
+        let endpoint = self.endpoints.start_conditional_register.clone();
+        self.request_register_challenge(
+            username,
+            endpoint,
+            Msg::SignConditionalRegisterChallenge,
+            orders,
+        );
+    }
+
+    /// Asks the server for a registration challenge from the given endpoint, wrapping it in the
+    /// message built by `on_challenge` once it arrives.
+    fn request_register_challenge(
+        &mut self,
+        username: String,
+        endpoint: String,
+        on_challenge: fn(CreationChallengeResponse) -> Msg,
+        orders: &mut impl Orders<Msg>,
+    ) {
         self.last_username = username.clone();
-        orders.perform_cmd(async move {
-            match get_challenge("/api/users/start_register", username.clone()).await {
-                Ok(ccr) => Msg::SignRegisterChallenge(ccr),
-                Err(err) => Msg::Error(err.to_string()),
+        store_last_username(&username);
+        let csrf_endpoint = self.endpoints.csrf.clone();
+        let timeout = self.challenge_timeout;
+        let (abort_controller, signal) = abort_controller();
+        let handle = orders.perform_cmd_with_handle(async move {
+            let params = AuthServerParams {
+                username,
+                display_name: None,
+            };
+            // Logged in users are adding a passkey to their account, which needs the CSRF token.
+            let request = async {
+                let csrf_token = get_csrf_token(&csrf_endpoint, signal.as_ref()).await?;
+                get_challenge(&endpoint, params, Some(&csrf_token), signal.as_ref()).await
+            };
+            match with_timeout(request, timeout).await {
+                Ok(ccr) => on_challenge(ccr),
+                Err(err) => err.into_msg(Flow::Register),
             }
         });
+        self.track(handle, abort_controller);
     }
 
     /// Initiate's the browser's passkey registration process, using the [CreationChallengeResponse]
@@ -70,28 +210,31 @@ impl AuthModel {
         ccr: CreationChallengeResponse,
         orders: &mut impl Orders<Msg>,
     ) {
-        // First, convert from our webauthn proto json safe format, into the browser
-        // compatible struct, with everything decoded as needed.
-        let c_options: web_sys::CredentialCreationOptions = ccr.into();
-
-        // Now, we can create a promise using the browser's credential creation API.
-        let promise = window()
-            .navigator()
-            .credentials()
-            .create_with_options(&c_options)
-            .expect_throw("Could not create credential");
-
-        // We need to convert the promise into a future so we can await it.
-        let signing_future = JsFuture::from(promise);
-        orders.perform_cmd(async move {
+        let (signing_future, abort_controller) = create_credential(ccr, false);
+        let strings = self.strings;
+        let handle = orders.perform_cmd_with_handle(async move {
             // Await the promise, if it resolves, we have a PublicKeyCredential, if it rejects,
             // the user *probably* cancelled the registration process. It's possible that the
             // browser could reject for other reasons, but we'll just assume it's a cancellation for
             // now.
             let jsval = match signing_future.await {
                 Ok(val) => val,
-                Err(_) => {
-                    return Msg::Error("Authentication cancelled".to_string());
+                Err(err) => {
+                    // The authenticator refuses with an InvalidStateError when it already holds
+                    // one of the credentials the server asked it to exclude.
+                    let already_registered = err
+                        .dyn_ref::<web_sys::DomException>()
+                        .is_some_and(|exception| exception.name() == "InvalidStateError");
+
+                    if already_registered {
+                        return Msg::Error(ErrorKind::Message(
+                            strings.passkey_already_registered.to_string(),
+                        ));
+                    }
+
+                    return Msg::Error(ErrorKind::Message(
+                        strings.authentication_cancelled.to_string(),
+                    ));
                 }
             };
 
@@ -104,6 +247,31 @@ impl AuthModel {
 
             Msg::FinishRegister(rpkc)
         });
+        self.track(handle, abort_controller);
+    }
+
+    /// Initiate's the browser's passkey registration process through conditional create, using
+    /// the [CreationChallengeResponse] from [start_conditional_register].
+    ///
+    /// The browser's password manager creates the passkey without prompting the user, which is
+    /// then passed to [finish_register]. Browsers are free to decline, in which case the user is
+    /// taken through a regular registration instead.
+    pub fn sign_conditional_register_challenge(
+        &mut self,
+        ccr: CreationChallengeResponse,
+        orders: &mut impl Orders<Msg>,
+    ) {
+        let (signing_future, abort_controller) = create_credential(ccr, true);
+        let handle = orders.perform_cmd_with_handle(async move {
+            match signing_future.await {
+                Ok(jsval) => {
+                    let w_rpkc = web_sys::PublicKeyCredential::from(jsval);
+                    Msg::FinishRegister(RegisterPublicKeyCredential::from(w_rpkc))
+                }
+                Err(_) => Msg::ConditionalRegisterDeclined,
+            }
+        });
+        self.track(handle, abort_controller);
     }
 
     /// Finish the registration process by sending the [RegisterPublicKeyCredential] to the server.
@@ -115,12 +283,20 @@ impl AuthModel {
         rpkc: RegisterPublicKeyCredential,
         orders: &mut impl Orders<Msg>,
     ) {
-        orders.perform_cmd(async move {
-            match submit_credential("/api/users/finish_registration", rpkc).await {
+        let endpoint = self.endpoints.finish_register.clone();
+        let csrf_endpoint = self.endpoints.csrf.clone();
+        let (abort_controller, signal) = abort_controller();
+        let handle = orders.perform_cmd_with_handle(async move {
+            let request = async {
+                let csrf_token = get_csrf_token(&csrf_endpoint, signal.as_ref()).await?;
+                submit_credential(&endpoint, rpkc, Some(&csrf_token), signal.as_ref()).await
+            };
+            match request.await {
                 Ok(_) => Msg::Success,
-                Err(err) => Msg::Error(err.to_string()),
+                Err(err) => err.into_msg(Flow::Register),
             }
         });
+        self.track(handle, abort_controller);
     }
 
     /// Start the authentication process for a user.
@@ -132,12 +308,22 @@ impl AuthModel {
     /// If the server responds with an error, it will be displayed to the user.
     pub fn start_authentication(&mut self, username: String, orders: &mut impl Orders<Msg>) {
         self.last_username = username.clone();
-        orders.perform_cmd(async move {
-            match get_challenge("/api/users/start_authentication", username.clone()).await {
+        store_last_username(&username);
+        let endpoint = self.endpoints.start_authentication.clone();
+        let timeout = self.challenge_timeout;
+        let (abort_controller, signal) = abort_controller();
+        let handle = orders.perform_cmd_with_handle(async move {
+            let params = AuthServerParams {
+                username,
+                display_name: None,
+            };
+            let request = get_challenge(&endpoint, params, None, signal.as_ref());
+            match with_timeout(request, timeout).await {
                 Ok(rcr) => Msg::SignAuthenticationChallenge(rcr),
-                Err(err) => Msg::Error(err.to_string()),
+                Err(err) => err.into_msg(Flow::Authenticate),
             }
         });
+        self.track(handle, abort_controller);
     }
 
     /// Initiate's the browser's passkey authentication process, using the
@@ -155,7 +341,13 @@ impl AuthModel {
     ) {
         // First, convert from our webauthn proto json safe format, into the browser
         // compatible struct, with everything decoded as needed.
-        let c_options: web_sys::CredentialRequestOptions = rcr.into();
+        let mut c_options: web_sys::CredentialRequestOptions = rcr.into();
+
+        // Cancelling aborts the browser's prompt along with it.
+        let (abort_controller, signal) = abort_controller();
+        if let Some(signal) = &signal {
+            c_options.signal(signal);
+        }
 
         // Now, we can create a promise using the browser's credential API.
         let promise = window()
@@ -166,7 +358,8 @@ impl AuthModel {
 
         // We need to convert the promise into a future so we can await it.
         let signing_future = JsFuture::from(promise);
-        orders.perform_cmd(async move {
+        let strings = self.strings;
+        let handle = orders.perform_cmd_with_handle(async move {
             // Await the promise, if it resolves, we have a PublicKeyCredential, if it rejects,
             // the user *probably* cancelled the registration process. It's possible that the
             // browser could reject for other reasons, but we'll just assume it's a cancellation for
@@ -174,7 +367,9 @@ impl AuthModel {
             let jsval = match signing_future.await {
                 Ok(val) => val,
                 Err(_) => {
-                    return Msg::Error("Authentication cancelled".to_string());
+                    return Msg::Error(ErrorKind::Message(
+                        strings.authentication_cancelled.to_string(),
+                    ));
                 }
             };
 
@@ -187,6 +382,20 @@ impl AuthModel {
 
             Msg::FinishAuthentication(pkc)
         });
+        self.track(handle, abort_controller);
+    }
+
+    /// Cancels whatever step of the registration/authentication process is running.
+    pub fn cancel(&mut self) {
+        self.in_flight = None;
+    }
+
+    /// Keeps hold of the command running the current step of the process, so it can be cancelled.
+    fn track(&mut self, handle: CmdHandle, abort_controller: Option<AbortController>) {
+        self.in_flight = Some(InFlight {
+            _handle: handle,
+            abort_controller,
+        });
     }
 
     /// Finish the authentication process by sending the [PublicKeyCredential] to the server.
@@ -198,28 +407,99 @@ impl AuthModel {
         pkc: PublicKeyCredential,
         orders: &mut impl Orders<Msg>,
     ) {
-        orders.perform_cmd(async move {
-            match submit_credential("/api/users/finish_authentication", pkc).await {
+        let endpoint = self.endpoints.finish_authentication.clone();
+        let (abort_controller, signal) = abort_controller();
+        let handle = orders.perform_cmd_with_handle(async move {
+            match submit_credential(&endpoint, pkc, None, signal.as_ref()).await {
                 Ok(_) => Msg::Success,
-                Err(err) => Msg::Error(err.to_string()),
+                Err(err) => err.into_msg(Flow::Authenticate),
             }
         });
+        self.track(handle, abort_controller);
     }
 }
 
+/// Asks the browser to create a credential for the given [CreationChallengeResponse], returning a
+/// future that resolves once it has along with the controller that aborts it.
+///
+/// When `conditional` is set the browser is asked to create it through conditional create, without
+/// prompting the user.
+fn create_credential(
+    ccr: CreationChallengeResponse,
+    conditional: bool,
+) -> (JsFuture, Option<AbortController>) {
+    // First, convert from our webauthn proto json safe format, into the browser
+    // compatible struct, with everything decoded as needed.
+    let mut c_options: web_sys::CredentialCreationOptions = ccr.into();
+
+    // Cancelling aborts the browser's prompt along with it.
+    let (abort_controller, signal) = abort_controller();
+    if let Some(signal) = &signal {
+        c_options.signal(signal);
+    }
+
+    // web-sys doesn't know about the mediation hint for credential creation yet, so it's set on
+    // the underlying object directly.
+    if conditional {
+        Reflect::set(&c_options, &"mediation".into(), &"conditional".into()).ok();
+    }
+
+    // Now, we can create a promise using the browser's credential creation API.
+    let promise = window()
+        .navigator()
+        .credentials()
+        .create_with_options(&c_options)
+        .expect_throw("Could not create credential");
+
+    // We need to convert the promise into a future so we can await it.
+    (JsFuture::from(promise), abort_controller)
+}
+
 /// An error returned by the server API.
 #[derive(Debug, Deserialize)]
 pub struct ApiError {
+    /// A machine-readable code identifying the kind of error (e.g. `user_already_exists`).
+    #[serde(default)]
+    pub code: String,
+    /// A human-readable description of the error.
     pub message: String,
 }
 
+impl From<ApiError> for AuthProcessError {
+    /// Picks out the errors the component handles specially by their code, anything else is shown
+    /// to the user as is.
+    fn from(error: ApiError) -> Self {
+        match error.code.as_str() {
+            "user_already_exists" => AuthProcessError::UserAlreadyExists(error.message),
+            _ => AuthProcessError::ApiError(error.message),
+        }
+    }
+}
+
 /// An error that can occur during the authentication process.
 #[derive(Debug, Error)]
 pub enum AuthProcessError {
-    /// Could not fetch the challenge from the server API.
-    #[error("Could not fetch challenge from server API: {0}")]
+    /// Could not fetch the challenge from the server API, usually because the server couldn't be
+    /// reached at all.
+    #[error("Could not reach the server, check your connection: {0}")]
     FetchChallengeFailure(gloo_net::Error),
 
+    /// Could not turn what's being sent to the server into JSON, so the request was never made.
+    #[error("Could not prepare the request: {0}")]
+    RequestSerializeFailure(gloo_net::Error),
+
+    /// The server was reached, but didn't respond in time.
+    #[error("The server didn't respond in time")]
+    Timeout,
+
+    /// Could not parse the CSRF token the server handed out.
+    #[error("Could not parse the CSRF token: {0}")]
+    CsrfTokenParseFailure(gloo_net::Error),
+
+    /// Could not parse the redirect target the server checked.
+    #[error("Could not parse the redirect target: {0}")]
+    RedirectParseFailure(gloo_net::Error),
+
     /// Could not parse the challenge response from the server.
     #[error("Could not parse challenge response: {0}")]
     ChallengeParseFailure(gloo_net::Error),
@@ -228,28 +508,133 @@ pub enum AuthProcessError {
     #[error("{0}")]
     ApiError(String),
 
+    /// Someone tried to register with a username that's already taken, most likely because they
+    /// forgot they already have an account.
+    #[error("{0}")]
+    UserAlreadyExists(String),
+
     /// The server responded with a non-200 status code but an error message could not be parsed.
     #[error("An error occurred but could not be parsed.")]
     ApiErrorParseFailure(gloo_net::Error),
 }
 
+impl AuthProcessError {
+    /// Turns the error into the message that shows it to the user, given the [Flow] it happened
+    /// during so that it can be retried.
+    ///
+    /// Not being able to reach the server gets its own view, as it's most likely a problem on the
+    /// user's end that retrying can fix, unlike anything the server said went wrong.
+    pub fn into_msg(self, flow: Flow) -> Msg {
+        match self {
+            AuthProcessError::FetchChallengeFailure(_) => Msg::Error(ErrorKind::Offline(flow)),
+            AuthProcessError::Timeout => Msg::TimedOut(flow),
+            AuthProcessError::UserAlreadyExists(_) => Msg::UserAlreadyExists,
+            err => Msg::Error(ErrorKind::Message(err.to_string())),
+        }
+    }
+}
+
+/// The kinds of error shown to the user, carried by [Msg::Error].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// The server couldn't be reached, holds the [Flow] to start again when the user retries.
+    Offline(Flow),
+    /// Anything else that went wrong, holds the message to show the user.
+    Message(String),
+}
+
+/// The header the session's CSRF token is sent back to the server in.
+const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// The session's CSRF token, as the server hands it out.
+#[derive(Deserialize)]
+struct CsrfToken {
+    token: String,
+}
+
+/// Asks the server for the session's CSRF token, which has to be sent along with anything that
+/// changes the account of a logged in user, like adding a passkey to it.
+///
+/// This will return a [AuthProcessError] if the request fails, or the server responds with an
+/// error. The request is aborted when `signal` is.
+pub async fn get_csrf_token(
+    endpoint: &str,
+    signal: Option<&AbortSignal>,
+) -> Result<String, AuthProcessError> {
+    let response = Request::get(endpoint)
+        .abort_signal(signal)
+        .send()
+        .await
+        .map_err(AuthProcessError::FetchChallengeFailure)?;
+
+    if response.status() != 200 {
+        return Err(response_error(response).await);
+    }
+
+    let CsrfToken { token } = response
+        .json()
+        .await
+        .map_err(AuthProcessError::CsrfTokenParseFailure)?;
+
+    Ok(token)
+}
+
+/// A redirect target the server allows users to be sent to.
+#[derive(Deserialize)]
+struct ValidatedRedirect {
+    target: String,
+}
+
+/// Asks the server whether users can be redirected to the given target once they're logged in,
+/// returning it in the form they should be sent to.
+///
+/// This will return a [AuthProcessError] if the request fails, or the server doesn't allow the
+/// target.
+pub async fn validate_redirect(endpoint: &str, target: &str) -> Result<String, AuthProcessError> {
+    let response = Request::get(endpoint)
+        .query([("target", target)])
+        .send()
+        .await
+        .map_err(AuthProcessError::FetchChallengeFailure)?;
+
+    if response.status() != 200 {
+        return Err(response_error(response).await);
+    }
+
+    let ValidatedRedirect { target } = response
+        .json()
+        .await
+        .map_err(AuthProcessError::RedirectParseFailure)?;
+
+    Ok(target)
+}
+
 /// Send a request to the server to get a passkey challenge.
 ///
 /// This will return a [AuthProcessError] if the request fails, or the server responds with an
-/// error.
+/// error. The request is aborted when `signal` is. The `csrf_token` is sent along if given, see
+/// [get_csrf_token].
 ///
 /// This is intended to be used for both registration and authentication challenges, so the
 /// response type is generic. In this case the response type [T] should be either
 /// [CreationChallengeResponse] or [RequestChallengeResponse].
-pub async fn get_challenge<T>(endpoint: &str, email: String) -> Result<T, AuthProcessError>
+pub async fn get_challenge<T>(
+    endpoint: &str,
+    params: AuthServerParams,
+    csrf_token: Option<&str>,
+    signal: Option<&AbortSignal>,
+) -> Result<T, AuthProcessError>
 where
     T: DeserializeOwned,
 {
-    let params = AuthServerParams { username: email };
-    let request = Request::post(endpoint)
-        .header("Content-Type", "application/json")
+    let mut request = Request::post(endpoint).header("Content-Type", "application/json");
+    if let Some(csrf_token) = csrf_token {
+        request = request.header(CSRF_HEADER, csrf_token);
+    }
+    let request = request
+        .abort_signal(signal)
         .json(&params)
-        .map_err(AuthProcessError::FetchChallengeFailure)?;
+        .map_err(AuthProcessError::RequestSerializeFailure)?;
 
     let response = request
         .send()
@@ -258,12 +643,7 @@ where
 
     // If the response is not 200, we have an error and throw whatever the server gave us back.
     if response.status() != 200 {
-        let error: ApiError = response
-            .json()
-            .await
-            .map_err(AuthProcessError::ApiErrorParseFailure)?;
-
-        return Err(AuthProcessError::ApiError(error.message));
+        return Err(response_error(response).await);
     }
 
     let challenge_response: T = response
@@ -274,27 +654,197 @@ where
     Ok(challenge_response)
 }
 
+/// Creates an [AbortController] along with the signal that's passed to whatever it should abort.
+///
+/// Very old browsers don't have them, in which case requests just can't be aborted.
+fn abort_controller() -> (Option<AbortController>, Option<AbortSignal>) {
+    let abort_controller = AbortController::new().ok();
+    let signal = abort_controller.as_ref().map(AbortController::signal);
+    (abort_controller, signal)
+}
+
+/// Runs a request future, giving up with [AuthProcessError::Timeout] if it takes longer than
+/// `timeout` milliseconds.
+///
+/// The request itself can't be cancelled, but whatever it eventually returns is ignored.
+pub async fn with_timeout<F, T>(request: F, timeout: u32) -> Result<T, AuthProcessError>
+where
+    F: Future<Output = Result<T, AuthProcessError>>,
+{
+    let mut request = Box::pin(request);
+    let mut timer = TimeoutFuture::new(timeout);
+
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(result) = request.as_mut().poll(cx) {
+            return Poll::Ready(result);
+        }
+
+        match std::pin::Pin::new(&mut timer).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(AuthProcessError::Timeout)),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+    .await
+}
+
 /// Send a credential to the server to complete the registration/authentication process.
 ///
 /// This will return a [AuthProcessError] if the request fails, or the server responds with an
-/// error.
+/// error. The request is aborted when `signal` is. The `csrf_token` is sent along if given, see
+/// [get_csrf_token].
 ///
 /// This is intended to be used for both registration and authentication challenges, so the
 /// response type is generic. In this case the response type [T] should be either
 /// [RegisterPublicKeyCredential] or [PublicKeyCredential].
-pub async fn submit_credential<T>(endpoint: &str, credential: T) -> Result<(), AuthProcessError>
+pub async fn submit_credential<T>(
+    endpoint: &str,
+    credential: T,
+    csrf_token: Option<&str>,
+    signal: Option<&AbortSignal>,
+) -> Result<(), AuthProcessError>
 where
     T: Serialize,
 {
-    let request = Request::post(endpoint)
-        .header("Content-Type", "application/json")
+    let mut request = Request::post(endpoint).header("Content-Type", "application/json");
+    if let Some(csrf_token) = csrf_token {
+        request = request.header(CSRF_HEADER, csrf_token);
+    }
+    let request = request
+        .abort_signal(signal)
         .json(&credential)
-        .map_err(AuthProcessError::FetchChallengeFailure)?;
+        .map_err(AuthProcessError::RequestSerializeFailure)?;
 
-    request
+    let response = request
         .send()
         .await
         .map_err(AuthProcessError::FetchChallengeFailure)?;
 
+    // The credential is only accepted if the server says so, otherwise it says what went wrong.
+    if response.status() != 200 {
+        return Err(response_error(response).await);
+    }
+
     Ok(())
 }
+
+/// Turns a response the server sent back with an error status into the [AuthProcessError] it
+/// describes, from the [ApiError] in its body.
+async fn response_error(response: Response) -> AuthProcessError {
+    match response.json::<ApiError>().await {
+        Ok(error) => error.into(),
+        Err(err) => AuthProcessError::ApiErrorParseFailure(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    async fn requests_that_never_finish_time_out() {
+        let request = std::future::pending::<Result<(), AuthProcessError>>();
+
+        let result = with_timeout(request, 10).await;
+        assert!(matches!(result, Err(AuthProcessError::Timeout)));
+    }
+
+    #[wasm_bindgen_test]
+    async fn requests_that_finish_in_time_are_returned() {
+        let request = async { Err::<(), _>(AuthProcessError::ApiError("woof".to_string())) };
+
+        let result = with_timeout(request, 1000).await;
+        assert!(matches!(result, Err(AuthProcessError::ApiError(message)) if message == "woof"));
+    }
+
+    #[wasm_bindgen_test]
+    fn taken_usernames_are_picked_out_by_their_code() {
+        let error = ApiError {
+            code: "user_already_exists".to_string(),
+            message: "That username is taken".to_string(),
+        };
+        assert!(matches!(
+            AuthProcessError::from(error),
+            AuthProcessError::UserAlreadyExists(_)
+        ));
+
+        let error = ApiError {
+            code: "invalid_username".to_string(),
+            message: "woof".to_string(),
+        };
+        assert!(matches!(
+            AuthProcessError::from(error),
+            AuthProcessError::ApiError(message) if message == "woof"
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn error_responses_are_turned_into_what_they_describe() {
+        let response =
+            |body: serde_json::Value| Response::builder().status(400).json(&body).unwrap();
+
+        let error = response(serde_json::json!({
+            "code": "user_already_exists",
+            "message": "That username is taken",
+        }));
+        assert!(matches!(
+            response_error(error).await,
+            AuthProcessError::UserAlreadyExists(_)
+        ));
+
+        let error = response(serde_json::json!({
+            "code": "missing_session",
+            "message": "woof",
+        }));
+        assert!(matches!(
+            response_error(error).await,
+            AuthProcessError::ApiError(message) if message == "woof"
+        ));
+
+        let error = response(serde_json::json!("woof"));
+        assert!(matches!(
+            response_error(error).await,
+            AuthProcessError::ApiErrorParseFailure(_)
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn errors_are_shown_according_to_their_kind() {
+        let network_error = || gloo_net::Error::GlooError("Failed to fetch".to_string());
+
+        let msg = AuthProcessError::FetchChallengeFailure(network_error()).into_msg(Flow::Register);
+        assert!(matches!(
+            msg,
+            Msg::Error(ErrorKind::Offline(Flow::Register))
+        ));
+
+        let msg = AuthProcessError::Timeout.into_msg(Flow::Authenticate);
+        assert!(matches!(msg, Msg::TimedOut(Flow::Authenticate)));
+
+        let msg = AuthProcessError::UserAlreadyExists("woof".to_string()).into_msg(Flow::Register);
+        assert!(matches!(msg, Msg::UserAlreadyExists));
+
+        let msg = AuthProcessError::ApiError("woof".to_string()).into_msg(Flow::Authenticate);
+        assert!(matches!(
+            msg,
+            Msg::Error(ErrorKind::Message(message)) if message == "woof"
+        ));
+
+        // Failing to build the request isn't something going back online would fix.
+        let msg =
+            AuthProcessError::RequestSerializeFailure(network_error()).into_msg(Flow::Register);
+        assert!(matches!(msg, Msg::Error(ErrorKind::Message(_))));
+
+        let msg = AuthProcessError::CsrfTokenParseFailure(network_error()).into_msg(Flow::Register);
+        assert!(matches!(msg, Msg::Error(ErrorKind::Message(_))));
+
+        let msg =
+            AuthProcessError::ChallengeParseFailure(network_error()).into_msg(Flow::Authenticate);
+        assert!(matches!(msg, Msg::Error(ErrorKind::Message(_))));
+
+        let msg =
+            AuthProcessError::ApiErrorParseFailure(network_error()).into_msg(Flow::Authenticate);
+        assert!(matches!(msg, Msg::Error(ErrorKind::Message(_))));
+    }
+}
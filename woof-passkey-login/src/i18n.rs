@@ -0,0 +1,169 @@
+//! Translations of the text shown to users by the component.
+
+/// A language the component can be shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    English,
+    Spanish,
+}
+
+impl Lang {
+    /// Picks the language for a BCP 47 language tag like `es` or `en-GB`, going by its primary
+    /// language subtag. Returns `None` for languages we don't have a translation for.
+    pub fn from_tag(tag: &str) -> Option<Lang> {
+        let primary = tag.split(['-', '_']).next()?;
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Lang::English),
+            "es" => Some(Lang::Spanish),
+            _ => None,
+        }
+    }
+
+    /// The table of text to show in this language.
+    pub fn strings(self) -> &'static Strings {
+        match self {
+            Lang::English => &ENGLISH,
+            Lang::Spanish => &SPANISH,
+        }
+    }
+}
+
+/// Every piece of text the component shows to users, keyed by what it's used for.
+#[derive(Debug)]
+pub struct Strings {
+    /// Placeholder of the username input.
+    pub username_placeholder: &'static str,
+    /// Accessible label of the username input.
+    pub username_label: &'static str,
+    /// Text of the sign in button, shown before the word for passkey.
+    pub sign_in_with: &'static str,
+    /// The word for passkey, highlighted in the sign in button.
+    pub passkey: &'static str,
+    /// Accessible label of the sign in button.
+    pub sign_in_label: &'static str,
+    /// Text of the register button.
+    pub register: &'static str,
+    /// Accessible label of the register button.
+    pub register_label: &'static str,
+    /// Link explaining what passkeys are.
+    pub what_is_a_passkey: &'static str,
+    /// Link to upload without signing in.
+    pub upload_anonymously: &'static str,
+    /// Shown while waiting on the server or authenticator to sign in.
+    pub waiting_for_authentication: &'static str,
+    /// Shown while waiting on the server or authenticator to create a passkey.
+    pub creating_passkey: &'static str,
+    /// Button that cancels signing in or registering.
+    pub cancel: &'static str,
+    /// Button that starts signing in or registering again after the server didn't respond.
+    pub try_again: &'static str,
+    /// Accessible label of the theme toggle while the light theme is shown.
+    pub switch_to_dark: &'static str,
+    /// Accessible label of the theme toggle while the dark theme is shown.
+    pub switch_to_light: &'static str,
+    /// Greeting shown above the username after succeeding.
+    pub welcome: &'static str,
+    /// Heading shown when the browser doesn't support passkeys.
+    pub unsupported_title: &'static str,
+    /// Explanation shown when the browser doesn't support passkeys.
+    pub unsupported_body: &'static str,
+    /// Error shown when trying to continue without a username.
+    pub username_empty: &'static str,
+    /// Error shown when the server doesn't send a challenge in time.
+    pub server_timed_out: &'static str,
+    /// Shown when the server couldn't be reached at all.
+    pub offline: &'static str,
+    /// Error shown when the browser's passkey prompt is dismissed.
+    pub authentication_cancelled: &'static str,
+    /// Error shown when registering a passkey the account already has.
+    pub passkey_already_registered: &'static str,
+    /// Error shown when registering with a username that already has an account.
+    pub user_already_exists: &'static str,
+    /// Button that signs in with the username that was already taken when registering.
+    pub sign_in_instead: &'static str,
+}
+
+pub static ENGLISH: Strings = Strings {
+    username_placeholder: "Enter your username",
+    username_label: "Username",
+    sign_in_with: "Sign in with ",
+    passkey: "Passkey",
+    sign_in_label: "Sign in with passkey",
+    register: "Register",
+    register_label: "Register a new passkey",
+    what_is_a_passkey: "What is a passkey?",
+    upload_anonymously: "Upload Anonymously",
+    waiting_for_authentication: "Waiting for authentication...",
+    creating_passkey: "Creating your passkey...",
+    cancel: "Cancel",
+    try_again: "Try again",
+    switch_to_dark: "Switch to the dark theme",
+    switch_to_light: "Switch to the light theme",
+    welcome: "Welcome",
+    unsupported_title: "Passkeys aren't supported",
+    unsupported_body: "Your browser doesn't support passkeys, so you can't sign in or register \
+                       with it. Try updating it, or switching to a browser that supports WebAuthn.",
+    username_empty: "Username cannot be empty",
+    server_timed_out: "The server didn't respond, it may be down or overloaded",
+    offline: "Couldn't reach the server, check your connection",
+    authentication_cancelled: "Authentication cancelled",
+    passkey_already_registered: "This passkey is already registered to your account",
+    user_already_exists: "You already have an account with this username",
+    sign_in_instead: "Sign in instead",
+};
+
+pub static SPANISH: Strings = Strings {
+    username_placeholder: "Introduce tu nombre de usuario",
+    username_label: "Nombre de usuario",
+    sign_in_with: "Iniciar sesión con ",
+    passkey: "Llave de acceso",
+    sign_in_label: "Iniciar sesión con llave de acceso",
+    register: "Registrarse",
+    register_label: "Registrar una nueva llave de acceso",
+    what_is_a_passkey: "¿Qué es una llave de acceso?",
+    upload_anonymously: "Subir de forma anónima",
+    waiting_for_authentication: "Esperando la autenticación...",
+    creating_passkey: "Creando tu llave de acceso...",
+    cancel: "Cancelar",
+    try_again: "Reintentar",
+    switch_to_dark: "Cambiar al tema oscuro",
+    switch_to_light: "Cambiar al tema claro",
+    welcome: "Bienvenido",
+    unsupported_title: "Las llaves de acceso no son compatibles",
+    unsupported_body: "Tu navegador no es compatible con las llaves de acceso, así que no puedes \
+                       iniciar sesión ni registrarte con él. Prueba a actualizarlo o a usar un \
+                       navegador compatible con WebAuthn.",
+    username_empty: "El nombre de usuario no puede estar vacío",
+    server_timed_out: "El servidor no respondió, puede que esté caído o sobrecargado",
+    offline: "No se pudo conectar con el servidor, comprueba tu conexión",
+    authentication_cancelled: "Autenticación cancelada",
+    passkey_already_registered: "Esta llave de acceso ya está registrada en tu cuenta",
+    user_already_exists: "Ya tienes una cuenta con este nombre de usuario",
+    sign_in_instead: "Iniciar sesión",
+};
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn language_tags_select_their_translation() {
+        let strings = Lang::from_tag("es-MX").unwrap().strings();
+        assert_eq!(strings.cancel, "Cancelar");
+        assert_eq!(strings.try_again, "Reintentar");
+
+        let strings = Lang::from_tag("en-GB").unwrap().strings();
+        assert_eq!(strings.cancel, "Cancel");
+        assert_eq!(strings.try_again, "Try again");
+    }
+
+    #[wasm_bindgen_test]
+    fn unknown_languages_have_no_translation() {
+        assert_eq!(Lang::from_tag("fr-FR"), None);
+        assert_eq!(Lang::from_tag(""), None);
+        assert_eq!(Lang::default().strings().welcome, "Welcome");
+    }
+}
@@ -1,11 +1,13 @@
 //! WebAuthn passkey authentication component intended to be used with Woof.
 
 pub mod auth;
+pub mod i18n;
 pub mod svg;
+pub mod theme;
 pub mod utils;
 pub mod views;
 
-use gloo_timers::callback::Timeout;
+use gloo_timers::future::TimeoutFuture;
 use seed::{
     prelude::*,
     *,
@@ -19,17 +21,81 @@ use webauthn_rs_proto::{
 };
 
 use crate::{
-    auth::AuthModel,
-    utils::set_panic_hook,
-    views::ViewState,
+    auth::{
+        validate_redirect,
+        ApiEndpoints,
+        AuthModel,
+        ErrorKind,
+        Flow,
+    },
+    theme::Theme,
+    utils::{
+        api_base,
+        challenge_timeout,
+        conditional_create_supported,
+        focus_element,
+        load_last_username,
+        load_theme,
+        passkeys_supported,
+        preferred_lang,
+        replace_class,
+        set_panic_hook,
+        store_theme,
+    },
+    views::{
+        ViewState,
+        USERNAME_INPUT_ID,
+    },
 };
 
+/// The ID of the element the application is mounted on.
+const ROOT_ELEMENT_ID: &str = "app";
+
+/// The ID of the card the application is shown in, which is styled according to the [Theme].
+const CARD_ELEMENT_ID: &str = "auth-card";
+
+/// How long to wait for the server to send a challenge by default, in milliseconds.
+const DEFAULT_CHALLENGE_TIMEOUT: u32 = 10_000;
+
 /// Initializes the application model.
-pub fn init(_: Url, _: &mut impl Orders<Msg>) -> AuthModel {
+pub fn init(url: Url, orders: &mut impl Orders<Msg>) -> AuthModel {
+    // There's no point showing the login form if the browser can't do anything with it.
+    let view_state = if passkeys_supported() {
+        focus_username(orders);
+        orders.perform_cmd(async {
+            Msg::ConditionalCreateSupported(conditional_create_supported().await)
+        });
+        ViewState::Init
+    } else {
+        ViewState::Unsupported
+    };
+
+    // The API is expected at the site root unless the page says otherwise.
+    let endpoints = api_base(ROOT_ELEMENT_ID)
+        .map(|base| ApiEndpoints::from_base(&base))
+        .unwrap_or_default();
+
+    // The card is rendered by the server in the light theme.
+    let theme = load_theme();
+    replace_class(
+        CARD_ELEMENT_ID,
+        Theme::Light.card_class(),
+        theme.card_class(),
+    );
+
     AuthModel {
-        view_state: ViewState::Init,
+        theme,
+        strings: preferred_lang(&url).strings(),
+        view_state,
+        endpoints,
+        challenge_timeout: challenge_timeout(ROOT_ELEMENT_ID).unwrap_or(DEFAULT_CHALLENGE_TIMEOUT),
+        // Assume it isn't supported until the browser says otherwise.
+        conditional_create: false,
+        retry: None,
+        in_flight: None,
         last_username: String::new(),
-        input_value: String::new(),
+        // Returning users most likely want to sign in with the same username as last time.
+        input_value: load_last_username().unwrap_or_default(),
     }
 }
 
@@ -48,6 +114,19 @@ pub enum Msg {
     /// Holds the [CreationChallengeResponse] received from the server.
     SignRegisterChallenge(CreationChallengeResponse),
 
+    /// Sent when a conditional registration has started and the server has sent a challenge.
+    /// Asks the browser's password manager to create a passkey without prompting the user.
+    ///
+    /// Holds the [CreationChallengeResponse] received from the server.
+    SignConditionalRegisterChallenge(CreationChallengeResponse),
+
+    /// Sent when the browser declines to create a passkey through conditional create.
+    /// Starts a regular registration for the same user instead.
+    ConditionalRegisterDeclined,
+
+    /// Sent once the browser has said whether it can create passkeys through conditional create.
+    ConditionalCreateSupported(bool),
+
     /// Sent when the a credential is successfully created by the browser.
     /// Sends the signed challenge to the server to finish the registration process.
     ///
@@ -73,34 +152,139 @@ pub enum Msg {
 
     /// Sent when an error occurs during the authentication/registration process.
     ///
-    /// Holds the error message string.
-    Error(String),
+    /// Holds the [ErrorKind], which decides how the error is shown.
+    Error(ErrorKind),
+
+    /// Sent when registering with a username that already has an account.
+    UserAlreadyExists,
+
+    /// Sent when the user presses the button to sign in after registering with a username that
+    /// already has an account. Starts authenticating with that username instead.
+    SignInInstead,
+
+    /// Sent when the server doesn't send a challenge in time.
+    ///
+    /// Holds the [Flow] that was being started, so it can be retried.
+    TimedOut(Flow),
+
+    /// Sent when the user presses the retry button after the server didn't respond.
+    Retry,
+
+    /// Sent when the user presses the cancel button while waiting on the server or their
+    /// authenticator. Aborts whatever is in flight and goes back to the initial state.
+    Cancel,
+
+    /// Sent when the user presses the theme toggle, switching between the light and dark themes.
+    ToggleTheme,
 
     /// A no-op message used to satisfy the compiler. This is used in the [input_ev] and
     /// [keyboard_ev] functions in the authentication view and ultimately does nothing.
     NoOp,
 }
 
+impl Msg {
+    /// Whether this message is the result of a step in the registration/authentication process,
+    /// rather than something the user did.
+    fn is_response(&self) -> bool {
+        matches!(
+            self,
+            Msg::SignRegisterChallenge(_)
+                | Msg::SignConditionalRegisterChallenge(_)
+                | Msg::ConditionalRegisterDeclined
+                | Msg::FinishRegister(_)
+                | Msg::SignAuthenticationChallenge(_)
+                | Msg::FinishAuthentication(_)
+                | Msg::Success
+                | Msg::Error(_)
+                | Msg::UserAlreadyExists
+                | Msg::TimedOut(_)
+        )
+    }
+}
+
 /// Updates the model based on the message received.
 /// This is where the bulk of the application logic is handled.
 pub fn update(msg: Msg, model: &mut AuthModel, orders: &mut impl Orders<Msg>) {
+    // A response that arrives after the process was cancelled has nothing left to update.
+    if msg.is_response() && !model.view_state.is_busy() {
+        return;
+    }
+
     match msg {
         Msg::InputChanged(text) => model.input_value = text,
-        Msg::Error(err) => model.view_state = ViewState::Error(err),
+        Msg::Error(ErrorKind::Offline(flow)) => {
+            model.retry = Some(flow);
+            model.view_state = ViewState::Offline;
+        }
+        Msg::Error(ErrorKind::Message(err)) => {
+            model.retry = None;
+            model.view_state = ViewState::Error(err);
+        }
+        Msg::UserAlreadyExists => {
+            model.retry = None;
+            model.view_state = ViewState::AlreadyRegistered(model.last_username.clone());
+        }
+        Msg::SignInInstead => {
+            if let ViewState::AlreadyRegistered(username) = &model.view_state {
+                model.input_value = username.clone();
+                orders.send_msg(Msg::BeginAuthentication);
+            }
+        }
+        Msg::TimedOut(flow) => {
+            model.retry = Some(flow);
+            model.view_state = ViewState::Error(model.strings.server_timed_out.to_string());
+        }
+        Msg::Cancel => {
+            model.cancel();
+            model.view_state = ViewState::Init;
+            focus_username(orders);
+        }
+        Msg::ToggleTheme => {
+            let theme = model.theme.toggled();
+            replace_class(
+                CARD_ELEMENT_ID,
+                model.theme.card_class(),
+                theme.card_class(),
+            );
+            store_theme(theme);
+            model.theme = theme;
+        }
+        Msg::Retry => match model.retry.take() {
+            Some(Flow::Register) => {
+                orders.send_msg(Msg::BeginRegister);
+            }
+            Some(Flow::Authenticate) => {
+                orders.send_msg(Msg::BeginAuthentication);
+            }
+            None => {}
+        },
         // Registration
         Msg::BeginRegister => {
             // If the input value is empty, don't do anything.
             // Just a basic check to prevent empty usernames
             if !model.input_value.is_empty() {
-                model.view_state = ViewState::Waiting;
-                model.start_register(model.input_value.clone(), orders);
+                model.view_state = ViewState::Registering;
+                // Conditional create is tried first where it's supported, as it spares the user
+                // from another prompt.
+                if model.conditional_create {
+                    model.start_conditional_register(model.input_value.clone(), orders);
+                } else {
+                    model.start_register(model.input_value.clone(), orders);
+                }
             } else {
-                model.view_state = ViewState::Error("Username cannot be empty".to_string());
+                model.view_state = ViewState::Error(model.strings.username_empty.to_string());
             }
         }
         Msg::SignRegisterChallenge(challenge_response) => {
             model.sign_register_challenge(challenge_response, orders);
         }
+        Msg::SignConditionalRegisterChallenge(challenge_response) => {
+            model.sign_conditional_register_challenge(challenge_response, orders);
+        }
+        Msg::ConditionalRegisterDeclined => {
+            model.start_register(model.last_username.clone(), orders);
+        }
+        Msg::ConditionalCreateSupported(supported) => model.conditional_create = supported,
         Msg::FinishRegister(register_response) => {
             model.finish_register(register_response, orders);
         }
@@ -110,7 +294,7 @@ pub fn update(msg: Msg, model: &mut AuthModel, orders: &mut impl Orders<Msg>) {
                 model.view_state = ViewState::Waiting;
                 model.start_authentication(model.input_value.clone(), orders);
             } else {
-                model.view_state = ViewState::Error("Username cannot be empty".to_string());
+                model.view_state = ViewState::Error(model.strings.username_empty.to_string());
             }
         }
         Msg::SignAuthenticationChallenge(challenge_response) => {
@@ -124,45 +308,74 @@ pub fn update(msg: Msg, model: &mut AuthModel, orders: &mut impl Orders<Msg>) {
             // right before the authentication/registration process started.
             model.view_state = ViewState::Success(model.last_username.clone());
 
-            // Replace any instances of the class `card` with `card-success`.
-            if let Some(element) = document().get_element_by_id("auth-card") {
+            // Replace the card's classes with the success one for the current theme.
+            if let Some(element) = document().get_element_by_id(CARD_ELEMENT_ID) {
                 let html_element: HtmlElement = element.unchecked_into();
-                html_element.set_class_name("card-success");
+                html_element.set_class_name(model.theme.success_card_class());
             }
 
             // Wait a little bit before redirecting to the desired page. This gives the user
             // enough time to see the success message.
-            Timeout::new(1500, move || {
-                // Access the current URL and get the query parameters.
-                let url = Url::current();
-                let query_params = url.search();
-
-                // Get the value of the redirect query parameter, or use the index route if it
-                // doesn't exist.
-                let default_redirect = "/".to_string();
-                let redirect_path = match query_params.get("redirect") {
-                    Some(redirect) => redirect.first().unwrap_or(&default_redirect),
-                    None => &default_redirect,
-                };
+            let endpoint = model.endpoints.validate_redirect.clone();
+            orders.perform_cmd(async move {
+                TimeoutFuture::new(1500).await;
 
                 // Redirect to the next page which can be specified by the `redirect` query
-                // parameter. Otherwise we redirect to the index route.
-                let redirect_path_segments: Vec<&str> = redirect_path.split('/').collect();
-                let redirect = Url::current().set_path(redirect_path_segments);
-                let redirect = redirect.set_search(UrlSearch::default());
-                redirect.go_and_load();
-            })
-            .forget();
+                // parameter, in the form the server normalized it to. If it isn't given, or the
+                // server doesn't allow it, we redirect to the index route.
+                let url = Url::current();
+                let requested = url
+                    .search()
+                    .get("redirect")
+                    .and_then(|values| values.first());
+                let target = match requested {
+                    Some(target) => validate_redirect(&endpoint, target).await.ok(),
+                    None => None,
+                };
+                window()
+                    .location()
+                    .assign(target.as_deref().unwrap_or("/"))
+                    .ok();
+            });
         }
         Msg::NoOp => {}
     }
 }
 
+/// Moves focus to the username input once it's been rendered, so keyboard and screen reader users
+/// land on the form.
+fn focus_username(orders: &mut impl Orders<Msg>) {
+    orders.after_next_render(|_| focus_element(USERNAME_INPUT_ID));
+}
+
 /// Renders the view based on the current state of the application.
 pub fn view(model: &AuthModel) -> Node<Msg> {
+    let retry = model.retry.is_some();
     match model.view_state {
-        ViewState::Error(ref err) => views::view(&model.view_state, Some(err)),
-        _ => views::view(&model.view_state, None),
+        ViewState::Error(ref err) => views::view(
+            model.strings,
+            model.theme,
+            &model.view_state,
+            &model.input_value,
+            Some(err),
+            retry,
+        ),
+        ViewState::AlreadyRegistered(_) => views::view(
+            model.strings,
+            model.theme,
+            &model.view_state,
+            &model.input_value,
+            Some(&model.strings.user_already_exists.to_string()),
+            false,
+        ),
+        _ => views::view(
+            model.strings,
+            model.theme,
+            &model.view_state,
+            &model.input_value,
+            None,
+            false,
+        ),
     }
 }
 
@@ -170,5 +383,5 @@ pub fn view(model: &AuthModel) -> Node<Msg> {
 #[wasm_bindgen(start)]
 pub fn start() {
     set_panic_hook();
-    App::start("app", init, update, view);
+    App::start(ROOT_ELEMENT_ID, init, update, view);
 }
@@ -12,6 +12,7 @@ use crate::Msg;
 pub fn warning_icon() -> Node<Msg> {
     svg![
         attrs! {
+            At::from("aria-hidden") => "true",
             At::ViewBox => "0 0 24 24",
             At::Version => "1.1",
         },
@@ -51,6 +52,7 @@ pub fn warning_icon() -> Node<Msg> {
 pub fn success_icon() -> Node<Msg> {
     svg![
         attrs! {
+            At::from("aria-hidden") => "true",
             At::ViewBox => "0 0 24 24",
             At::Version => "1.1",
         },
@@ -90,6 +92,7 @@ pub fn success_icon() -> Node<Msg> {
 pub fn passkey_icon() -> Node<Msg> {
     svg![
         attrs! {
+            At::from("aria-hidden") => "true",
             At::Id => "passkey-icon",
             At::ViewBox => "0 0 24 24",
         },
@@ -121,6 +124,7 @@ pub fn passkey_icon() -> Node<Msg> {
 pub fn spinner_icon() -> Node<Msg> {
     svg![
         attrs! {
+            At::from("aria-hidden") => "true",
             At::Class => "spinner",
             At::ViewBox => "0 0 50 50",
         },
@@ -140,6 +144,7 @@ pub fn spinner_icon() -> Node<Msg> {
 pub fn profile_icon() -> Node<Msg> {
     svg![
         attrs! {
+            At::from("aria-hidden") => "true",
             At::ViewBox => "0 0 18 18",
             At::Fill => "none",
         },
@@ -0,0 +1,75 @@
+//! The light and dark color schemes the component can be shown in.
+
+/// A color scheme for the card the component is mounted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    /// The class the card has while the form is shown.
+    pub fn card_class(self) -> &'static str {
+        match self {
+            Theme::Light => "card",
+            Theme::Dark => "card-dark",
+        }
+    }
+
+    /// The class the card is swapped to once the user has been authenticated.
+    pub fn success_card_class(self) -> &'static str {
+        match self {
+            Theme::Light => "card-success",
+            Theme::Dark => "card-success-dark",
+        }
+    }
+
+    /// The other theme, which the toggle switches to.
+    pub fn toggled(self) -> Theme {
+        match self {
+            Theme::Light => Theme::Dark,
+            Theme::Dark => Theme::Light,
+        }
+    }
+
+    /// The name the theme is remembered under in `localStorage`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+
+    /// Parses a theme remembered in `localStorage`.
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name {
+            "light" => Some(Theme::Light),
+            "dark" => Some(Theme::Dark),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn themes_map_to_their_card_classes() {
+        assert_eq!(Theme::Light.card_class(), "card");
+        assert_eq!(Theme::Light.success_card_class(), "card-success");
+        assert_eq!(Theme::Dark.card_class(), "card-dark");
+        assert_eq!(Theme::Dark.success_card_class(), "card-success-dark");
+    }
+
+    #[wasm_bindgen_test]
+    fn themes_round_trip_through_their_names() {
+        for theme in [Theme::Light, Theme::Dark] {
+            assert_eq!(Theme::from_name(theme.as_str()), Some(theme));
+            assert_eq!(theme.toggled().toggled(), theme);
+        }
+        assert_eq!(Theme::from_name("purple"), None);
+    }
+}
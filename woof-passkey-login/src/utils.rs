@@ -1,5 +1,27 @@
 //! Utility functions for the component.
 
+use js_sys::{
+    Function,
+    Promise,
+    Reflect,
+};
+use seed::prelude::*;
+use web_sys::{
+    HtmlElement,
+    Storage,
+};
+
+use crate::{
+    i18n::Lang,
+    theme::Theme,
+};
+
+/// The `localStorage` key the last used username is remembered under.
+const LAST_USERNAME_KEY: &str = "woof:last_username";
+
+/// The `localStorage` key the chosen theme is remembered under.
+const THEME_KEY: &str = "woof:theme";
+
 /// Sets the panic hook for the application to provide better error messages in the browser.
 pub fn set_panic_hook() {
     // When the `console_error_panic_hook` feature is enabled, we can call the
@@ -11,3 +33,146 @@ pub fn set_panic_hook() {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
 }
+
+/// Checks whether the browser supports WebAuthn, and by extension passkeys.
+///
+/// Browsers without support have neither a `PublicKeyCredential` global nor a
+/// `navigator.credentials` container, and would otherwise throw as soon as we try to use them.
+pub fn passkeys_supported() -> bool {
+    let window = seed::window();
+    let has_public_key_credential = window.get("PublicKeyCredential").is_some();
+    let has_credentials_container = !window.navigator().credentials().is_undefined();
+
+    has_public_key_credential && has_credentials_container
+}
+
+/// Checks whether the browser can create passkeys through conditional create, where the password
+/// manager makes one without showing a modal prompt.
+///
+/// Browsers advertise this through `PublicKeyCredential.getClientCapabilities()`, which older
+/// browsers don't have at all. Anything other than a clear yes is treated as unsupported.
+pub async fn conditional_create_supported() -> bool {
+    let public_key_credential = match seed::window().get("PublicKeyCredential") {
+        Some(public_key_credential) => public_key_credential,
+        None => return false,
+    };
+
+    let promise = Reflect::get(&public_key_credential, &"getClientCapabilities".into())
+        .ok()
+        .and_then(|function| function.dyn_into::<Function>().ok())
+        .and_then(|function| function.call0(&public_key_credential).ok())
+        .and_then(|promise| promise.dyn_into::<Promise>().ok());
+
+    let capabilities = match promise {
+        Some(promise) => JsFuture::from(promise).await,
+        None => return false,
+    };
+
+    capabilities
+        .ok()
+        .and_then(|capabilities| Reflect::get(&capabilities, &"conditionalCreate".into()).ok())
+        .and_then(|supported| supported.as_bool())
+        .unwrap_or(false)
+}
+
+/// Moves keyboard focus to the element with the given ID, if it exists and can take focus.
+pub fn focus_element(id: &str) {
+    if let Some(element) = seed::document().get_element_by_id(id) {
+        element.unchecked_into::<HtmlElement>().focus().ok();
+    }
+}
+
+/// Picks the language to show the component in.
+///
+/// A `lang` query parameter takes priority so a language can be chosen explicitly, otherwise the
+/// browser's preferred language is used. Anything we don't have a translation for falls back to
+/// English.
+pub fn preferred_lang(url: &Url) -> Lang {
+    let query_lang = url
+        .search()
+        .get("lang")
+        .and_then(|values| values.first())
+        .and_then(|tag| Lang::from_tag(tag));
+
+    query_lang
+        .or_else(|| Lang::from_tag(&seed::window().navigator().language()?))
+        .unwrap_or_default()
+}
+
+/// Gets the browser's `localStorage`, if it's available.
+///
+/// Access can be denied outright (e.g. in some private browsing modes), in which case this
+/// returns `None` rather than throwing.
+fn local_storage() -> Option<Storage> {
+    seed::window().local_storage().ok().flatten()
+}
+
+/// Loads the username that was last used to sign in or register, if one was remembered.
+pub fn load_last_username() -> Option<String> {
+    local_storage()?.get_item(LAST_USERNAME_KEY).ok().flatten()
+}
+
+/// Remembers the username so it can be filled in on the next visit.
+///
+/// This is purely a convenience, so any failure to store it is ignored.
+pub fn store_last_username(username: &str) {
+    if let Some(storage) = local_storage() {
+        storage.set_item(LAST_USERNAME_KEY, username).ok();
+    }
+}
+
+/// Loads the theme the user last chose, falling back to the one their system prefers.
+pub fn load_theme() -> Theme {
+    let stored = local_storage()
+        .and_then(|storage| storage.get_item(THEME_KEY).ok().flatten())
+        .and_then(|name| Theme::from_name(&name));
+
+    stored.unwrap_or_else(|| {
+        let prefers_dark = seed::window()
+            .match_media("(prefers-color-scheme: dark)")
+            .ok()
+            .flatten()
+            .is_some_and(|query| query.matches());
+
+        if prefers_dark {
+            Theme::Dark
+        } else {
+            Theme::Light
+        }
+    })
+}
+
+/// Remembers the theme the user chose so it's used on the next visit.
+///
+/// Like [store_last_username], any failure to store it is ignored.
+pub fn store_theme(theme: Theme) {
+    if let Some(storage) = local_storage() {
+        storage.set_item(THEME_KEY, theme.as_str()).ok();
+    }
+}
+
+/// Swaps one class of the element with the given ID for another, leaving the rest of its classes
+/// alone.
+pub fn replace_class(id: &str, old: &str, new: &str) {
+    if let Some(element) = seed::document().get_element_by_id(id) {
+        element.class_list().replace(old, new).ok();
+    }
+}
+
+/// Reads the base URL of the server API from the `data-api-base` attribute on the element the
+/// component is mounted on, if it has one.
+pub fn api_base(root_element_id: &str) -> Option<String> {
+    seed::document()
+        .get_element_by_id(root_element_id)?
+        .get_attribute("data-api-base")
+}
+
+/// Reads how long to wait for the server to send a challenge, in milliseconds, from the
+/// `data-challenge-timeout` attribute on the element the component is mounted on, if it has one.
+pub fn challenge_timeout(root_element_id: &str) -> Option<u32> {
+    seed::document()
+        .get_element_by_id(root_element_id)?
+        .get_attribute("data-challenge-timeout")?
+        .parse()
+        .ok()
+}
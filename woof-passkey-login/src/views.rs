@@ -6,6 +6,7 @@ use seed::{
 };
 
 use crate::{
+    i18n::Strings,
     svg::{
         passkey_icon,
         profile_icon,
@@ -13,41 +14,87 @@ use crate::{
         success_icon,
         warning_icon,
     },
+    theme::Theme,
     Msg,
 };
 
+/// The ID of the username input, so focus can be moved to it.
+pub const USERNAME_INPUT_ID: &str = "username";
+
+/// The page explaining what passkeys are.
+pub const PASSKEYS_INFO_PATH: &str = "/passkeys-info";
+
+/// The page pastes can be created from without an account.
+pub const ANONYMOUS_UPLOAD_PATH: &str = "/paste";
+
 /// Defines the different states the authentication view can be in.
 /// This is used to determine which view to render independently of the model message.
 #[derive(PartialEq)]
 pub enum ViewState {
     /// The initial state of the view.
     Init,
-    /// The view is waiting for a response from the user/server.
+    /// The view is waiting for a response from the user/server while logging in.
     Waiting,
+    /// The view is waiting for a response from the user/server while creating a new passkey.
+    Registering,
     /// The view has successfully authenticated the user, holds the now authenticated username.
     Success(String),
     /// The view has encountered an error, holds the error message.
     Error(String),
+    /// The server couldn't be reached, which is shown apart from the errors it returns as it's
+    /// most likely down to the user's connection.
+    Offline,
+    /// Registration failed because the username already has an account, holds the username so
+    /// the user can sign in with it instead.
+    AlreadyRegistered(String),
+    /// The browser doesn't support passkeys, so authentication can't happen at all.
+    Unsupported,
+}
+
+impl ViewState {
+    /// Whether a ceremony is in progress, during which the form can't be used.
+    pub fn is_busy(&self) -> bool {
+        matches!(self, ViewState::Waiting | ViewState::Registering)
+    }
 }
 
 /// Defines the HTML view for the authentication component and reacts to changes in [ViewState].
 ///
 /// An error message is displayed if [ViewState] is [ViewState::Error] and the error text is not
-/// None.
-pub fn view(state: &ViewState, error_text: Option<&String>) -> Node<Msg> {
+/// None, along with a button to try again if `retry` is set. [ViewState::AlreadyRegistered] shows
+/// its error along with a button to sign in instead, and [ViewState::Offline] asks the user to
+/// check their connection before trying again. The username input is pre-filled with
+/// `input_value`, and all text is taken from `strings`. The theme toggle switches away from
+/// `theme`.
+pub fn view(
+    strings: &Strings,
+    theme: Theme,
+    state: &ViewState,
+    input_value: &str,
+    error_text: Option<&String>,
+    retry: bool,
+) -> Node<Msg> {
     match state {
-        ViewState::Success(user) => view_success(user),
+        ViewState::Success(user) => view_success(strings, user),
+        ViewState::Unsupported => view_unsupported(strings),
         _ => {
             div![
                 div![
                     C!["flex", "flex-col", "gap", "items-center", "justify-center",],
                     div![
                         C!["input-container"],
-                        i![C!["input-icon"], profile_icon()],
+                        i![
+                            C!["input-icon"],
+                            attrs! { At::from("aria-hidden") => "true" },
+                            profile_icon()
+                        ],
                         input![
                             C!["input-purple"],
                             attrs! {
-                                At::Placeholder => "Enter your username",
+                                At::Id => USERNAME_INPUT_ID,
+                                At::Placeholder => strings.username_placeholder,
+                                At::from("aria-label") => strings.username_label,
+                                At::Value => input_value,
                             },
                             // We store the input value in the model by sending a message every
                             // time the input changes.
@@ -66,43 +113,60 @@ pub fn view(state: &ViewState, error_text: Option<&String>) -> Node<Msg> {
                     button![
                         C!["button-purple"],
                         attrs! {
-                            At::Disabled => (state == &ViewState::Waiting).as_at_value(),
+                            At::Type => "button",
+                            At::from("aria-label") => strings.sign_in_label,
+                            At::Disabled => state.is_busy().as_at_value(),
                         },
                         ev(Ev::Click, |_| Msg::BeginAuthentication),
                         passkey_icon(),
-                        span!["Sign in with ", strong!("Passkey")],
+                        span![strings.sign_in_with, strong!(strings.passkey)],
                     ],
                     button![
                         C!["button-gray"],
                         attrs! {
-                            At::Disabled => (state == &ViewState::Waiting).as_at_value(),
+                            At::Type => "button",
+                            At::from("aria-label") => strings.register_label,
+                            At::Disabled => state.is_busy().as_at_value(),
                         },
                         ev(Ev::Click, |_| Msg::BeginRegister),
-                        "Register"
+                        strings.register
                     ],
                 ],
-                IF!(state != &ViewState::Waiting => error_message(error_text)),
-                IF!(state == &ViewState::Waiting => waiting_message()),
+                IF!(*state == ViewState::Offline => offline_message(strings)),
+                IF!(!state.is_busy() && *state != ViewState::Offline => error_message(
+                    strings,
+                    error_text,
+                    retry,
+                    matches!(state, ViewState::AlreadyRegistered(_))
+                )),
+                IF!(state.is_busy() => waiting_message(strings, state)),
                 div![
                     C!["flex", "flex-row", "justify-between pt-4"],
+                    // These are plain links with an `href`, so they can be tabbed to and followed
+                    // with Enter like any other.
                     a![
                         C![
                             "text-sm",
                             "text-gray-500",
                             "hover:text-gray-700",
+                            "focus:text-gray-700",
                             "underline"
                         ],
-                        "What is a passkey?"
+                        attrs! { At::Href => PASSKEYS_INFO_PATH },
+                        strings.what_is_a_passkey
                     ],
                     a![
                         C![
                             "text-sm",
                             "text-gray-500",
                             "hover:text-gray-700",
+                            "focus:text-gray-700",
                             "underline"
                         ],
-                        "Upload Anonymously"
-                    ]
+                        attrs! { At::Href => ANONYMOUS_UPLOAD_PATH },
+                        strings.upload_anonymously
+                    ],
+                    theme_toggle(strings, theme),
                 ]
             ]
         }
@@ -110,44 +174,144 @@ pub fn view(state: &ViewState, error_text: Option<&String>) -> Node<Msg> {
 }
 
 /// Defines the HTML view for the error message.
-/// If `error_text` is None, the error message is made invisible.
-pub fn error_message(error_text: Option<&String>) -> Node<Msg> {
+/// If `error_text` is None, the error message is made invisible. A button to sign in instead is
+/// shown along with it if `sign_in_instead` is set.
+///
+/// The message is an assertive live region, so screen readers announce it as soon as it appears.
+pub fn error_message(
+    strings: &Strings,
+    error_text: Option<&String>,
+    retry: bool,
+    sign_in_instead: bool,
+) -> Node<Msg> {
     div![
         C![
             "text-red-500 w-full fade-in mt-2",
             IF!(error_text.is_none() => "invisible"),
         ],
+        live_region(),
+        div![
+            C!["flex flex-row items-center"],
+            warning_icon(),
+            span![error_text.unwrap_or(&"Nothing yet...".to_string())],
+            IF!(retry => button![
+                C!["pl-2", "underline", "hover:text-red-700"],
+                attrs! { At::Type => "button" },
+                ev(Ev::Click, |_| Msg::Retry),
+                strings.try_again
+            ]),
+            IF!(sign_in_instead => button![
+                C!["pl-2", "underline", "hover:text-red-700"],
+                attrs! { At::Type => "button" },
+                ev(Ev::Click, |_| Msg::SignInInstead),
+                strings.sign_in_instead
+            ]),
+        ]
+    ]
+}
+
+/// Defines the HTML view shown when the server couldn't be reached.
+/// This looks like the error message, but isn't as alarming since it's most likely a problem with
+/// the user's connection, and always has a button to try again.
+pub fn offline_message(strings: &Strings) -> Node<Msg> {
+    div![
+        C!["text-gray-500 w-full fade-in mt-2"],
+        live_region(),
         div![
             C!["flex flex-row items-center"],
             warning_icon(),
-            span![error_text.unwrap_or(&"Nothing yet...".to_string())]
+            span![strings.offline],
+            button![
+                C!["pl-2", "underline", "hover:text-gray-700"],
+                attrs! { At::Type => "button" },
+                ev(Ev::Click, |_| Msg::Retry),
+                strings.try_again
+            ],
         ]
     ]
 }
 
 /// Defines the HTML view for the waiting message.
-/// This looks like the error message, but with a spinner instead of a warning icon.
-pub fn waiting_message() -> Node<Msg> {
+/// This looks like the error message, but with a spinner instead of a warning icon, and is
+/// announced the same way.
+pub fn waiting_message(strings: &Strings, state: &ViewState) -> Node<Msg> {
+    let message = match state {
+        ViewState::Registering => strings.creating_passkey,
+        _ => strings.waiting_for_authentication,
+    };
+
     div![
         C!["text-gray-500 w-full fade-in mt-2 fade-in"],
+        live_region(),
         div![
             C!["flex flex-row items-center"],
             spinner_icon(),
-            span!["Waiting for authentication..."]
+            span![message],
+            button![
+                C!["pl-2", "underline", "hover:text-gray-700"],
+                attrs! { At::Type => "button" },
+                ev(Ev::Click, |_| Msg::Cancel),
+                strings.cancel
+            ],
         ]
     ]
 }
 
+/// Defines the HTML view for the button that switches between the light and dark themes.
+pub fn theme_toggle(strings: &Strings, theme: Theme) -> Node<Msg> {
+    let (icon, label) = match theme {
+        Theme::Light => ("☾", strings.switch_to_dark),
+        Theme::Dark => ("☀", strings.switch_to_light),
+    };
+
+    button![
+        C!["text-sm", "text-gray-500", "hover:text-gray-700"],
+        attrs! {
+            At::Type => "button",
+            At::Title => label,
+            At::from("aria-label") => label,
+        },
+        ev(Ev::Click, |_| Msg::ToggleTheme),
+        icon
+    ]
+}
+
+/// The attributes that make a message a live region, which screen readers announce whenever its
+/// contents change without the user having to move to it.
+fn live_region() -> Attrs {
+    attrs! {
+        At::from("role") => "alert",
+        At::from("aria-live") => "assertive",
+    }
+}
+
 /// Defines the HTML view for the success message.
 /// This plays a fade-in animation and displays the user's name.
-pub fn view_success(user: &String) -> Node<Msg> {
+pub fn view_success(strings: &Strings, user: &String) -> Node<Msg> {
     div![
         C!["flex", "flex-row", "items-center", "fade-in-up"],
         success_icon(),
         div![
             C!["flex", "flex-col", "test"],
-            span!["Welcome"],
+            span![strings.welcome],
             span!(strong![C!["text-4xl"], format!("{}", user)])
         ]
     ]
 }
+
+/// Defines the HTML view shown when the browser doesn't support passkeys.
+/// This replaces the whole form since none of it would work anyway.
+pub fn view_unsupported(strings: &Strings) -> Node<Msg> {
+    div![
+        C!["flex", "flex-col", "items-center", "text-center", "fade-in"],
+        div![
+            C!["flex flex-row items-center text-red-500"],
+            warning_icon(),
+            strong![strings.unsupported_title]
+        ],
+        p![
+            C!["text-sm", "text-gray-500", "pt-2"],
+            strings.unsupported_body
+        ]
+    ]
+}